@@ -0,0 +1,271 @@
+use crate::config::{AuditConfig, FsyncPolicy};
+use crate::latency::Outcome;
+use crate::llm::{ChatMessage, CompletionResult};
+use crate::logging::RotatingFile;
+use crate::redaction::TextRedactor;
+use anyhow::{Context, Result};
+use serde_json::{json, Value};
+use sha2::{Digest, Sha256};
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::thread;
+use std::time::{Duration, Instant};
+
+const FSYNC_INTERVAL: Duration = Duration::from_secs(1);
+// 启动时只读文件末尾这么多字节来找上一行
+const TAIL_BYTES: u64 = 1024 * 1024;
+
+// 一次上游调用；文本在写入前按 [audit] 的规则脱敏
+pub struct AuditCall<'a> {
+    pub workflow_id: Option<u64>,
+    pub request_id: Option<&'a str>,
+    pub model: &'a str,
+    pub api_base: &'a str,
+    pub messages: &'a [ChatMessage],
+    pub result: &'a Result<CompletionResult>,
+    // 已按 API Key 遮盖过的错误信息
+    pub error: Option<String>,
+}
+
+enum Message {
+    Entry(Value),
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+// 只追加的 JSONL 文件；每行的 prev_hash 是上一行的 SHA-256，改动或删除任意一行都会让后续校验失败
+pub struct AuditLog {
+    sender: Sender<Message>,
+    redactor: TextRedactor,
+}
+
+impl AuditLog {
+    pub fn open(config: &AuditConfig) -> Result<Self> {
+        let redactor = TextRedactor::new(&config.redact_rules(), &config.redact_patterns)?;
+        if redactor.is_empty() {
+            tracing::warn!(
+                "Audit log redaction is disabled; prompts and responses are stored verbatim"
+            );
+        }
+        let path = Path::new(&config.path);
+        let prev_hash = last_line_hash(path)
+            .with_context(|| format!("Failed to read audit log: {}", path.display()))?;
+        let file = RotatingFile::open_with(
+            path,
+            config.rotation,
+            config.max_size_bytes(),
+            config.max_files(),
+        )?;
+        let fsync = config.fsync;
+        // 审计记录不能丢，用无界队列；写入与落盘都在独立线程上
+        let (sender, receiver) = mpsc::channel();
+        thread::Builder::new()
+            .name("chorus-audit".to_string())
+            .spawn(move || run_writer(file, receiver, prev_hash, fsync))
+            .context("Failed to start the audit writer")?;
+        Ok(Self { sender, redactor })
+    }
+
+    pub fn record(&self, call: AuditCall<'_>) {
+        let host = url::Url::parse(call.api_base)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        let messages: Vec<Value> = call
+            .messages
+            .iter()
+            .map(|message| {
+                json!({
+                    "role": message.role,
+                    "content": self.redactor.redact(&message.content),
+                })
+            })
+            .collect();
+        let response = call
+            .result
+            .as_ref()
+            .ok()
+            .map(|result| self.redactor.redact(&result.content));
+        let entry = json!({
+            "timestamp": chrono::Utc::now().to_rfc3339(),
+            "workflow_id": call.workflow_id,
+            "request_id": call.request_id,
+            "model": call.model,
+            "host": host,
+            "status": Outcome::of(call.result).as_str(),
+            "error": call.error.map(|error| self.redactor.redact(&error)),
+            "messages": messages,
+            "response": response,
+        });
+        if self.sender.send(Message::Entry(entry)).is_err() {
+            tracing::error!("Audit writer has stopped; an upstream call was not audited");
+        }
+    }
+
+    // 测试中等待队列里的记录全部写入
+    #[cfg(test)]
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+}
+
+pub fn line_hash(line: &str) -> String {
+    Sha256::digest(line.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+// 重启后接着已有文件的哈希链；文件不存在或为空时从头开始
+fn last_line_hash(path: &Path) -> Result<Option<String>> {
+    let mut file = match File::open(path) {
+        Ok(file) => file,
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(err.into()),
+    };
+    let len = file.metadata()?.len();
+    file.seek(SeekFrom::Start(len.saturating_sub(TAIL_BYTES)))?;
+    let mut tail = String::new();
+    file.read_to_string(&mut tail)?;
+    Ok(tail
+        .lines()
+        .rev()
+        .find(|line| !line.trim().is_empty())
+        .map(line_hash))
+}
+
+fn run_writer(
+    file: RotatingFile,
+    receiver: Receiver<Message>,
+    mut prev_hash: Option<String>,
+    fsync: FsyncPolicy,
+) {
+    let mut last_sync = Instant::now();
+    let mut dirty = false;
+    loop {
+        match receiver.recv_timeout(FSYNC_INTERVAL) {
+            Ok(Message::Entry(mut entry)) => {
+                entry["prev_hash"] = json!(prev_hash);
+                let line = entry.to_string();
+                let written = {
+                    use tracing_subscriber::fmt::MakeWriter;
+                    file.make_writer()
+                        .write_all(format!("{}\n", line).as_bytes())
+                };
+                if let Err(err) = written {
+                    tracing::error!("Failed to write audit log: {}", err);
+                    continue;
+                }
+                prev_hash = Some(line_hash(&line));
+                dirty = true;
+            }
+            #[cfg(test)]
+            Ok(Message::Flush(done)) => {
+                let _ = done.send(());
+                continue;
+            }
+            Err(RecvTimeoutError::Timeout) => {}
+            Err(RecvTimeoutError::Disconnected) => {
+                if dirty && fsync != FsyncPolicy::Never {
+                    let _ = file.sync();
+                }
+                break;
+            }
+        }
+        let due = match fsync {
+            FsyncPolicy::Always => true,
+            FsyncPolicy::Interval => last_sync.elapsed() >= FSYNC_INTERVAL,
+            FsyncPolicy::Never => false,
+        };
+        if dirty && due {
+            if let Err(err) = file.sync() {
+                tracing::error!("Failed to sync audit log: {}", err);
+            }
+            last_sync = Instant::now();
+            dirty = false;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_log(tag: &str) -> std::path::PathBuf {
+        crate::test_util::temp_dir(&format!("audit_{}", tag)).join("audit.jsonl")
+    }
+
+    fn completion(content: &str) -> Result<CompletionResult> {
+        Ok(CompletionResult {
+            content: content.to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn calls_are_redacted_and_chained() {
+        let path = temp_log("chain");
+        let config = AuditConfig {
+            path: path.to_string_lossy().into_owned(),
+            redact_patterns: vec![r"ACCT-\d+".to_string()],
+            fsync: FsyncPolicy::Always,
+            ..Default::default()
+        };
+        let messages = vec![ChatMessage {
+            role: "user".to_string(),
+            content: "mail me at carol@example.com about ACCT-991".to_string(),
+        }];
+        let log = AuditLog::open(&config).unwrap();
+        log.record(AuditCall {
+            workflow_id: Some(7),
+            request_id: Some("req-1/synthesizer"),
+            model: "m1",
+            api_base: "https://api.example.com/v1",
+            messages: &messages,
+            result: &completion("call +1 415 555 0100"),
+            error: None,
+        });
+        log.flush();
+        drop(log);
+
+        // 重新打开后接着上一行的哈希
+        let log = AuditLog::open(&config).unwrap();
+        let failed: Result<CompletionResult> = Err(anyhow::anyhow!("boom"));
+        log.record(AuditCall {
+            workflow_id: None,
+            request_id: None,
+            model: "m1",
+            api_base: "https://api.example.com/v1",
+            messages: &messages,
+            result: &failed,
+            error: Some("boom for dave@example.com".to_string()),
+        });
+        log.flush();
+
+        let content = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = content.lines().collect();
+        assert_eq!(lines.len(), 2, "{}", content);
+        let first: Value = serde_json::from_str(lines[0]).unwrap();
+        assert_eq!(first["workflow_id"], 7);
+        assert_eq!(first["request_id"], "req-1/synthesizer");
+        assert_eq!(first["host"], "api.example.com");
+        assert_eq!(first["status"], "ok");
+        assert_eq!(
+            first["messages"][0]["content"],
+            "mail me at [REDACTED:email] about [REDACTED:custom]"
+        );
+        assert_eq!(first["response"], "call [REDACTED:phone]");
+        assert_eq!(first["prev_hash"], Value::Null);
+
+        let second: Value = serde_json::from_str(lines[1]).unwrap();
+        assert_eq!(second["status"], "error");
+        assert_eq!(second["error"], "boom for [REDACTED:email]");
+        assert_eq!(second["response"], Value::Null);
+        assert_eq!(second["prev_hash"], line_hash(lines[0]));
+        let _ = std::fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,15 +1,30 @@
+use crate::config_keys::find_unknown_keys;
+use crate::config_migrations;
+use crate::env_overrides;
+use crate::llm::{
+    ApiFormat, Endpoint, GenerationParams, HeaderInjection, PoolSettings, ProxySetting,
+    DEFAULT_MAX_RESPONSE_BYTES, PROTECTED_BODY_FIELDS,
+};
+use crate::ratelimit::RateLimits;
+use crate::show::{mask_api_key, redact_url_credentials};
+use crate::tokens::Encoding;
+use crate::usage::Pricing;
 use anyhow::{anyhow, Context, Result};
 use serde::de::Error as DeError;
 use serde::{de::Deserializer, Deserialize, Serialize};
 use serde_json::{Map as JsonMap, Number as JsonNumber, Value as JsonValue};
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
+use std::fmt;
 use std::fs;
+use std::net::{IpAddr, SocketAddr};
 use std::path::{Path, PathBuf};
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use toml::Value;
 
 const DEFAULT_CONFIG: &str = r#"# Chorus 默认配置
+config_version = 2
+
 [server]
 host = "127.0.0.1"
 port = 11435
@@ -61,71 +76,38 @@ name = "ring-1t"
 
 [workflow-integration]
 nested_worker_depth = 1
+analyzer = { ref = "glm-4.6", auto_temperature = true }
+synthesizer = { ref = "qwen3-max" }
+selector = { ref = "qwen3-max" }
 
-json = """{
-  "analyzer": {
-    "ref": "glm-4.6",
-    "auto_temperature": true
-  },
-  "workers": [
-    {
-      "name": "deepseek-v3.2",
-      "temperature": 1
-    },
-    {
-      "analyzer": {
-        "ref": "glm-4.6",
-        "auto_temperature": true
-      },
-      "workers": [
-        {
-          "name": "kimi-k2-0905",
-          "temperature": 1
-        },
-        {
-          "name": "deepseek-v3.2",
-          "temperature": 1
-        },
-        {
-          "name": "glm-4.6",
-          "temperature": 1
-        },
-        {
-          "analyzer": {
-            "ref": "glm-4.6",
-            "auto_temperature": true
-          },
-          "workers": [
-            {
-              "name": "qwen3-coder",
-              "temperature": 1
-            },
-            {
-              "name": "deepseek-v3.1",
-              "temperature": 1
-            },
-            {
-              "name": "qwen3-max",
-              "temperature": 1
-            }
-          ],
-          "synthesizer": {
-            "ref": "qwen3-max"
-          }
-        }
-      ],
-      "synthesizer": {
-        "ref": "qwen3-max"
-      }
-    }
-  ],
-  "synthesizer": {
-    "ref": "qwen3-max"
-  },
-  "selector": {
-    "ref": "qwen3-max"
-  }
-}"""
+[[workflow-integration.workers]]
+name = "deepseek-v3.2"
+temperature = 1
+
+[[workflow-integration.workers]]
+analyzer = { ref = "glm-4.6", auto_temperature = true }
+synthesizer = { ref = "qwen3-max" }
+
+[[workflow-integration.workers.workers]]
+name = "kimi-k2-0905"
+temperature = 1
+
+[[workflow-integration.workers.workers]]
+name = "deepseek-v3.2"
+temperature = 1
+
+[[workflow-integration.workers.workers]]
+name = "glm-4.6"
+temperature = 1
+
+[[workflow-integration.workers.workers]]
+analyzer = { ref = "glm-4.6", auto_temperature = true }
+workers = [
+  { name = "qwen3-coder", temperature = 1 },
+  { name = "deepseek-v3.1", temperature = 1 },
+  { name = "qwen3-max", temperature = 1 },
+]
+synthesizer = { ref = "qwen3-max" }
 
 [workflow.timeouts]
 # 所有超时时间单位均为秒
@@ -155,371 +137,1611 @@ pub struct Config {
     )]
     pub workflow_integration: WorkflowPlan,
     pub workflow: WorkflowConfig,
+    #[serde(default)]
+    pub network: NetworkConfig,
+    #[serde(default)]
+    pub logging: LoggingConfig,
+    #[serde(default)]
+    pub stats: StatsConfig,
+    // 不写 [telemetry] endpoint 时不初始化任何 OpenTelemetry 组件
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub telemetry: Option<TelemetryConfig>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub history: Option<HistoryConfig>,
+    // 默认关闭；配置后记录每次发往上游的提示词与回复
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub audit: Option<AuditConfig>,
+    // 默认关闭；配置后每个 HTTP 请求结束时写一条访问日志
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub access_log: Option<AccessLogConfig>,
+    #[serde(
+        rename = "model-group",
+        default,
+        skip_serializing_if = "BTreeMap::is_empty"
+    )]
+    pub model_groups: BTreeMap<String, ModelGroup>,
+    // `[workflow-integration] json_file` 解析后的路径，热加载时一并监视
+    #[serde(skip)]
+    pub workflow_json_file: Option<PathBuf>,
+    // `include` 展开后实际合并的文件，热加载时一并监视
+    #[serde(skip)]
+    pub include_files: Vec<PathBuf>,
+    // `--profile` / CHORUS_PROFILE 选中的 profile，热加载时沿用同一个
+    #[serde(skip)]
+    pub profile: Option<ActiveProfile>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ServerConfig {
-    pub host: String,
-    pub port: u16,
+// 工作流中的 `{"group": "fast"}` 会展开为每个成员一个 worker
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ModelGroup {
+    pub models: Vec<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct ModelConfig {
+#[derive(Debug, Clone)]
+pub struct ActiveProfile {
     pub name: String,
-    pub api_base: String,
-    pub api_key: String,
+    // 基础配置单独校验得到的问题，用来区分错误出自基础配置还是 profile；基础配置无法单独加载时为 None
+    base_problems: Option<HashSet<String>>,
+}
+
+impl ActiveProfile {
+    fn annotate(&self, problems: &mut [String]) {
+        let Some(base_problems) = &self.base_problems else {
+            return;
+        };
+        for problem in problems.iter_mut() {
+            let origin = if base_problems.contains(problem.as_str()) {
+                "base config".to_string()
+            } else {
+                format!("profile '{}'", self.name)
+            };
+            problem.push_str(&format!(" (from {})", origin));
+        }
+    }
+}
+
+#[derive(Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct NetworkConfig {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub no_proxy: Vec<String>,
+    // 以下未设置时沿用 reqwest 默认值：空闲连接数不限、空闲 90 秒后关闭、不开 TCP keepalive
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_max_idle_per_host: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pool_idle_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tcp_keepalive_secs: Option<u64>,
+    // PEM 文件，追加到系统根证书之后；相对路径按主配置文件所在目录解析
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ca_certificate: Option<String>,
+    // 单次上游响应（含流式累计）的字节上限，未设置时为 8 MiB
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_bytes: Option<u64>,
+    // 类似 curl --resolve：主机名固定解析到给定地址，不再查询 DNS
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolve: BTreeMap<String, String>,
+}
+
+impl fmt::Debug for NetworkConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NetworkConfig")
+            .field("proxy", &self.proxy.as_deref().map(redact_url_credentials))
+            .field("no_proxy", &self.no_proxy)
+            .field("pool_max_idle_per_host", &self.pool_max_idle_per_host)
+            .field("pool_idle_timeout_secs", &self.pool_idle_timeout_secs)
+            .field("tcp_keepalive_secs", &self.tcp_keepalive_secs)
+            .field("ca_certificate", &self.ca_certificate)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("resolve", &self.resolve)
+            .finish()
+    }
+}
+
+impl NetworkConfig {
+    pub fn pool_settings(&self) -> PoolSettings {
+        PoolSettings {
+            max_idle_per_host: self.pool_max_idle_per_host,
+            idle_timeout: self.pool_idle_timeout_secs.map(Duration::from_secs),
+            tcp_keepalive: self.tcp_keepalive_secs.map(Duration::from_secs),
+        }
+    }
+}
+
+// 模型级 `proxy = "direct"` 表示绕过全局代理直连
+const DIRECT_PROXY: &str = "direct";
+const DEFAULT_BACKUP_RETENTION: usize = 3;
+
+#[derive(Debug, Clone)]
+pub struct MigrationOptions {
+    // 只把迁移结果打印到 stderr 并在内存中加载，不改动配置文件
+    pub dry_run: bool,
+    pub keep_backups: usize,
+    // 把 `json = """..."""` 改写成内联 TOML 表；会重排整个工作流段，所以需要显式开启
+    pub inline_workflow: bool,
+}
+
+impl Default for MigrationOptions {
+    fn default() -> Self {
+        Self {
+            dry_run: false,
+            keep_backups: DEFAULT_BACKUP_RETENTION,
+            inline_workflow: false,
+        }
+    }
+}
+
+impl MigrationOptions {
+    pub fn from_env() -> Self {
+        let flag = |name: &str| {
+            env::var(name)
+                .map(|v| matches!(v.trim(), "1" | "true" | "yes"))
+                .unwrap_or(false)
+        };
+        let dry_run = flag("CHORUS_MIGRATE_DRY_RUN");
+        let keep_backups = match env::var("CHORUS_MIGRATE_KEEP_BACKUPS") {
+            Ok(value) => value.trim().parse().unwrap_or_else(|_| {
+                tracing::warn!(
+                    "Ignoring invalid CHORUS_MIGRATE_KEEP_BACKUPS value '{}'",
+                    value
+                );
+                DEFAULT_BACKUP_RETENTION
+            }),
+            Err(_) => DEFAULT_BACKUP_RETENTION,
+        };
+        Self {
+            dry_run,
+            keep_backups,
+            inline_workflow: flag("CHORUS_MIGRATE_INLINE_WORKFLOW"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct LoggingConfig {
+    // EnvFilter 语法，例如 "info" 或 "chorus=debug,tower_http=info"；设置 RUST_LOG 时以环境变量为准
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub level: Option<String>,
     #[serde(default)]
-    pub temperature: Option<f32>,
+    pub format: LogFormat,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
     #[serde(default)]
-    pub auto_temperature: Option<bool>,
+    pub rotation: LogRotation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+    // 输出 workflow_id 等 span 字段
+    #[serde(default)]
+    pub include_spans: bool,
+    // 每个工作流结束时的一行汇总日志，默认开启
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub workflow_summary: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WorkflowPlan {
-    pub analyzer: WorkflowModelTarget,
-    #[serde(default)]
-    pub workers: Vec<WorkflowWorker>,
+impl LoggingConfig {
+    pub fn workflow_summary(&self) -> bool {
+        self.workflow_summary.unwrap_or(true)
+    }
+}
+
+const DEFAULT_LATENCY_BUCKETS_MS: &[u64] = &[
+    100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000, 120_000,
+];
+const DEFAULT_STATS_WINDOW_SECS: u64 = 900;
+const DEFAULT_USAGE_RETENTION_DAYS: u64 = 35;
+
+// /api/stats/models 与 /metrics 的延迟统计
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct StatsConfig {
+    // 直方图桶上界（毫秒），须严格递增
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub synthesizer: Option<WorkflowModelTarget>,
+    pub latency_buckets_ms: Option<Vec<u64>>,
+    // p50/p95/p99 与错误率统计的滑动窗口，默认 15 分钟
     #[serde(default, skip_serializing_if = "Option::is_none")]
-    pub selector: Option<WorkflowModelTarget>,
+    pub window_secs: Option<u64>,
+    // /api/usage 按小时汇总的用量保留多少天，默认 35 天
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage_retention_days: Option<u64>,
+    // POST /api/stats/models/reset 不做鉴权，默认关闭
     #[serde(default)]
-    pub nested_worker_depth: Option<u32>,
+    pub allow_reset: bool,
 }
 
-impl WorkflowPlan {
-    pub fn label(&self) -> String {
-        if let Some(synthesizer) = &self.synthesizer {
-            format!("workflow:{}", synthesizer.model)
-        } else if let Some(selector) = &self.selector {
-            format!("workflow:selector:{}", selector.model)
-        } else {
-            format!("workflow:{}", self.analyzer.model)
-        }
+impl StatsConfig {
+    pub fn latency_buckets(&self) -> Vec<Duration> {
+        self.latency_buckets_ms
+            .as_deref()
+            .unwrap_or(DEFAULT_LATENCY_BUCKETS_MS)
+            .iter()
+            .copied()
+            .map(Duration::from_millis)
+            .collect()
     }
 
-    pub fn worker_labels(&self) -> Vec<String> {
-        self.workers.iter().map(WorkflowWorker::label).collect()
+    pub fn window(&self) -> Duration {
+        Duration::from_secs(self.window_secs.unwrap_or(DEFAULT_STATS_WINDOW_SECS))
     }
 
-    pub fn to_json_string(&self) -> Result<String> {
-        let value = self.to_json_value()?;
-        serde_json::to_string_pretty(&value)
-            .with_context(|| "Failed to serialize workflow integration to JSON")
+    pub fn usage_retention(&self) -> chrono::Duration {
+        let days = self
+            .usage_retention_days
+            .unwrap_or(DEFAULT_USAGE_RETENTION_DAYS);
+        chrono::Duration::days(i64::try_from(days).unwrap_or(i64::MAX).min(36_500))
     }
+}
 
-    pub fn from_json_str(json: &str) -> Result<Self> {
-        let mut value: JsonValue = serde_json::from_str(json)
-            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))?;
+const DEFAULT_TELEMETRY_SERVICE_NAME: &str = "chorus";
+pub const DEFAULT_REQUEST_ID_HEADERS: &[&str] = &[
+    "x-request-id",
+    "request-id",
+    "anthropic-request-id",
+    "x-amzn-requestid",
+    "cf-ray",
+];
+const DEFAULT_HISTORY_MAX_ROWS: u64 = 100_000;
+
+// 工作流执行记录写入 SQLite，供 /api/workflows 查询
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct HistoryConfig {
+    // 相对路径按配置文件所在目录解析
+    pub path: String,
+    // 默认只保存提示词的哈希
+    #[serde(default)]
+    pub store_prompts: bool,
+    // 两项保留策略可同时设置；都不写时最多保留 100000 条
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_rows: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_age_days: Option<u64>,
+}
 
-        Self::ensure_workflow_targets(&mut value)
-            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))?;
+impl HistoryConfig {
+    pub fn max_rows(&self) -> Option<u64> {
+        match (self.max_rows, self.max_age_days) {
+            (None, None) => Some(DEFAULT_HISTORY_MAX_ROWS),
+            (rows, _) => rows,
+        }
+    }
 
-        let mut plan: Self = serde_json::from_value(value)
-            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))?;
+    pub fn max_age(&self) -> Option<Duration> {
+        self.max_age_days
+            .map(|days| Duration::from_secs(days.saturating_mul(24 * 60 * 60)))
+    }
+}
 
-        plan.validate_structure()
-            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))?;
-        plan.inherit_missing_synthesizers();
+// OTLP/HTTP 链路导出，以及从上游响应中采集哪些请求编号
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct TelemetryConfig {
+    // collector 地址，如 http://otel-collector:4318；只写到端口时自动补上 /v1/traces。不写时不导出链路
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub endpoint: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub service_name: Option<String>,
+    // 新链路的采样比例（0.0–1.0），默认全采；带 traceparent 的请求沿用上游的采样决定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample_ratio: Option<f64>,
+    // 上游响应中记作请求编号的头，按顺序第一个出现的记入 tracing span 的 provider_request_id；不写时用默认列表
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id_headers: Option<Vec<String>>,
+}
 
-        Ok(plan)
+impl TelemetryConfig {
+    pub fn service_name(&self) -> &str {
+        self.service_name
+            .as_deref()
+            .unwrap_or(DEFAULT_TELEMETRY_SERVICE_NAME)
     }
 
-    fn ensure_workflow_targets(value: &mut JsonValue) -> Result<()> {
-        if !value.is_object() {
-            return Err(anyhow!(
-                "Workflow integration JSON must start with an object at the root"
-            ));
-        }
+    pub fn sample_ratio(&self) -> f64 {
+        self.sample_ratio.unwrap_or(1.0)
+    }
 
-        Self::ensure_workflow_targets_recursive(value, None, "workflow")?;
-        Ok(())
+    pub fn traces_endpoint(&self) -> Option<String> {
+        let endpoint = self.endpoint.as_ref()?;
+        Some(match url::Url::parse(endpoint) {
+            Ok(url) if url.path() == "/" => format!("{}v1/traces", url),
+            _ => endpoint.clone(),
+        })
     }
+}
 
-    fn ensure_workflow_targets_recursive(
-        node: &mut JsonValue,
-        inherited_synthesizer: Option<JsonValue>,
-        path: &str,
-    ) -> Result<Option<JsonValue>> {
-        let map = node
-            .as_object_mut()
-            .ok_or_else(|| anyhow!("Workflow node at {} must be a JSON object", path))?;
+const DEFAULT_AUDIT_REDACT: &[&str] = &["email", "phone"];
 
-        let mut synthesizer_value = match map.get("synthesizer") {
-            Some(existing) => {
-                if !existing.is_object() {
-                    return Err(anyhow!(
-                        "Workflow node at {} has an invalid `synthesizer` field; expected an object with `ref` or `name`.",
-                        path
-                    ));
-                }
-                Some(existing.clone())
-            }
-            None => None,
-        };
+// 审计日志：每次上游调用一行 JSON，按行串成哈希链
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AuditConfig {
+    // 相对路径按配置文件所在目录解析
+    pub path: String,
+    // 内置脱敏规则，默认 email 与 phone；写 [] 关闭
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub redact: Option<Vec<String>>,
+    // 额外的正则，匹配内容替换为 [REDACTED:custom]
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub redact_patterns: Vec<String>,
+    #[serde(default)]
+    pub fsync: FsyncPolicy,
+    #[serde(default)]
+    pub rotation: LogRotation,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_size_mb: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_files: Option<usize>,
+}
 
-        if let Some(selector_value) = map.get("selector") {
-            if !selector_value.is_object() {
-                return Err(anyhow!(
-                    "Workflow node at {} has an invalid `selector` field; expected an object with `ref` or `name`.",
-                    path
-                ));
-            }
+impl AuditConfig {
+    pub fn redact_rules(&self) -> Vec<String> {
+        match &self.redact {
+            Some(rules) => rules.clone(),
+            None => DEFAULT_AUDIT_REDACT.iter().map(|s| s.to_string()).collect(),
         }
+    }
 
-        let has_selector = map.contains_key("selector");
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(DEFAULT_LOG_MAX_SIZE_MB) * 1024 * 1024
+    }
 
-        if synthesizer_value.is_none() && !has_selector {
-            if let Some(inherited) = &inherited_synthesizer {
-                map.insert("synthesizer".to_string(), inherited.clone());
-                synthesizer_value = Some(inherited.clone());
-            }
-        }
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES)
+    }
+}
 
-        if synthesizer_value.is_none() && !has_selector {
-            return Err(anyhow!(
-                "Workflow node at {} must define at least one of `synthesizer` or `selector`",
-                path
-            ));
-        }
+// HTTP 访问日志，经 tracing 以 chorus::access 为 target 输出
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AccessLogConfig {
+    // 配置了该段即开启，写 false 临时关闭
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+    // 默认与 [logging] format 相同
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<LogFormat>,
+    // 单独写入的文件，相对路径按配置文件所在目录解析；不写时与应用日志输出到同一处
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+    // 不记录的路径：完全相同，或以 * 结尾时按前缀匹配
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub exclude_paths: Vec<String>,
+    // 来自这些地址（IP 或 CIDR）的连接才采信 X-Forwarded-For
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub trusted_proxies: Vec<String>,
+}
 
-        let inherited_for_children = if let Some(synth) = &synthesizer_value {
-            Some(synth.clone())
-        } else {
-            inherited_synthesizer.clone()
-        };
+impl AccessLogConfig {
+    pub fn enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
 
-        if let Some(workers) = map
-            .get_mut("workers")
-            .and_then(|workers| workers.as_array_mut())
-        {
-            for (index, worker) in workers.iter_mut().enumerate() {
-                if Self::is_nested_workflow(worker) {
-                    let nested_path = format!("{} -> workers[{}]", path, index);
-                    Self::ensure_workflow_targets_recursive(
-                        worker,
-                        inherited_for_children.clone(),
-                        &nested_path,
-                    )?;
-                }
-            }
-        }
+    pub fn excludes(&self, path: &str) -> bool {
+        self.exclude_paths
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => path.starts_with(prefix),
+                None => path == pattern,
+            })
+    }
 
-        Ok(synthesizer_value.or(inherited_synthesizer))
+    // 配置校验时已检查过格式，这里忽略无法解析的项
+    pub fn trusted_proxies(&self) -> Vec<IpRange> {
+        self.trusted_proxies
+            .iter()
+            .filter_map(|proxy| IpRange::parse(proxy))
+            .collect()
     }
 
-    pub fn inherit_missing_synthesizers(&mut self) {
-        self.apply_synthesizer_inheritance(None);
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(file) = &mut self.file {
+            *file = base.join(&*file).to_string_lossy().into_owned();
+        }
     }
+}
 
-    pub fn apply_worker_replication(&mut self) {
-        if let Some(depth) = self.nested_worker_depth {
-            if depth > 1 {
-                let analyzer = self.analyzer.clone();
-                let synthesizer = self.synthesizer.clone();
-                let selector = self.selector.clone();
+// 单个地址或 CIDR 网段
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IpRange {
+    network: IpAddr,
+    prefix: u8,
+}
 
-                let mut new_workers = Vec::new();
-                for worker in self.workers.drain(..) {
-                    let replicated = Self::replicate_worker_static(
-                        worker,
-                        depth,
-                        &analyzer,
-                        &synthesizer,
-                        &selector,
-                    );
-                    new_workers.push(replicated);
-                }
-                self.workers = new_workers;
+impl IpRange {
+    pub fn parse(value: &str) -> Option<Self> {
+        let (addr, prefix) = match value.split_once('/') {
+            Some((addr, prefix)) => (addr, Some(prefix)),
+            None => (value, None),
+        };
+        let network: IpAddr = addr.trim().parse().ok()?;
+        let max = if network.is_ipv4() { 32 } else { 128 };
+        let prefix = match prefix {
+            Some(prefix) => prefix.trim().parse().ok().filter(|prefix| *prefix <= max)?,
+            None => max,
+        };
+        Some(Self { network, prefix })
+    }
+
+    pub fn contains(&self, ip: IpAddr) -> bool {
+        // IPv4 映射的 IPv6 地址按 IPv4 比较
+        let ip = match ip {
+            IpAddr::V6(v6) => v6.to_ipv4_mapped().map_or(ip, IpAddr::V4),
+            ip => ip,
+        };
+        match (self.network, ip) {
+            (IpAddr::V4(network), IpAddr::V4(ip)) => {
+                let mask = u32::MAX
+                    .checked_shl(32 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u32::from(network) & mask == u32::from(ip) & mask
+            }
+            (IpAddr::V6(network), IpAddr::V6(ip)) => {
+                let mask = u128::MAX
+                    .checked_shl(128 - u32::from(self.prefix))
+                    .unwrap_or(0);
+                u128::from(network) & mask == u128::from(ip) & mask
             }
+            _ => false,
         }
     }
+}
 
-    fn replicate_worker_static(
-        worker: WorkflowWorker,
-        depth: u32,
-        analyzer: &WorkflowModelTarget,
-        synthesizer: &Option<WorkflowModelTarget>,
-        selector: &Option<WorkflowModelTarget>,
-    ) -> WorkflowWorker {
-        match worker {
-            WorkflowWorker::Model(model_target) => {
-                if depth == 1 {
-                    WorkflowWorker::Model(model_target)
-                } else {
-                    let mut nested_plan = WorkflowPlan {
-                        analyzer: analyzer.clone(),
-                        workers: vec![
-                            WorkflowWorker::Model(model_target.clone()),
-                            WorkflowWorker::Model(model_target),
-                        ],
-                        synthesizer: synthesizer.clone(),
-                        selector: selector.clone(),
-                        nested_worker_depth: Some(depth - 1),
-                    };
-                    nested_plan.apply_worker_replication();
-                    WorkflowWorker::Workflow(Box::new(nested_plan))
-                }
-            }
-            WorkflowWorker::Workflow(_) => worker,
+// always：每行写完都落盘；interval：至多每秒一次；never：交给操作系统
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FsyncPolicy {
+    Always,
+    #[default]
+    Interval,
+    Never,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogFormat {
+    #[default]
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum LogRotation {
+    #[default]
+    Never,
+    Daily,
+    Size,
+}
+
+const DEFAULT_LOG_MAX_SIZE_MB: u64 = 100;
+const DEFAULT_LOG_MAX_FILES: usize = 7;
+
+impl LoggingConfig {
+    pub fn max_size_bytes(&self) -> u64 {
+        self.max_size_mb.unwrap_or(DEFAULT_LOG_MAX_SIZE_MB) * 1024 * 1024
+    }
+
+    pub fn max_files(&self) -> usize {
+        self.max_files.unwrap_or(DEFAULT_LOG_MAX_FILES)
+    }
+
+    fn resolve_paths(&mut self, base: &Path) {
+        if let Some(file) = &mut self.file {
+            *file = base.join(&*file).to_string_lossy().into_owned();
         }
     }
+}
 
-    fn apply_synthesizer_inheritance(
-        &mut self,
-        inherited: Option<&WorkflowModelTarget>,
-    ) -> Option<WorkflowModelTarget> {
-        let has_selector = self.selector.is_some();
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ServerConfig {
+    // host / port 与 unix_socket 至少配置一种；只配 unix_socket 时不监听 TCP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub host: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub port: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket: Option<String>,
+    // 八进制权限，例如 `unix_socket_mode = 0o660`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub unix_socket_mode: Option<u32>,
+    // 只作用于 TCP 监听；Unix socket 仍为明文 HTTP
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tls: Option<TlsConfig>,
+}
 
-        let current = if let Some(existing) = &self.synthesizer {
-            Some(existing.clone())
-        } else if has_selector {
-            inherited.cloned()
-        } else if let Some(parent) = inherited {
-            self.synthesizer = Some(parent.clone());
-            self.synthesizer.clone()
-        } else {
-            None
-        };
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct TlsConfig {
+    pub cert_path: String,
+    pub key_path: String,
+}
 
-        for worker in self.workers.iter_mut() {
-            if let WorkflowWorker::Workflow(plan) = worker {
-                plan.apply_synthesizer_inheritance(current.as_ref());
-            }
+const DEFAULT_UNIX_SOCKET_MODE: u32 = 0o660;
+
+impl ServerConfig {
+    pub fn tcp_address(&self) -> Option<String> {
+        match (&self.host, self.port) {
+            (Some(host), Some(port)) => Some(format!("{}:{}", host, port)),
+            _ => None,
         }
+    }
 
-        current
+    pub fn tcp_scheme(&self) -> &'static str {
+        if self.tls.is_some() {
+            "https"
+        } else {
+            "http"
+        }
     }
 
-    fn is_nested_workflow(value: &JsonValue) -> bool {
-        value.as_object().map_or(false, |map| {
-            map.contains_key("analyzer") && map.contains_key("workers")
-        })
+    pub fn unix_socket_mode(&self) -> u32 {
+        self.unix_socket_mode.unwrap_or(DEFAULT_UNIX_SOCKET_MODE)
     }
 
-    fn to_json_value(&self) -> Result<JsonValue> {
-        let mut map = JsonMap::new();
-        map.insert(
-            "analyzer".to_string(),
-            JsonValue::Object(Self::target_to_json_map(&self.analyzer, "ref")),
-        );
+    // 用于日志：`127.0.0.1:11435`、`unix:/run/chorus/chorus.sock` 或两者
+    pub fn describe_listeners(&self) -> String {
+        let listeners: Vec<String> = self
+            .tcp_address()
+            .map(|addr| format!("{}://{}", self.tcp_scheme(), addr))
+            .into_iter()
+            .chain(
+                self.unix_socket
+                    .as_ref()
+                    .map(|path| format!("unix:{}", path)),
+            )
+            .collect();
+        listeners.join(" and ")
+    }
+}
 
-        let mut workers = Vec::with_capacity(self.workers.len());
-        for worker in &self.workers {
-            workers.push(Self::worker_to_json(worker)?);
-        }
-        map.insert("workers".to_string(), JsonValue::Array(workers));
-        if let Some(synthesizer) = &self.synthesizer {
-            map.insert(
-                "synthesizer".to_string(),
-                JsonValue::Object(Self::target_to_json_map(synthesizer, "ref")),
-            );
-        }
-        if let Some(selector) = &self.selector {
-            map.insert(
-                "selector".to_string(),
-                JsonValue::Object(Self::target_to_json_map(selector, "ref")),
-            );
-        }
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct ModelConfig {
+    pub name: String,
+    #[serde(deserialize_with = "deserialize_api_base")]
+    pub api_base: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub api_key: String,
+    // 与 api_key 互斥；加载时读取文件内容，结果只保存在内存里
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_key_file: Option<String>,
+    #[serde(skip)]
+    pub(crate) resolved_api_key: Option<String>,
+    // 实际读取的 api_key_file 路径，热加载时一并监视
+    #[serde(skip)]
+    pub(crate) api_key_path: Option<PathBuf>,
+    #[serde(default)]
+    pub api_format: ApiFormat,
+    // 只用于 api_format = "azure"：部署名与 api-version 查询参数
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub deployment: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub api_version: Option<String>,
+    // 网关路径与默认不一致时使用，如 "/api/v1/chat/completions"
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chat_path: Option<String>,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub auto_temperature: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub default_presence_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proxy: Option<String>,
+    // 按主机名覆盖 network.resolve 中的同名项
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub resolve: BTreeMap<String, String>,
+    // 只用于排查问题：不校验上游证书，启动时会打印警告
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub insecure_skip_tls_verify: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_rpm: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_tpm: Option<u32>,
+    // 进程内对该模型同时在途的上游请求数上限，所有工作流共享
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_concurrent_requests: Option<u32>,
+    // 覆盖 network.max_response_bytes，留给确实会返回超长内容的模型
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_response_bytes: Option<u64>,
+    // 每 1000 个输入 / 输出 token 的价格，只用于 /api/usage 的费用估算，单位由使用者自定
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub input_price_per_1k: Option<f64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output_price_per_1k: Option<f64>,
+    // 每次请求附带的固定请求头，例如内部网关要求的签名或租户标识
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub extra_headers: BTreeMap<String, String>,
+    // 原样并入请求体的厂商参数，如 reasoning_effort、enable_thinking
+    #[serde(
+        default,
+        deserialize_with = "deserialize_extra_body",
+        skip_serializing_if = "JsonMap::is_empty"
+    )]
+    pub extra_body: JsonMap<String, JsonValue>,
+    // token 估算方式：指定 BPE 编码，或按每 token 字符数折算；都不设时按模型名推断
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tokenizer: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub chars_per_token: Option<f32>,
+    // finish_reason 为 length 时最多自动续写几次，默认不续写
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_continuations: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analyzer_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_timeout_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthesizer_timeout_secs: Option<u64>,
+    // `enabled = false` 时保留定义但不参与执行，工作流引用仍然有效
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub enabled: Option<bool>,
+}
 
-        Ok(JsonValue::Object(map))
+impl fmt::Debug for ModelConfig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        // 日志与错误信息里可能出现 {:?}，密钥只保留末尾几位
+        f.debug_struct("ModelConfig")
+            .field("name", &self.name)
+            .field("api_base", &self.api_base)
+            .field("api_key", &mask_api_key(self.api_key()))
+            .field("api_key_file", &self.api_key_file)
+            .field("api_format", &self.api_format)
+            .field("deployment", &self.deployment)
+            .field("api_version", &self.api_version)
+            .field("chat_path", &self.chat_path)
+            .field("temperature", &self.temperature)
+            .field("auto_temperature", &self.auto_temperature)
+            .field("default_max_tokens", &self.default_max_tokens)
+            .field("default_top_p", &self.default_top_p)
+            .field("default_top_k", &self.default_top_k)
+            .field("default_frequency_penalty", &self.default_frequency_penalty)
+            .field("default_presence_penalty", &self.default_presence_penalty)
+            .field("proxy", &self.proxy.as_deref().map(redact_url_credentials))
+            .field("resolve", &self.resolve)
+            .field("insecure_skip_tls_verify", &self.insecure_skip_tls_verify)
+            .field("rate_limit_rpm", &self.rate_limit_rpm)
+            .field("rate_limit_tpm", &self.rate_limit_tpm)
+            .field("max_concurrent_requests", &self.max_concurrent_requests)
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("input_price_per_1k", &self.input_price_per_1k)
+            .field("output_price_per_1k", &self.output_price_per_1k)
+            .field(
+                "extra_headers",
+                &self.extra_headers.keys().collect::<Vec<_>>(),
+            )
+            .field("extra_body", &self.extra_body)
+            .field("tokenizer", &self.tokenizer)
+            .field("chars_per_token", &self.chars_per_token)
+            .field("max_continuations", &self.max_continuations)
+            .field("analyzer_timeout_secs", &self.analyzer_timeout_secs)
+            .field("worker_timeout_secs", &self.worker_timeout_secs)
+            .field("synthesizer_timeout_secs", &self.synthesizer_timeout_secs)
+            .field("enabled", &self.enabled)
+            .finish()
     }
+}
 
-    pub fn validate_structure(&self) -> Result<()> {
-        self.validate_with_context(None, "workflow")
+impl ModelConfig {
+    // 优先使用 api_key_file 读到的值
+    pub fn api_key(&self) -> &str {
+        self.resolved_api_key.as_deref().unwrap_or(&self.api_key)
     }
 
-    fn validate_with_context(
-        &self,
-        inherited_synthesizer: Option<&WorkflowModelTarget>,
-        path: &str,
-    ) -> Result<()> {
-        let synthesizer = self.synthesizer.as_ref().or(inherited_synthesizer);
-        let has_selector = self.selector.is_some();
+    fn resolve_api_key_file(&mut self, base: &Path) -> Result<()> {
+        let Some(file) = &self.api_key_file else {
+            return Ok(());
+        };
+        // 两者同时存在时交给 validate_workflow 报错，不猜测优先级
+        if !self.api_key.is_empty() {
+            return Ok(());
+        }
 
-        if synthesizer.is_none() && !has_selector {
+        let path = base.join(file);
+        let content = fs::read_to_string(&path).with_context(|| {
+            format!(
+                "Failed to read api_key_file {} for model '{}'",
+                path.display(),
+                self.name
+            )
+        })?;
+        let key = content.trim_end_matches(['\r', '\n']);
+        if key.trim().is_empty() {
             return Err(anyhow!(
-                "Workflow node at {} must define at least one of `synthesizer` or `selector`",
-                path
+                "api_key_file {} for model '{}' is empty",
+                path.display(),
+                self.name
             ));
         }
+        self.resolved_api_key = Some(key.to_string());
+        self.api_key_path = Some(path);
+        Ok(())
+    }
 
-        for (index, worker) in self.workers.iter().enumerate() {
-            if let WorkflowWorker::Workflow(plan) = worker {
-                let nested_path = format!("{} -> workers[{}]", path, index);
-                plan.validate_with_context(synthesizer, &nested_path)?;
-            }
+    pub fn is_enabled(&self) -> bool {
+        self.enabled.unwrap_or(true)
+    }
+
+    pub fn endpoint(&self) -> Endpoint {
+        Endpoint {
+            deployment: self.deployment.clone(),
+            api_version: self.api_version.clone(),
+            chat_path: self.chat_path.clone(),
+            ..Endpoint::new(self.api_base.clone(), self.api_format)
         }
+    }
 
-        Ok(())
+    pub fn skips_tls_verify(&self) -> bool {
+        self.insecure_skip_tls_verify.unwrap_or(false)
     }
 
-    fn worker_to_json(worker: &WorkflowWorker) -> Result<JsonValue> {
-        match worker {
-            WorkflowWorker::Model(target) => {
-                Ok(JsonValue::Object(Self::target_to_json_map(target, "name")))
-            }
-            WorkflowWorker::Workflow(plan) => plan.to_json_value(),
+    // 两个价格都没写时不估算费用；只写一个时另一个按 0 计
+    pub fn pricing(&self) -> Option<Pricing> {
+        if self.input_price_per_1k.is_none() && self.output_price_per_1k.is_none() {
+            return None;
         }
+        Some(Pricing {
+            input_per_1k: self.input_price_per_1k.unwrap_or(0.0),
+            output_per_1k: self.output_price_per_1k.unwrap_or(0.0),
+        })
     }
 
-    fn target_to_json_map(target: &WorkflowModelTarget, key: &str) -> JsonMap<String, JsonValue> {
-        let mut map = JsonMap::new();
-        map.insert(key.to_string(), JsonValue::String(target.model.clone()));
-        if let Some(temp) = target.temperature {
-            if let Some(number) = JsonNumber::from_f64(temp as f64) {
-                map.insert("temperature".to_string(), JsonValue::Number(number));
+    pub fn rate_limits(&self) -> RateLimits {
+        RateLimits {
+            rpm: self.rate_limit_rpm,
+            tpm: self.rate_limit_tpm,
+        }
+    }
+
+    pub fn generation_defaults(&self) -> GenerationParams {
+        GenerationParams {
+            max_tokens: self.default_max_tokens,
+            top_p: self.default_top_p,
+            top_k: self.default_top_k,
+            frequency_penalty: self.default_frequency_penalty,
+            presence_penalty: self.default_presence_penalty,
+            extra_body: self.extra_body.clone(),
+        }
+    }
+}
+
+// TOML 的日期时间经 serde 会变成带私有键的对象，这里还原成字符串再交给上游
+const TOML_DATETIME_KEY: &str = "$__toml_private_datetime";
+
+fn normalize_toml_json(value: JsonValue) -> JsonValue {
+    match value {
+        JsonValue::Object(map) => {
+            if map.len() == 1 {
+                if let Some(JsonValue::String(datetime)) = map.get(TOML_DATETIME_KEY) {
+                    return JsonValue::String(datetime.clone());
+                }
             }
+            JsonValue::Object(
+                map.into_iter()
+                    .map(|(key, value)| (key, normalize_toml_json(value)))
+                    .collect(),
+            )
         }
-        if let Some(auto) = target.auto_temperature {
-            map.insert("auto_temperature".to_string(), JsonValue::Bool(auto));
+        JsonValue::Array(items) => {
+            JsonValue::Array(items.into_iter().map(normalize_toml_json).collect())
+        }
+        other => other,
+    }
+}
+
+fn deserialize_extra_body<'de, D>(
+    deserializer: D,
+) -> std::result::Result<JsonMap<String, JsonValue>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let map = JsonMap::<String, JsonValue>::deserialize(deserializer)?;
+    Ok(map
+        .into_iter()
+        .map(|(key, value)| (key, normalize_toml_json(value)))
+        .collect())
+}
+
+fn tokenizer_problems(model: &ModelConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    if model.tokenizer.is_some() && model.chars_per_token.is_some() {
+        problems.push(format!(
+            "model '{}' sets both tokenizer and chars_per_token; keep only one",
+            model.name
+        ));
+    }
+    if let Some(name) = model.tokenizer.as_deref() {
+        if Encoding::from_name(name).is_none() {
+            problems.push(format!(
+                "model '{}' tokenizer '{}' is not supported; use one of: {}",
+                model.name,
+                name,
+                Encoding::NAMES.join(", ")
+            ));
+        }
+    }
+    if let Some(chars_per_token) = model.chars_per_token {
+        if !(chars_per_token.is_finite() && chars_per_token > 0.0) {
+            problems.push(format!(
+                "model '{}' chars_per_token must be a positive number",
+                model.name
+            ));
         }
-        map
     }
+    problems
+}
+
+fn extra_body_problems(extra_body: &JsonMap<String, JsonValue>) -> Vec<String> {
+    PROTECTED_BODY_FIELDS
+        .iter()
+        .filter(|field| extra_body.contains_key(**field))
+        .map(|field| {
+            format!(
+                "extra_body must not override '{}'; it is set by the client",
+                field
+            )
+        })
+        .collect()
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WorkflowModelTarget {
-    #[serde(rename = "ref", alias = "name")]
-    pub model: String,
+pub struct WorkflowPlan {
+    pub analyzer: WorkflowModelTarget,
     #[serde(default)]
-    pub temperature: Option<f32>,
+    pub workers: Vec<WorkflowWorker>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthesizer: Option<WorkflowModelTarget>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector: Option<WorkflowModelTarget>,
     #[serde(default)]
-    pub auto_temperature: Option<bool>,
+    pub nested_worker_depth: Option<u32>,
+    #[serde(
+        default,
+        skip_serializing_if = "BTreeMap::is_empty",
+        deserialize_with = "deserialize_presets"
+    )]
+    pub presets: BTreeMap<String, WorkflowPlan>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset_model_prefix: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize)]
-pub enum WorkflowWorker {
-    Model(WorkflowModelTarget),
-    Workflow(Box<WorkflowPlan>),
+const DEFAULT_PRESET_MODEL_PREFIX: &str = "chorus-";
+// 内联写法中定义工作流结构的键，不能与 `json` / `json_file` 同时出现
+const INLINE_WORKFLOW_KEYS: &[&str] = &["analyzer", "workers", "synthesizer", "selector"];
+// 每层复制都会让 worker 数量翻倍
+const MAX_NESTED_WORKER_DEPTH: u32 = 6;
+const MAX_TEMPERATURE: f32 = 2.0;
+
+fn temperature_problem(temperature: Option<f32>) -> Option<String> {
+    let value = temperature?;
+    if (0.0..=MAX_TEMPERATURE).contains(&value) {
+        return None;
+    }
+    Some(format!(
+        "must be between 0.0 and {:.1}, got {}",
+        MAX_TEMPERATURE, value
+    ))
 }
 
-impl WorkflowWorker {
+impl WorkflowPlan {
     pub fn label(&self) -> String {
-        match self {
-            WorkflowWorker::Model(target) => target.model.clone(),
-            WorkflowWorker::Workflow(plan) => plan.label(),
+        if let Some(synthesizer) = &self.synthesizer {
+            format!("workflow:{}", synthesizer.model)
+        } else if let Some(selector) = &self.selector {
+            format!("workflow:selector:{}", selector.model)
+        } else {
+            format!("workflow:{}", self.analyzer.model)
         }
     }
-}
 
-impl<'de> Deserialize<'de> for WorkflowWorker {
-    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
-    where
-        D: Deserializer<'de>,
-    {
-        let value = JsonValue::deserialize(deserializer)?;
-        match value {
-            JsonValue::Object(map) => {
-                let has_name = map.contains_key("name") || map.contains_key("ref");
-                let has_analyzer = map.contains_key("analyzer");
-                let has_workers = map.contains_key("workers");
-                let has_synthesizer = map.contains_key("synthesizer");
-                let has_selector = map.contains_key("selector");
+    pub fn worker_labels(&self) -> Vec<String> {
+        self.workers.iter().map(WorkflowWorker::label).collect()
+    }
 
-                let value = JsonValue::Object(map);
+    pub fn preset_model_prefix(&self) -> &str {
+        self.preset_model_prefix
+            .as_deref()
+            .unwrap_or(DEFAULT_PRESET_MODEL_PREFIX)
+    }
 
-                if has_analyzer || has_workers || has_synthesizer || has_selector {
+    // 对外暴露的模型名，例如 chorus-deep
+    pub fn preset_model_names(&self) -> Vec<String> {
+        let prefix = self.preset_model_prefix();
+        self.presets
+            .keys()
+            .map(|name| format!("{}{}", prefix, name))
+            .collect()
+    }
+
+    // 请求中的 model 既可以带前缀，也可以直接写预设名；匹配不到时走默认工作流
+    pub fn resolve_preset(&self, model: &str) -> Option<String> {
+        let model = model.trim();
+        let name = model
+            .strip_prefix(self.preset_model_prefix())
+            .filter(|name| self.presets.contains_key(*name))
+            .unwrap_or(model);
+        self.presets.get_key_value(name).map(|(k, _)| k.clone())
+    }
+
+    pub fn plan_for_preset(&self, preset: Option<&str>) -> &WorkflowPlan {
+        preset
+            .and_then(|name| self.presets.get(name))
+            .unwrap_or(self)
+    }
+
+    pub fn to_json_string(&self) -> Result<String> {
+        let value = self.to_json_value()?;
+        serde_json::to_string_pretty(&value)
+            .with_context(|| "Failed to serialize workflow integration to JSON")
+    }
+
+    pub fn from_json_str(json: &str) -> Result<Self> {
+        let value: JsonValue = serde_json::from_str(json)
+            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))?;
+        Self::from_json_value(value)
+            .map_err(|err| anyhow!("Failed to parse workflow integration JSON: {}", err))
+    }
+
+    // json 字符串与内联 TOML 两种写法都转换成同一棵 JSON 树后走这里，校验与继承规则完全一致
+    pub fn from_json_value(mut value: JsonValue) -> Result<Self> {
+        Self::ensure_workflow_targets(&mut value)?;
+        let mut plan: Self = serde_json::from_value(value)?;
+        plan.validate_structure()?;
+        plan.inherit_missing_synthesizers();
+        Ok(plan)
+    }
+
+    fn ensure_workflow_targets(value: &mut JsonValue) -> Result<()> {
+        if !value.is_object() {
+            return Err(anyhow!(
+                "Workflow integration JSON must start with an object at the root"
+            ));
+        }
+
+        Self::ensure_workflow_targets_recursive(value, None, "workflow")?;
+        Ok(())
+    }
+
+    fn ensure_workflow_targets_recursive(
+        node: &mut JsonValue,
+        inherited_synthesizer: Option<JsonValue>,
+        path: &str,
+    ) -> Result<Option<JsonValue>> {
+        let map = node
+            .as_object_mut()
+            .ok_or_else(|| anyhow!("Workflow node at {} must be a JSON object", path))?;
+
+        let mut synthesizer_value = match map.get("synthesizer") {
+            Some(existing) => {
+                if !existing.is_object() {
+                    return Err(anyhow!(
+                        "Workflow node at {} has an invalid `synthesizer` field; expected an object with `ref` or `name`.",
+                        path
+                    ));
+                }
+                Some(existing.clone())
+            }
+            None => None,
+        };
+
+        if let Some(selector_value) = map.get("selector") {
+            if !selector_value.is_object() {
+                return Err(anyhow!(
+                    "Workflow node at {} has an invalid `selector` field; expected an object with `ref` or `name`.",
+                    path
+                ));
+            }
+        }
+
+        let has_selector = map.contains_key("selector");
+
+        if synthesizer_value.is_none() && !has_selector {
+            if let Some(inherited) = &inherited_synthesizer {
+                map.insert("synthesizer".to_string(), inherited.clone());
+                synthesizer_value = Some(inherited.clone());
+            }
+        }
+
+        if synthesizer_value.is_none() && !has_selector {
+            return Err(anyhow!(
+                "Workflow node at {} must define at least one of `synthesizer` or `selector`",
+                path
+            ));
+        }
+
+        let inherited_for_children = if let Some(synth) = &synthesizer_value {
+            Some(synth.clone())
+        } else {
+            inherited_synthesizer.clone()
+        };
+
+        if let Some(workers) = map
+            .get_mut("workers")
+            .and_then(|workers| workers.as_array_mut())
+        {
+            for (index, worker) in workers.iter_mut().enumerate() {
+                if Self::is_nested_workflow(worker) {
+                    let nested_path = format!("{} -> workers[{}]", path, index);
+                    Self::ensure_workflow_targets_recursive(
+                        worker,
+                        inherited_for_children.clone(),
+                        &nested_path,
+                    )?;
+                }
+            }
+        }
+
+        Ok(synthesizer_value.or(inherited_synthesizer))
+    }
+
+    pub fn inherit_missing_synthesizers(&mut self) {
+        self.apply_synthesizer_inheritance(None);
+    }
+
+    // 每个节点的 nested_worker_depth 只作用于它直接包含的模型节点；
+    // 子工作流未设置时保持原样，不继承上层的值
+    pub fn apply_worker_replication(&mut self) {
+        for worker in self.workers.iter_mut() {
+            if let WorkflowWorker::Workflow(plan) = worker {
+                plan.apply_worker_replication();
+            }
+        }
+
+        // 超出上限的深度留给 validate_workflow 报告，这里不展开，避免指数级膨胀
+        if let Some(depth) = self.nested_worker_depth {
+            if depth > 1 && depth <= MAX_NESTED_WORKER_DEPTH {
+                let analyzer = self.analyzer.clone();
+                let synthesizer = self.synthesizer.clone();
+                let selector = self.selector.clone();
+
+                let mut new_workers = Vec::new();
+                for worker in self.workers.drain(..) {
+                    let replicated = Self::replicate_worker_static(
+                        worker,
+                        depth,
+                        &analyzer,
+                        &synthesizer,
+                        &selector,
+                    );
+                    new_workers.push(replicated);
+                }
+                self.workers = new_workers;
+            }
+        }
+    }
+
+    fn replicate_worker_static(
+        worker: WorkflowWorker,
+        depth: u32,
+        analyzer: &WorkflowModelTarget,
+        synthesizer: &Option<WorkflowModelTarget>,
+        selector: &Option<WorkflowModelTarget>,
+    ) -> WorkflowWorker {
+        match worker {
+            WorkflowWorker::Model(model_target) => {
+                if depth == 1 {
+                    WorkflowWorker::Model(model_target)
+                } else {
+                    let mut nested_plan = WorkflowPlan {
+                        analyzer: analyzer.clone(),
+                        workers: vec![
+                            WorkflowWorker::Model(model_target.clone()),
+                            WorkflowWorker::Model(model_target),
+                        ],
+                        synthesizer: synthesizer.clone(),
+                        selector: selector.clone(),
+                        nested_worker_depth: Some(depth - 1),
+                        presets: BTreeMap::new(),
+                        preset_model_prefix: None,
+                    };
+                    nested_plan.apply_worker_replication();
+                    WorkflowWorker::Workflow(Box::new(nested_plan))
+                }
+            }
+            WorkflowWorker::Workflow(_) => worker,
+        }
+    }
+
+    fn apply_synthesizer_inheritance(
+        &mut self,
+        inherited: Option<&WorkflowModelTarget>,
+    ) -> Option<WorkflowModelTarget> {
+        let has_selector = self.selector.is_some();
+
+        let current = if let Some(existing) = &self.synthesizer {
+            Some(existing.clone())
+        } else if has_selector {
+            inherited.cloned()
+        } else if let Some(parent) = inherited {
+            self.synthesizer = Some(parent.clone());
+            self.synthesizer.clone()
+        } else {
+            None
+        };
+
+        for worker in self.workers.iter_mut() {
+            if let WorkflowWorker::Workflow(plan) = worker {
+                plan.apply_synthesizer_inheritance(current.as_ref());
+            }
+        }
+
+        current
+    }
+
+    fn is_nested_workflow(value: &JsonValue) -> bool {
+        value.as_object().is_some_and(Self::is_nested_workflow_map)
+    }
+
+    fn is_nested_workflow_map(map: &JsonMap<String, JsonValue>) -> bool {
+        map.contains_key("analyzer") && map.contains_key("workers")
+    }
+
+    pub fn to_json_value(&self) -> Result<JsonValue> {
+        let mut map = JsonMap::new();
+        map.insert(
+            "analyzer".to_string(),
+            JsonValue::Object(Self::target_to_json_map(&self.analyzer, "ref")),
+        );
+
+        let mut workers = Vec::with_capacity(self.workers.len());
+        for worker in &self.workers {
+            workers.push(Self::worker_to_json(worker)?);
+        }
+        map.insert("workers".to_string(), JsonValue::Array(workers));
+        if let Some(synthesizer) = &self.synthesizer {
+            map.insert(
+                "synthesizer".to_string(),
+                JsonValue::Object(Self::target_to_json_map(synthesizer, "ref")),
+            );
+        }
+        if let Some(selector) = &self.selector {
+            map.insert(
+                "selector".to_string(),
+                JsonValue::Object(Self::target_to_json_map(selector, "ref")),
+            );
+        }
+        if !self.presets.is_empty() {
+            let mut presets = JsonMap::new();
+            for (name, plan) in &self.presets {
+                presets.insert(name.clone(), plan.to_json_value()?);
+            }
+            map.insert("presets".to_string(), JsonValue::Object(presets));
+        }
+        if let Some(prefix) = &self.preset_model_prefix {
+            map.insert(
+                "preset_model_prefix".to_string(),
+                JsonValue::String(prefix.clone()),
+            );
+        }
+
+        Ok(JsonValue::Object(map))
+    }
+
+    pub fn validate_structure(&self) -> Result<()> {
+        let mut problems = Vec::new();
+        self.collect_structure_problems(&mut problems);
+        if problems.is_empty() {
+            Ok(())
+        } else {
+            Err(anyhow!(problems.join("; ")))
+        }
+    }
+
+    // 结构问题逐条收集，不在第一处出错时停下
+    pub fn collect_structure_problems(&self, problems: &mut Vec<String>) {
+        self.collect_problems_at(None, "workflow", problems);
+        for (name, preset) in &self.presets {
+            let path = format!("workflow preset '{}'", name);
+            if name.trim().is_empty() {
+                problems.push("Workflow preset names must not be empty".to_string());
+            }
+            if !preset.presets.is_empty() || preset.preset_model_prefix.is_some() {
+                problems.push(format!(
+                    "Workflow node at {} defines its own presets; presets are only allowed under [workflow-integration]",
+                    path
+                ));
+            }
+            preset.collect_problems_at(None, &path, problems);
+        }
+    }
+
+    fn collect_problems_at(
+        &self,
+        inherited_synthesizer: Option<&WorkflowModelTarget>,
+        path: &str,
+        problems: &mut Vec<String>,
+    ) {
+        let synthesizer = self.synthesizer.as_ref().or(inherited_synthesizer);
+        let has_selector = self.selector.is_some();
+
+        if self.nested_worker_depth == Some(0) {
+            problems.push(format!(
+                "Workflow node at {} has nested_worker_depth = 0; use 1 to disable replication",
+                path
+            ));
+        }
+
+        if synthesizer.is_none() && !has_selector {
+            problems.push(format!(
+                "Workflow node at {} must define at least one of `synthesizer` or `selector`",
+                path
+            ));
+        }
+
+        Self::reject_rubric(&self.analyzer, &format!("{} analyzer", path), problems);
+        if let Some(synthesizer) = &self.synthesizer {
+            Self::reject_rubric(synthesizer, &format!("{} synthesizer", path), problems);
+        }
+        for (role, target) in [
+            ("analyzer", Some(&self.analyzer)),
+            ("synthesizer", self.synthesizer.as_ref()),
+            ("selector", self.selector.as_ref()),
+        ] {
+            if target.is_some_and(|target| target.group.is_some()) {
+                problems.push(format!(
+                    "Workflow node at {} {} uses `group`, which is only supported on worker entries",
+                    path, role
+                ));
+            }
+        }
+        if let Some(rubric) = self.selector.as_ref().and_then(|s| s.rubric.as_ref()) {
+            Self::collect_rubric_problems(rubric, &format!("{} selector", path), problems);
+        }
+
+        for (index, worker) in self.workers.iter().enumerate() {
+            let nested_path = format!("{} -> workers[{}]", path, index);
+            match worker {
+                WorkflowWorker::Workflow(plan) => {
+                    if !plan.presets.is_empty() {
+                        problems.push(format!(
+                            "Workflow node at {} defines presets; presets are only allowed under [workflow-integration]",
+                            nested_path
+                        ));
+                    }
+                    plan.collect_problems_at(synthesizer, &nested_path, problems);
+                }
+                WorkflowWorker::Model(target) => {
+                    Self::reject_rubric(target, &nested_path, problems);
+                }
+            }
+        }
+    }
+
+    fn reject_rubric(target: &WorkflowModelTarget, path: &str, problems: &mut Vec<String>) {
+        if target.rubric.is_some() {
+            problems.push(format!(
+                "Workflow node at {} defines a `rubric`, which is only supported on selector nodes",
+                path
+            ));
+        }
+    }
+
+    fn collect_rubric_problems(rubric: &[RubricCriterion], path: &str, problems: &mut Vec<String>) {
+        if rubric.is_empty() {
+            problems.push(format!(
+                "Workflow node at {} has an empty `rubric`; remove it or add at least one criterion",
+                path
+            ));
+            return;
+        }
+
+        let mut seen = Vec::with_capacity(rubric.len());
+        for (index, criterion) in rubric.iter().enumerate() {
+            let name = criterion.name.trim();
+            if name.is_empty() {
+                problems.push(format!(
+                    "Workflow node at {} has a rubric criterion at index {} with an empty name",
+                    path, index
+                ));
+                continue;
+            }
+            if !(criterion.weight.is_finite() && criterion.weight > 0.0) {
+                problems.push(format!(
+                    "Workflow node at {} rubric criterion `{}` must have a positive weight, got {}",
+                    path, name, criterion.weight
+                ));
+            }
+            if seen.contains(&name) {
+                problems.push(format!(
+                    "Workflow node at {} rubric criterion `{}` is defined more than once",
+                    path, name
+                ));
+            }
+            seen.push(name);
+        }
+    }
+
+    fn collect_reference_problems(
+        &self,
+        models: &HashMap<String, ModelConfig>,
+        path: &str,
+        problems: &mut Vec<String>,
+    ) {
+        let mut check_target =
+            |role: &str, target: &WorkflowModelTarget| match models.get(&target.model) {
+                None => problems.push(format!(
+                    "{} {} references unknown model '{}'; define it under [[model]]",
+                    path, role, target.model
+                )),
+                Some(model) if !model.is_enabled() => problems.push(format!(
+                    "{} {} references disabled model '{}'; enable it or pick another ref",
+                    path, role, target.model
+                )),
+                Some(_) => {}
+            };
+
+        check_target("analyzer", &self.analyzer);
+        if let Some(synthesizer) = &self.synthesizer {
+            check_target("synthesizer", synthesizer);
+        }
+        if let Some(selector) = &self.selector {
+            check_target("selector", selector);
+        }
+
+        if self.workers.is_empty() {
+            problems.push(format!("{} has no worker nodes configured", path));
+        }
+
+        for (index, worker) in self.workers.iter().enumerate() {
+            let worker_path = format!("{} -> workers[{}]", path, index);
+            match worker {
+                // 组成员的问题在 [model-group] 校验中统一报告
+                WorkflowWorker::Model(target) if target.group.is_some() => {}
+                WorkflowWorker::Model(target) => {
+                    if !models.contains_key(&target.model) {
+                        problems.push(format!(
+                            "{} references unknown model '{}'; define it under [[model]]",
+                            worker_path, target.model
+                        ));
+                    }
+                }
+                WorkflowWorker::Workflow(plan) => {
+                    plan.collect_reference_problems(models, &worker_path, problems);
+                }
+            }
+        }
+    }
+
+    fn collect_range_problems(&self, path: &str, problems: &mut Vec<String>) {
+        if let Some(depth) = self.nested_worker_depth {
+            if depth > MAX_NESTED_WORKER_DEPTH {
+                problems.push(format!(
+                    "{} nested_worker_depth = {} exceeds the maximum of {}",
+                    path, depth, MAX_NESTED_WORKER_DEPTH
+                ));
+            }
+        }
+        let mut check_target = |role: &str, target: &WorkflowModelTarget| {
+            if let Some(problem) = temperature_problem(target.temperature) {
+                problems.push(format!("{} {} temperature {}", path, role, problem));
+            }
+            for problem in extra_body_problems(&target.extra_body) {
+                problems.push(format!("{} {} {}", path, role, problem));
+            }
+        };
+        check_target("analyzer", &self.analyzer);
+        if let Some(synthesizer) = &self.synthesizer {
+            check_target("synthesizer", synthesizer);
+        }
+        if let Some(selector) = &self.selector {
+            check_target("selector", selector);
+        }
+
+        for (index, worker) in self.workers.iter().enumerate() {
+            let worker_path = format!("{} -> workers[{}]", path, index);
+            match worker {
+                WorkflowWorker::Model(target) => {
+                    if let Some(problem) = temperature_problem(target.temperature) {
+                        problems.push(format!("{} temperature {}", worker_path, problem));
+                    }
+                    for problem in extra_body_problems(&target.extra_body) {
+                        problems.push(format!("{} {}", worker_path, problem));
+                    }
+                }
+                WorkflowWorker::Workflow(plan) => {
+                    plan.collect_range_problems(&worker_path, problems);
+                }
+            }
+        }
+    }
+
+    fn worker_to_json(worker: &WorkflowWorker) -> Result<JsonValue> {
+        match worker {
+            WorkflowWorker::Model(target) => {
+                Ok(JsonValue::Object(Self::target_to_json_map(target, "name")))
+            }
+            WorkflowWorker::Workflow(plan) => plan.to_json_value(),
+        }
+    }
+
+    fn target_to_json_map(target: &WorkflowModelTarget, key: &str) -> JsonMap<String, JsonValue> {
+        let mut map = JsonMap::new();
+        map.insert(key.to_string(), JsonValue::String(target.model.clone()));
+        if let Some(temp) = target.temperature {
+            if let Some(number) = JsonNumber::from_f64(temp as f64) {
+                map.insert("temperature".to_string(), JsonValue::Number(number));
+            }
+        }
+        if let Some(auto) = target.auto_temperature {
+            map.insert("auto_temperature".to_string(), JsonValue::Bool(auto));
+        }
+        if let Some(rubric) = &target.rubric {
+            if let Ok(value) = serde_json::to_value(rubric) {
+                map.insert("rubric".to_string(), value);
+            }
+        }
+        if let Ok(JsonValue::Object(params)) = serde_json::to_value(target.generation_params()) {
+            map.extend(params);
+        }
+        if !target.extra_body.is_empty() {
+            map.insert(
+                "extra_body".to_string(),
+                JsonValue::Object(target.extra_body.clone()),
+            );
+        }
+        if let Some(group) = &target.group {
+            map.insert("group".to_string(), JsonValue::String(group.clone()));
+        }
+        map
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct WorkflowModelTarget {
+    #[serde(rename = "ref")]
+    pub model: String,
+    #[serde(default)]
+    pub temperature: Option<f32>,
+    #[serde(default)]
+    pub auto_temperature: Option<bool>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rubric: Option<Vec<RubricCriterion>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    // 覆盖模型的同名 extra_body 键
+    #[serde(default, skip_serializing_if = "JsonMap::is_empty")]
+    pub extra_body: JsonMap<String, JsonValue>,
+    // 由 [model-group] 展开而来时记录组名，只用于展示与报错
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub group: Option<String>,
+}
+
+impl WorkflowModelTarget {
+    pub fn generation_params(&self) -> GenerationParams {
+        GenerationParams {
+            max_tokens: self.max_tokens,
+            top_p: self.top_p,
+            top_k: self.top_k,
+            frequency_penalty: self.frequency_penalty,
+            presence_penalty: self.presence_penalty,
+            extra_body: self.extra_body.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RubricCriterion {
+    pub name: String,
+    pub weight: f32,
+}
+
+impl<'de> Deserialize<'de> for WorkflowModelTarget {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        // `ref` and `name` are interchangeable; when both are given `ref` wins.
+        #[derive(Deserialize)]
+        struct RawTarget {
+            #[serde(rename = "ref", default)]
+            reference: Option<String>,
+            #[serde(default)]
+            name: Option<String>,
+            #[serde(default)]
+            temperature: Option<f32>,
+            #[serde(default)]
+            auto_temperature: Option<bool>,
+            #[serde(default)]
+            rubric: Option<Vec<RubricCriterion>>,
+            #[serde(default)]
+            max_tokens: Option<u32>,
+            #[serde(default)]
+            top_p: Option<f32>,
+            #[serde(default)]
+            top_k: Option<u32>,
+            #[serde(default)]
+            frequency_penalty: Option<f32>,
+            #[serde(default)]
+            presence_penalty: Option<f32>,
+            #[serde(default, deserialize_with = "deserialize_extra_body")]
+            extra_body: JsonMap<String, JsonValue>,
+            #[serde(default)]
+            group: Option<String>,
+        }
+
+        let raw = RawTarget::deserialize(deserializer)?;
+        let model = match (raw.reference, raw.name) {
+            (Some(reference), Some(name)) => {
+                if reference != name {
+                    tracing::warn!(
+                        "Workflow node specifies both ref '{}' and name '{}'; using ref",
+                        reference,
+                        name
+                    );
+                }
+                reference
+            }
+            (Some(reference), None) => reference,
+            (None, Some(name)) => name,
+            (None, None) => {
+                return Err(D::Error::custom(
+                    "Workflow model target is missing a `ref` or `name` field",
+                ))
+            }
+        };
+
+        Ok(WorkflowModelTarget {
+            model,
+            temperature: raw.temperature,
+            auto_temperature: raw.auto_temperature,
+            rubric: raw.rubric,
+            max_tokens: raw.max_tokens,
+            top_p: raw.top_p,
+            top_k: raw.top_k,
+            frequency_penalty: raw.frequency_penalty,
+            presence_penalty: raw.presence_penalty,
+            extra_body: raw.extra_body,
+            group: raw.group,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub enum WorkflowWorker {
+    Model(WorkflowModelTarget),
+    Workflow(Box<WorkflowPlan>),
+}
+
+impl WorkflowWorker {
+    // 嵌套节点展开为 `workflow:<synth>[子节点...]`，便于看出复制后的结构
+    pub fn label(&self) -> String {
+        match self {
+            WorkflowWorker::Model(target) => target.model.clone(),
+            WorkflowWorker::Workflow(plan) => {
+                format!("{}[{}]", plan.label(), plan.worker_labels().join(", "))
+            }
+        }
+    }
+}
+
+impl<'de> Deserialize<'de> for WorkflowWorker {
+    fn deserialize<D>(deserializer: D) -> std::result::Result<Self, D::Error>
+    where
+        D: Deserializer<'de>,
+    {
+        let value = JsonValue::deserialize(deserializer)?;
+        match value {
+            JsonValue::Object(map) => {
+                let has_name = map.contains_key("name") || map.contains_key("ref");
+                let has_analyzer = map.contains_key("analyzer");
+                let has_workers = map.contains_key("workers");
+                let has_synthesizer = map.contains_key("synthesizer");
+                let has_selector = map.contains_key("selector");
+
+                let value = JsonValue::Object(map);
+
+                if has_analyzer || has_workers || has_synthesizer || has_selector {
                     if !has_analyzer {
                         return Err(D::Error::custom(
                             "Nested workflow worker is missing required `analyzer` field. Each nested workflow must include an `analyzer` configuration.",
@@ -531,367 +1753,2235 @@ impl<'de> Deserialize<'de> for WorkflowWorker {
                         ));
                     }
 
-                    let plan: WorkflowPlan = serde_json::from_value(value).map_err(|err| {
-                        D::Error::custom(format!("Failed to parse nested workflow worker: {}", err))
-                    })?;
-                    return Ok(WorkflowWorker::Workflow(Box::new(plan)));
+                    let plan: WorkflowPlan = serde_json::from_value(value).map_err(|err| {
+                        D::Error::custom(format!("Failed to parse nested workflow worker: {}", err))
+                    })?;
+                    return Ok(WorkflowWorker::Workflow(Box::new(plan)));
+                }
+
+                if !has_name {
+                    if let Some(group) = value.get("group").and_then(JsonValue::as_str) {
+                        return Err(D::Error::custom(format!(
+                            "Workflow worker references model group '{}', which was not expanded; define it under [model-group.{}]",
+                            group, group
+                        )));
+                    }
+                }
+
+                if has_name {
+                    let target: WorkflowModelTarget =
+                        serde_json::from_value(value).map_err(|err| {
+                            D::Error::custom(format!(
+                                "Failed to parse workflow worker model: {}",
+                                err
+                            ))
+                        })?;
+                    return Ok(WorkflowWorker::Model(target));
+                }
+
+                Err(D::Error::custom(
+                    "Workflow worker entries must either specify a `name`/`ref` model or a nested workflow with `analyzer`, `workers`, and `synthesizer` fields",
+                ))
+            }
+            JsonValue::String(name) => Ok(WorkflowWorker::Model(WorkflowModelTarget {
+                model: name,
+                temperature: None,
+                auto_temperature: None,
+                ..Default::default()
+            })),
+            other => Err(D::Error::custom(format!(
+                "Workflow worker entries must be JSON objects or string model references, got {}",
+                other
+            ))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WorkflowConfig {
+    // 超时超过这个值时只给出警告，多半是把毫秒写成了秒
+    #[serde(default = "default_timeout_warning_secs")]
+    pub timeout_warning_secs: u64,
+    pub timeouts: TimeoutConfig,
+    #[serde(default)]
+    pub domains: HashMap<String, DomainTimeoutOverride>,
+    #[serde(default)]
+    pub retry: RetryConfig,
+    #[serde(default)]
+    pub slow: SlowCallConfig,
+}
+
+// 上游调用超过阈值时打印警告并计数；未单独设置的阶段取该阶段超时的 ratio 倍，selector 沿用 synthesizer 的超时
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SlowCallConfig {
+    #[serde(default = "default_slow_ratio")]
+    pub ratio: f64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub analyzer_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub worker_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub selector_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub synthesizer_secs: Option<u64>,
+}
+
+impl Default for SlowCallConfig {
+    fn default() -> Self {
+        Self {
+            ratio: default_slow_ratio(),
+            analyzer_secs: None,
+            worker_secs: None,
+            selector_secs: None,
+            synthesizer_secs: None,
+        }
+    }
+}
+
+fn default_slow_ratio() -> f64 {
+    0.8
+}
+
+// 上游返回 429/5xx 或连接失败时的重试策略，所有尝试共用同一个阶段超时
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RetryConfig {
+    #[serde(default = "default_retry_max_attempts")]
+    pub max_attempts: u32,
+    #[serde(default = "default_retry_base_backoff_ms")]
+    pub base_backoff_ms: u64,
+}
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        Self {
+            max_attempts: default_retry_max_attempts(),
+            base_backoff_ms: default_retry_base_backoff_ms(),
+        }
+    }
+}
+
+fn default_retry_max_attempts() -> u32 {
+    3
+}
+
+fn default_retry_base_backoff_ms() -> u64 {
+    500
+}
+
+const DEFAULT_TIMEOUT_WARNING_SECS: u64 = 3600;
+
+fn default_timeout_warning_secs() -> u64 {
+    DEFAULT_TIMEOUT_WARNING_SECS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TimeoutConfig {
+    pub analyzer_timeout_secs: u64,
+    pub worker_timeout_secs: u64,
+    pub synthesizer_timeout_secs: u64,
+    // 只限制建立连接的时间；上面的阶段超时仍是整个请求的总时限
+    #[serde(default = "default_connect_timeout_secs")]
+    pub connect_timeout_secs: u64,
+}
+
+const DEFAULT_CONNECT_TIMEOUT_SECS: u64 = 10;
+
+fn default_connect_timeout_secs() -> u64 {
+    DEFAULT_CONNECT_TIMEOUT_SECS
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, Default)]
+pub struct DomainTimeoutOverride {
+    pub analyzer_timeout_secs: Option<u64>,
+    pub worker_timeout_secs: Option<u64>,
+    pub synthesizer_timeout_secs: Option<u64>,
+    pub connect_timeout_secs: Option<u64>,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("Workflow validation failed:\n{}", format_problems(.problems))]
+pub struct WorkflowValidationError {
+    pub problems: Vec<String>,
+}
+
+// 整体加载失败后逐段检查得到的问题；message 用来与整体加载的错误去重
+#[derive(Debug, Clone, PartialEq)]
+pub struct SectionProblem {
+    pub section: String,
+    pub message: String,
+}
+
+impl fmt::Display for SectionProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.section, self.message)
+    }
+}
+
+fn format_problems(problems: &[String]) -> String {
+    problems
+        .iter()
+        .map(|problem| format!("  - {}", problem))
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn check_proxy_url(proxy: &str) -> std::result::Result<(), String> {
+    let url =
+        url::Url::parse(proxy).map_err(|err| format!("'{}' is not a valid URL: {}", proxy, err))?;
+    let proxy = &redact_url_credentials(proxy);
+    if !matches!(url.scheme(), "http" | "https" | "socks5" | "socks5h") {
+        return Err(format!(
+            "'{}' must use http, https, socks5 or socks5h (got '{}')",
+            proxy,
+            url.scheme()
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(format!("'{}' is missing a host", proxy));
+    }
+    Ok(())
+}
+
+pub(crate) fn check_api_base(api_base: &str) -> std::result::Result<url::Url, String> {
+    let url = url::Url::parse(api_base)
+        .map_err(|err| format!("'{}' is not a valid URL: {}", api_base, err))?;
+    if !matches!(url.scheme(), "http" | "https") {
+        return Err(format!(
+            "'{}' must use http or https (got '{}')",
+            api_base,
+            url.scheme()
+        ));
+    }
+    if url.host_str().is_none_or(str::is_empty) {
+        return Err(format!("'{}' is missing a host", api_base));
+    }
+    if url.query().is_some() || url.fragment().is_some() {
+        return Err(format!(
+            "'{}' must not contain a query string or fragment",
+            api_base
+        ));
+    }
+    Ok(url)
+}
+
+fn collect_api_format_problems(model: &ModelConfig, problems: &mut Vec<String>) {
+    // Gemini 的模型 ID 直接拼进 URL 路径，name 必须就是这个 ID
+    let is_model_id = |name: &str| {
+        !name.is_empty()
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || matches!(c, '-' | '.' | '_'))
+    };
+    if model.api_format == ApiFormat::Gemini && !is_model_id(&model.name) {
+        problems.push(format!(
+            "model '{}' uses api_format = \"gemini\", so its name must be the Gemini model ID (e.g. \"gemini-2.5-flash\")",
+            model.name
+        ));
+    }
+
+    let fields = [
+        ("deployment", model.deployment.as_deref()),
+        ("api_version", model.api_version.as_deref()),
+    ];
+    for (field, value) in fields {
+        match (model.api_format, value) {
+            (ApiFormat::Azure, None) => problems.push(format!(
+                "model '{}' uses api_format = \"azure\" but does not set {}",
+                model.name, field
+            )),
+            (ApiFormat::Azure, Some(value)) if value.trim().is_empty() => problems.push(format!(
+                "model '{}' {} must not be empty",
+                model.name, field
+            )),
+            (ApiFormat::Azure, _) | (_, None) => {}
+            (_, Some(_)) => problems.push(format!(
+                "model '{}' sets {}, which is only used with api_format = \"azure\"",
+                model.name, field
+            )),
+        }
+    }
+}
+
+fn check_chat_path(model: &ModelConfig) -> std::result::Result<(), String> {
+    let Some(path) = model.chat_path.as_deref() else {
+        return Ok(());
+    };
+    if path.trim_start_matches('/').trim().is_empty() {
+        return Err("must not be empty; omit it to use the default path".to_string());
+    }
+    if url::Url::parse(path).is_ok() {
+        return Err(format!(
+            "'{}' must be a path relative to api_base, not a full URL",
+            path
+        ));
+    }
+    if path.contains(['?', '#']) {
+        return Err(format!(
+            "'{}' must not contain a query string or fragment",
+            path
+        ));
+    }
+    // api_base 自身的问题已单独报告
+    if check_api_base(&model.api_base).is_err() {
+        return Ok(());
+    }
+    let url = model.endpoint().chat_url(&model.name);
+    url::Url::parse(&url)
+        .map(|_| ())
+        .map_err(|err| format!("'{}' does not form a valid URL ({}): {}", path, url, err))
+}
+
+// 鉴权头由客户端按 api_format 设置，不允许在这里覆盖
+const RESERVED_HEADERS: &[&str] = &[
+    "authorization",
+    "api-key",
+    "x-api-key",
+    "content-type",
+    "content-length",
+    "host",
+];
+
+fn extra_header_problems(model: &ModelConfig) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (name, value) in &model.extra_headers {
+        if RESERVED_HEADERS.contains(&name.to_ascii_lowercase().as_str()) {
+            problems.push(format!(
+                "model '{}' extra_headers must not set '{}'; it is managed by the client",
+                model.name, name
+            ));
+        } else if let Err(err) = HeaderInjection::new([(name, value)]) {
+            problems.push(format!("model '{}' extra_headers: {}", model.name, err));
+        }
+    }
+    problems
+}
+
+// 端口可写可不写；实际连接的端口总是取 api_base 中的端口
+fn parse_resolve_address(address: &str) -> std::result::Result<SocketAddr, String> {
+    let address = address.trim();
+    let bare = address
+        .strip_prefix('[')
+        .and_then(|rest| rest.strip_suffix(']'))
+        .unwrap_or(address);
+    address
+        .parse::<SocketAddr>()
+        .or_else(|_| bare.parse::<IpAddr>().map(|ip| SocketAddr::new(ip, 0)))
+        .map_err(|_| {
+            format!(
+                "address '{}' is not an IP address or IP:port (e.g. \"10.0.0.7\" or \"10.0.0.7:443\")",
+                address
+            )
+        })
+}
+
+fn resolve_problems(field: &str, entries: &BTreeMap<String, String>) -> Vec<String> {
+    let mut problems = Vec::new();
+    for (host, address) in entries {
+        let valid_host = !host.is_empty()
+            && host.parse::<IpAddr>().is_err()
+            && matches!(url::Host::parse(host), Ok(url::Host::Domain(_)))
+            && !host.contains(':');
+        if !valid_host {
+            problems.push(format!(
+                "{} entry '{}' must be a bare host name without scheme or port",
+                field, host
+            ));
+        }
+        if let Err(err) = parse_resolve_address(address) {
+            problems.push(format!("{} entry '{}' {}", field, host, err));
+        }
+    }
+    problems
+}
+
+fn is_loopback_host(url: &url::Url) -> bool {
+    match url.host() {
+        Some(url::Host::Domain(domain)) => {
+            domain.eq_ignore_ascii_case("localhost") || domain.ends_with(".localhost")
+        }
+        Some(url::Host::Ipv4(ip)) => ip.is_loopback(),
+        Some(url::Host::Ipv6(ip)) => ip.is_loopback(),
+        None => false,
+    }
+}
+
+fn take_model_entries(table: &mut toml::Table, source: &Path) -> Result<Vec<toml::Table>> {
+    match table.remove("model") {
+        None => Ok(Vec::new()),
+        Some(Value::Table(model)) => Ok(vec![model]),
+        Some(Value::Array(items)) => items
+            .into_iter()
+            .map(|item| match item {
+                Value::Table(model) => Ok(model),
+                other => Err(anyhow!(
+                    "[[model]] entries in {} must be tables, got {}",
+                    source.display(),
+                    other
+                )),
+            })
+            .collect(),
+        Some(other) => Err(anyhow!(
+            "`model` in {} must be a table or array of tables, got {}",
+            source.display(),
+            other
+        )),
+    }
+}
+
+// 推断类型的覆盖值可能在解析时才出错，错误里带上生效的变量名
+fn with_env_overrides<T>(result: Result<T>, applied: &[String]) -> Result<T> {
+    if applied.is_empty() {
+        return result;
+    }
+    result.with_context(|| format!("With environment overrides: {}", applied.join(", ")))
+}
+
+fn peek_section_table(
+    mut root: toml::Table,
+    profile: Option<&str>,
+    section: &str,
+) -> Option<toml::Table> {
+    let mut table = match root.remove(section) {
+        Some(Value::Table(table)) => table,
+        _ => toml::Table::new(),
+    };
+    let overlay = profile.and_then(|name| {
+        root.get("profile")?
+            .get(name)?
+            .get(section)?
+            .as_table()
+            .cloned()
+    });
+    if let Some(overlay) = overlay {
+        overlay_table(&mut table, overlay, section).ok()?;
+    }
+    // CHORUS_LOGGING__* 等同样生效；出错时留给正式加载报告
+    let mut wrapped = toml::Table::new();
+    wrapped.insert(section.to_string(), Value::Table(table));
+    let prefix = format!("CHORUS_{}__", section.to_uppercase());
+    let section_vars: Vec<(String, String)> = env_overrides::from_env()
+        .into_iter()
+        .filter(|(name, _)| name.starts_with(&prefix))
+        .collect();
+    env_overrides::apply(&mut wrapped, &section_vars).ok()?;
+    match wrapped.remove(section) {
+        Some(Value::Table(table)) => Some(table),
+        _ => None,
+    }
+}
+
+// [profile.<name>] 不参与解析，只在选中时叠加到基础配置上
+fn take_profiles(root: &mut toml::Table, path: &str) -> Result<toml::Table> {
+    let profiles = match root.remove("profile") {
+        None => return Ok(toml::Table::new()),
+        Some(Value::Table(profiles)) => profiles,
+        Some(other) => {
+            return Err(anyhow!(
+                "`profile` in {} must be a table of named profiles, got {}",
+                path,
+                other
+            ))
+        }
+    };
+    for (name, overlay) in &profiles {
+        let Value::Table(overlay) = overlay else {
+            return Err(anyhow!("[profile.{}] in {} must be a table", name, path));
+        };
+        if let Some(key) = ["include", "profile", "strict_config", "config_version"]
+            .iter()
+            .find(|key| overlay.contains_key(**key))
+        {
+            return Err(anyhow!(
+                "[profile.{}] in {} cannot set `{}`; it only applies to the base config",
+                name,
+                path,
+                key
+            ));
+        }
+    }
+    Ok(profiles)
+}
+
+fn select_profile<'a>(
+    profiles: &'a toml::Table,
+    name: &str,
+    path: &str,
+) -> Result<&'a toml::Table> {
+    if let Some(Value::Table(overlay)) = profiles.get(name) {
+        return Ok(overlay);
+    }
+    if profiles.is_empty() {
+        return Err(anyhow!(
+            "Unknown profile '{}': {} does not define any [profile.<name>] sections",
+            name,
+            path
+        ));
+    }
+    let available: Vec<&str> = profiles.keys().map(String::as_str).collect();
+    Err(anyhow!(
+        "Unknown profile '{}' in {}; available profiles: {}",
+        name,
+        path,
+        available.join(", ")
+    ))
+}
+
+// profile 叠加规则：表逐键深度合并，数组整体替换；键名以 `+` 结尾时追加到原数组，
+// 以名字为键的表（如 [profile.staging.model.<name>]）合并到数组中同名的元素
+fn overlay_table(target: &mut toml::Table, overlay: toml::Table, path: &str) -> Result<()> {
+    for (key, value) in overlay {
+        let key_path = format!("{}.{}", path, key);
+        if let Some(array_key) = key.strip_suffix('+') {
+            let Value::Array(extra) = value else {
+                return Err(anyhow!(
+                    "`{}` must be an array to append to `{}`",
+                    key_path,
+                    array_key
+                ));
+            };
+            match target
+                .entry(array_key.to_string())
+                .or_insert_with(|| Value::Array(Vec::new()))
+            {
+                Value::Array(existing) => existing.extend(extra),
+                _ => {
+                    return Err(anyhow!(
+                        "`{}` appends to `{}`, which is not an array in the base config",
+                        key_path,
+                        array_key
+                    ))
+                }
+            }
+            continue;
+        }
+
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(incoming)) => {
+                overlay_table(existing, incoming, &key_path)?
+            }
+            (Some(Value::Array(existing)), Value::Table(incoming)) => {
+                overlay_named_entries(existing, incoming, &key, &key_path)?
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+    Ok(())
+}
+
+fn overlay_named_entries(
+    entries: &mut [Value],
+    overlay: toml::Table,
+    key: &str,
+    path: &str,
+) -> Result<()> {
+    for (name, patch) in overlay {
+        let entry_path = format!("{}.{}", path, name);
+        let Value::Table(patch) = patch else {
+            return Err(anyhow!("`{}` must be a table", entry_path));
+        };
+        let entry = entries
+            .iter_mut()
+            .find_map(|entry| match entry {
+                Value::Table(table)
+                    if table.get("name").and_then(Value::as_str) == Some(name.as_str()) =>
+                {
+                    Some(table)
+                }
+                _ => None,
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "`{}` overrides {} '{}', which is not defined in the base config; use `{}+` to add it",
+                    entry_path,
+                    key,
+                    name,
+                    key
+                )
+            })?;
+        overlay_table(entry, patch, &entry_path)?;
+    }
+    Ok(())
+}
+
+// 把 profile 还原成普通配置的形状再检查未知键：去掉 `+` 后缀，按名字覆盖的 model 表视为数组
+fn overlay_key_view(overlay: &toml::Table) -> toml::Table {
+    overlay
+        .iter()
+        .map(|(key, value)| {
+            let key = key.strip_suffix('+').unwrap_or(key).to_string();
+            let value = match value {
+                Value::Table(models) if key == "model" => Value::Array(
+                    models
+                        .values()
+                        .map(|model| match model {
+                            Value::Table(model) => Value::Table(overlay_key_view(model)),
+                            other => other.clone(),
+                        })
+                        .collect(),
+                ),
+                Value::Table(table) => Value::Table(overlay_key_view(table)),
+                other => other.clone(),
+            };
+            (key, value)
+        })
+        .collect()
+}
+
+fn merge_toml_tables(target: &mut toml::Table, overlay: toml::Table) {
+    for (key, value) in overlay {
+        match (target.get_mut(&key), value) {
+            (Some(Value::Table(existing)), Value::Table(incoming)) => {
+                merge_toml_tables(existing, incoming)
+            }
+            (_, value) => {
+                target.insert(key, value);
+            }
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ModelOneOrMany {
+    One(Box<ModelConfig>),
+    Many(Vec<ModelConfig>),
+}
+
+fn deserialize_models<'de, D>(deserializer: D) -> std::result::Result<Vec<ModelConfig>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let v = ModelOneOrMany::deserialize(deserializer)?;
+    Ok(match v {
+        ModelOneOrMany::One(m) => vec![*m],
+        ModelOneOrMany::Many(vs) => vs,
+    })
+}
+
+// 加载时统一去掉首尾空白与末尾的 `/`，请求时直接拼接 `/chat/completions`
+fn deserialize_api_base<'de, D>(deserializer: D) -> std::result::Result<String, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let raw = String::deserialize(deserializer)?;
+    Ok(raw.trim().trim_end_matches('/').to_string())
+}
+
+fn deserialize_workflow_plan<'de, D>(deserializer: D) -> std::result::Result<WorkflowPlan, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct JsonWrapper {
+        json: String,
+        #[serde(default)]
+        nested_worker_depth: Option<u32>,
+        #[serde(default, deserialize_with = "deserialize_presets")]
+        presets: BTreeMap<String, WorkflowPlan>,
+        #[serde(default)]
+        preset_model_prefix: Option<String>,
+    }
+
+    let value = JsonValue::deserialize(deserializer)?;
+    match value {
+        JsonValue::String(json) => {
+            let mut plan = WorkflowPlan::from_json_str(&json).map_err(|err| {
+                DeError::custom(format!("Failed to parse workflow json: {}", err))
+            })?;
+            plan.apply_worker_replication();
+            Ok(plan)
+        }
+        JsonValue::Object(map) if map.contains_key("json") => {
+            if let Some(key) = INLINE_WORKFLOW_KEYS
+                .iter()
+                .find(|key| map.contains_key(**key))
+            {
+                return Err(DeError::custom(format!(
+                    "workflow sets both `json` and inline `{}`; define it in only one form",
+                    key
+                )));
+            }
+            let wrapper: JsonWrapper =
+                serde_json::from_value(JsonValue::Object(map)).map_err(DeError::custom)?;
+            let mut plan = WorkflowPlan::from_json_str(&wrapper.json).map_err(|err| {
+                DeError::custom(format!("Failed to parse workflow json: {}", err))
+            })?;
+            plan.nested_worker_depth = wrapper.nested_worker_depth.or(plan.nested_worker_depth);
+            plan.apply_worker_replication();
+            plan.presets.extend(wrapper.presets);
+            if wrapper.preset_model_prefix.is_some() {
+                plan.preset_model_prefix = wrapper.preset_model_prefix;
+            }
+            plan.validate_structure().map_err(|err| {
+                DeError::custom(format!("Failed to parse workflow json: {}", err))
+            })?;
+            Ok(plan)
+        }
+        inline => {
+            let mut plan = WorkflowPlan::from_json_value(inline)
+                .map_err(|err| DeError::custom(format!("Invalid inline workflow: {}", err)))?;
+            plan.apply_worker_replication();
+            Ok(plan)
+        }
+    }
+}
+
+// 在解析工作流之前把 `{"group": "fast"}` 展开成普通模型节点，
+// 之后的继承、nested_worker_depth 复制与引用校验都只看到展开后的结果
+fn expand_model_groups(root: &mut toml::Table) -> Result<bool> {
+    let groups: BTreeMap<String, ModelGroup> = match root.get("model-group") {
+        Some(value) => value
+            .clone()
+            .try_into()
+            .with_context(|| "Invalid [model-group] section")?,
+        None => BTreeMap::new(),
+    };
+    let Some(integration) = root.get_mut("workflow-integration") else {
+        return Ok(false);
+    };
+    let mut value = serde_json::to_value(&*integration)
+        .with_context(|| "Failed to read [workflow-integration]")?;
+    let mut problems = Vec::new();
+    if !expand_integration_groups(&mut value, &groups, "workflow", &mut problems) {
+        return Ok(false);
+    }
+    if !problems.is_empty() {
+        return Err(WorkflowValidationError { problems }.into());
+    }
+    *integration = Value::try_from(value)
+        .with_context(|| "Failed to rewrite workflow with expanded model groups")?;
+    Ok(true)
+}
+
+fn expand_integration_groups(
+    value: &mut JsonValue,
+    groups: &BTreeMap<String, ModelGroup>,
+    path: &str,
+    problems: &mut Vec<String>,
+) -> bool {
+    match value {
+        // JSON 写错时留给正常的解析流程报告
+        JsonValue::String(json) => {
+            let Ok(mut node) = serde_json::from_str::<JsonValue>(json) else {
+                return false;
+            };
+            let changed = expand_integration_groups(&mut node, groups, path, problems);
+            if changed {
+                *json = node.to_string();
+            }
+            changed
+        }
+        JsonValue::Object(map) => {
+            let mut changed = false;
+            if let Some(json) = map.get_mut("json") {
+                changed |= expand_integration_groups(json, groups, path, problems);
+            }
+            changed |= expand_plan_groups(map, groups, path, problems);
+            if let Some(JsonValue::Object(presets)) = map.get_mut("presets") {
+                for (name, preset) in presets.iter_mut() {
+                    let preset_path = format!("workflow preset '{}'", name);
+                    changed |= expand_integration_groups(preset, groups, &preset_path, problems);
+                }
+            }
+            changed
+        }
+        _ => false,
+    }
+}
+
+fn expand_plan_groups(
+    node: &mut JsonMap<String, JsonValue>,
+    groups: &BTreeMap<String, ModelGroup>,
+    path: &str,
+    problems: &mut Vec<String>,
+) -> bool {
+    let Some(JsonValue::Array(workers)) = node.get_mut("workers") else {
+        return false;
+    };
+    let mut changed = false;
+    let mut expanded = Vec::with_capacity(workers.len());
+    for (index, worker) in workers.drain(..).enumerate() {
+        let worker_path = format!("{} -> workers[{}]", path, index);
+        match worker {
+            JsonValue::Object(mut entry) if WorkflowPlan::is_nested_workflow_map(&entry) => {
+                changed |= expand_plan_groups(&mut entry, groups, &worker_path, problems);
+                expanded.push(JsonValue::Object(entry));
+            }
+            JsonValue::Object(entry) if entry.contains_key("group") => {
+                changed = true;
+                expanded.extend(expand_group_entry(entry, groups, &worker_path, problems));
+            }
+            other => expanded.push(other),
+        }
+    }
+    *workers = expanded;
+    changed
+}
+
+// 每个成员复制一份原条目，temperature 等设置随之继承
+fn expand_group_entry(
+    mut entry: JsonMap<String, JsonValue>,
+    groups: &BTreeMap<String, ModelGroup>,
+    path: &str,
+    problems: &mut Vec<String>,
+) -> Vec<JsonValue> {
+    let Some(JsonValue::String(name)) = entry.remove("group") else {
+        problems.push(format!(
+            "{} has an invalid `group`; expected a group name",
+            path
+        ));
+        return Vec::new();
+    };
+    if entry.contains_key("name") || entry.contains_key("ref") {
+        problems.push(format!(
+            "{} sets both `group` and `name`/`ref`; use only one",
+            path
+        ));
+        return Vec::new();
+    }
+    let Some(group) = groups.get(&name) else {
+        problems.push(format!(
+            "{} references unknown model group '{}'; define it under [model-group.{}]",
+            path, name, name
+        ));
+        return Vec::new();
+    };
+    if group.models.is_empty() {
+        problems.push(format!(
+            "{} references model group '{}', which has no models",
+            path, name
+        ));
+        return Vec::new();
+    }
+    group
+        .models
+        .iter()
+        .map(|model| {
+            let mut worker = entry.clone();
+            worker.insert("name".to_string(), JsonValue::String(model.clone()));
+            worker.insert("group".to_string(), JsonValue::String(name.clone()));
+            JsonValue::Object(worker)
+        })
+        .collect()
+}
+
+fn embeds_workflow_json(table: &toml::Table) -> bool {
+    table.get("json").is_some_and(Value::is_str)
+        || table
+            .get("presets")
+            .and_then(Value::as_table)
+            .is_some_and(|presets| {
+                presets
+                    .values()
+                    .any(|preset| preset.as_table().is_some_and(embeds_workflow_json))
+            })
+}
+
+// 先写临时文件再 rename，避免写到一半崩溃时损坏唯一的配置
+fn write_atomically(path: &Path, content: &str) -> Result<()> {
+    let file_name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("Invalid config path: {}", path.display()))?;
+    let tmp_path = path.with_file_name(format!(
+        ".{}.tmp.{}",
+        file_name.to_string_lossy(),
+        std::process::id()
+    ));
+
+    fs::write(&tmp_path, content)
+        .with_context(|| format!("Failed to write temporary file {}", tmp_path.display()))?;
+    if let Err(err) = fs::rename(&tmp_path, path) {
+        let _ = fs::remove_file(&tmp_path);
+        return Err(err).with_context(|| {
+            format!(
+                "Failed to move {} into place at {}",
+                tmp_path.display(),
+                path.display()
+            )
+        });
+    }
+    Ok(())
+}
+
+// 预设与默认工作流写法相同：json 字符串或内联表
+fn deserialize_presets<'de, D>(
+    deserializer: D,
+) -> std::result::Result<BTreeMap<String, WorkflowPlan>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    struct Preset(#[serde(deserialize_with = "deserialize_workflow_plan")] WorkflowPlan);
+
+    let presets = BTreeMap::<String, Preset>::deserialize(deserializer)?;
+    Ok(presets
+        .into_iter()
+        .map(|(name, preset)| (name, preset.0))
+        .collect())
+}
+
+impl Config {
+    // 只查找配置文件，不生成默认配置也不迁移；旧版本文件由加载时在内存中迁移
+    pub fn resolve_auto_path() -> Result<PathBuf> {
+        if let Some(path) = Self::env_config_path() {
+            return Ok(path);
+        }
+
+        let path = Self::user_config_path()?;
+        if !path.exists() {
+            anyhow::bail!(
+                "No config file found at {}; set CHORUS_CONFIG, pass --config, or run `chorus init`",
+                path.display()
+            );
+        }
+        Ok(path)
+    }
+
+    // 自动加载配置（env > ~/.config/chorus/config.toml），dry-run 时返回内存中的迁移结果
+    pub fn load_auto(options: &MigrationOptions, profile: Option<&str>) -> Result<(Self, PathBuf)> {
+        if let Some(path) = Self::env_config_path() {
+            let config = Self::load_profile(&path.to_string_lossy(), profile)?;
+            return Ok((config, path));
+        }
+
+        Self::load_from_user_config_with(options, profile)
+    }
+
+    fn env_config_path() -> Option<PathBuf> {
+        let path = PathBuf::from(env::var("CHORUS_CONFIG").ok()?);
+        if path.exists() {
+            Some(path)
+        } else {
+            tracing::warn!(
+                "CHORUS_CONFIG points to non-existent file: {}",
+                path.display()
+            );
+            None
+        }
+    }
+
+    #[cfg(test)]
+    pub fn load(path: &str) -> Result<Self> {
+        Self::load_profile(path, None)
+    }
+
+    pub fn load_profile(path: &str, profile: Option<&str>) -> Result<Self> {
+        let content = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path))?;
+        Self::load_content(path, &content, profile)
+    }
+
+    fn load_content(path: &str, content: &str, profile: Option<&str>) -> Result<Self> {
+        let mut root: toml::Table = toml::from_str(content)
+            .with_context(|| format!("Failed to parse TOML from {}", path))?;
+        let has_include = root.contains_key("include");
+        let mut include_files = Vec::new();
+        if has_include {
+            (root, include_files) = Self::merge_includes(Path::new(path), root)?;
+        }
+        let profiles = take_profiles(&mut root, path)?;
+        Self::check_unknown_keys(&root, &profiles, path)?;
+        // 旧版本的文件在内存中迁移后再解析，不改动磁盘上的文件
+        let migrations = config_migrations::migrate(&mut root, path)?;
+        if !migrations.is_empty() {
+            tracing::warn!(
+                "{} uses an older config format ({}); migrated in memory, the file on disk is unchanged",
+                path,
+                migrations.join(", ")
+            );
+        }
+        let has_include = has_include || !migrations.is_empty();
+
+        let Some(name) = profile else {
+            let applied = env_overrides::apply(&mut root, &env_overrides::from_env())?;
+            let rewritten = has_include || !applied.is_empty();
+            let mut cfg =
+                with_env_overrides(Self::parse_root(path, content, root, rewritten), &applied)?;
+            cfg.include_files = include_files;
+            cfg.warn_insecure_api_bases();
+            cfg.warn_long_timeouts();
+            return Ok(cfg);
+        };
+        let overlay = select_profile(&profiles, name, path)?;
+        let base = root.clone();
+        overlay_table(&mut root, overlay.clone(), &format!("profile.{}", name))?;
+        // 环境变量覆盖优先于 profile
+        let applied = env_overrides::apply(&mut root, &env_overrides::from_env())?;
+        let mut cfg = with_env_overrides(Self::parse_root(path, content, root, true), &applied)
+            .with_context(|| format!("Failed to apply profile '{}' from {}", name, path))?;
+
+        // 基础配置本身可能不完整（缺的部分由 profile 补上），这时不区分错误来源
+        let base_problems = Self::parse_root(path, content, base, has_include)
+            .ok()
+            .map(|base| match base.validate_workflow() {
+                Ok(()) => HashSet::new(),
+                Err(err) => err.problems.into_iter().collect(),
+            });
+        cfg.profile = Some(ActiveProfile {
+            name: name.to_string(),
+            base_problems,
+        });
+        cfg.include_files = include_files;
+        cfg.warn_insecure_api_bases();
+        cfg.warn_long_timeouts();
+        Ok(cfg)
+    }
+
+    fn parse_root(
+        path: &str,
+        content: &str,
+        mut root: toml::Table,
+        rewritten: bool,
+    ) -> Result<Self> {
+        let workflow_json_file = Self::inline_workflow_json_file(&mut root, Path::new(path))?;
+        let expanded_groups = expand_model_groups(&mut root)?;
+
+        // 未经改写时直接解析原文，报错信息能带上行列位置
+        let mut cfg: Config = if rewritten || workflow_json_file.is_some() || expanded_groups {
+            Value::Table(root)
+                .try_into()
+                .with_context(|| format!("Failed to parse merged configuration from {}", path))?
+        } else {
+            toml::from_str(content)
+                .with_context(|| format!("Failed to parse TOML from {}", path))?
+        };
+        cfg.workflow_json_file = workflow_json_file;
+        cfg.resolve_relative_paths(Path::new(path))?;
+        Ok(cfg)
+    }
+
+    // serde 在第一个错误处就停下；整体加载失败时逐段再解析一遍，让 validate 一次列出尽量多的问题。
+    // 读不到文件或 TOML 语法错误时无从继续，返回空列表，由整体加载的错误说明
+    pub fn section_problems(path: &Path, profile: Option<&str>) -> Vec<SectionProblem> {
+        let path_str = path.to_string_lossy();
+        let Ok(content) = fs::read_to_string(path) else {
+            return Vec::new();
+        };
+        let Ok(mut root) = toml::from_str::<toml::Table>(&content) else {
+            return Vec::new();
+        };
+        let mut problems = Vec::new();
+        let mut push = |section: &str, message: String| {
+            problems.push(SectionProblem {
+                section: section.to_string(),
+                message: message.trim_end().to_string(),
+            })
+        };
+
+        if root.contains_key("include") {
+            match Self::merge_includes(path, root.clone()) {
+                Ok((merged, _)) => root = merged,
+                Err(err) => {
+                    push("include", format!("{:#}", err));
+                    root.remove("include");
                 }
+            }
+        }
+        let profiles = take_profiles(&mut root, &path_str).unwrap_or_else(|err| {
+            push("profile", format!("{:#}", err));
+            toml::Table::new()
+        });
+        if let Some(name) = profile {
+            let applied = select_profile(&profiles, name, &path_str).and_then(|overlay| {
+                overlay_table(&mut root, overlay.clone(), &format!("profile.{}", name))
+            });
+            if let Err(err) = applied {
+                push(&format!("profile.{}", name), format!("{:#}", err));
+            }
+        }
+        if root
+            .get("strict_config")
+            .and_then(Value::as_bool)
+            .unwrap_or(true)
+        {
+            for key in find_unknown_keys(&root) {
+                push("keys", key.to_string());
+            }
+        }
+        if let Err(err) = config_migrations::migrate(&mut root, &path_str) {
+            push("config_version", format!("{:#}", err));
+            return problems;
+        }
+        if let Err(err) = Self::inline_workflow_json_file(&mut root, path) {
+            push("[workflow-integration]", format!("{:#}", err));
+        }
+        if let Err(err) = expand_model_groups(&mut root) {
+            push("[model-group]", format!("{:#}", err));
+        }
 
-                if has_name {
-                    let target: WorkflowModelTarget =
-                        serde_json::from_value(value).map_err(|err| {
-                            D::Error::custom(format!(
-                                "Failed to parse workflow worker model: {}",
-                                err
-                            ))
-                        })?;
-                    return Ok(WorkflowWorker::Model(target));
+        for key in ["server", "model", "workflow-integration", "workflow"] {
+            if !root.contains_key(key) {
+                push("config", format!("missing field `{}`", key));
+            }
+        }
+        fn check<T: serde::de::DeserializeOwned>(value: &Value) -> std::result::Result<(), String> {
+            value
+                .clone()
+                .try_into::<T>()
+                .map(drop)
+                .map_err(|err| err.to_string())
+        }
+        for (key, value) in &root {
+            let section = format!("[{}]", key);
+            let result = match key.as_str() {
+                "server" => check::<ServerConfig>(value),
+                "workflow" => check::<WorkflowConfig>(value),
+                "network" => check::<NetworkConfig>(value),
+                "logging" => check::<LoggingConfig>(value),
+                "stats" => check::<StatsConfig>(value),
+                "telemetry" => check::<TelemetryConfig>(value),
+                "history" => check::<HistoryConfig>(value),
+                "audit" => check::<AuditConfig>(value),
+                "access_log" => check::<AccessLogConfig>(value),
+                "workflow-integration" => deserialize_workflow_plan(value.clone())
+                    .map(drop)
+                    .map_err(|err| err.to_string()),
+                "model" => {
+                    let entries = match value {
+                        Value::Array(entries) => entries.as_slice(),
+                        single => std::slice::from_ref(single),
+                    };
+                    for (index, entry) in entries.iter().enumerate() {
+                        if let Err(message) = check::<ModelConfig>(entry) {
+                            let name = entry.get("name").and_then(Value::as_str).unwrap_or("?");
+                            push(&format!("[[model]] #{} '{}'", index + 1, name), message);
+                        }
+                    }
+                    Ok(())
                 }
+                _ => Ok(()),
+            };
+            if let Err(message) = result {
+                push(&section, message);
+            }
+        }
+        problems
+    }
 
-                Err(D::Error::custom(
-                    "Workflow worker entries must either specify a `name`/`ref` model or a nested workflow with `analyzer`, `workers`, and `synthesizer` fields",
+    pub fn profile_name(&self) -> Option<&str> {
+        self.profile.as_ref().map(|profile| profile.name.as_str())
+    }
+
+    // 把 `json_file` 的内容读进来替换成等价的 `json`，之后与内联写法走同一条解析与校验流程
+    fn inline_workflow_json_file(
+        root: &mut toml::Table,
+        config_path: &Path,
+    ) -> Result<Option<PathBuf>> {
+        let Some(Value::Table(integration)) = root.get_mut("workflow-integration") else {
+            return Ok(None);
+        };
+        let file = match integration.remove("json_file") {
+            None => return Ok(None),
+            Some(Value::String(file)) => file,
+            Some(other) => {
+                return Err(anyhow!(
+                    "`workflow-integration.json_file` in {} must be a string, got {}",
+                    config_path.display(),
+                    other
                 ))
             }
-            JsonValue::String(name) => Ok(WorkflowWorker::Model(WorkflowModelTarget {
-                model: name,
-                temperature: None,
-                auto_temperature: None,
-            })),
-            other => Err(D::Error::custom(format!(
-                "Workflow worker entries must be JSON objects or string model references, got {}",
-                other
-            ))),
+        };
+        if integration.contains_key("json") {
+            return Err(anyhow!(
+                "[workflow-integration] in {} sets both `json` and `json_file`; keep only one",
+                config_path.display()
+            ));
         }
+        if let Some(key) = INLINE_WORKFLOW_KEYS
+            .iter()
+            .find(|key| integration.contains_key(**key))
+        {
+            return Err(anyhow!(
+                "[workflow-integration] in {} sets both `json_file` and inline `{}`; keep only one",
+                config_path.display(),
+                key
+            ));
+        }
+
+        let base = config_path.parent().unwrap_or_else(|| Path::new("."));
+        let file = base.join(file);
+        let json = fs::read_to_string(&file)
+            .with_context(|| format!("Failed to read workflow JSON file: {}", file.display()))?;
+        WorkflowPlan::from_json_str(&json)
+            .with_context(|| format!("Invalid workflow in {}", file.display()))?;
+        integration.insert("json".to_string(), Value::String(json));
+        Ok(Some(file))
     }
-}
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct WorkflowConfig {
-    pub timeouts: TimeoutConfig,
-    #[serde(default)]
-    pub domains: HashMap<String, DomainTimeoutOverride>,
-}
+    // 读取 api_key_file，并把相对路径按主配置文件所在目录解析（include 进来的模型也一样）
+    fn resolve_relative_paths(&mut self, config_path: &Path) -> Result<()> {
+        let base = config_path.parent().unwrap_or_else(|| Path::new("."));
+        for model in &mut self.models {
+            model.resolve_api_key_file(base)?;
+        }
+        if let Some(tls) = &mut self.server.tls {
+            for path in [&mut tls.cert_path, &mut tls.key_path] {
+                *path = base.join(&*path).to_string_lossy().into_owned();
+            }
+        }
+        if let Some(path) = &mut self.network.ca_certificate {
+            *path = base.join(&*path).to_string_lossy().into_owned();
+        }
+        self.logging.resolve_paths(base);
+        if let Some(history) = &mut self.history {
+            history.path = base.join(&history.path).to_string_lossy().into_owned();
+        }
+        if let Some(audit) = &mut self.audit {
+            audit.path = base.join(&audit.path).to_string_lossy().into_owned();
+        }
+        if let Some(access_log) = &mut self.access_log {
+            access_log.resolve_paths(base);
+        }
+        Ok(())
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-pub struct TimeoutConfig {
-    pub analyzer_timeout_secs: u64,
-    pub worker_timeout_secs: u64,
-    pub synthesizer_timeout_secs: u64,
-}
+    // 日志要在完整加载配置之前初始化，这里只按 serve 的查找顺序读取 [logging]；
+    // 读取失败时使用默认值，真正的错误由随后的完整加载报告
+    pub fn peek_logging(profile: Option<&str>) -> LoggingConfig {
+        let path = Self::env_config_path()
+            .or_else(|| Self::user_config_path().ok().filter(|path| path.exists()));
+        let Some(path) = path else {
+            return LoggingConfig::default();
+        };
+        let mut logging: LoggingConfig = Self::peek_section(&path, profile, "logging")
+            .and_then(|table| Value::Table(table).try_into().ok())
+            .unwrap_or_default();
+        logging.resolve_paths(path.parent().unwrap_or_else(|| Path::new(".")));
+        logging
+    }
 
-#[derive(Debug, Clone, Serialize, Deserialize, Default)]
-pub struct DomainTimeoutOverride {
-    pub analyzer_timeout_secs: Option<u64>,
-    pub worker_timeout_secs: Option<u64>,
-    pub synthesizer_timeout_secs: Option<u64>,
-}
+    // 访问日志的输出层也在初始化日志时建立
+    pub fn peek_access_log(profile: Option<&str>) -> Option<AccessLogConfig> {
+        let path = Self::env_config_path()
+            .or_else(|| Self::user_config_path().ok().filter(|path| path.exists()))?;
+        let mut access_log: AccessLogConfig = Self::peek_section(&path, profile, "access_log")
+            .and_then(|table| Value::Table(table).try_into().ok())?;
+        access_log.resolve_paths(path.parent().unwrap_or_else(|| Path::new(".")));
+        Some(access_log)
+    }
 
-#[derive(Deserialize)]
-#[serde(untagged)]
-enum ModelOneOrMany {
-    One(ModelConfig),
-    Many(Vec<ModelConfig>),
-}
+    // 链路导出和日志一起初始化，同样要在加载完整配置之前读出来
+    pub fn peek_telemetry(profile: Option<&str>) -> Option<TelemetryConfig> {
+        let path = Self::env_config_path()
+            .or_else(|| Self::user_config_path().ok().filter(|path| path.exists()))?;
+        Self::peek_section(&path, profile, "telemetry")
+            .and_then(|table| Value::Table(table).try_into().ok())
+    }
 
-fn deserialize_models<'de, D>(deserializer: D) -> std::result::Result<Vec<ModelConfig>, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    let v = ModelOneOrMany::deserialize(deserializer)?;
-    Ok(match v {
-        ModelOneOrMany::One(m) => vec![m],
-        ModelOneOrMany::Many(vs) => vs,
-    })
-}
+    fn peek_section(path: &Path, profile: Option<&str>, section: &str) -> Option<toml::Table> {
+        fs::read_to_string(path)
+            .ok()
+            .and_then(|content| toml::from_str::<toml::Table>(&content).ok())
+            .and_then(|root| peek_section_table(root, profile, section))
+    }
 
-fn deserialize_workflow_plan<'de, D>(deserializer: D) -> std::result::Result<WorkflowPlan, D::Error>
-where
-    D: Deserializer<'de>,
-{
-    #[derive(Deserialize)]
-    struct JsonWrapper {
-        json: String,
-        #[serde(default)]
-        nested_worker_depth: Option<u32>,
+    // 默认拒绝无法识别的键；`strict_config = false` 时只打印警告，便于旧版本读取新配置
+    fn check_unknown_keys(root: &toml::Table, profiles: &toml::Table, path: &str) -> Result<()> {
+        let mut unknown = find_unknown_keys(root);
+        // 未选中的 profile 也要检查，免得切换时才发现拼写错误
+        for (name, overlay) in profiles {
+            if let Value::Table(overlay) = overlay {
+                unknown.extend(
+                    find_unknown_keys(&overlay_key_view(overlay))
+                        .into_iter()
+                        .map(|mut key| {
+                            key.path = format!("profile.{}.{}", name, key.path);
+                            key
+                        }),
+                );
+            }
+        }
+        if unknown.is_empty() {
+            return Ok(());
+        }
+
+        let strict = root
+            .get("strict_config")
+            .and_then(Value::as_bool)
+            .unwrap_or(true);
+        if !strict {
+            for key in &unknown {
+                tracing::warn!("Ignoring {} in {}", key, path);
+            }
+            return Ok(());
+        }
+
+        let listed: Vec<String> = unknown.iter().map(|key| format!("  - {}", key)).collect();
+        Err(anyhow!(
+            "Unrecognized keys in {} (set `strict_config = false` to ignore them):\n{}",
+            path,
+            listed.join("\n")
+        ))
     }
 
-    #[derive(Deserialize)]
-    #[serde(untagged)]
-    enum PlanInput {
-        Json(JsonWrapper),
-        PlainString(String),
-        Plan(WorkflowPlan),
+    // include 中的文件按顺序合并：[[model]] 追加，其余表逐键覆盖（后者优先）
+    // 返回合并后的表与实际读取的文件
+    fn merge_includes(
+        main_path: &Path,
+        mut root: toml::Table,
+    ) -> Result<(toml::Table, Vec<PathBuf>)> {
+        let patterns = match root.remove("include") {
+            Some(Value::String(pattern)) => vec![pattern],
+            Some(Value::Array(items)) => items
+                .into_iter()
+                .map(|item| match item {
+                    Value::String(pattern) => Ok(pattern),
+                    other => Err(anyhow!(
+                        "`include` entries in {} must be strings, got {}",
+                        main_path.display(),
+                        other
+                    )),
+                })
+                .collect::<Result<Vec<_>>>()?,
+            Some(other) => {
+                return Err(anyhow!(
+                    "`include` in {} must be a string or an array of strings, got {}",
+                    main_path.display(),
+                    other
+                ))
+            }
+            None => Vec::new(),
+        };
+
+        let base = main_path.parent().unwrap_or_else(|| Path::new("."));
+        let mut files = Vec::new();
+        let mut models = take_model_entries(&mut root, main_path)?;
+        let mut model_sources: HashMap<String, PathBuf> = HashMap::new();
+        for model in &models {
+            if let Some(name) = model.get("name").and_then(Value::as_str) {
+                model_sources.insert(name.to_string(), main_path.to_path_buf());
+            }
+        }
+
+        for pattern in patterns {
+            for file in Self::expand_include(base, &pattern)? {
+                let content = fs::read_to_string(&file).with_context(|| {
+                    format!("Failed to read included config file: {}", file.display())
+                })?;
+                let mut table: toml::Table = toml::from_str(&content)
+                    .with_context(|| format!("Failed to parse TOML from {}", file.display()))?;
+                if table.contains_key("include") {
+                    return Err(anyhow!(
+                        "{} contains `include`, but nested includes are not supported; list every file in {}",
+                        file.display(),
+                        main_path.display()
+                    ));
+                }
+
+                for model in take_model_entries(&mut table, &file)? {
+                    if let Some(name) = model.get("name").and_then(Value::as_str) {
+                        if let Some(previous) = model_sources.get(name) {
+                            return Err(anyhow!(
+                                "model '{}' is defined in both {} and {}",
+                                name,
+                                previous.display(),
+                                file.display()
+                            ));
+                        }
+                        model_sources.insert(name.to_string(), file.clone());
+                    }
+                    models.push(model);
+                }
+
+                merge_toml_tables(&mut root, table);
+                files.push(file);
+            }
+        }
+
+        if !models.is_empty() {
+            root.insert(
+                "model".to_string(),
+                Value::Array(models.into_iter().map(Value::Table).collect()),
+            );
+        }
+        Ok((root, files))
+    }
+
+    // 主配置文件之外参与加载的文件：json_file、include 与 api_key_file
+    pub fn source_files(&self) -> Vec<PathBuf> {
+        let mut files: Vec<PathBuf> = self.workflow_json_file.iter().cloned().collect();
+        files.extend(self.include_files.iter().cloned());
+        files.extend(
+            self.models
+                .iter()
+                .filter_map(|model| model.api_key_path.clone()),
+        );
+        files.dedup();
+        files
+    }
+
+    fn expand_include(base: &Path, pattern: &str) -> Result<Vec<PathBuf>> {
+        let full = base.join(pattern);
+        if !pattern.contains(['*', '?', '[']) {
+            if !full.exists() {
+                return Err(anyhow!(
+                    "Included config file not found: {}",
+                    full.display()
+                ));
+            }
+            return Ok(vec![full]);
+        }
+
+        let full_pattern = full.to_string_lossy();
+        let mut files = glob::glob(&full_pattern)
+            .with_context(|| format!("Invalid include pattern: {}", pattern))?
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to expand include pattern: {}", pattern))?;
+        files.retain(|path| path.is_file());
+        files.sort();
+        Ok(files)
+    }
+
+    pub fn user_config_path() -> Result<PathBuf> {
+        let home = env::var("HOME").context("HOME env var not set")?;
+        Ok(Path::new(&home)
+            .join(".config")
+            .join("chorus")
+            .join("config.toml"))
+    }
+
+    fn ensure_user_config_exists(options: &MigrationOptions) -> Result<(PathBuf, Option<String>)> {
+        let path = Self::user_config_path()?;
+        if let Some(dir) = path.parent() {
+            if !dir.exists() {
+                fs::create_dir_all(dir)
+                    .with_context(|| format!("Failed to create config dir: {}", dir.display()))?;
+            }
+        }
+        if !path.exists() {
+            write_atomically(&path, DEFAULT_CONFIG)
+                .with_context(|| format!("Failed to write default config to {}", path.display()))?;
+            tracing::warn!(
+                "Wrote a sample config with placeholder API keys to {}; run `chorus init` to set up a working one",
+                path.display()
+            );
+            Ok((path, None))
+        } else {
+            let preview = Self::migrate_config_if_needed(&path, options)?;
+            Ok((path, preview))
+        }
+    }
+
+    // 返回值仅在 dry-run 且需要迁移时为 Some，内容为迁移后的 TOML
+    pub fn migrate_config_if_needed(
+        config_path: &Path,
+        options: &MigrationOptions,
+    ) -> Result<Option<String>> {
+        let content = fs::read_to_string(config_path)
+            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
+
+        let mut root: toml::Table = match toml::from_str(&content) {
+            Ok(root) => root,
+            Err(_) => return Ok(None),
+        };
+        let source = config_path.display().to_string();
+        let from_version = config_migrations::detect_version(&root).unwrap_or_default();
+        let applied = config_migrations::migrate(&mut root, &source)?;
+
+        let (migrated_toml, migrations) = if applied.is_empty() {
+            let has_json = root
+                .get("workflow-integration")
+                .and_then(Value::as_table)
+                .is_some_and(embeds_workflow_json);
+            if !has_json {
+                return Ok(None);
+            }
+            if !options.inline_workflow {
+                tracing::info!(
+                    "{} embeds the workflow as a JSON string; rerun with --migrate-inline-workflow to rewrite it as inline TOML",
+                    config_path.display()
+                );
+                return Ok(None);
+            }
+            (
+                Self::inline_workflow_json(&content)?,
+                vec!["内联 workflow 表"],
+            )
+        } else {
+            tracing::info!(
+                "Migrating {} from config_version {} to {}",
+                config_path.display(),
+                from_version,
+                config_migrations::CURRENT_VERSION
+            );
+            let migrated_toml = toml::to_string_pretty(&root)
+                .with_context(|| "Failed to serialize migrated config")?;
+            // 迁移步骤按 json 写出工作流，再统一改写成内联表，与 --migrate-inline-workflow 的输出一致
+            (Self::inline_workflow_json(&migrated_toml)?, applied)
+        };
+
+        if options.dry_run {
+            let mut preview = format!(
+                "# Chorus 配置文件（迁移预览：{}，未写入磁盘）\n\n",
+                migrations.join("，")
+            );
+            preview.push_str(&migrated_toml);
+            eprintln!("{}", Self::dry_run_banner(config_path, &preview));
+            tracing::info!(
+                "Dry run: config migration ({}) not written to {}",
+                migrations.join("，"),
+                config_path.display()
+            );
+            return Ok(Some(preview));
+        }
+
+        let backup_path = Self::backup_config_file(config_path)?;
+        tracing::info!("Old config backed up to: {}", backup_path.display());
+        Self::prune_backups(config_path, options.keep_backups);
+
+        let mut new_content = String::new();
+        new_content.push_str(&format!(
+            "# Chorus 配置文件（已自动迁移：{}）\n",
+            migrations.join("，")
+        ));
+        new_content.push_str(&format!("# 旧配置已备份到: {}\n\n", backup_path.display()));
+        new_content.push_str(&migrated_toml);
+
+        write_atomically(config_path, &new_content).with_context(|| {
+            format!(
+                "Failed to write migrated config to {}",
+                config_path.display()
+            )
+        })?;
+
+        tracing::info!(
+            "Config migration completed successfully: {}",
+            migrations.join("，")
+        );
+        tracing::info!("New config written to: {}", config_path.display());
+
+        Ok(None)
     }
 
-    match PlanInput::deserialize(deserializer)? {
-        PlanInput::Json(wrapper) => {
-            let mut plan = WorkflowPlan::from_json_str(&wrapper.json).map_err(|err| {
-                DeError::custom(format!("Failed to parse workflow json: {}", err))
-            })?;
-            plan.nested_worker_depth = wrapper.nested_worker_depth;
-            plan.apply_worker_replication();
-            Ok(plan)
+    // 只改写 [workflow-integration]，其余内容（include、注释等）原样保留；改写前后的工作流必须完全一致
+    fn inline_workflow_json(content: &str) -> Result<String> {
+        #[derive(Deserialize)]
+        struct IntegrationOnly {
+            #[serde(
+                rename = "workflow-integration",
+                deserialize_with = "deserialize_workflow_plan"
+            )]
+            plan: WorkflowPlan,
         }
-        PlanInput::PlainString(json) => WorkflowPlan::from_json_str(&json)
-            .map_err(|err| DeError::custom(format!("Failed to parse workflow json: {}", err))),
-        PlanInput::Plan(mut plan) => {
-            plan.validate_structure().map_err(|err| {
-                DeError::custom(format!("Failed to parse workflow json: {}", err))
-            })?;
-            plan.inherit_missing_synthesizers();
-            plan.apply_worker_replication();
-            Ok(plan)
+
+        let before: IntegrationOnly =
+            toml::from_str(content).with_context(|| "Failed to parse workflow for migration")?;
+        let Some(migrated) = crate::workflow_toml::inline_workflow_json(content)? else {
+            return Ok(content.to_string());
+        };
+        let after: IntegrationOnly = toml::from_str(&migrated)
+            .with_context(|| "Inline workflow produced by migration failed to parse")?;
+        if after.plan.to_json_string()? != before.plan.to_json_string()? {
+            return Err(anyhow!(
+                "Inline workflow produced by migration does not match the original JSON"
+            ));
         }
+        Ok(migrated)
     }
-}
 
-impl Config {
-    pub fn load_auto() -> Result<Self> {
-        if let Ok(path) = env::var("CHORUS_CONFIG") {
-            let path = PathBuf::from(path);
-            if path.exists() {
-                return Self::load(&path.to_string_lossy());
-            } else {
+    // 第一份备份叫 .toml.bak，之后按毫秒时间戳命名，重名时往后顺延；
+    // create_new 保证不会覆盖已有的备份，同一秒内多次迁移也各留一份
+    fn backup_config_file(config_path: &Path) -> Result<PathBuf> {
+        const MAX_ATTEMPTS: u64 = 1000;
+        let millis = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis() as u64;
+        let content = fs::read(config_path)
+            .with_context(|| format!("Failed to read {}", config_path.display()))?;
+
+        for attempt in 0..=MAX_ATTEMPTS {
+            let backup_path = match attempt {
+                0 => config_path.with_extension("toml.bak"),
+                n => config_path.with_extension(format!("toml.bak.{}", millis + n - 1)),
+            };
+            let mut options = fs::OpenOptions::new();
+            options.write(true).create_new(true);
+            // 备份里同样有明文密钥，不能比原文件更宽松
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::OpenOptionsExt;
+                options.mode(0o600);
+            }
+            let mut file = match options.open(&backup_path) {
+                Ok(file) => file,
+                Err(err) if err.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(err) => {
+                    return Err(err).with_context(|| {
+                        format!("Failed to backup config to {}", backup_path.display())
+                    })
+                }
+            };
+            std::io::Write::write_all(&mut file, &content)
+                .with_context(|| format!("Failed to backup config to {}", backup_path.display()))?;
+            return Ok(backup_path);
+        }
+        Err(anyhow!(
+            "Failed to backup config {}: no free backup name after {} attempts",
+            config_path.display(),
+            MAX_ATTEMPTS
+        ))
+    }
+
+    // 只保留最新的 keep 份备份（至少保留刚写入的那一份）
+    fn prune_backups(config_path: &Path, keep: usize) {
+        let (Some(dir), Some(file_name)) = (config_path.parent(), config_path.file_name()) else {
+            return;
+        };
+        let prefix = format!("{}.bak", file_name.to_string_lossy());
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(err) => {
                 tracing::warn!(
-                    "CHORUS_CONFIG points to non-existent file: {}",
-                    path.display()
+                    "Failed to list config backups in {}: {}",
+                    dir.display(),
+                    err
                 );
+                return;
+            }
+        };
+
+        let mut backups: Vec<(u64, PathBuf)> = entries
+            .filter_map(|entry| entry.ok())
+            .filter_map(|entry| {
+                let name = entry.file_name().to_string_lossy().into_owned();
+                let suffix = name.strip_prefix(&prefix)?;
+                let timestamp = match suffix {
+                    "" => 0,
+                    _ => suffix.strip_prefix('.')?.parse().ok()?,
+                };
+                Some((timestamp, entry.path()))
+            })
+            .collect();
+        backups.sort();
+
+        let excess = backups.len().saturating_sub(keep.max(1));
+        for (_, path) in backups.into_iter().take(excess) {
+            match fs::remove_file(&path) {
+                Ok(()) => tracing::info!("Removed old config backup: {}", path.display()),
+                Err(err) => {
+                    tracing::warn!("Failed to remove config backup {}: {}", path.display(), err)
+                }
             }
         }
+    }
 
-        Self::load_from_user_config()
+    // 预览里仍是明文密钥（内存加载要用），打印前遮盖
+    pub(crate) fn dry_run_banner(config_path: &Path, preview: &str) -> String {
+        format!(
+            "# --- migrated config for {} (dry run) ---\n{}",
+            config_path.display(),
+            crate::show::redact_config_text(preview)
+        )
     }
 
-    pub fn load(path: &str) -> Result<Self> {
-        let content = fs::read_to_string(path)
-            .with_context(|| format!("Failed to read config file: {}", path))?;
-        let cfg: Config = toml::from_str(&content)
-            .with_context(|| format!("Failed to parse TOML from {}", path))?;
-        Ok(cfg)
+    #[allow(dead_code)]
+    pub fn load_from_user_config() -> Result<Self> {
+        Ok(Self::load_from_user_config_with(&MigrationOptions::from_env(), None)?.0)
     }
 
-    fn user_config_path() -> Result<PathBuf> {
-        let home = env::var("HOME").context("HOME env var not set")?;
-        Ok(Path::new(&home)
-            .join(".config")
-            .join("chorus")
-            .join("config.toml"))
+    pub fn load_from_user_config_with(
+        options: &MigrationOptions,
+        profile: Option<&str>,
+    ) -> Result<(Self, PathBuf)> {
+        let (path, preview) = Self::ensure_user_config_exists(options)?;
+        let path_str = path.to_string_lossy().into_owned();
+        let config = match preview {
+            Some(content) => Self::load_content(&path_str, &content, profile)
+                .with_context(|| "Failed to load migrated config preview")?,
+            None => Self::load_profile(&path_str, profile)?,
+        };
+        Ok((config, path))
     }
 
-    fn ensure_user_config_exists() -> Result<PathBuf> {
-        let path = Self::user_config_path()?;
-        if let Some(dir) = path.parent() {
-            if !dir.exists() {
-                fs::create_dir_all(dir)
-                    .with_context(|| format!("Failed to create config dir: {}", dir.display()))?;
-            }
+    pub fn build_model_map(&self) -> HashMap<String, ModelConfig> {
+        self.models
+            .iter()
+            .cloned()
+            .map(|m| (m.name.clone(), m))
+            .collect()
+    }
+
+    pub fn validate_workflow(&self) -> std::result::Result<(), WorkflowValidationError> {
+        let models = self.build_model_map();
+        let mut problems = Vec::new();
+
+        self.workflow_integration
+            .collect_structure_problems(&mut problems);
+        self.workflow_integration
+            .collect_reference_problems(&models, "workflow", &mut problems);
+        for (name, preset) in &self.workflow_integration.presets {
+            preset.collect_reference_problems(
+                &models,
+                &format!("workflow preset '{}'", name),
+                &mut problems,
+            );
         }
-        if !path.exists() {
-            fs::write(&path, DEFAULT_CONFIG)
-                .with_context(|| format!("Failed to write default config to {}", path.display()))?;
+        self.workflow_integration
+            .collect_range_problems("workflow", &mut problems);
+        for (name, preset) in &self.workflow_integration.presets {
+            preset.collect_range_problems(&format!("workflow preset '{}'", name), &mut problems);
+        }
+        self.collect_model_problems(&mut problems);
+        self.collect_model_group_problems(&models, &mut problems);
+        self.collect_timeout_problems(&mut problems);
+        self.collect_network_problems(&mut problems);
+        self.collect_server_problems(&mut problems);
+        self.collect_logging_problems(&mut problems);
+        self.collect_stats_problems(&mut problems);
+        self.collect_telemetry_problems(&mut problems);
+        self.collect_history_problems(&mut problems);
+        self.collect_audit_problems(&mut problems);
+        self.collect_access_log_problems(&mut problems);
+        self.collect_rate_limit_problems(&mut problems);
+        self.collect_pricing_problems(&mut problems);
+        if let Some(profile) = &self.profile {
+            profile.annotate(&mut problems);
+        }
+
+        if problems.is_empty() {
+            Ok(())
         } else {
-            Self::migrate_config_if_needed(&path)?;
+            Err(WorkflowValidationError { problems })
         }
-        Ok(path)
     }
 
-    fn migrate_config_if_needed(config_path: &Path) -> Result<()> {
-        let content = fs::read_to_string(config_path)
-            .with_context(|| format!("Failed to read config file: {}", config_path.display()))?;
-
-        let value: Value = match toml::from_str(&content) {
-            Ok(value) => value,
-            Err(_) => return Ok(()),
-        };
+    fn collect_model_problems(&self, problems: &mut Vec<String>) {
+        let mut seen = HashSet::new();
+        for model in &self.models {
+            if !seen.insert(model.name.as_str()) {
+                problems.push(format!("model '{}' is defined more than once", model.name));
+            }
+            if !model.api_key.is_empty() && model.api_key_file.is_some() {
+                problems.push(format!(
+                    "model '{}' sets both api_key and api_key_file; keep only one",
+                    model.name
+                ));
+            }
+            if let Err(err) = check_api_base(&model.api_base) {
+                problems.push(format!("model '{}' api_base {}", model.name, err));
+            }
+            collect_api_format_problems(model, problems);
+            if let Err(err) = check_chat_path(model) {
+                problems.push(format!("model '{}' chat_path {}", model.name, err));
+            }
+            problems.extend(extra_header_problems(model));
+            for problem in extra_body_problems(&model.extra_body) {
+                problems.push(format!("model '{}' {}", model.name, problem));
+            }
+            problems.extend(tokenizer_problems(model));
+            if let Some(problem) = temperature_problem(model.temperature) {
+                problems.push(format!("model '{}' temperature {}", model.name, problem));
+            }
+        }
+    }
 
-        let workflow_table = value.get("workflow-integration").and_then(Value::as_table);
+    fn collect_model_group_problems(
+        &self,
+        models: &HashMap<String, ModelConfig>,
+        problems: &mut Vec<String>,
+    ) {
+        for (name, group) in &self.model_groups {
+            if group.models.is_empty() {
+                problems.push(format!("model-group '{}' has no models", name));
+            }
+            for model in &group.models {
+                if !models.contains_key(model) {
+                    problems.push(format!(
+                        "model-group '{}' references unknown model '{}'; define it under [[model]]",
+                        name, model
+                    ));
+                }
+            }
+        }
+    }
 
-        let has_json = workflow_table
-            .and_then(|table| table.get("json"))
-            .and_then(Value::as_str)
-            .is_some();
+    // 明文 http 只在本机地址上使用，否则 API key 会不加密地经过网络
+    fn warn_insecure_api_bases(&self) {
+        for model in &self.models {
+            let Ok(url) = check_api_base(&model.api_base) else {
+                continue;
+            };
+            let loopback = is_loopback_host(&url);
+            // 本地 llama.cpp、LM Studio 等不需要 key；远程 https 接口没有 key 多半是漏配
+            if model.api_key().is_empty() {
+                if url.scheme() == "https" && !loopback {
+                    tracing::warn!(
+                        "Model '{}' has no api_key; requests to {} will be sent without authentication",
+                        model.name,
+                        model.api_base
+                    );
+                }
+            } else if url.scheme() == "http" && !loopback {
+                tracing::warn!(
+                    "Model '{}' uses plain http for {}; API keys will be sent unencrypted",
+                    model.name,
+                    model.api_base
+                );
+            }
+            if model.skips_tls_verify() {
+                tracing::warn!(
+                    "INSECURE: model '{}' sets insecure_skip_tls_verify = true; certificates from {} are NOT verified and the API key can be intercepted",
+                    model.name,
+                    model.api_base
+                );
+            }
+        }
+    }
 
-        let legacy_fields_present = workflow_table
-            .map(|table| {
-                table.contains_key("analyzer_model")
-                    || table.contains_key("worker_models")
-                    || table.contains_key("synthesizer_model")
-            })
-            .unwrap_or(false);
+    fn collect_rate_limit_problems(&self, problems: &mut Vec<String>) {
+        for model in &self.models {
+            for (field, value) in [
+                ("rate_limit_rpm", model.rate_limit_rpm),
+                ("rate_limit_tpm", model.rate_limit_tpm),
+                ("max_concurrent_requests", model.max_concurrent_requests),
+            ] {
+                if value == Some(0) {
+                    problems.push(format!(
+                        "model '{}' {} must be greater than 0; omit it to disable the limit",
+                        model.name, field
+                    ));
+                }
+            }
+        }
+    }
 
-        if has_json && !legacy_fields_present {
-            return Ok(());
+    fn collect_pricing_problems(&self, problems: &mut Vec<String>) {
+        for model in &self.models {
+            for (field, value) in [
+                ("input_price_per_1k", model.input_price_per_1k),
+                ("output_price_per_1k", model.output_price_per_1k),
+            ] {
+                if value.is_some_and(|price| !(price.is_finite() && price >= 0.0)) {
+                    problems.push(format!(
+                        "model '{}' {} must be a non-negative number",
+                        model.name, field
+                    ));
+                }
+            }
         }
+    }
 
-        let mut migrations: Vec<&str> = Vec::new();
+    // 模型名 -> 价格，只包含配置了价格的模型
+    pub fn pricing(&self) -> HashMap<String, Pricing> {
+        self.models
+            .iter()
+            .filter_map(|model| Some((model.name.clone(), model.pricing()?)))
+            .collect()
+    }
 
-        let config = if legacy_fields_present {
-            tracing::info!(
-                "Detected legacy workflow-integration format, migrating to workflow plan JSON"
+    fn collect_network_problems(&self, problems: &mut Vec<String>) {
+        let network = &self.network;
+        if let Some(proxy) = &network.proxy {
+            if let Err(err) = check_proxy_url(proxy) {
+                problems.push(format!("network.proxy {}", err));
+            }
+        }
+        for (field, value) in [
+            ("pool_idle_timeout_secs", network.pool_idle_timeout_secs),
+            ("tcp_keepalive_secs", network.tcp_keepalive_secs),
+            ("max_response_bytes", network.max_response_bytes),
+        ] {
+            if value == Some(0) {
+                problems.push(format!(
+                    "network.{} must be greater than 0; omit it to use the default",
+                    field
+                ));
+            }
+        }
+        problems.extend(resolve_problems("network.resolve", &network.resolve));
+        if network.pool_max_idle_per_host == Some(0) && network.pool_idle_timeout_secs.is_some() {
+            problems.push(
+                "network.pool_idle_timeout_secs has no effect when pool_max_idle_per_host = 0 disables connection reuse"
+                    .to_string(),
             );
-
-            #[derive(Deserialize)]
-            struct LegacyWorkflowIntegration {
-                analyzer_model: String,
-                worker_models: Vec<String>,
-                synthesizer_model: String,
-            }
-
-            #[derive(Deserialize)]
-            struct LegacyConfig {
-                server: ServerConfig,
-                #[serde(rename = "model", deserialize_with = "deserialize_models")]
-                models: Vec<ModelConfig>,
-                #[serde(rename = "workflow-integration")]
-                workflow_integration: LegacyWorkflowIntegration,
-                workflow: WorkflowConfig,
-            }
-
-            migrations.push("workflow 节点结构");
-
-            match toml::from_str::<LegacyConfig>(&content) {
-                Ok(legacy) => Config {
-                    server: legacy.server,
-                    models: legacy.models,
-                    workflow_integration: WorkflowPlan {
-                        analyzer: WorkflowModelTarget {
-                            model: legacy.workflow_integration.analyzer_model,
-                            temperature: None,
-                            auto_temperature: None,
-                        },
-                        workers: legacy
-                            .workflow_integration
-                            .worker_models
-                            .into_iter()
-                            .map(|model| {
-                                WorkflowWorker::Model(WorkflowModelTarget {
-                                    model,
-                                    temperature: None,
-                                    auto_temperature: None,
-                                })
-                            })
-                            .collect(),
-                        synthesizer: Some(WorkflowModelTarget {
-                            model: legacy.workflow_integration.synthesizer_model,
-                            temperature: None,
-                            auto_temperature: None,
-                        }),
-                        selector: None,
-                        nested_worker_depth: None,
-                    },
-                    workflow: legacy.workflow,
-                },
-                Err(err) => {
-                    tracing::warn!(
-                        "Detected legacy workflow-integration fields but failed to parse legacy format: {}. Falling back to workflow plan JSON parser.",
-                        err
-                    );
-                    toml::from_str::<Config>(&content).with_context(|| {
-                        "Failed to parse config after falling back to workflow plan JSON parser"
-                    })?
+        }
+        for model in &self.models {
+            problems.extend(resolve_problems(
+                &format!("model '{}' resolve", model.name),
+                &model.resolve,
+            ));
+            if model.max_response_bytes == Some(0) {
+                problems.push(format!(
+                    "model '{}' max_response_bytes must be greater than 0; omit it to use network.max_response_bytes",
+                    model.name
+                ));
+            }
+            match model.proxy.as_deref() {
+                None | Some(DIRECT_PROXY) => {}
+                Some(proxy) => {
+                    if let Err(err) = check_proxy_url(proxy) {
+                        problems.push(format!("model '{}' proxy {}", model.name, err));
+                    }
                 }
             }
-        } else {
-            toml::from_str::<Config>(&content)
-                .with_context(|| "Failed to parse config for migration")?
-        };
+        }
+    }
 
-        if !has_json {
-            migrations.push("workflow json 格式");
+    fn collect_logging_problems(&self, problems: &mut Vec<String>) {
+        let logging = &self.logging;
+        if let Some(level) = &logging.level {
+            if let Err(err) = tracing_subscriber::EnvFilter::try_new(level) {
+                problems.push(format!("logging.level '{}' is invalid: {}", level, err));
+            }
+        }
+        if logging.max_size_mb == Some(0) {
+            problems.push("logging.max_size_mb must be greater than 0".to_string());
         }
+        if logging.max_files == Some(0) {
+            problems.push("logging.max_files must be greater than 0".to_string());
+        }
+        if logging.file.is_none() && logging.rotation != LogRotation::Never {
+            problems.push("logging.rotation requires logging.file".to_string());
+        }
+    }
 
-        if migrations.is_empty() {
-            return Ok(());
+    fn collect_stats_problems(&self, problems: &mut Vec<String>) {
+        let stats = &self.stats;
+        if stats.window_secs == Some(0) {
+            problems.push(
+                "stats.window_secs must be greater than 0; omit it to use the default".to_string(),
+            );
+        }
+        if stats.usage_retention_days == Some(0) {
+            problems.push(
+                "stats.usage_retention_days must be greater than 0; omit it to use the default"
+                    .to_string(),
+            );
+        }
+        if let Some(buckets) = &stats.latency_buckets_ms {
+            if buckets.is_empty() || buckets[0] == 0 {
+                problems.push(
+                    "stats.latency_buckets_ms must list at least one bound greater than 0"
+                        .to_string(),
+                );
+            } else if buckets.windows(2).any(|pair| pair[0] >= pair[1]) {
+                problems.push("stats.latency_buckets_ms must be strictly increasing".to_string());
+            }
         }
+    }
 
-        let backup_path = Self::backup_config_file(config_path)?;
-        tracing::info!("Old config backed up to: {}", backup_path.display());
+    fn collect_telemetry_problems(&self, problems: &mut Vec<String>) {
+        let Some(telemetry) = &self.telemetry else {
+            return;
+        };
+        if let Some(endpoint) = &telemetry.endpoint {
+            match url::Url::parse(endpoint) {
+                Ok(url) if matches!(url.scheme(), "http" | "https") => {}
+                _ => problems.push(format!(
+                    "telemetry.endpoint '{}' must be an http(s) URL such as \"http://localhost:4318\"",
+                    endpoint
+                )),
+            }
+        }
+        if telemetry
+            .service_name
+            .as_deref()
+            .is_some_and(|name| name.trim().is_empty())
+        {
+            problems.push(
+                "telemetry.service_name must not be empty; omit it to use \"chorus\"".to_string(),
+            );
+        }
+        if let Some(ratio) = telemetry.sample_ratio {
+            if !(0.0..=1.0).contains(&ratio) {
+                problems.push(format!(
+                    "telemetry.sample_ratio must be between 0.0 and 1.0, got {}",
+                    ratio
+                ));
+            }
+        }
+        for name in telemetry.request_id_headers.iter().flatten() {
+            if reqwest::header::HeaderName::from_bytes(name.as_bytes()).is_err() {
+                problems.push(format!(
+                    "telemetry.request_id_headers entry '{}' is not a valid header name",
+                    name
+                ));
+            }
+        }
+    }
 
-        let mut new_content = String::new();
-        new_content.push_str(&format!(
-            "# Chorus 配置文件（已自动迁移：{}）\n",
-            migrations.join("，")
-        ));
-        new_content.push_str(&format!("# 旧配置已备份到: {}\n\n", backup_path.display()));
+    fn collect_history_problems(&self, problems: &mut Vec<String>) {
+        let Some(history) = &self.history else {
+            return;
+        };
+        if history.path.trim().is_empty() {
+            problems.push("history.path must not be empty".to_string());
+        }
+        if history.max_rows == Some(0) {
+            problems.push("history.max_rows must be greater than 0".to_string());
+        }
+        if history.max_age_days == Some(0) {
+            problems.push("history.max_age_days must be greater than 0".to_string());
+        }
+    }
 
-        let new_value = Self::config_to_toml_value(&config)?;
-        new_content.push_str(
-            &toml::to_string_pretty(&new_value)
-                .with_context(|| "Failed to serialize migrated config")?,
-        );
+    fn collect_audit_problems(&self, problems: &mut Vec<String>) {
+        let Some(audit) = &self.audit else {
+            return;
+        };
+        if audit.path.trim().is_empty() {
+            problems.push("audit.path must not be empty".to_string());
+        }
+        for rule in audit.redact_rules() {
+            if !crate::redaction::BUILTIN_NAMES.contains(&rule.as_str()) {
+                problems.push(format!(
+                    "audit.redact rule '{}' is unknown (expected one of: {})",
+                    rule,
+                    crate::redaction::BUILTIN_NAMES.join(", ")
+                ));
+            }
+        }
+        for pattern in &audit.redact_patterns {
+            if let Err(err) = crate::redaction::compile(pattern) {
+                problems.push(format!("audit.redact_patterns: {:#}", err));
+            }
+        }
+        if audit.max_size_mb == Some(0) {
+            problems.push("audit.max_size_mb must be greater than 0".to_string());
+        }
+    }
 
-        fs::write(config_path, new_content).with_context(|| {
-            format!(
-                "Failed to write migrated config to {}",
-                config_path.display()
-            )
-        })?;
+    fn collect_access_log_problems(&self, problems: &mut Vec<String>) {
+        let Some(access_log) = &self.access_log else {
+            return;
+        };
+        if access_log
+            .file
+            .as_deref()
+            .is_some_and(|file| file.trim().is_empty())
+        {
+            problems.push("access_log.file must not be empty".to_string());
+        }
+        for path in &access_log.exclude_paths {
+            if !path.starts_with('/') {
+                problems.push(format!(
+                    "access_log.exclude_paths entry '{}' must start with '/'",
+                    path
+                ));
+            }
+        }
+        for proxy in &access_log.trusted_proxies {
+            if IpRange::parse(proxy).is_none() {
+                problems.push(format!(
+                    "access_log.trusted_proxies entry '{}' is not an IP address or CIDR range",
+                    proxy
+                ));
+            }
+        }
+    }
 
-        tracing::info!(
-            "Config migration completed successfully: {}",
-            migrations.join("，")
-        );
-        tracing::info!("New config written to: {}", config_path.display());
+    fn collect_server_problems(&self, problems: &mut Vec<String>) {
+        let server = &self.server;
+        if server.port == Some(0) {
+            problems.push("server.port must be greater than 0".to_string());
+        }
+        if server.host.is_some() != server.port.is_some() {
+            problems.push("server.host and server.port must be set together".to_string());
+        }
+        if server.tcp_address().is_none() && server.unix_socket.is_none() {
+            problems.push("[server] needs host and port, unix_socket, or both".to_string());
+        }
+        if let Some(path) = &server.unix_socket {
+            if cfg!(not(unix)) {
+                problems.push("server.unix_socket is only supported on Unix".to_string());
+            } else if path.trim().is_empty() {
+                problems.push("server.unix_socket must not be empty".to_string());
+            }
+        }
+        if let Some(tls) = &server.tls {
+            if server.tcp_address().is_none() {
+                problems.push("server.tls requires server.host and server.port".to_string());
+            }
+            for (field, value) in [("cert_path", &tls.cert_path), ("key_path", &tls.key_path)] {
+                if value.trim().is_empty() {
+                    problems.push(format!("server.tls.{} must not be empty", field));
+                }
+            }
+        }
+        if server.unix_socket_mode.is_some_and(|mode| mode > 0o777) {
+            problems.push(
+                "server.unix_socket_mode must be an octal permission such as 0o660".to_string(),
+            );
+        }
+    }
 
-        Ok(())
+    pub fn max_response_bytes_for(&self, model: &ModelConfig) -> u64 {
+        model
+            .max_response_bytes
+            .or(self.network.max_response_bytes)
+            .unwrap_or(DEFAULT_MAX_RESPONSE_BYTES)
     }
 
-    fn config_to_toml_value(config: &Config) -> Result<Value> {
-        let mut root = toml::map::Map::new();
+    // network.resolve 与模型自己的 resolve 合并，后者优先；按主机名排序，可直接作缓存键
+    pub fn resolve_for(&self, model: &ModelConfig) -> Vec<(String, SocketAddr)> {
+        let mut merged = BTreeMap::new();
+        for (host, address) in self.network.resolve.iter().chain(&model.resolve) {
+            if let Ok(addr) = parse_resolve_address(address) {
+                merged.insert(host.to_ascii_lowercase(), addr);
+            }
+        }
+        merged.into_iter().collect()
+    }
 
-        let server_value =
-            Value::try_from(&config.server).with_context(|| "Failed to serialize server config")?;
-        root.insert("server".to_string(), server_value);
+    // 配置加载时已校验过头名称，这里跳过解析不了的项即可
+    pub fn request_id_headers(&self) -> Vec<reqwest::header::HeaderName> {
+        match self
+            .telemetry
+            .as_ref()
+            .and_then(|telemetry| telemetry.request_id_headers.as_ref())
+        {
+            Some(names) => names
+                .iter()
+                .filter_map(|name| reqwest::header::HeaderName::from_bytes(name.as_bytes()).ok())
+                .collect(),
+            None => DEFAULT_REQUEST_ID_HEADERS
+                .iter()
+                .map(|name| reqwest::header::HeaderName::from_static(name))
+                .collect(),
+        }
+    }
 
-        let models_value =
-            Value::try_from(&config.models).with_context(|| "Failed to serialize model configs")?;
-        root.insert("model".to_string(), models_value);
+    pub fn proxy_for(&self, model: &ModelConfig) -> ProxySetting {
+        match model.proxy.as_deref() {
+            Some(DIRECT_PROXY) => return ProxySetting::Direct,
+            Some(proxy) => return ProxySetting::Url(proxy.to_string()),
+            None => {}
+        }
 
-        let workflow_json = config
-            .workflow_integration
-            .to_json_string()
-            .with_context(|| "Failed to serialize workflow integration to JSON string")?;
-        let mut workflow_integration = toml::map::Map::new();
-        workflow_integration.insert("json".to_string(), Value::String(workflow_json));
-        root.insert(
-            "workflow-integration".to_string(),
-            Value::Table(workflow_integration),
-        );
+        let Some(proxy) = &self.network.proxy else {
+            return ProxySetting::System;
+        };
 
-        let workflow_value = Value::try_from(&config.workflow)
-            .with_context(|| "Failed to serialize workflow configuration")?;
-        root.insert("workflow".to_string(), workflow_value);
+        let host = url::Url::parse(&model.api_base)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.to_ascii_lowercase()));
+        if let Some(host) = host {
+            let bypass = self.network.no_proxy.iter().any(|entry| {
+                let entry = entry.trim().trim_start_matches('.').to_ascii_lowercase();
+                entry == "*" || host == entry || host.ends_with(&format!(".{}", entry))
+            });
+            if bypass {
+                return ProxySetting::Direct;
+            }
+        }
 
-        Ok(Value::Table(root))
+        ProxySetting::Url(proxy.clone())
     }
 
-    fn backup_config_file(config_path: &Path) -> Result<PathBuf> {
-        let mut backup_path = config_path.with_extension("toml.bak");
-        if backup_path.exists() {
-            let timestamp = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap_or_default()
-                .as_secs();
-            backup_path = config_path.with_extension(format!("toml.bak.{}", timestamp));
+    // 所有写在配置里的超时，连同用于报错的位置描述
+    fn configured_timeouts(&self) -> Vec<(String, u64)> {
+        let timeouts = &self.workflow.timeouts;
+        let mut configured: Vec<(String, u64)> = [
+            ("analyzer_timeout_secs", timeouts.analyzer_timeout_secs),
+            ("worker_timeout_secs", timeouts.worker_timeout_secs),
+            (
+                "synthesizer_timeout_secs",
+                timeouts.synthesizer_timeout_secs,
+            ),
+            ("connect_timeout_secs", timeouts.connect_timeout_secs),
+        ]
+        .into_iter()
+        .map(|(field, value)| (format!("workflow.timeouts.{}", field), value))
+        .collect();
+
+        for model in &self.models {
+            for (field, value) in [
+                ("analyzer_timeout_secs", model.analyzer_timeout_secs),
+                ("worker_timeout_secs", model.worker_timeout_secs),
+                ("synthesizer_timeout_secs", model.synthesizer_timeout_secs),
+            ] {
+                if let Some(value) = value {
+                    configured.push((format!("model '{}' {}", model.name, field), value));
+                }
+            }
         }
 
-        fs::copy(config_path, &backup_path)
-            .with_context(|| format!("Failed to backup config to {}", backup_path.display()))?;
+        let mut domains: Vec<_> = self.workflow.domains.iter().collect();
+        domains.sort_by(|a, b| a.0.cmp(b.0));
+        for (domain, ovr) in domains {
+            for (field, value) in [
+                ("analyzer_timeout_secs", ovr.analyzer_timeout_secs),
+                ("worker_timeout_secs", ovr.worker_timeout_secs),
+                ("synthesizer_timeout_secs", ovr.synthesizer_timeout_secs),
+                ("connect_timeout_secs", ovr.connect_timeout_secs),
+            ] {
+                if let Some(value) = value {
+                    configured.push((format!("workflow.domains.\"{}\".{}", domain, field), value));
+                }
+            }
+        }
+        configured
+    }
 
-        Ok(backup_path)
+    fn collect_timeout_problems(&self, problems: &mut Vec<String>) {
+        for (location, value) in self.configured_timeouts() {
+            if value == 0 {
+                problems.push(format!("{} must be greater than 0", location));
+            }
+        }
+        if self.workflow.timeout_warning_secs == 0 {
+            problems.push("workflow.timeout_warning_secs must be greater than 0".to_string());
+        }
+        let slow = &self.workflow.slow;
+        if !(slow.ratio > 0.0 && slow.ratio <= 1.0) {
+            problems.push(format!(
+                "workflow.slow.ratio must be greater than 0.0 and at most 1.0, got {}",
+                slow.ratio
+            ));
+        }
+        for (key, value) in [
+            ("analyzer_secs", slow.analyzer_secs),
+            ("worker_secs", slow.worker_secs),
+            ("selector_secs", slow.selector_secs),
+            ("synthesizer_secs", slow.synthesizer_secs),
+        ] {
+            if value == Some(0) {
+                problems.push(format!("workflow.slow.{} must be greater than 0", key));
+            }
+        }
+        if self.workflow.retry.max_attempts == 0 {
+            problems.push(
+                "workflow.retry.max_attempts must be greater than 0; use 1 to disable retries"
+                    .to_string(),
+            );
+        }
     }
 
-    pub fn load_from_user_config() -> Result<Self> {
-        let path = Self::ensure_user_config_exists()?;
-        Self::load(&path.to_string_lossy())
+    fn warn_long_timeouts(&self) {
+        let ceiling = self.workflow.timeout_warning_secs;
+        for (location, value) in self.configured_timeouts() {
+            if ceiling > 0 && value > ceiling {
+                tracing::warn!(
+                    "{} = {} is longer than workflow.timeout_warning_secs ({}s); timeouts are in seconds, not milliseconds",
+                    location,
+                    value,
+                    ceiling
+                );
+            }
+        }
     }
 
-    pub fn build_model_map(&self) -> HashMap<String, ModelConfig> {
-        self.models
-            .iter()
-            .cloned()
-            .map(|m| (m.name.clone(), m))
-            .collect()
+    // 优先级：模型 > 域名 > 全局，逐字段回退
+    pub fn effective_timeouts_for(&self, model: &ModelConfig) -> TimeoutConfig {
+        let domain = url::Url::parse(&model.api_base)
+            .ok()
+            .and_then(|url| url.host_str().map(|h| h.to_string()));
+        let base = self.effective_timeouts_for_domain(domain.as_deref());
+        TimeoutConfig {
+            analyzer_timeout_secs: model
+                .analyzer_timeout_secs
+                .unwrap_or(base.analyzer_timeout_secs),
+            worker_timeout_secs: model
+                .worker_timeout_secs
+                .unwrap_or(base.worker_timeout_secs),
+            synthesizer_timeout_secs: model
+                .synthesizer_timeout_secs
+                .unwrap_or(base.synthesizer_timeout_secs),
+            connect_timeout_secs: base.connect_timeout_secs,
+        }
     }
 
     pub fn effective_timeouts_for_domain(&self, domain: Option<&str>) -> TimeoutConfig {
@@ -907,6 +3997,9 @@ impl Config {
                     synthesizer_timeout_secs: ovr
                         .synthesizer_timeout_secs
                         .unwrap_or(self.workflow.timeouts.synthesizer_timeout_secs),
+                    connect_timeout_secs: ovr
+                        .connect_timeout_secs
+                        .unwrap_or(self.workflow.timeouts.connect_timeout_secs),
                 };
             }
         }
@@ -0,0 +1,730 @@
+use crate::config::{
+    AccessLogConfig, AuditConfig, Config, DomainTimeoutOverride, HistoryConfig, LoggingConfig,
+    ModelConfig, ModelGroup, NetworkConfig, RetryConfig, RubricCriterion, ServerConfig,
+    SlowCallConfig, StatsConfig, TelemetryConfig, TimeoutConfig, TlsConfig, WorkflowConfig,
+    WorkflowModelTarget, WorkflowPlan,
+};
+use serde::de::{self, Deserialize, DeserializeSeed, Deserializer, IntoDeserializer, Visitor};
+use serde_json::Value as JsonValue;
+use std::fmt;
+
+// 只在顶层出现、由加载流程直接读取的键
+const EXTRA_TOP_LEVEL_KEYS: &[&str] = &["include", "strict_config", "config_version"];
+// `[workflow-integration]` 表本身的键（json 包装写法与迁移前的旧字段）
+const INTEGRATION_KEYS: &[&str] = &[
+    "json",
+    "json_file",
+    "analyzer_model",
+    "worker_models",
+    "synthesizer_model",
+];
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct UnknownKey {
+    pub path: String,
+    pub suggestion: Option<String>,
+}
+
+impl fmt::Display for UnknownKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown key `{}`", self.path)?;
+        if let Some(suggestion) = &self.suggestion {
+            write!(f, " (did you mean `{}`?)", suggestion)?;
+        }
+        Ok(())
+    }
+}
+
+// 对照各配置结构体的字段名检查原始 TOML，返回所有无法识别的键
+pub fn find_unknown_keys(root: &toml::Table) -> Vec<UnknownKey> {
+    let mut found = Vec::new();
+    let top_level: Vec<&str> = struct_fields::<Config>()
+        .iter()
+        .chain(EXTRA_TOP_LEVEL_KEYS)
+        .copied()
+        .collect();
+    check_table(root, "", &top_level, &mut found);
+
+    if let Some(toml::Value::Table(server)) = root.get("server") {
+        check_table(
+            server,
+            "server",
+            struct_fields::<ServerConfig>(),
+            &mut found,
+        );
+        if let Some(toml::Value::Table(tls)) = server.get("tls") {
+            check_table(tls, "server.tls", struct_fields::<TlsConfig>(), &mut found);
+        }
+    }
+
+    match root.get("model") {
+        Some(toml::Value::Table(model)) => {
+            check_table(model, "model", struct_fields::<ModelConfig>(), &mut found);
+        }
+        Some(toml::Value::Array(models)) => {
+            for (index, model) in models.iter().enumerate() {
+                if let toml::Value::Table(model) = model {
+                    check_table(
+                        model,
+                        &format!("model[{}]", index),
+                        struct_fields::<ModelConfig>(),
+                        &mut found,
+                    );
+                }
+            }
+        }
+        _ => {}
+    }
+
+    if let Some(toml::Value::Table(workflow)) = root.get("workflow") {
+        check_table(
+            workflow,
+            "workflow",
+            struct_fields::<WorkflowConfig>(),
+            &mut found,
+        );
+        if let Some(toml::Value::Table(timeouts)) = workflow.get("timeouts") {
+            check_table(
+                timeouts,
+                "workflow.timeouts",
+                struct_fields::<TimeoutConfig>(),
+                &mut found,
+            );
+        }
+        if let Some(toml::Value::Table(retry)) = workflow.get("retry") {
+            check_table(
+                retry,
+                "workflow.retry",
+                struct_fields::<RetryConfig>(),
+                &mut found,
+            );
+        }
+        if let Some(toml::Value::Table(slow)) = workflow.get("slow") {
+            check_table(
+                slow,
+                "workflow.slow",
+                struct_fields::<SlowCallConfig>(),
+                &mut found,
+            );
+        }
+        if let Some(toml::Value::Table(domains)) = workflow.get("domains") {
+            for (domain, value) in domains {
+                if let toml::Value::Table(table) = value {
+                    check_table(
+                        table,
+                        &format!("workflow.domains.\"{}\"", domain),
+                        struct_fields::<DomainTimeoutOverride>(),
+                        &mut found,
+                    );
+                }
+            }
+        }
+    }
+
+    if let Some(toml::Value::Table(groups)) = root.get("model-group") {
+        for (name, group) in groups {
+            if let toml::Value::Table(group) = group {
+                check_table(
+                    group,
+                    &format!("model-group.{}", name),
+                    struct_fields::<ModelGroup>(),
+                    &mut found,
+                );
+            }
+        }
+    }
+
+    if let Some(toml::Value::Table(logging)) = root.get("logging") {
+        check_table(
+            logging,
+            "logging",
+            struct_fields::<LoggingConfig>(),
+            &mut found,
+        );
+    }
+
+    if let Some(toml::Value::Table(stats)) = root.get("stats") {
+        check_table(stats, "stats", struct_fields::<StatsConfig>(), &mut found);
+    }
+
+    if let Some(toml::Value::Table(audit)) = root.get("audit") {
+        check_table(audit, "audit", struct_fields::<AuditConfig>(), &mut found);
+    }
+    if let Some(toml::Value::Table(access_log)) = root.get("access_log") {
+        check_table(
+            access_log,
+            "access_log",
+            struct_fields::<AccessLogConfig>(),
+            &mut found,
+        );
+    }
+
+    if let Some(toml::Value::Table(history)) = root.get("history") {
+        check_table(
+            history,
+            "history",
+            struct_fields::<HistoryConfig>(),
+            &mut found,
+        );
+    }
+
+    if let Some(toml::Value::Table(telemetry)) = root.get("telemetry") {
+        check_table(
+            telemetry,
+            "telemetry",
+            struct_fields::<TelemetryConfig>(),
+            &mut found,
+        );
+    }
+
+    if let Some(toml::Value::Table(network)) = root.get("network") {
+        check_table(
+            network,
+            "network",
+            struct_fields::<NetworkConfig>(),
+            &mut found,
+        );
+    }
+
+    if let Some(integration) = root.get("workflow-integration") {
+        if let Ok(value) = serde_json::to_value(integration) {
+            check_integration(&value, "workflow-integration", true, &mut found);
+        }
+    }
+
+    found
+}
+
+// 同时覆盖 json 字符串写法与内联表写法
+fn check_integration(value: &JsonValue, path: &str, top_level: bool, found: &mut Vec<UnknownKey>) {
+    let node = match value {
+        JsonValue::String(json) => {
+            if let Ok(node) = serde_json::from_str::<JsonValue>(json) {
+                check_plan(&node, path, top_level, found);
+            }
+            return;
+        }
+        JsonValue::Object(map) => map,
+        _ => return,
+    };
+
+    let mut known: Vec<&str> = plan_keys(top_level);
+    known.extend(INTEGRATION_KEYS);
+    check_keys(node.keys(), path, &known, found);
+
+    if let Some(JsonValue::String(json)) = node.get("json") {
+        if let Ok(inner) = serde_json::from_str::<JsonValue>(json) {
+            check_plan(&inner, &format!("{}.json", path), top_level, found);
+        }
+    }
+    check_plan_children(node, path, found);
+    if top_level {
+        if let Some(JsonValue::Object(presets)) = node.get("presets") {
+            for (name, preset) in presets {
+                check_integration(preset, &format!("{}.presets.{}", path, name), false, found);
+            }
+        }
+    }
+}
+
+fn check_plan(value: &JsonValue, path: &str, top_level: bool, found: &mut Vec<UnknownKey>) {
+    let Some(node) = value.as_object() else {
+        return;
+    };
+    check_keys(node.keys(), path, &plan_keys(top_level), found);
+    check_plan_children(node, path, found);
+    if top_level {
+        if let Some(JsonValue::Object(presets)) = node.get("presets") {
+            for (name, preset) in presets {
+                check_plan(preset, &format!("{}.presets.{}", path, name), false, found);
+            }
+        }
+    }
+}
+
+fn check_plan_children(
+    node: &serde_json::Map<String, JsonValue>,
+    path: &str,
+    found: &mut Vec<UnknownKey>,
+) {
+    for role in ["analyzer", "synthesizer", "selector"] {
+        if let Some(target) = node.get(role) {
+            check_target(target, &format!("{}.{}", path, role), found);
+        }
+    }
+
+    if let Some(JsonValue::Array(workers)) = node.get("workers") {
+        for (index, worker) in workers.iter().enumerate() {
+            let worker_path = format!("{}.workers[{}]", path, index);
+            let is_nested = worker
+                .as_object()
+                .is_some_and(|map| map.contains_key("analyzer") && map.contains_key("workers"));
+            if is_nested {
+                check_plan(worker, &worker_path, false, found);
+            } else {
+                check_target(worker, &worker_path, found);
+            }
+        }
+    }
+}
+
+fn check_target(value: &JsonValue, path: &str, found: &mut Vec<UnknownKey>) {
+    let Some(target) = value.as_object() else {
+        return;
+    };
+    check_keys(
+        target.keys(),
+        path,
+        struct_fields::<WorkflowModelTarget>(),
+        found,
+    );
+
+    if let Some(JsonValue::Array(rubric)) = target.get("rubric") {
+        for (index, criterion) in rubric.iter().enumerate() {
+            if let Some(criterion) = criterion.as_object() {
+                check_keys(
+                    criterion.keys(),
+                    &format!("{}.rubric[{}]", path, index),
+                    struct_fields::<RubricCriterion>(),
+                    found,
+                );
+            }
+        }
+    }
+}
+
+fn plan_keys(top_level: bool) -> Vec<&'static str> {
+    struct_fields::<WorkflowPlan>()
+        .iter()
+        .copied()
+        .filter(|key| top_level || !matches!(*key, "presets" | "preset_model_prefix"))
+        .collect()
+}
+
+fn check_table(table: &toml::Table, path: &str, known: &[&str], found: &mut Vec<UnknownKey>) {
+    check_keys(table.keys(), path, known, found);
+}
+
+fn check_keys<'a>(
+    keys: impl Iterator<Item = &'a String>,
+    path: &str,
+    known: &[&str],
+    found: &mut Vec<UnknownKey>,
+) {
+    for key in keys {
+        if known.contains(&key.as_str()) {
+            continue;
+        }
+        let path = if path.is_empty() {
+            key.clone()
+        } else {
+            format!("{}.{}", path, key)
+        };
+        found.push(UnknownKey {
+            path,
+            suggestion: suggest(key, known).map(str::to_string),
+        });
+    }
+}
+
+fn suggest<'a>(key: &str, known: &[&'a str]) -> Option<&'a str> {
+    let max_distance = (key.chars().count() / 3).clamp(1, 3);
+    known
+        .iter()
+        .map(|candidate| (edit_distance(key, candidate), *candidate))
+        .filter(|(distance, _)| *distance <= max_distance)
+        .min_by_key(|(distance, _)| *distance)
+        .map(|(_, candidate)| candidate)
+}
+
+// 带相邻字符交换的编辑距离，`prot` 与 `port` 只差一步
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut dist = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for (i, row) in dist.iter_mut().enumerate() {
+        row[0] = i;
+    }
+    for (j, cell) in dist[0].iter_mut().enumerate() {
+        *cell = j;
+    }
+    for i in 1..=a.len() {
+        for j in 1..=b.len() {
+            let cost = usize::from(a[i - 1] != b[j - 1]);
+            let mut best = (dist[i - 1][j] + 1)
+                .min(dist[i][j - 1] + 1)
+                .min(dist[i - 1][j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                best = best.min(dist[i - 2][j - 2] + 1);
+            }
+            dist[i][j] = best;
+        }
+    }
+    dist[a.len()][b.len()]
+}
+
+// 借助 serde derive 生成的字段表获取结构体的键名，字段增删时无需同步维护
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldNames<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("field introspection only"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("field introspection only"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+// 配置字段在 TOML 中的值类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FieldKind {
+    Boolean,
+    Integer,
+    Float,
+    String,
+    Table,
+    Array,
+}
+
+impl FieldKind {
+    pub fn of(value: &toml::Value) -> Option<Self> {
+        match value {
+            toml::Value::Boolean(_) => Some(Self::Boolean),
+            toml::Value::Integer(_) => Some(Self::Integer),
+            toml::Value::Float(_) => Some(Self::Float),
+            toml::Value::String(_) => Some(Self::String),
+            toml::Value::Table(_) => Some(Self::Table),
+            toml::Value::Array(_) => Some(Self::Array),
+            toml::Value::Datetime(_) => None,
+        }
+    }
+}
+
+// 从 serde derive 生成的反序列化代码里读出 path 处字段期望的类型；
+// 路径不对应任何字段，或字段自行解析、接受多种写法时返回 None
+pub fn field_kind(path: &[String]) -> Option<FieldKind> {
+    let mut kind = None;
+    match path.split_first() {
+        // 该段经 JSON 中转解析，直接按计划结构探测
+        Some((first, rest)) if first == "workflow-integration" => {
+            let _ = WorkflowPlan::deserialize(KindProbe {
+                path: rest,
+                kind: &mut kind,
+            });
+        }
+        _ => {
+            let _ = Config::deserialize(KindProbe {
+                path,
+                kind: &mut kind,
+            });
+        }
+    }
+    kind
+}
+
+struct KindProbe<'a> {
+    path: &'a [String],
+    kind: &'a mut Option<FieldKind>,
+}
+
+impl KindProbe<'_> {
+    fn found<T>(self, kind: FieldKind) -> Result<T, de::value::Error> {
+        if self.path.is_empty() {
+            *self.kind = Some(kind);
+        }
+        Err(de::Error::custom("type introspection only"))
+    }
+}
+
+impl<'de> Deserializer<'de> for KindProbe<'_> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("type introspection only"))
+    }
+
+    fn deserialize_bool<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Boolean)
+    }
+
+    fn deserialize_i8<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_i16<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_i32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_i64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_u8<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_u16<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_u32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_u64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Integer)
+    }
+
+    fn deserialize_f32<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Float)
+    }
+
+    fn deserialize_f64<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Float)
+    }
+
+    fn deserialize_char<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::String)
+    }
+
+    fn deserialize_str<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::String)
+    }
+
+    fn deserialize_string<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::String)
+    }
+
+    // 配置里的枚举都是单元变体，TOML 中写作字符串
+    fn deserialize_enum<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::String)
+    }
+
+    fn deserialize_seq<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        self.found(FieldKind::Array)
+    }
+
+    fn deserialize_option<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        visitor.visit_some(self)
+    }
+
+    fn deserialize_newtype_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        visitor.visit_newtype_struct(self)
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        self.deserialize_map(visitor)
+    }
+
+    // 只喂给访问者路径上的下一个键，它的值再交给下一层探测
+    fn deserialize_map<V: Visitor<'de>>(self, visitor: V) -> Result<V::Value, Self::Error> {
+        match self.path.split_first() {
+            None => self.found(FieldKind::Table),
+            Some((key, rest)) => visitor.visit_map(PathEntry {
+                key: Some(key),
+                rest,
+                kind: self.kind,
+            }),
+        }
+    }
+
+    serde::forward_to_deserialize_any! {
+        i128 u128 bytes byte_buf unit unit_struct tuple tuple_struct identifier ignored_any
+    }
+}
+
+struct PathEntry<'a> {
+    key: Option<&'a String>,
+    rest: &'a [String],
+    kind: &'a mut Option<FieldKind>,
+}
+
+impl<'de> de::MapAccess<'de> for PathEntry<'_> {
+    type Error = de::value::Error;
+
+    fn next_key_seed<K: DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, Self::Error> {
+        match self.key.take() {
+            Some(key) => seed.deserialize(key.as_str().into_deserializer()).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    fn next_value_seed<V: DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, Self::Error> {
+        seed.deserialize(KindProbe {
+            path: self.rest,
+            kind: &mut *self.kind,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unknown(toml_str: &str) -> Vec<String> {
+        let root: toml::Table = toml::from_str(toml_str).unwrap();
+        find_unknown_keys(&root)
+            .iter()
+            .map(ToString::to_string)
+            .collect()
+    }
+
+    #[test]
+    fn struct_fields_follow_serde_names() {
+        assert!(struct_fields::<WorkflowModelTarget>().contains(&"ref"));
+        assert!(struct_fields::<ModelConfig>().contains(&"rate_limit_rpm"));
+        assert!(struct_fields::<Config>().contains(&"workflow-integration"));
+    }
+
+    #[test]
+    fn typos_are_reported_with_suggestions() {
+        let found = unknown(
+            r#"
+strict_config = true
+
+[server]
+host = "127.0.0.1"
+prot = 11435
+
+[[model]]
+name = "m1"
+api_base = "https://api.example.com/v1"
+api_key = "k"
+temprature = 0.5
+
+[workflow.timeouts]
+worker_timout_secs = 6
+
+[workflow.domains."app.example.com"]
+analyser_timeout_secs = 3
+
+[netwrok]
+proxy = "http://proxy:8080"
+"#,
+        );
+        assert_eq!(
+            found,
+            vec![
+                "unknown key `netwrok` (did you mean `network`?)",
+                "unknown key `server.prot` (did you mean `port`?)",
+                "unknown key `model[0].temprature` (did you mean `temperature`?)",
+                "unknown key `workflow.timeouts.worker_timout_secs` (did you mean `worker_timeout_secs`?)",
+                "unknown key `workflow.domains.\"app.example.com\".analyser_timeout_secs` (did you mean `analyzer_timeout_secs`?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn workflow_nodes_are_checked_in_json_and_inline_forms() {
+        let found = unknown(
+            r#"
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "m1", "auto_temprature": true},
+  "workers": [
+    {"name": "m1"},
+    {"analyzer": {"ref": "m1"}, "workers": [{"name": "m1", "colour": "red"}], "synthesiser": {"ref": "m1"}}
+  ],
+  "selector": {"ref": "m1", "rubric": [{"name": "x", "wieght": 2}]}
+}"""
+
+[workflow-integration.presets.fast]
+analyzer = { ref = "m1" }
+workers = [{ name = "m1", max_token = 10 }]
+synthesizer = { ref = "m1" }
+"#,
+        );
+        assert_eq!(
+            found,
+            vec![
+                "unknown key `workflow-integration.json.analyzer.auto_temprature` (did you mean `auto_temperature`?)",
+                "unknown key `workflow-integration.json.selector.rubric[0].wieght` (did you mean `weight`?)",
+                "unknown key `workflow-integration.json.workers[1].synthesiser` (did you mean `synthesizer`?)",
+                "unknown key `workflow-integration.json.workers[1].workers[0].colour`",
+                "unknown key `workflow-integration.presets.fast.workers[0].max_token` (did you mean `max_tokens`?)",
+            ]
+        );
+    }
+
+    #[test]
+    fn field_kinds_come_from_the_config_structs() {
+        let kind = |path: &str| {
+            let path: Vec<String> = path.split('.').map(str::to_string).collect();
+            field_kind(&path)
+        };
+        assert_eq!(kind("server.port"), Some(FieldKind::Integer));
+        assert_eq!(kind("server.host"), Some(FieldKind::String));
+        assert_eq!(kind("server.tls"), Some(FieldKind::Table));
+        assert_eq!(kind("telemetry.sample_ratio"), Some(FieldKind::Float));
+        assert_eq!(kind("logging.include_spans"), Some(FieldKind::Boolean));
+        assert_eq!(kind("access_log.format"), Some(FieldKind::String));
+        assert_eq!(kind("network.no_proxy"), Some(FieldKind::Array));
+        assert_eq!(
+            kind("workflow.domains.app.worker_timeout_secs"),
+            Some(FieldKind::Integer)
+        );
+        assert_eq!(
+            kind("workflow-integration.nested_worker_depth"),
+            Some(FieldKind::Integer)
+        );
+        assert_eq!(kind("server.prot"), None);
+        assert_eq!(kind("server.port.extra"), None);
+    }
+
+    #[test]
+    fn edit_distance_counts_single_edits() {
+        assert_eq!(
+            edit_distance("worker_timout_secs", "worker_timeout_secs"),
+            1
+        );
+        assert_eq!(edit_distance("prot", "port"), 1);
+        assert_eq!(edit_distance("abc", "abc"), 0);
+        assert_eq!(edit_distance("", "abc"), 3);
+        assert_eq!(suggest("colour", &["ref", "name"]), None);
+    }
+}
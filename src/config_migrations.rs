@@ -0,0 +1,214 @@
+use anyhow::{anyhow, bail, Context, Result};
+use serde_json::json;
+use toml::Value;
+
+// 当前程序支持的最高配置版本；格式发生不兼容变化时加一，并在 MIGRATIONS 末尾注册对应步骤
+pub const CURRENT_VERSION: u32 = 2;
+pub const VERSION_KEY: &str = "config_version";
+const LEGACY_WORKFLOW_FIELDS: &[&str] = &["analyzer_model", "worker_models", "synthesizer_model"];
+
+pub struct Migration {
+    // 执行后版本变为 from + 1
+    pub from: u32,
+    pub description: &'static str,
+    pub apply: fn(&mut toml::Table) -> Result<()>,
+}
+
+// 按版本顺序排列，每一步只处理相邻两个版本之间的变化
+pub const MIGRATIONS: &[Migration] = &[Migration {
+    from: 1,
+    description: "workflow 节点结构",
+    apply: legacy_workflow_fields,
+}];
+
+// 没写 config_version 的文件：带旧版 analyzer_model 等字段视为 v1，否则视为 v2（引入版本号之前的最后一种格式）
+pub fn detect_version(root: &toml::Table) -> Result<u32> {
+    match root.get(VERSION_KEY) {
+        Some(Value::Integer(version)) if *version >= 1 => u32::try_from(*version)
+            .map_err(|_| anyhow!("`{}` {} is out of range", VERSION_KEY, version)),
+        Some(other) => bail!(
+            "`{}` must be a positive integer, got {}",
+            VERSION_KEY,
+            other
+        ),
+        None if has_legacy_workflow_fields(root) => Ok(1),
+        None => Ok(2),
+    }
+}
+
+// 比程序更新的配置无法可靠解析，直接拒绝
+pub fn ensure_supported(root: &toml::Table, source: &str) -> Result<u32> {
+    let version = detect_version(root).with_context(|| format!("Invalid {}", source))?;
+    if version > CURRENT_VERSION {
+        bail!(
+            "{} has config_version {}, but this chorus only understands up to {}; please upgrade chorus",
+            source,
+            version,
+            CURRENT_VERSION
+        );
+    }
+    Ok(version)
+}
+
+// 依次执行待处理的迁移并写入最新版本号，返回执行过的步骤说明；已是最新版本时不做改动
+pub fn migrate(root: &mut toml::Table, source: &str) -> Result<Vec<&'static str>> {
+    let version = ensure_supported(root, source)?;
+    let mut applied = Vec::new();
+    for step in MIGRATIONS.iter().filter(|step| step.from >= version) {
+        (step.apply)(root).with_context(|| {
+            format!(
+                "Failed to migrate {} from config_version {} to {}",
+                source,
+                step.from,
+                step.from + 1
+            )
+        })?;
+        applied.push(step.description);
+    }
+    if !applied.is_empty() {
+        root.insert(
+            VERSION_KEY.to_string(),
+            Value::Integer(i64::from(CURRENT_VERSION)),
+        );
+    }
+    Ok(applied)
+}
+
+fn has_legacy_workflow_fields(root: &toml::Table) -> bool {
+    root.get("workflow-integration")
+        .and_then(Value::as_table)
+        .is_some_and(|table| {
+            LEGACY_WORKFLOW_FIELDS
+                .iter()
+                .any(|field| table.contains_key(*field))
+        })
+}
+
+// v1 -> v2：analyzer_model / worker_models / synthesizer_model 改写为 workflow JSON
+fn legacy_workflow_fields(root: &mut toml::Table) -> Result<()> {
+    let Some(Value::Table(integration)) = root.get_mut("workflow-integration") else {
+        return Ok(());
+    };
+    let analyzer = integration.remove("analyzer_model");
+    let workers = integration.remove("worker_models");
+    let synthesizer = integration.remove("synthesizer_model");
+
+    // 旧字段不完整时沿用同一处已有的 workflow 定义，旧字段直接丢弃
+    let (Some(analyzer), Some(workers), Some(synthesizer)) = (analyzer, workers, synthesizer)
+    else {
+        tracing::warn!(
+            "Legacy workflow-integration fields are incomplete; keeping the existing workflow definition"
+        );
+        return Ok(());
+    };
+
+    let model_name = |value: &Value, field: &str| -> Result<String> {
+        value
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| anyhow!("`{}` must be a model name, got {}", field, value))
+    };
+    let workers = workers
+        .as_array()
+        .ok_or_else(|| anyhow!("`worker_models` must be an array of model names"))?
+        .iter()
+        .map(|worker| Ok(json!({ "name": model_name(worker, "worker_models")? })))
+        .collect::<Result<Vec<_>>>()?;
+    let plan = json!({
+        "analyzer": { "ref": model_name(&analyzer, "analyzer_model")? },
+        "workers": workers,
+        "synthesizer": { "ref": model_name(&synthesizer, "synthesizer_model")? },
+    });
+
+    // 旧格式的三个字段就是完整的工作流定义，同时存在的其它写法以它为准
+    for key in [
+        "json",
+        "json_file",
+        "analyzer",
+        "workers",
+        "synthesizer",
+        "selector",
+    ] {
+        integration.remove(key);
+    }
+    integration.insert(
+        "json".to_string(),
+        Value::String(serde_json::to_string_pretty(&plan)?),
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const V1: &str = r#"
+[[model]]
+name = "m1"
+
+[workflow-integration]
+analyzer_model = "m1"
+worker_models = ["m1", "m2"]
+synthesizer_model = "m2"
+"#;
+
+    fn table(content: &str) -> toml::Table {
+        toml::from_str(content).unwrap()
+    }
+
+    #[test]
+    fn registry_is_contiguous_and_reaches_current_version() {
+        for (index, step) in MIGRATIONS.iter().enumerate() {
+            assert_eq!(step.from, index as u32 + 1);
+        }
+        assert_eq!(MIGRATIONS.len() as u32 + 1, CURRENT_VERSION);
+    }
+
+    #[test]
+    fn legacy_fields_become_workflow_json() {
+        let mut root = table(V1);
+        legacy_workflow_fields(&mut root).unwrap();
+        let integration = root["workflow-integration"].as_table().unwrap();
+        assert_eq!(integration.len(), 1);
+        let plan: serde_json::Value =
+            serde_json::from_str(integration["json"].as_str().unwrap()).unwrap();
+        assert_eq!(
+            plan,
+            json!({
+                "analyzer": {"ref": "m1"},
+                "workers": [{"name": "m1"}, {"name": "m2"}],
+                "synthesizer": {"ref": "m2"},
+            })
+        );
+    }
+
+    #[test]
+    fn migrate_stamps_version_only_when_steps_ran() {
+        let mut root = table(V1);
+        assert_eq!(detect_version(&root).unwrap(), 1);
+        assert_eq!(
+            migrate(&mut root, "config.toml").unwrap(),
+            ["workflow 节点结构"]
+        );
+        assert_eq!(root[VERSION_KEY].as_integer(), Some(2));
+
+        let mut current = table("[server]\nport = 1\n");
+        assert!(migrate(&mut current, "config.toml").unwrap().is_empty());
+        assert!(!current.contains_key(VERSION_KEY));
+    }
+
+    #[test]
+    fn newer_versions_ask_for_an_upgrade() {
+        let err = migrate(&mut table("config_version = 99\n"), "config.toml")
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("config_version 99"), "{}", err);
+        assert!(err.contains("please upgrade chorus"), "{}", err);
+
+        let err = format!(
+            "{:#}",
+            detect_version(&table("config_version = \"2\"\n")).unwrap_err()
+        );
+        assert!(err.contains("must be a positive integer"), "{}", err);
+    }
+}
@@ -0,0 +1,291 @@
+use crate::config_keys::{field_kind, find_unknown_keys, FieldKind};
+use crate::show::mask_api_key;
+use anyhow::{anyhow, bail, Result};
+use std::env;
+use toml::Value;
+
+const PREFIX: &str = "CHORUS_";
+const SEPARATOR: &str = "__";
+// 键名的最后一段包含这些词时，日志里只显示掩码后的值
+const SECRET_MARKERS: &[&str] = &["key", "secret", "token", "password"];
+const HYPHENATED_KEYS: &[&str] = &["workflow-integration", "model-group"];
+
+// 形如 CHORUS_SERVER__PORT 的环境变量；不带 `__` 的（CHORUS_CONFIG 等）不是配置覆盖
+pub fn from_env() -> Vec<(String, String)> {
+    let mut vars: Vec<(String, String)> = env::vars_os()
+        .filter_map(|(name, value)| Some((name.into_string().ok()?, value.into_string().ok()?)))
+        .filter(|(name, _)| is_override(name))
+        .collect();
+    vars.sort();
+    vars
+}
+
+fn is_override(name: &str) -> bool {
+    name.strip_prefix(PREFIX)
+        .is_some_and(|rest| rest.contains(SEPARATOR))
+}
+
+// 按配置结构中字段的类型解析并写入配置表，返回实际生效的变量名
+pub fn apply(root: &mut toml::Table, vars: &[(String, String)]) -> Result<Vec<String>> {
+    let mut applied = Vec::new();
+    for (name, raw) in vars {
+        if !is_override(name) {
+            continue;
+        }
+        let (path, value) = apply_one(root, name, raw)?;
+        let shown = if looks_secret(&path) {
+            mask_api_key(raw)
+        } else {
+            value.to_string()
+        };
+        tracing::info!(
+            "Config override from {}: {} = {}",
+            name,
+            path.join("."),
+            shown
+        );
+        applied.push(name.clone());
+    }
+    Ok(applied)
+}
+
+fn apply_one(root: &mut toml::Table, name: &str, raw: &str) -> Result<(Vec<String>, Value)> {
+    let segments: Vec<String> = name[PREFIX.len()..]
+        .split(SEPARATOR)
+        .map(str::to_ascii_lowercase)
+        .collect();
+    if segments.iter().any(String::is_empty) {
+        bail!(
+            "{}: empty path segment; separate keys with a single `__`",
+            name
+        );
+    }
+
+    let mut path: Vec<String> = Vec::new();
+    let mut table = root;
+    for (index, segment) in segments.iter().enumerate() {
+        let key = resolve_key(table, segment, path.is_empty());
+        path.push(key.clone());
+        let dotted = path.join(".");
+        if path.len() == 1 && key == "model" {
+            bail!(
+                "{}: `model` entries are array elements, which cannot be overridden from the environment",
+                name
+            );
+        }
+        if path.len() == 2 && path[0] == "workflow-integration" && key.starts_with("json") {
+            bail!(
+                "{}: the embedded workflow JSON (`{}`) cannot be overridden from the environment",
+                name,
+                dotted
+            );
+        }
+
+        if index + 1 == segments.len() {
+            let kind = field_kind(&path).or_else(|| table.get(&key).and_then(FieldKind::of));
+            let value = parse_typed(kind, raw)
+                .map_err(|err| anyhow!("{}: invalid value for `{}`: {}", name, dotted, err))?;
+            check_known(name, &path, &value)?;
+            table.insert(key, value.clone());
+            return Ok((path, value));
+        }
+
+        table = match table
+            .entry(key)
+            .or_insert_with(|| Value::Table(toml::Table::new()))
+        {
+            Value::Table(next) => next,
+            Value::Array(_) => bail!(
+                "{}: `{}` is an array; array elements cannot be overridden from the environment",
+                name,
+                dotted
+            ),
+            _ => bail!("{}: `{}` is not a table", name, dotted),
+        };
+    }
+    unreachable!("override path has at least one segment")
+}
+
+// 环境变量名里只能用 `_`，与配置中带 `-` 的键（workflow-integration 等）对应
+fn resolve_key(table: &toml::Table, segment: &str, top_level: bool) -> String {
+    if let Some(existing) = table.keys().find(|key| key.replace('-', "_") == segment) {
+        return existing.clone();
+    }
+    if top_level {
+        if let Some(key) = HYPHENATED_KEYS
+            .iter()
+            .find(|key| key.replace('-', "_") == segment)
+        {
+            return key.to_string();
+        }
+    }
+    segment.to_string()
+}
+
+// 单独用这条路径构造一张表检查未知键，拼错的变量名在启动时就能发现
+fn check_known(name: &str, path: &[String], value: &Value) -> Result<()> {
+    let mut probe = value.clone();
+    for key in path.iter().rev() {
+        let mut table = toml::Table::new();
+        table.insert(key.clone(), probe);
+        probe = Value::Table(table);
+    }
+    let Value::Table(probe) = probe else {
+        unreachable!("probe is wrapped in at least one table")
+    };
+    match find_unknown_keys(&probe).into_iter().next() {
+        Some(unknown) => bail!("{} does not match a config field: {}", name, unknown),
+        None => Ok(()),
+    }
+}
+
+fn parse_typed(kind: Option<FieldKind>, raw: &str) -> Result<Value> {
+    let trimmed = raw.trim();
+    Ok(match kind {
+        Some(FieldKind::Integer) => Value::Integer(
+            trimmed
+                .parse()
+                .map_err(|_| anyhow!("expected an integer, got '{}'", raw))?,
+        ),
+        Some(FieldKind::Float) => Value::Float(
+            trimmed
+                .parse()
+                .map_err(|_| anyhow!("expected a number, got '{}'", raw))?,
+        ),
+        Some(FieldKind::Boolean) => Value::Boolean(parse_bool(trimmed)?),
+        Some(FieldKind::String) => Value::String(raw.to_string()),
+        Some(FieldKind::Table) => bail!("it is a table; override its fields individually"),
+        Some(FieldKind::Array) => {
+            bail!("it is an array; arrays cannot be overridden from the environment")
+        }
+        // 字段自行解析、接受多种写法（如 workflow 角色的 ref）时结构里看不出类型，按字面推断
+        None => {
+            if let Ok(value) = parse_bool(trimmed) {
+                Value::Boolean(value)
+            } else if let Ok(value) = trimmed.parse::<i64>() {
+                Value::Integer(value)
+            } else if let Ok(value) = trimmed.parse::<f64>() {
+                Value::Float(value)
+            } else {
+                Value::String(raw.to_string())
+            }
+        }
+    })
+}
+
+fn parse_bool(raw: &str) -> Result<bool> {
+    match raw.to_ascii_lowercase().as_str() {
+        "true" => Ok(true),
+        "false" => Ok(false),
+        _ => bail!("expected true or false, got '{}'", raw),
+    }
+}
+
+fn looks_secret(path: &[String]) -> bool {
+    path.last()
+        .is_some_and(|key| SECRET_MARKERS.iter().any(|marker| key.contains(marker)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn vars(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect()
+    }
+
+    fn base() -> toml::Table {
+        toml::from_str(
+            "[server]\nport = 11435\n\n[[model]]\nname = \"m1\"\n\n[workflow-integration]\njson = \"{}\"\n\n[workflow.timeouts]\nworker_timeout_secs = 6\n",
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn overrides_are_typed_and_create_missing_tables() {
+        let mut root = base();
+        let applied = apply(
+            &mut root,
+            &vars(&[
+                ("CHORUS_SERVER__PORT", "8080"),
+                ("CHORUS_WORKFLOW__TIMEOUTS__WORKER_TIMEOUT_SECS", "120"),
+                ("CHORUS_WORKFLOW_INTEGRATION__NESTED_WORKER_DEPTH", "2"),
+                ("CHORUS_NETWORK__PROXY", "http://proxy:3128"),
+                ("CHORUS_CONFIG", "/ignored.toml"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(applied.len(), 4);
+        assert_eq!(root["server"]["port"].as_integer(), Some(8080));
+        assert_eq!(
+            root["workflow"]["timeouts"]["worker_timeout_secs"].as_integer(),
+            Some(120)
+        );
+        assert_eq!(
+            root["workflow-integration"]["nested_worker_depth"].as_integer(),
+            Some(2)
+        );
+        assert_eq!(root["network"]["proxy"].as_str(), Some("http://proxy:3128"));
+    }
+
+    #[test]
+    fn missing_keys_take_their_type_from_the_config_structs() {
+        let mut root = base();
+        apply(
+            &mut root,
+            &vars(&[
+                ("CHORUS_SERVER__HOST", "127001"),
+                ("CHORUS_TELEMETRY__SERVICE_NAME", "true"),
+                ("CHORUS_TELEMETRY__SAMPLE_RATIO", "1"),
+                ("CHORUS_LOGGING__INCLUDE_SPANS", "TRUE"),
+            ]),
+        )
+        .unwrap();
+        assert_eq!(root["server"]["host"].as_str(), Some("127001"));
+        assert_eq!(root["telemetry"]["service_name"].as_str(), Some("true"));
+        assert_eq!(root["telemetry"]["sample_ratio"].as_float(), Some(1.0));
+        assert_eq!(root["logging"]["include_spans"].as_bool(), Some(true));
+
+        let err = apply(
+            &mut base(),
+            &vars(&[("CHORUS_TELEMETRY__SAMPLE_RATIO", "half")]),
+        )
+        .unwrap_err();
+        assert!(err.to_string().contains("expected a number"), "{}", err);
+    }
+
+    #[test]
+    fn invalid_overrides_name_the_variable() {
+        let cases = [
+            (
+                "CHORUS_SERVER__PORT",
+                "eighty",
+                "CHORUS_SERVER__PORT: invalid value",
+            ),
+            ("CHORUS_SERVER__PROT", "80", "unknown key `server.prot`"),
+            ("CHORUS_MODEL__API_KEY", "k", "array elements"),
+            (
+                "CHORUS_WORKFLOW_INTEGRATION__JSON",
+                "{}",
+                "embedded workflow JSON",
+            ),
+        ];
+        for (name, value, expected) in cases {
+            let err = apply(&mut base(), &vars(&[(name, value)]))
+                .unwrap_err()
+                .to_string();
+            assert!(err.contains(name), "{}", err);
+            assert!(err.contains(expected), "{}", err);
+        }
+    }
+
+    #[test]
+    fn secret_looking_keys_are_masked() {
+        assert!(looks_secret(&["server".into(), "tls".into(), "key".into()]));
+        assert!(looks_secret(&["model".into(), "api_key_file".into()]));
+        assert!(!looks_secret(&["server".into(), "port".into()]));
+    }
+}
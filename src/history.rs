@@ -0,0 +1,790 @@
+use crate::config::HistoryConfig;
+use crate::llm::Usage;
+use crate::usage::UsageCounts;
+use crate::workflow::WorkflowExecutionDetails;
+use anyhow::{Context, Result};
+use chrono::{DateTime, TimeZone, Utc};
+use rusqlite::{params, Connection, OptionalExtension};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, SyncSender, TrySendError};
+use std::sync::{Mutex, MutexGuard};
+use std::thread;
+use std::time::Duration;
+
+// 写入队列满时直接丢弃记录，不让请求等待磁盘
+const QUEUE_CAPACITY: usize = 1024;
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+pub const DEFAULT_LIST_LIMIT: usize = 20;
+pub const MAX_LIST_LIMIT: usize = 200;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS workflows (
+    workflow_id INTEGER PRIMARY KEY,
+    request_id TEXT,
+    started_at INTEGER NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    status TEXT NOT NULL,
+    error TEXT,
+    preset TEXT,
+    prompt_hash TEXT NOT NULL,
+    prompt TEXT,
+    models TEXT NOT NULL,
+    prompt_tokens INTEGER,
+    completion_tokens INTEGER,
+    total_tokens INTEGER,
+    final_response TEXT,
+    details TEXT
+);
+CREATE INDEX IF NOT EXISTS workflows_started_at ON workflows (started_at);
+CREATE INDEX IF NOT EXISTS workflows_status ON workflows (status, workflow_id);
+CREATE TABLE IF NOT EXISTS usage_hourly (
+    hour INTEGER NOT NULL,
+    model TEXT NOT NULL,
+    requests INTEGER NOT NULL,
+    errors INTEGER NOT NULL,
+    prompt_tokens INTEGER NOT NULL,
+    completion_tokens INTEGER NOT NULL,
+    total_tokens INTEGER NOT NULL,
+    PRIMARY KEY (hour, model)
+);
+";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum HistoryStatus {
+    Success,
+    Error,
+}
+
+impl HistoryStatus {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::Error => "error",
+        }
+    }
+
+    pub fn parse(value: &str) -> Option<Self> {
+        match value {
+            "success" => Some(Self::Success),
+            "error" => Some(Self::Error),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct HistoryRecord {
+    pub workflow_id: u64,
+    pub request_id: Option<String>,
+    pub started_at: DateTime<Utc>,
+    pub duration_ms: u64,
+    pub status: HistoryStatus,
+    pub error: Option<String>,
+    pub preset: Option<String>,
+    pub prompt_hash: String,
+    pub prompt: Option<String>,
+    pub final_response: Option<String>,
+    pub details: Option<WorkflowExecutionDetails>,
+}
+
+impl HistoryRecord {
+    // 各阶段用到的模型，按出现顺序去重；嵌套工作流一并展开
+    pub fn models(&self) -> Vec<String> {
+        let mut models = Vec::new();
+        if let Some(details) = &self.details {
+            collect_models(details, &mut models);
+        }
+        models
+    }
+
+    // 目前只有 worker 的每次尝试记录了 token 用量
+    pub fn usage(&self) -> Usage {
+        let mut usage = Usage::default();
+        if let Some(details) = &self.details {
+            add_worker_usage(details, &mut usage);
+        }
+        usage
+    }
+}
+
+pub fn prompt_hash(prompt: &str) -> String {
+    Sha256::digest(prompt.as_bytes())
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect()
+}
+
+fn collect_models(details: &WorkflowExecutionDetails, models: &mut Vec<String>) {
+    let mut push = |model: &str| {
+        if !models.iter().any(|known| known == model) {
+            models.push(model.to_string());
+        }
+    };
+    push(&details.analyzer.model);
+    for worker in &details.workers {
+        for attempt in &worker.attempts {
+            push(&attempt.model);
+        }
+    }
+    if let Some(selector) = &details.selector {
+        push(&selector.model);
+    }
+    if let Some(synthesizer) = &details.synthesizer {
+        push(&synthesizer.model);
+    }
+    for nested in details.workers.iter().filter_map(|w| w.nested.as_deref()) {
+        collect_models(nested, models);
+    }
+}
+
+fn add_worker_usage(details: &WorkflowExecutionDetails, total: &mut Usage) {
+    for worker in &details.workers {
+        for usage in worker.attempts.iter().filter_map(|a| a.usage.as_ref()) {
+            for (sum, value) in [
+                (&mut total.prompt_tokens, usage.prompt_tokens),
+                (&mut total.completion_tokens, usage.completion_tokens),
+                (&mut total.total_tokens, usage.total_tokens),
+            ] {
+                if let Some(value) = value {
+                    *sum = Some(sum.unwrap_or(0) + value);
+                }
+            }
+        }
+        if let Some(nested) = worker.nested.as_deref() {
+            add_worker_usage(nested, total);
+        }
+    }
+}
+
+// /api/workflows 列表中的一行
+#[derive(Debug, Clone, Serialize)]
+pub struct HistorySummary {
+    pub workflow_id: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    pub started_at: String,
+    pub duration_ms: u64,
+    pub status: HistoryStatus,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
+    pub prompt_hash: String,
+    pub models: Vec<String>,
+    pub usage: Usage,
+}
+
+// /api/workflows/{id} 返回的完整记录
+#[derive(Debug, Clone, Serialize)]
+pub struct HistoryEntry {
+    #[serde(flatten)]
+    pub summary: HistorySummary,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub final_response: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub details: Option<WorkflowExecutionDetails>,
+}
+
+impl HistoryEntry {
+    // 去掉各 worker 的完整输出（含嵌套工作流）以及选择器复述的候选内容，最终回复保留
+    pub fn without_outputs(mut self) -> Self {
+        if let Some(details) = &mut self.details {
+            strip_outputs(details);
+        }
+        self
+    }
+}
+
+fn strip_outputs(details: &mut WorkflowExecutionDetails) {
+    for worker in &mut details.workers {
+        worker.response = None;
+        if let Some(nested) = worker.nested.as_deref_mut() {
+            strip_outputs(nested);
+        }
+    }
+    if let Some(selector) = &mut details.selector {
+        selector.selected_response = None;
+        selector.raw_output = None;
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct HistoryQuery {
+    pub limit: usize,
+    // 只返回 workflow_id 小于该值的记录，用上一页最后一条的 ID 翻页
+    pub before: Option<u64>,
+    pub status: Option<HistoryStatus>,
+}
+
+enum Message {
+    Record(Box<HistoryRecord>),
+    // 某小时某模型的用量增量，与已有行累加
+    Usage {
+        hour: i64,
+        model: String,
+        counts: UsageCounts,
+    },
+    // 删除早于该小时的用量
+    PruneUsage(i64),
+    #[cfg(test)]
+    Flush(mpsc::Sender<()>),
+}
+
+pub struct HistoryStore {
+    sender: SyncSender<Message>,
+    reader: Mutex<Connection>,
+    store_prompts: bool,
+    next_workflow_id: u64,
+}
+
+impl HistoryStore {
+    // 写入与清理都在独立线程上进行；读取用另一条连接，WAL 模式下互不阻塞
+    pub fn open(config: &HistoryConfig) -> Result<Self> {
+        let path = Path::new(&config.path);
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            std::fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create history dir: {}", dir.display()))?;
+        }
+        let writer = open_connection(path)?;
+        writer
+            .execute_batch(SCHEMA)
+            .with_context(|| format!("Failed to create history tables in {}", config.path))?;
+        let last_id: Option<i64> = writer
+            .query_row("SELECT MAX(workflow_id) FROM workflows", [], |row| {
+                row.get(0)
+            })
+            .with_context(|| format!("Failed to read history from {}", config.path))?;
+        let reader = open_connection(path)?;
+
+        let retention = Retention {
+            max_rows: config.max_rows(),
+            max_age: config.max_age(),
+        };
+        retention.prune(&writer);
+        let (sender, receiver) = mpsc::sync_channel(QUEUE_CAPACITY);
+        thread::Builder::new()
+            .name("chorus-history".to_string())
+            .spawn(move || run_writer(writer, receiver, retention))
+            .context("Failed to start the history writer")?;
+
+        Ok(Self {
+            sender,
+            reader: Mutex::new(reader),
+            store_prompts: config.store_prompts,
+            next_workflow_id: last_id.map_or(1, |id| id as u64 + 1),
+        })
+    }
+
+    pub fn store_prompts(&self) -> bool {
+        self.store_prompts
+    }
+
+    // 重启后 workflow_id 接着已有记录编号，避免与旧记录冲突
+    pub fn next_workflow_id(&self) -> u64 {
+        self.next_workflow_id
+    }
+
+    pub fn record(&self, record: HistoryRecord) {
+        let workflow_id = record.workflow_id;
+        match self.sender.try_send(Message::Record(Box::new(record))) {
+            Ok(()) => {}
+            Err(TrySendError::Full(_)) => tracing::warn!(
+                "History queue is full; dropping the record for workflow {}",
+                workflow_id
+            ),
+            Err(TrySendError::Disconnected(_)) => tracing::warn!(
+                "History writer has stopped; dropping the record for workflow {}",
+                workflow_id
+            ),
+        }
+    }
+
+    pub fn record_usage(&self, hour: i64, model: &str, counts: UsageCounts) {
+        let message = Message::Usage {
+            hour,
+            model: model.to_string(),
+            counts,
+        };
+        if let Err(err) = self.sender.try_send(message) {
+            let reason = match err {
+                TrySendError::Full(_) => "History queue is full",
+                TrySendError::Disconnected(_) => "History writer has stopped",
+            };
+            tracing::warn!("{}; dropping a usage update for model {}", reason, model);
+        }
+    }
+
+    pub fn prune_usage(&self, before_hour: i64) {
+        let _ = self.sender.try_send(Message::PruneUsage(before_hour));
+    }
+
+    // 启动时恢复用量汇总，只读取不早于 since_hour 的小时
+    pub fn load_usage(&self, since_hour: i64) -> Result<Vec<(i64, String, UsageCounts)>> {
+        let conn = self.lock();
+        let mut statement = conn.prepare_cached(
+            "SELECT hour, model, requests, errors, prompt_tokens, completion_tokens, total_tokens
+             FROM usage_hourly WHERE hour >= ?1",
+        )?;
+        let rows = statement.query_map(params![since_hour], |row| {
+            Ok((
+                row.get(0)?,
+                row.get(1)?,
+                UsageCounts {
+                    requests: row.get::<_, i64>(2)? as u64,
+                    errors: row.get::<_, i64>(3)? as u64,
+                    prompt_tokens: row.get::<_, i64>(4)? as u64,
+                    completion_tokens: row.get::<_, i64>(5)? as u64,
+                    total_tokens: row.get::<_, i64>(6)? as u64,
+                    estimated_cost: None,
+                },
+            ))
+        })?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    // 同步查询；调用方应放在 spawn_blocking 中执行
+    pub fn list(&self, query: &HistoryQuery) -> Result<Vec<HistorySummary>> {
+        let conn = self.lock();
+        let mut statement = conn.prepare_cached(&format!(
+            "SELECT {} FROM workflows
+             WHERE (?1 IS NULL OR workflow_id < ?1) AND (?2 IS NULL OR status = ?2)
+             ORDER BY workflow_id DESC
+             LIMIT ?3",
+            SUMMARY_COLUMNS
+        ))?;
+        let rows = statement.query_map(
+            params![
+                query.before.map(|id| id as i64),
+                query.status.map(HistoryStatus::as_str),
+                query.limit as i64
+            ],
+            summary_from_row,
+        )?;
+        Ok(rows.collect::<rusqlite::Result<Vec<_>>>()?)
+    }
+
+    pub fn get(&self, workflow_id: u64) -> Result<Option<HistoryEntry>> {
+        let conn = self.lock();
+        let row = conn
+            .prepare_cached(&format!(
+                "SELECT {}, prompt, final_response, details FROM workflows WHERE workflow_id = ?1",
+                SUMMARY_COLUMNS
+            ))?
+            .query_row(params![workflow_id as i64], |row| {
+                Ok((
+                    summary_from_row(row)?,
+                    row.get::<_, Option<String>>(12)?,
+                    row.get::<_, Option<String>>(13)?,
+                    row.get::<_, Option<String>>(14)?,
+                ))
+            })
+            .optional()?;
+        let Some((summary, prompt, final_response, details)) = row else {
+            return Ok(None);
+        };
+        let details = details
+            .map(|json| serde_json::from_str(&json))
+            .transpose()
+            .with_context(|| {
+                format!("Stored details of workflow {} are unreadable", workflow_id)
+            })?;
+        Ok(Some(HistoryEntry {
+            summary,
+            prompt,
+            final_response,
+            details,
+        }))
+    }
+
+    // 查不到记录时说明原因：早于最早的记录、尚未执行，还是运行中或写入时被丢弃
+    pub fn missing_reason(&self, workflow_id: u64) -> Result<String> {
+        let (oldest, newest): (Option<i64>, Option<i64>) = self.lock().query_row(
+            "SELECT MIN(workflow_id), MAX(workflow_id) FROM workflows",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )?;
+        let reason = match (oldest, newest) {
+            (Some(oldest), _) if (workflow_id as i64) < oldest => format!(
+                "Workflow {} predates the stored history; it ran before [history] was enabled or was removed by the retention policy",
+                workflow_id
+            ),
+            (_, Some(newest)) if (workflow_id as i64) < newest => format!(
+                "Workflow {} was not recorded; its record may have been dropped because the history queue was full",
+                workflow_id
+            ),
+            _ => format!(
+                "Workflow {} has not been recorded; it may still be running or does not exist",
+                workflow_id
+            ),
+        };
+        Ok(reason)
+    }
+
+    // 测试中等待队列里的记录全部写入
+    #[cfg(test)]
+    pub fn flush(&self) {
+        let (done, wait) = mpsc::channel();
+        if self.sender.send(Message::Flush(done)).is_ok() {
+            let _ = wait.recv();
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Connection> {
+        self.reader
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+const SUMMARY_COLUMNS: &str = "workflow_id, request_id, started_at, duration_ms, status, error, \
+    preset, prompt_hash, models, prompt_tokens, completion_tokens, total_tokens";
+
+fn summary_from_row(row: &rusqlite::Row) -> rusqlite::Result<HistorySummary> {
+    let status: String = row.get(4)?;
+    let models: String = row.get(8)?;
+    Ok(HistorySummary {
+        workflow_id: row.get::<_, i64>(0)? as u64,
+        request_id: row.get(1)?,
+        started_at: format_timestamp(row.get(2)?),
+        duration_ms: row.get::<_, i64>(3)? as u64,
+        status: HistoryStatus::parse(&status).unwrap_or(HistoryStatus::Error),
+        error: row.get(5)?,
+        preset: row.get(6)?,
+        prompt_hash: row.get(7)?,
+        models: serde_json::from_str(&models).unwrap_or_default(),
+        usage: Usage {
+            prompt_tokens: row.get(9)?,
+            completion_tokens: row.get(10)?,
+            total_tokens: row.get(11)?,
+        },
+    })
+}
+
+fn open_connection(path: &Path) -> Result<Connection> {
+    let conn = Connection::open(path)
+        .with_context(|| format!("Failed to open history database: {}", path.display()))?;
+    conn.busy_timeout(Duration::from_secs(5))?;
+    conn.pragma_update(None, "journal_mode", "WAL")?;
+    Ok(conn)
+}
+
+fn format_timestamp(millis: i64) -> String {
+    Utc.timestamp_millis_opt(millis)
+        .single()
+        .unwrap_or_default()
+        .to_rfc3339()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Retention {
+    max_rows: Option<u64>,
+    max_age: Option<Duration>,
+}
+
+impl Retention {
+    fn prune(&self, conn: &Connection) {
+        if let Err(err) = self.try_prune(conn) {
+            tracing::warn!("Failed to prune workflow history: {:#}", err);
+        }
+    }
+
+    fn try_prune(&self, conn: &Connection) -> Result<()> {
+        let mut removed = 0;
+        if let Some(max_age) = self.max_age {
+            let cutoff = Utc::now() - chrono::Duration::from_std(max_age)?;
+            removed += conn.execute(
+                "DELETE FROM workflows WHERE started_at < ?1",
+                params![cutoff.timestamp_millis()],
+            )?;
+        }
+        if let Some(max_rows) = self.max_rows {
+            let cutoff: Option<i64> = conn
+                .query_row(
+                    "SELECT workflow_id FROM workflows ORDER BY workflow_id DESC LIMIT 1 OFFSET ?1",
+                    params![max_rows as i64],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(cutoff) = cutoff {
+                removed += conn.execute(
+                    "DELETE FROM workflows WHERE workflow_id <= ?1",
+                    params![cutoff],
+                )?;
+            }
+        }
+        if removed > 0 {
+            tracing::debug!("Pruned {} workflow history rows", removed);
+        }
+        Ok(())
+    }
+}
+
+fn run_writer(conn: Connection, receiver: Receiver<Message>, retention: Retention) {
+    loop {
+        match receiver.recv_timeout(PRUNE_INTERVAL) {
+            Ok(Message::Record(record)) => {
+                if let Err(err) = insert(&conn, &record) {
+                    tracing::warn!(
+                        "Failed to store history for workflow {}: {:#}",
+                        record.workflow_id,
+                        err
+                    );
+                }
+            }
+            Ok(Message::Usage {
+                hour,
+                model,
+                counts,
+            }) => {
+                if let Err(err) = add_usage(&conn, hour, &model, &counts) {
+                    tracing::warn!("Failed to store usage for model {}: {:#}", model, err);
+                }
+            }
+            Ok(Message::PruneUsage(before_hour)) => {
+                if let Err(err) = conn.execute(
+                    "DELETE FROM usage_hourly WHERE hour < ?1",
+                    params![before_hour],
+                ) {
+                    tracing::warn!("Failed to prune stored usage: {}", err);
+                }
+            }
+            #[cfg(test)]
+            Ok(Message::Flush(done)) => {
+                retention.prune(&conn);
+                let _ = done.send(());
+            }
+            Err(RecvTimeoutError::Timeout) => retention.prune(&conn),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+    }
+}
+
+fn add_usage(conn: &Connection, hour: i64, model: &str, counts: &UsageCounts) -> Result<()> {
+    conn.prepare_cached(
+        "INSERT INTO usage_hourly (
+            hour, model, requests, errors, prompt_tokens, completion_tokens, total_tokens
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)
+        ON CONFLICT (hour, model) DO UPDATE SET
+            requests = requests + excluded.requests,
+            errors = errors + excluded.errors,
+            prompt_tokens = prompt_tokens + excluded.prompt_tokens,
+            completion_tokens = completion_tokens + excluded.completion_tokens,
+            total_tokens = total_tokens + excluded.total_tokens",
+    )?
+    .execute(params![
+        hour,
+        model,
+        counts.requests as i64,
+        counts.errors as i64,
+        counts.prompt_tokens as i64,
+        counts.completion_tokens as i64,
+        counts.total_tokens as i64,
+    ])?;
+    Ok(())
+}
+
+fn insert(conn: &Connection, record: &HistoryRecord) -> Result<()> {
+    let usage = record.usage();
+    let details = record
+        .details
+        .as_ref()
+        .map(serde_json::to_string)
+        .transpose()?;
+    conn.prepare_cached(
+        "INSERT INTO workflows (
+            workflow_id, request_id, started_at, duration_ms, status, error, preset,
+            prompt_hash, prompt, models, prompt_tokens, completion_tokens, total_tokens,
+            final_response, details
+        ) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15)",
+    )?
+    .execute(params![
+        record.workflow_id as i64,
+        record.request_id,
+        record.started_at.timestamp_millis(),
+        record.duration_ms as i64,
+        record.status.as_str(),
+        record.error,
+        record.preset,
+        record.prompt_hash,
+        record.prompt,
+        serde_json::to_string(&record.models())?,
+        usage.prompt_tokens,
+        usage.completion_tokens,
+        usage.total_tokens,
+        record.final_response,
+        details,
+    ])?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::usage::{GroupBy, UsageTracker};
+    use std::sync::Arc;
+
+    // 数据库放在尚不存在的子目录里，顺带覆盖自动建目录
+    fn temp_db(tag: &str) -> String {
+        crate::test_util::temp_dir(&format!("history_{}", tag))
+            .join("data")
+            .join("history.db")
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn record(workflow_id: u64, status: HistoryStatus) -> HistoryRecord {
+        HistoryRecord {
+            workflow_id,
+            request_id: Some(format!("req-{}", workflow_id)),
+            started_at: Utc::now(),
+            duration_ms: 120,
+            status,
+            error: (status == HistoryStatus::Error).then(|| "boom".to_string()),
+            preset: None,
+            prompt_hash: prompt_hash("hello"),
+            prompt: None,
+            final_response: None,
+            details: None,
+        }
+    }
+
+    #[test]
+    fn usage_buckets_survive_a_restart() {
+        let path = temp_db("usage");
+        let config = HistoryConfig {
+            path: path.clone(),
+            ..Default::default()
+        };
+        let usage = Usage {
+            prompt_tokens: Some(10),
+            completion_tokens: Some(4),
+            total_tokens: Some(14),
+        };
+        let store = Arc::new(HistoryStore::open(&config).unwrap());
+        let tracker = UsageTracker::new(chrono::Duration::days(7))
+            .with_store(store.clone())
+            .unwrap();
+        tracker.record("m1", Some(&usage), true);
+        tracker.record("m1", None, false);
+        // 早于保留期的行在下次启动时删除
+        store.record_usage(0, "m2", UsageCounts::of_call(Some(&usage), true));
+        store.flush();
+        drop(tracker);
+        drop(store);
+
+        let store = Arc::new(HistoryStore::open(&config).unwrap());
+        let tracker = UsageTracker::new(chrono::Duration::days(7))
+            .with_store(store.clone())
+            .unwrap();
+        let now = Utc::now();
+        let rows = tracker.query(
+            now - chrono::Duration::days(1),
+            now,
+            GroupBy::Model,
+            &Default::default(),
+        );
+        assert_eq!(rows.len(), 1);
+        assert_eq!(
+            rows[0].counts,
+            UsageCounts {
+                requests: 2,
+                errors: 1,
+                prompt_tokens: 10,
+                completion_tokens: 4,
+                total_tokens: 14,
+                estimated_cost: None,
+            }
+        );
+        store.flush();
+        assert!(store
+            .load_usage(0)
+            .unwrap()
+            .iter()
+            .all(|(hour, _, _)| *hour > 0));
+        let _ = std::fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn records_are_listed_newest_first_and_filtered() {
+        let path = temp_db("list");
+        let config = HistoryConfig {
+            path: path.clone(),
+            ..Default::default()
+        };
+        let store = HistoryStore::open(&config).unwrap();
+        assert_eq!(store.next_workflow_id(), 1);
+        for (id, status) in [
+            (1, HistoryStatus::Success),
+            (2, HistoryStatus::Error),
+            (3, HistoryStatus::Success),
+        ] {
+            store.record(record(id, status));
+        }
+        store.flush();
+
+        let all = store
+            .list(&HistoryQuery {
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+        let ids: Vec<u64> = all.iter().map(|row| row.workflow_id).collect();
+        assert_eq!(ids, vec![3, 2, 1]);
+        assert_eq!(all[1].error.as_deref(), Some("boom"));
+        assert_eq!(
+            all[0].prompt_hash,
+            "2cf24dba5fb0a30e26e83b2ac5b9e29e1b161e5c1fa7425e73043362938b9824"
+        );
+
+        let page = store
+            .list(&HistoryQuery {
+                limit: 10,
+                before: Some(3),
+                status: Some(HistoryStatus::Success),
+            })
+            .unwrap();
+        assert_eq!(page.len(), 1);
+        assert_eq!(page[0].workflow_id, 1);
+
+        drop(store);
+        // 重启后接着编号
+        assert_eq!(HistoryStore::open(&config).unwrap().next_workflow_id(), 4);
+        let _ = std::fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+
+    #[test]
+    fn retention_keeps_the_newest_rows() {
+        let path = temp_db("retention");
+        let store = HistoryStore::open(&HistoryConfig {
+            path: path.clone(),
+            max_rows: Some(2),
+            ..Default::default()
+        })
+        .unwrap();
+        for id in 1..=5 {
+            store.record(record(id, HistoryStatus::Success));
+        }
+        store.flush();
+
+        let rows = store
+            .list(&HistoryQuery {
+                limit: 10,
+                ..Default::default()
+            })
+            .unwrap();
+        let ids: Vec<u64> = rows.iter().map(|row| row.workflow_id).collect();
+        assert_eq!(ids, vec![5, 4]);
+        assert!(store.get(2).unwrap().is_none());
+        assert!(store.missing_reason(2).unwrap().contains("predates"));
+        assert!(store
+            .missing_reason(9)
+            .unwrap()
+            .contains("has not been recorded"));
+        let _ = std::fs::remove_dir_all(Path::new(&path).parent().unwrap());
+    }
+}
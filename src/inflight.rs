@@ -0,0 +1,306 @@
+use crate::latency::Phase;
+use chrono::{DateTime, Utc};
+use serde::Serialize;
+use std::collections::{BTreeMap, HashMap};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+// /api/status 最多列出的工作流数，总数另外给出
+pub const MAX_LISTED_WORKFLOWS: usize = 100;
+
+struct Workflow {
+    request_id: Option<String>,
+    preset: Option<String>,
+    started_at: DateTime<Utc>,
+    started: Instant,
+    phase: Phase,
+    phase_started: Instant,
+    calls: HashMap<u64, Call>,
+    tally: WorkflowTally,
+}
+
+struct Call {
+    model: String,
+    phase: Phase,
+    started: Instant,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InflightWorkflow {
+    pub workflow_id: u64,
+    pub request_id: Option<String>,
+    pub preset: Option<String>,
+    pub started_at: String,
+    pub elapsed_ms: u64,
+    pub phase: Phase,
+    pub upstream_calls: Vec<InflightCall>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InflightCall {
+    pub model: String,
+    pub phase: Phase,
+    pub elapsed_ms: u64,
+}
+
+// 工作流结束时交给汇总日志的累计数据
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct WorkflowTally {
+    // 顶层工作流各阶段的耗时，包括等待限流与请求空位；没进入的阶段不出现
+    pub phase_ms: BTreeMap<Phase, u64>,
+    // 最慢的一次 worker 阶段上游调用（嵌套工作流里的也算）
+    pub slowest_worker: Option<(String, u64)>,
+    pub workers_ok: usize,
+    pub workers_failed: usize,
+    // 所有阶段上游报告的 token 之和
+    pub total_tokens: u64,
+    // 选择器没能给出结果、改用 worker 的回答
+    pub selector_fallback: bool,
+}
+
+// 正在执行的工作流及其未返回的上游调用；只在阶段切换与调用开始/结束时短暂加锁
+#[derive(Default)]
+pub struct InflightRegistry {
+    workflows: Mutex<HashMap<u64, Workflow>>,
+    next_call_id: AtomicU64,
+}
+
+impl InflightRegistry {
+    // 返回的 guard 释放时注销，请求被取消或出错提前返回时也一样
+    pub fn register(
+        self: &Arc<Self>,
+        workflow_id: u64,
+        request_id: Option<String>,
+        preset: Option<String>,
+    ) -> Registration {
+        self.lock().insert(
+            workflow_id,
+            Workflow {
+                request_id,
+                preset,
+                started_at: Utc::now(),
+                started: Instant::now(),
+                phase: Phase::Analyzer,
+                phase_started: Instant::now(),
+                calls: HashMap::new(),
+                tally: WorkflowTally::default(),
+            },
+        );
+        Registration {
+            registry: self.clone(),
+            workflow_id,
+        }
+    }
+
+    pub fn enter_phase(&self, workflow_id: u64, phase: Phase) {
+        if let Some(workflow) = self.lock().get_mut(&workflow_id) {
+            workflow.close_phase();
+            workflow.phase = phase;
+        }
+    }
+
+    pub fn call_finished(
+        &self,
+        workflow_id: u64,
+        model: &str,
+        phase: Phase,
+        elapsed: Duration,
+        tokens: u64,
+    ) {
+        let mut workflows = self.lock();
+        let Some(tally) = workflows
+            .get_mut(&workflow_id)
+            .map(|workflow| &mut workflow.tally)
+        else {
+            return;
+        };
+        tally.total_tokens += tokens;
+        let elapsed_ms = elapsed.as_millis() as u64;
+        if phase == Phase::Worker
+            && tally
+                .slowest_worker
+                .as_ref()
+                .is_none_or(|(_, slowest)| elapsed_ms > *slowest)
+        {
+            tally.slowest_worker = Some((model.to_string(), elapsed_ms));
+        }
+    }
+
+    pub fn workers_finished(&self, workflow_id: u64, ok: usize, failed: usize) {
+        if let Some(workflow) = self.lock().get_mut(&workflow_id) {
+            workflow.tally.workers_ok += ok;
+            workflow.tally.workers_failed += failed;
+        }
+    }
+
+    pub fn selector_fell_back(&self, workflow_id: u64) {
+        if let Some(workflow) = self.lock().get_mut(&workflow_id) {
+            workflow.tally.selector_fallback = true;
+        }
+    }
+
+    // 嵌套工作流的调用记在所属的顶层工作流下
+    pub fn call_started(
+        self: &Arc<Self>,
+        workflow_id: u64,
+        model: &str,
+        phase: Phase,
+    ) -> Option<PendingCall> {
+        let call_id = self.next_call_id.fetch_add(1, Ordering::Relaxed);
+        let mut workflows = self.lock();
+        let workflow = workflows.get_mut(&workflow_id)?;
+        workflow.calls.insert(
+            call_id,
+            Call {
+                model: model.to_string(),
+                phase,
+                started: Instant::now(),
+            },
+        );
+        Some(PendingCall {
+            registry: self.clone(),
+            workflow_id,
+            call_id,
+        })
+    }
+
+    // 最早开始的排在前面，最多 limit 条；同时返回总数
+    pub fn snapshot(&self, limit: usize) -> (usize, Vec<InflightWorkflow>) {
+        let workflows = self.lock();
+        let mut listed: Vec<(&u64, &Workflow)> = workflows.iter().collect();
+        listed.sort_by_key(|(id, workflow)| (workflow.started, **id));
+        let listed = listed
+            .into_iter()
+            .take(limit)
+            .map(|(id, workflow)| {
+                let mut calls: Vec<(&u64, &Call)> = workflow.calls.iter().collect();
+                calls.sort_by_key(|(call_id, call)| (call.started, **call_id));
+                InflightWorkflow {
+                    workflow_id: *id,
+                    request_id: workflow.request_id.clone(),
+                    preset: workflow.preset.clone(),
+                    started_at: workflow.started_at.to_rfc3339(),
+                    elapsed_ms: workflow.started.elapsed().as_millis() as u64,
+                    phase: workflow.phase,
+                    upstream_calls: calls
+                        .into_iter()
+                        .map(|(_, call)| InflightCall {
+                            model: call.model.clone(),
+                            phase: call.phase,
+                            elapsed_ms: call.started.elapsed().as_millis() as u64,
+                        })
+                        .collect(),
+                }
+            })
+            .collect();
+        (workflows.len(), listed)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, HashMap<u64, Workflow>> {
+        self.workflows
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl Workflow {
+    fn close_phase(&mut self) {
+        let elapsed = self.phase_started.elapsed().as_millis() as u64;
+        *self.tally.phase_ms.entry(self.phase).or_default() += elapsed;
+        self.phase_started = Instant::now();
+    }
+}
+
+pub struct Registration {
+    registry: Arc<InflightRegistry>,
+    workflow_id: u64,
+}
+
+impl Registration {
+    // 注销并取出累计数据，当前阶段计到此刻为止
+    pub fn finish(self) -> WorkflowTally {
+        self.registry
+            .lock()
+            .remove(&self.workflow_id)
+            .map(|mut workflow| {
+                workflow.close_phase();
+                workflow.tally
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        self.registry.lock().remove(&self.workflow_id);
+    }
+}
+
+pub struct PendingCall {
+    registry: Arc<InflightRegistry>,
+    workflow_id: u64,
+    call_id: u64,
+}
+
+impl Drop for PendingCall {
+    fn drop(&mut self) {
+        if let Some(workflow) = self.registry.lock().get_mut(&self.workflow_id) {
+            workflow.calls.remove(&self.call_id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn workflows_and_calls_are_removed_when_their_guards_drop() {
+        let registry = Arc::new(InflightRegistry::default());
+        let first = registry.register(1, Some("req-1".to_string()), None);
+        let second = registry.register(2, None, Some("fast".to_string()));
+        registry.enter_phase(1, Phase::Worker);
+        let call = registry.call_started(1, "m1", Phase::Worker);
+        let _other = registry.call_started(1, "m2", Phase::Synthesizer);
+        // 未注册的工作流不记录调用
+        assert!(registry.call_started(9, "m1", Phase::Worker).is_none());
+
+        let (total, listed) = registry.snapshot(1);
+        assert_eq!(total, 2);
+        assert_eq!(listed.len(), 1);
+        assert_eq!(listed[0].workflow_id, 1);
+        assert_eq!(listed[0].phase, Phase::Worker);
+        let models: Vec<&str> = listed[0]
+            .upstream_calls
+            .iter()
+            .map(|call| call.model.as_str())
+            .collect();
+        assert_eq!(models, vec!["m1", "m2"]);
+
+        drop(call);
+        let (_, listed) = registry.snapshot(10);
+        assert_eq!(listed[0].upstream_calls.len(), 1);
+        assert_eq!(listed[1].preset.as_deref(), Some("fast"));
+
+        registry.call_finished(1, "m1", Phase::Worker, Duration::from_millis(40), 12);
+        registry.call_finished(1, "m2", Phase::Worker, Duration::from_millis(90), 0);
+        registry.call_finished(1, "m3", Phase::Synthesizer, Duration::from_millis(500), 30);
+        registry.workers_finished(1, 1, 1);
+        registry.enter_phase(1, Phase::Synthesizer);
+        let tally = first.finish();
+        assert_eq!(tally.slowest_worker, Some(("m2".to_string(), 90)));
+        assert_eq!((tally.workers_ok, tally.workers_failed), (1, 1));
+        assert_eq!(tally.total_tokens, 42);
+        assert!(!tally.selector_fallback);
+        let phases: Vec<Phase> = tally.phase_ms.keys().copied().collect();
+        assert_eq!(
+            phases,
+            vec![Phase::Analyzer, Phase::Worker, Phase::Synthesizer]
+        );
+        drop(second);
+        let (total, listed) = registry.snapshot(10);
+        assert_eq!(total, 0);
+        assert!(listed.is_empty());
+    }
+}
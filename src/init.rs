@@ -0,0 +1,366 @@
+use crate::config::{self, Config};
+use crate::config_keys::find_unknown_keys;
+use crate::config_migrations;
+use anyhow::{anyhow, bail, Context, Result};
+use std::fs;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+const DEFAULT_API_BASE: &str = "https://api.openai.com/v1";
+
+#[derive(Debug, Clone, Default)]
+pub struct InitAnswers {
+    pub api_base: Option<String>,
+    pub api_key: Option<String>,
+    pub model: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Provider {
+    pub api_base: String,
+    pub api_key: String,
+    pub model: String,
+}
+
+impl InitAnswers {
+    pub fn into_provider(self) -> Result<Provider> {
+        let missing: Vec<&str> = [
+            ("--api-base", self.api_base.is_none()),
+            ("--api-key", self.api_key.is_none()),
+            ("--model", self.model.is_none()),
+        ]
+        .into_iter()
+        .filter_map(|(flag, missing)| missing.then_some(flag))
+        .collect();
+        if !missing.is_empty() {
+            bail!(
+                "--non-interactive requires {} to be set",
+                missing.join(", ")
+            );
+        }
+
+        let provider = Provider {
+            api_base: self.api_base.unwrap_or_default(),
+            api_key: self.api_key.unwrap_or_default(),
+            model: self.model.unwrap_or_default(),
+        };
+        provider.check()?;
+        Ok(provider)
+    }
+
+    // 已通过参数给出的值不再询问；terminal 为 true 时输入 API key 不回显
+    pub fn prompt(
+        self,
+        input: &mut impl BufRead,
+        output: &mut impl Write,
+        terminal: bool,
+    ) -> Result<Provider> {
+        let api_base = match self.api_base {
+            Some(value) => value,
+            None => ask(input, output, "API base URL", Some(DEFAULT_API_BASE))?,
+        };
+        let api_key = match self.api_key {
+            Some(value) => value,
+            None => {
+                let hidden = if terminal { HiddenInput::start() } else { None };
+                let answer = ask(input, output, "API key", None);
+                if hidden.is_some() {
+                    // 回车也没有回显，补一个换行
+                    writeln!(output)?;
+                }
+                answer?
+            }
+        };
+        let model = match self.model {
+            Some(value) => value,
+            None => ask(input, output, "Model name", None)?,
+        };
+
+        let provider = Provider {
+            api_base,
+            api_key,
+            model,
+        };
+        provider.check()?;
+        Ok(provider)
+    }
+}
+
+impl Provider {
+    fn check(&self) -> Result<()> {
+        config::check_api_base(self.api_base.trim())
+            .map_err(|err| anyhow!("API base URL {}", err))?;
+        if self.api_key.trim().is_empty() {
+            bail!("API key must not be empty");
+        }
+        let model = self.model.trim();
+        if model.is_empty() {
+            bail!("Model name must not be empty");
+        }
+        if model.chars().any(char::is_control) || model.contains("'''") {
+            bail!("Model name '{}' contains unsupported characters", model);
+        }
+        Ok(())
+    }
+}
+
+fn ask(
+    input: &mut impl BufRead,
+    output: &mut impl Write,
+    label: &str,
+    default: Option<&str>,
+) -> Result<String> {
+    loop {
+        match default {
+            Some(default) => write!(output, "{} [{}]: ", label, default)?,
+            None => write!(output, "{}: ", label)?,
+        }
+        output.flush()?;
+
+        let mut line = String::new();
+        if input.read_line(&mut line)? == 0 {
+            return Err(anyhow!("Input ended before {} was provided", label));
+        }
+        let answer = line.trim();
+        if !answer.is_empty() {
+            return Ok(answer.to_string());
+        }
+        if let Some(default) = default {
+            return Ok(default.to_string());
+        }
+        writeln!(output, "{} is required.", label)?;
+    }
+}
+
+// --api-key-stdin：取标准输入的第一行，便于从密钥管理工具通过管道传入
+pub fn read_api_key(input: &mut impl BufRead) -> Result<String> {
+    let mut line = String::new();
+    input
+        .read_line(&mut line)
+        .context("Failed to read the API key from stdin")?;
+    let key = line.trim();
+    if key.is_empty() {
+        bail!("--api-key-stdin was given but stdin had no API key");
+    }
+    Ok(key.to_string())
+}
+
+// 关闭终端回显直到被 drop；stty 作用于继承来的 stdin，也就是当前终端
+struct HiddenInput;
+
+impl HiddenInput {
+    fn start() -> Option<Self> {
+        stty("-echo").then_some(Self)
+    }
+}
+
+impl Drop for HiddenInput {
+    fn drop(&mut self) {
+        stty("echo");
+    }
+}
+
+#[cfg(unix)]
+fn stty(mode: &str) -> bool {
+    std::process::Command::new("stty")
+        .arg(mode)
+        .stderr(std::process::Stdio::null())
+        .status()
+        .is_ok_and(|status| status.success())
+}
+
+// 其他平台没有 stty，照常回显
+#[cfg(not(unix))]
+fn stty(_mode: &str) -> bool {
+    false
+}
+
+// 单个模型同时担任 analyzer / worker / synthesizer 的最小可用配置
+pub fn render_starter_config(provider: &Provider) -> Result<String> {
+    let model = provider.model.trim();
+    let quote = |value: &str| toml::Value::String(value.trim().to_string()).to_string();
+
+    let content = format!(
+        r#"# Chorus 配置文件（由 `chorus init` 生成）
+config_version = {version}
+
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+name = {name}
+api_base = {api_base}
+api_key = {api_key}
+
+[workflow-integration]
+analyzer = {{ ref = {name}, auto_temperature = true }}
+workers = [{{ name = {name} }}]
+synthesizer = {{ ref = {name} }}
+
+[workflow.timeouts]
+# 所有超时时间单位均为秒
+analyzer_timeout_secs = 30
+worker_timeout_secs = 60
+synthesizer_timeout_secs = 60
+"#,
+        version = config_migrations::CURRENT_VERSION,
+        name = quote(model),
+        api_base = quote(&provider.api_base),
+        api_key = quote(&provider.api_key),
+    );
+
+    // 生成结果必须能通过 `chorus validate`
+    let root: toml::Table =
+        toml::from_str(&content).context("Generated config is not valid TOML")?;
+    let unknown = find_unknown_keys(&root);
+    if let Some(key) = unknown.first() {
+        bail!("Generated config contains {}", key);
+    }
+    let config: Config = toml::from_str(&content).context("Generated config failed to parse")?;
+    config
+        .validate_workflow()
+        .context("Generated config failed validation")?;
+
+    Ok(content)
+}
+
+pub fn write_config(path: &Path, content: &str, force: bool) -> Result<()> {
+    if path.exists() && !force {
+        bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
+    }
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create config dir: {}", dir.display()))?;
+    }
+
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options
+        .open(path)
+        .with_context(|| format!("Failed to create {}", path.display()))?;
+    // 覆盖已有文件时 mode 不生效，需要单独收紧权限
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(path, fs::Permissions::from_mode(0o600))
+            .with_context(|| format!("Failed to restrict permissions on {}", path.display()))?;
+    }
+    file.write_all(content.as_bytes())
+        .with_context(|| format!("Failed to write {}", path.display()))?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use std::path::PathBuf;
+
+    fn provider() -> Provider {
+        Provider {
+            api_base: "https://api.example.com/v1".to_string(),
+            api_key: "sk-\"quoted\"\\key".to_string(),
+            model: "gpt-4o-mini".to_string(),
+        }
+    }
+
+    fn temp_path(tag: &str) -> PathBuf {
+        crate::test_util::temp_dir(&format!("init_{}", tag)).join("config.toml")
+    }
+
+    #[test]
+    fn starter_config_validates_and_keeps_values() {
+        let content = render_starter_config(&provider()).unwrap();
+        let config: Config = toml::from_str(&content).unwrap();
+        assert_eq!(config.models[0].api_key, "sk-\"quoted\"\\key");
+        assert_eq!(config.workflow_integration.analyzer.model, "gpt-4o-mini");
+        assert_eq!(
+            config.workflow_integration.worker_labels(),
+            vec!["gpt-4o-mini"]
+        );
+    }
+
+    #[test]
+    fn prompts_only_for_missing_values() {
+        let answers = InitAnswers {
+            api_key: Some("sk-test".to_string()),
+            ..Default::default()
+        };
+        let mut input = Cursor::new("\n\nqwen3-max\n");
+        let mut output = Vec::new();
+        let provider = answers.prompt(&mut input, &mut output, false).unwrap();
+
+        assert_eq!(provider.api_base, DEFAULT_API_BASE);
+        assert_eq!(provider.model, "qwen3-max");
+        let transcript = String::from_utf8(output).unwrap();
+        assert!(!transcript.contains("API key"));
+        assert!(transcript.contains("Model name is required."));
+    }
+
+    #[test]
+    fn api_key_is_the_first_line_of_stdin() {
+        let mut input = Cursor::new("  sk-from-pipe  \nqwen3-max\n");
+        assert_eq!(read_api_key(&mut input).unwrap(), "sk-from-pipe");
+        // 剩下的行留给后续提问
+        let provider = InitAnswers {
+            api_base: Some(DEFAULT_API_BASE.to_string()),
+            api_key: Some("sk-from-pipe".to_string()),
+            model: None,
+        }
+        .prompt(&mut input, &mut Vec::new(), false)
+        .unwrap();
+        assert_eq!(provider.model, "qwen3-max");
+
+        let err = read_api_key(&mut Cursor::new("\n")).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--api-key-stdin was given but stdin had no API key"
+        );
+    }
+
+    #[test]
+    fn non_interactive_mode_lists_missing_flags() {
+        let err = InitAnswers {
+            model: Some("m1".to_string()),
+            ..Default::default()
+        }
+        .into_provider()
+        .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "--non-interactive requires --api-base, --api-key to be set"
+        );
+    }
+
+    #[test]
+    fn existing_file_needs_force() {
+        let path = temp_path("force");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        let content = render_starter_config(&provider()).unwrap();
+
+        write_config(&path, &content, false).unwrap();
+        let err = write_config(&path, "overwritten", false).unwrap_err();
+        assert!(err.to_string().contains("--force"));
+        assert_eq!(fs::read_to_string(&path).unwrap(), content);
+
+        write_config(&path, "overwritten", true).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "overwritten");
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
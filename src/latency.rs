@@ -0,0 +1,758 @@
+use crate::transport::TransportError;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fmt::Write as _;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::time::Instant;
+
+// 滑动窗口切成若干段，过期的段整段丢弃
+const WINDOW_SLOTS: u64 = 12;
+
+// token 数与回答字数的桶边界是固定的，上限覆盖常见模型的上下文长度
+const TOKEN_BOUNDS: &[u64] = &[
+    256, 512, 1024, 2048, 4096, 8192, 16384, 32768, 65536, 131072, 262144,
+];
+const CHAR_BOUNDS: &[u64] = &[100, 250, 500, 1000, 2500, 5000, 10000, 25000, 50000, 100000];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Phase {
+    Analyzer,
+    Worker,
+    Selector,
+    Synthesizer,
+}
+
+impl Phase {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Analyzer => "analyzer",
+            Self::Worker => "worker",
+            Self::Selector => "selector",
+            Self::Synthesizer => "synthesizer",
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Ok,
+    Error,
+    Timeout,
+}
+
+impl Outcome {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Ok => "ok",
+            Self::Error => "error",
+            Self::Timeout => "timeout",
+        }
+    }
+
+    pub fn of<T>(result: &anyhow::Result<T>) -> Self {
+        match result {
+            Ok(_) => Self::Ok,
+            Err(err) => {
+                let timed_out = err
+                    .downcast_ref::<TransportError>()
+                    .is_some_and(TransportError::is_timeout);
+                if timed_out {
+                    Self::Timeout
+                } else {
+                    Self::Error
+                }
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LatencyStats {
+    pub model: String,
+    pub phase: Phase,
+    pub requests: u64,
+    pub errors: u64,
+    pub timeouts: u64,
+    // 超过慢调用阈值的次数，见 [workflow.slow]
+    pub slow: u64,
+    pub error_rate: f64,
+    pub timeout_rate: f64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p50_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p95_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub p99_ms: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_ms: Option<u64>,
+    // 成功调用的输入、输出 token 数与回答字数分布，没有样本时不输出
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<SizeStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<SizeStats>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub response_chars: Option<SizeStats>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct SizeStats {
+    pub count: u64,
+    pub avg: u64,
+    pub p50: u64,
+    pub p95: u64,
+    pub max: u64,
+}
+
+// 一次上游调用的输入输出规模；token 数优先取上游报告的用量，没有时用本地估算
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallSize {
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub response_chars: u64,
+}
+
+// 按固定桶计数的分布，另记总和与最大值
+#[derive(Clone, Default)]
+struct Histogram {
+    // 比最后一个桶边界多一个，放超出上限的样本
+    buckets: Vec<u64>,
+    count: u64,
+    sum: u64,
+    max: u64,
+}
+
+impl Histogram {
+    fn new(bucket_count: usize) -> Self {
+        Self {
+            buckets: vec![0; bucket_count + 1],
+            ..Default::default()
+        }
+    }
+
+    fn add(&mut self, bounds: &[u64], value: u64) {
+        self.buckets[bounds.partition_point(|&bound| bound < value)] += 1;
+        self.count += 1;
+        self.sum += value;
+        self.max = self.max.max(value);
+    }
+
+    fn merge(&mut self, other: &Histogram) {
+        for (total, count) in self.buckets.iter_mut().zip(&other.buckets) {
+            *total += count;
+        }
+        self.count += other.count;
+        self.sum += other.sum;
+        self.max = self.max.max(other.max);
+    }
+
+    // 取第 q 分位所在桶的上界，不超过窗口内的最大值
+    fn quantile(&self, bounds: &[u64], q: f64) -> Option<u64> {
+        if self.count == 0 {
+            return None;
+        }
+        let rank = ((self.count as f64) * q).ceil().max(1.0) as u64;
+        let mut seen = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            seen += count;
+            if seen >= rank {
+                let upper = bounds.get(index).copied().unwrap_or(self.max);
+                return Some(upper.min(self.max));
+            }
+        }
+        Some(self.max)
+    }
+
+    fn summary(&self, bounds: &[u64]) -> Option<SizeStats> {
+        Some(SizeStats {
+            count: self.count,
+            avg: self.sum.checked_div(self.count)?,
+            p50: self.quantile(bounds, 0.50)?,
+            p95: self.quantile(bounds, 0.95)?,
+            max: self.max,
+        })
+    }
+
+    // Prometheus 直方图的 _bucket/_sum/_count 三组行，scale 把内部单位换算成导出单位
+    fn render(&self, out: &mut String, name: &str, labels: &str, bounds: &[u64], scale: f64) {
+        let separator = if labels.is_empty() { "" } else { "," };
+        let mut cumulative = 0;
+        for (index, count) in self.buckets.iter().enumerate() {
+            cumulative += count;
+            let le = match bounds.get(index) {
+                Some(bound) => format!("{}", *bound as f64 / scale),
+                None => "+Inf".to_string(),
+            };
+            let _ = writeln!(
+                out,
+                "{}_bucket{{{}{}le=\"{}\"}} {}",
+                name, labels, separator, le, cumulative
+            );
+        }
+        let labels = if labels.is_empty() {
+            String::new()
+        } else {
+            format!("{{{}}}", labels)
+        };
+        let _ = writeln!(out, "{}_sum{} {}", name, labels, self.sum as f64 / scale);
+        let _ = writeln!(out, "{}_count{} {}", name, labels, self.count);
+    }
+}
+
+#[derive(Clone)]
+struct Counts {
+    latency: Histogram,
+    errors: u64,
+    timeouts: u64,
+    slow: u64,
+    prompt_tokens: Histogram,
+    completion_tokens: Histogram,
+    response_chars: Histogram,
+}
+
+impl Counts {
+    fn new(bucket_count: usize) -> Self {
+        Self {
+            latency: Histogram::new(bucket_count),
+            errors: 0,
+            timeouts: 0,
+            slow: 0,
+            prompt_tokens: Histogram::new(TOKEN_BOUNDS.len()),
+            completion_tokens: Histogram::new(TOKEN_BOUNDS.len()),
+            response_chars: Histogram::new(CHAR_BOUNDS.len()),
+        }
+    }
+
+    fn add(&mut self, bounds_ms: &[u64], elapsed_ms: u64, outcome: Outcome) {
+        self.latency.add(bounds_ms, elapsed_ms);
+        match outcome {
+            Outcome::Ok => {}
+            Outcome::Error => self.errors += 1,
+            Outcome::Timeout => self.timeouts += 1,
+        }
+    }
+
+    fn add_size(&mut self, size: CallSize) {
+        self.prompt_tokens.add(TOKEN_BOUNDS, size.prompt_tokens);
+        self.completion_tokens
+            .add(TOKEN_BOUNDS, size.completion_tokens);
+        self.response_chars.add(CHAR_BOUNDS, size.response_chars);
+    }
+
+    fn merge(&mut self, other: &Counts) {
+        self.latency.merge(&other.latency);
+        self.errors += other.errors;
+        self.timeouts += other.timeouts;
+        self.slow += other.slow;
+        self.prompt_tokens.merge(&other.prompt_tokens);
+        self.completion_tokens.merge(&other.completion_tokens);
+        self.response_chars.merge(&other.response_chars);
+    }
+}
+
+struct Series<T> {
+    // 自启动（或重置）以来的累计值，供 /metrics 使用
+    lifetime: T,
+    // 滑动窗口的各段，记下每段对应的段号，段号过期即视为空
+    slots: Vec<(u64, T)>,
+    empty: T,
+}
+
+impl<T: Clone> Series<T> {
+    fn new(empty: T) -> Self {
+        Self {
+            lifetime: empty.clone(),
+            slots: (0..WINDOW_SLOTS)
+                .map(|_| (u64::MAX, empty.clone()))
+                .collect(),
+            empty,
+        }
+    }
+
+    // 当前段；段号变了说明是上一轮留下的，先清空
+    fn slot(&mut self, slot: u64) -> &mut T {
+        let (number, counts) = &mut self.slots[(slot % WINDOW_SLOTS) as usize];
+        if *number != slot {
+            *number = slot;
+            *counts = self.empty.clone();
+        }
+        counts
+    }
+
+    // 把仍在窗口内的各段合并起来
+    fn window(&self, current: u64, merge: impl Fn(&mut T, &T)) -> T {
+        let mut window = self.empty.clone();
+        for (number, counts) in &self.slots {
+            if *number <= current && current - number < WINDOW_SLOTS {
+                merge(&mut window, counts);
+            }
+        }
+        window
+    }
+}
+
+type SeriesKey = (String, Phase);
+type SharedSeries = Arc<Mutex<Series<Counts>>>;
+
+struct Layout {
+    bounds_ms: Vec<u64>,
+    window: Duration,
+}
+
+// 按 (模型, 阶段) 记录上游调用耗时与 token、字数分布；与限流器一样在热加载后沿用
+pub struct LatencyRecorder {
+    layout: RwLock<Layout>,
+    started: Instant,
+    series: RwLock<HashMap<SeriesKey, SharedSeries>>,
+    // 返回给客户端的最终回答字数，不分模型
+    final_chars: Mutex<Series<Histogram>>,
+}
+
+impl LatencyRecorder {
+    pub fn new(buckets: &[Duration], window: Duration) -> Self {
+        Self {
+            layout: RwLock::new(Layout {
+                bounds_ms: buckets.iter().map(|b| b.as_millis() as u64).collect(),
+                window,
+            }),
+            started: Instant::now(),
+            series: RwLock::new(HashMap::new()),
+            final_chars: Mutex::new(Series::new(Histogram::new(CHAR_BOUNDS.len()))),
+        }
+    }
+
+    // 桶边界或窗口变化后旧数据没法换算，直接清空
+    pub fn configure(&self, buckets: &[Duration], window: Duration) {
+        let bounds_ms: Vec<u64> = buckets.iter().map(|b| b.as_millis() as u64).collect();
+        let mut layout = self.layout.write().unwrap_or_else(|p| p.into_inner());
+        if layout.bounds_ms != bounds_ms || layout.window != window {
+            *layout = Layout { bounds_ms, window };
+            drop(layout);
+            self.reset();
+        }
+    }
+
+    pub fn reset(&self) {
+        self.series
+            .write()
+            .unwrap_or_else(|p| p.into_inner())
+            .clear();
+        *self.final_chars.lock().unwrap_or_else(|p| p.into_inner()) =
+            Series::new(Histogram::new(CHAR_BOUNDS.len()));
+    }
+
+    pub fn record(&self, model: &str, phase: Phase, elapsed: Duration, outcome: Outcome) {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let elapsed_ms = elapsed.as_millis() as u64;
+        let slot = self.slot_number(&layout);
+
+        let series = self.series_for(&layout, model, phase);
+        let mut series = series.lock().unwrap_or_else(|p| p.into_inner());
+        series.lifetime.add(&layout.bounds_ms, elapsed_ms, outcome);
+        series
+            .slot(slot)
+            .add(&layout.bounds_ms, elapsed_ms, outcome);
+    }
+
+    // 慢调用另外计数；阈值由工作流按阶段超时换算后判断
+    pub fn record_slow(&self, model: &str, phase: Phase) {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let slot = self.slot_number(&layout);
+        let series = self.series_for(&layout, model, phase);
+        let mut series = series.lock().unwrap_or_else(|p| p.into_inner());
+        series.lifetime.slow += 1;
+        series.slot(slot).slow += 1;
+    }
+
+    // 只记成功的调用，失败的调用没有输出
+    pub fn record_size(&self, model: &str, phase: Phase, size: CallSize) {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let slot = self.slot_number(&layout);
+        let series = self.series_for(&layout, model, phase);
+        let mut series = series.lock().unwrap_or_else(|p| p.into_inner());
+        series.lifetime.add_size(size);
+        series.slot(slot).add_size(size);
+    }
+
+    pub fn record_final_response(&self, chars: u64) {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let slot = self.slot_number(&layout);
+        let mut series = self.final_chars.lock().unwrap_or_else(|p| p.into_inner());
+        series.lifetime.add(CHAR_BOUNDS, chars);
+        series.slot(slot).add(CHAR_BOUNDS, chars);
+    }
+
+    fn series_for(&self, layout: &Layout, model: &str, phase: Phase) -> SharedSeries {
+        let key = (model.to_string(), phase);
+        let existing = self
+            .series
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .get(&key)
+            .cloned();
+        match existing {
+            Some(series) => series,
+            None => self
+                .series
+                .write()
+                .unwrap_or_else(|p| p.into_inner())
+                .entry(key)
+                .or_insert_with(|| {
+                    Arc::new(Mutex::new(Series::new(Counts::new(layout.bounds_ms.len()))))
+                })
+                .clone(),
+        }
+    }
+
+    // 滑动窗口内的分位数与错误率，按模型名、阶段排序
+    pub fn stats(&self) -> Vec<LatencyStats> {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let current = self.slot_number(&layout);
+        let mut stats: Vec<_> = self
+            .snapshot()
+            .into_iter()
+            .map(|((model, phase), series)| {
+                let window = series
+                    .lock()
+                    .unwrap_or_else(|p| p.into_inner())
+                    .window(current, Counts::merge);
+                let total = window.latency.count;
+                let rate = |count: u64| {
+                    if total == 0 {
+                        0.0
+                    } else {
+                        (count as f64 / total as f64 * 1000.0).round() / 1000.0
+                    }
+                };
+                LatencyStats {
+                    model,
+                    phase,
+                    requests: total,
+                    errors: window.errors,
+                    timeouts: window.timeouts,
+                    slow: window.slow,
+                    error_rate: rate(window.errors),
+                    timeout_rate: rate(window.timeouts),
+                    p50_ms: window.latency.quantile(&layout.bounds_ms, 0.50),
+                    p95_ms: window.latency.quantile(&layout.bounds_ms, 0.95),
+                    p99_ms: window.latency.quantile(&layout.bounds_ms, 0.99),
+                    max_ms: (total > 0).then_some(window.latency.max),
+                    prompt_tokens: window.prompt_tokens.summary(TOKEN_BOUNDS),
+                    completion_tokens: window.completion_tokens.summary(TOKEN_BOUNDS),
+                    response_chars: window.response_chars.summary(CHAR_BOUNDS),
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| (&a.model, a.phase).cmp(&(&b.model, b.phase)));
+        stats
+    }
+
+    // 滑动窗口内最终回答的字数分布
+    pub fn final_response_stats(&self) -> Option<SizeStats> {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let current = self.slot_number(&layout);
+        self.final_chars
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .window(current, Histogram::merge)
+            .summary(CHAR_BOUNDS)
+    }
+
+    pub fn window(&self) -> Duration {
+        self.layout.read().unwrap_or_else(|p| p.into_inner()).window
+    }
+
+    // Prometheus 文本格式，直方图为累计值
+    pub fn render_prometheus(&self) -> String {
+        let layout = self.layout.read().unwrap_or_else(|p| p.into_inner());
+        let mut series: Vec<_> = self
+            .snapshot()
+            .into_iter()
+            .map(|(key, series)| {
+                let counts = series
+                    .lock()
+                    .unwrap_or_else(|p| p.into_inner())
+                    .lifetime
+                    .clone();
+                (key, counts)
+            })
+            .collect();
+        series.sort_by(|a, b| a.0.cmp(&b.0));
+        let labels = |model: &str, phase: Phase| {
+            format!(
+                "model=\"{}\",phase=\"{}\"",
+                escape_label(model),
+                phase.as_str()
+            )
+        };
+
+        let mut out = String::new();
+        out.push_str(
+            "# HELP chorus_upstream_latency_seconds Upstream call latency by model and phase.\n",
+        );
+        out.push_str("# TYPE chorus_upstream_latency_seconds histogram\n");
+        for ((model, phase), counts) in &series {
+            counts.latency.render(
+                &mut out,
+                "chorus_upstream_latency_seconds",
+                &labels(model, *phase),
+                &layout.bounds_ms,
+                1000.0,
+            );
+        }
+
+        out.push_str(
+            "# HELP chorus_upstream_requests_total Upstream calls by model, phase and outcome.\n",
+        );
+        out.push_str("# TYPE chorus_upstream_requests_total counter\n");
+        for ((model, phase), counts) in &series {
+            let ok = counts.latency.count - counts.errors - counts.timeouts;
+            for (outcome, value) in [
+                ("ok", ok),
+                ("error", counts.errors),
+                ("timeout", counts.timeouts),
+            ] {
+                let _ = writeln!(
+                    out,
+                    "chorus_upstream_requests_total{{{},outcome=\"{}\"}} {}",
+                    labels(model, *phase),
+                    outcome,
+                    value
+                );
+            }
+        }
+
+        out.push_str(
+            "# HELP chorus_upstream_slow_total Upstream calls over the slow threshold by model and phase.\n",
+        );
+        out.push_str("# TYPE chorus_upstream_slow_total counter\n");
+        for ((model, phase), counts) in &series {
+            let _ = writeln!(
+                out,
+                "chorus_upstream_slow_total{{{}}} {}",
+                labels(model, *phase),
+                counts.slow
+            );
+        }
+
+        for (name, help, histogram, bounds) in [
+            (
+                "chorus_upstream_prompt_tokens",
+                "Prompt tokens of successful upstream calls by model and phase.",
+                (|counts: &Counts| &counts.prompt_tokens) as fn(&Counts) -> &Histogram,
+                TOKEN_BOUNDS,
+            ),
+            (
+                "chorus_upstream_completion_tokens",
+                "Completion tokens of successful upstream calls by model and phase.",
+                |counts| &counts.completion_tokens,
+                TOKEN_BOUNDS,
+            ),
+            (
+                "chorus_upstream_response_chars",
+                "Response length in characters of successful upstream calls by model and phase.",
+                |counts| &counts.response_chars,
+                CHAR_BOUNDS,
+            ),
+        ] {
+            let _ = writeln!(out, "# HELP {} {}", name, help);
+            let _ = writeln!(out, "# TYPE {} histogram", name);
+            for ((model, phase), counts) in &series {
+                histogram(counts).render(&mut out, name, &labels(model, *phase), bounds, 1.0);
+            }
+        }
+
+        out.push_str(
+            "# HELP chorus_final_response_chars Length in characters of final workflow answers.\n",
+        );
+        out.push_str("# TYPE chorus_final_response_chars histogram\n");
+        self.final_chars
+            .lock()
+            .unwrap_or_else(|p| p.into_inner())
+            .lifetime
+            .render(
+                &mut out,
+                "chorus_final_response_chars",
+                "",
+                CHAR_BOUNDS,
+                1.0,
+            );
+        out
+    }
+
+    fn snapshot(&self) -> Vec<(SeriesKey, SharedSeries)> {
+        self.series
+            .read()
+            .unwrap_or_else(|p| p.into_inner())
+            .iter()
+            .map(|(key, series)| (key.clone(), series.clone()))
+            .collect()
+    }
+
+    fn slot_number(&self, layout: &Layout) -> u64 {
+        let slot_ms = (layout.window.as_millis() as u64 / WINDOW_SLOTS).max(1);
+        self.started.elapsed().as_millis() as u64 / slot_ms
+    }
+}
+
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn recorder() -> LatencyRecorder {
+        let buckets: Vec<Duration> = [100, 500, 1000, 5000]
+            .into_iter()
+            .map(Duration::from_millis)
+            .collect();
+        LatencyRecorder::new(&buckets, Duration::from_secs(60))
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn percentiles_and_rates_come_from_the_window() {
+        let recorder = recorder();
+        for _ in 0..90 {
+            recorder.record("m1", Phase::Worker, Duration::from_millis(80), Outcome::Ok);
+        }
+        for _ in 0..8 {
+            recorder.record(
+                "m1",
+                Phase::Worker,
+                Duration::from_millis(700),
+                Outcome::Error,
+            );
+        }
+        for _ in 0..2 {
+            recorder.record(
+                "m1",
+                Phase::Worker,
+                Duration::from_millis(7500),
+                Outcome::Timeout,
+            );
+        }
+        recorder.record(
+            "m1",
+            Phase::Synthesizer,
+            Duration::from_millis(300),
+            Outcome::Ok,
+        );
+        recorder.record_slow("m1", Phase::Synthesizer);
+
+        let stats = recorder.stats();
+        assert_eq!(stats.len(), 2);
+        let worker = &stats[0];
+        assert_eq!(worker.phase, Phase::Worker);
+        assert_eq!(worker.requests, 100);
+        assert_eq!(worker.error_rate, 0.08);
+        assert_eq!(worker.timeout_rate, 0.02);
+        assert_eq!(worker.p50_ms, Some(100));
+        assert_eq!(worker.p95_ms, Some(1000));
+        // 超出最后一个桶时用窗口内的最大值
+        assert_eq!(worker.p99_ms, Some(7500));
+        assert_eq!(stats[1].p50_ms, Some(300));
+        assert_eq!((stats[0].slow, stats[1].slow), (0, 1));
+
+        // 窗口过后分位数清空，累计值仍保留在 /metrics 中
+        tokio::time::advance(Duration::from_secs(61)).await;
+        let stats = recorder.stats();
+        assert_eq!(stats[0].requests, 0);
+        assert_eq!(stats[0].p95_ms, None);
+        let metrics = recorder.render_prometheus();
+        assert!(metrics.contains(
+            "chorus_upstream_latency_seconds_bucket{model=\"m1\",phase=\"worker\",le=\"0.1\"} 90\n"
+        ));
+        assert!(metrics.contains(
+            "chorus_upstream_latency_seconds_bucket{model=\"m1\",phase=\"worker\",le=\"+Inf\"} 100\n"
+        ));
+        assert!(metrics.contains(
+            "chorus_upstream_requests_total{model=\"m1\",phase=\"worker\",outcome=\"timeout\"} 2\n"
+        ));
+        assert!(
+            metrics.contains("chorus_upstream_slow_total{model=\"m1\",phase=\"synthesizer\"} 1\n")
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_and_length_distributions_are_summarized_and_exported() {
+        let recorder = recorder();
+        for prompt_tokens in [300, 3000, 3000, 300000] {
+            recorder.record_size(
+                "m1",
+                Phase::Synthesizer,
+                CallSize {
+                    prompt_tokens,
+                    completion_tokens: 600,
+                    response_chars: 1200,
+                },
+            );
+        }
+        recorder.record_final_response(80);
+        recorder.record_final_response(1200);
+
+        let stats = recorder.stats();
+        let prompt = stats[0].prompt_tokens.unwrap();
+        assert_eq!((prompt.count, prompt.p50, prompt.max), (4, 4096, 300000));
+        // 超出最后一个桶时用窗口内的最大值
+        assert_eq!(prompt.p95, 300000);
+        assert_eq!(stats[0].completion_tokens.unwrap().avg, 600);
+        assert_eq!(stats[0].response_chars.unwrap().p50, 1200);
+        assert_eq!(recorder.final_response_stats().unwrap().p50, 100);
+
+        let metrics = recorder.render_prometheus();
+        assert!(metrics.contains(
+            "chorus_upstream_prompt_tokens_bucket{model=\"m1\",phase=\"synthesizer\",le=\"4096\"} 3\n"
+        ));
+        assert!(metrics.contains(
+            "chorus_upstream_completion_tokens_sum{model=\"m1\",phase=\"synthesizer\"} 2400\n"
+        ));
+        assert!(metrics.contains("chorus_final_response_chars_bucket{le=\"+Inf\"} 2\n"));
+
+        // 窗口过后摘要清空，累计值仍在 /metrics 中
+        tokio::time::advance(Duration::from_secs(61)).await;
+        assert_eq!(recorder.stats()[0].prompt_tokens, None);
+        assert_eq!(recorder.final_response_stats(), None);
+        assert!(recorder
+            .render_prometheus()
+            .contains("chorus_final_response_chars_count 2\n"));
+        recorder.reset();
+        assert!(recorder
+            .render_prometheus()
+            .contains("chorus_final_response_chars_count 0\n"));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reset_and_new_buckets_clear_recorded_data() {
+        let recorder = recorder();
+        recorder.record(
+            "m1",
+            Phase::Analyzer,
+            Duration::from_millis(50),
+            Outcome::Ok,
+        );
+        recorder.reset();
+        assert!(recorder.stats().is_empty());
+
+        recorder.record(
+            "m1",
+            Phase::Analyzer,
+            Duration::from_millis(50),
+            Outcome::Ok,
+        );
+        recorder.configure(&[Duration::from_millis(100)], Duration::from_secs(60));
+        assert!(recorder.stats().is_empty());
+        recorder.record(
+            "m1",
+            Phase::Analyzer,
+            Duration::from_millis(50),
+            Outcome::Ok,
+        );
+        assert_eq!(recorder.stats()[0].p50_ms, Some(50));
+    }
+}
@@ -1,10 +1,23 @@
+use crate::audit::{AuditCall, AuditLog};
+use crate::redaction::Redactor;
+use crate::show::redact_url_credentials;
+use crate::telemetry;
+use crate::transport::{
+    HttpTransport, RawResponse, ReqwestTransport, TransportError, TransportRequest,
+};
+use crate::usage::UsageTracker;
 use anyhow::{anyhow, Context, Result};
 use futures::StreamExt;
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue};
 use reqwest::Client;
 use serde::{Deserialize, Serialize};
 use serde_json::json;
-use std::time::Duration;
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tokio::sync::mpsc::UnboundedSender;
+use tracing::Instrument;
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct ChatMessage {
@@ -31,743 +44,4086 @@ pub struct Choice {
     pub finish_reason: Option<String>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
-#[allow(dead_code)]
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
 pub struct Usage {
-    pub prompt_tokens: Option<i32>,
-    pub completion_tokens: Option<i32>,
-    pub total_tokens: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub prompt_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub completion_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub total_tokens: Option<u32>,
 }
 
-#[derive(Debug)]
-pub struct CompletionResult {
-    pub content: String,
-    pub streamed: bool,
+impl Usage {
+    // 上游没给 total_tokens 时用输入与输出相加
+    pub fn total(&self) -> u64 {
+        self.total_tokens.map_or_else(
+            || {
+                u64::from(self.prompt_tokens.unwrap_or(0))
+                    + u64::from(self.completion_tokens.unwrap_or(0))
+            },
+            u64::from,
+        )
+    }
 }
 
-#[derive(Clone)]
-pub struct LLMClient {
-    client: Client,
-    api_base: String,
-    api_key: String,
+#[derive(Debug, thiserror::Error)]
+#[error("LLM API request failed with status {status}: {body}{}", describe_request_ids(.provider_request_ids))]
+pub struct LlmHttpError {
+    pub status: reqwest::StatusCode,
+    pub body: String,
+    // 上游响应里的 Retry-After
+    pub retry_after: Option<Duration>,
+    pub provider_request_ids: ProviderRequestIds,
 }
 
-impl LLMClient {
-    pub fn new(api_base: String, api_key: String, timeout_secs: u64) -> Result<Self> {
-        let client = Client::builder()
-            .timeout(Duration::from_secs(timeout_secs))
-            .build()
-            .with_context(|| format!("Failed to build HTTP client for {}", api_base))?;
-
-        Ok(Self {
-            client,
-            api_base,
-            api_key,
-        })
+fn describe_request_ids(ids: &ProviderRequestIds) -> String {
+    if ids.is_empty() {
+        String::new()
+    } else {
+        format!(" ({})", ids)
     }
+}
 
-    pub async fn chat_completion(
-        &self,
-        model: &str,
-        messages: Vec<ChatMessage>,
-        temperature: Option<f32>,
-    ) -> Result<String> {
-        let result = self
-            .chat_completion_with_stream(model, messages, temperature, None)
-            .await?;
-        Ok(result.content)
+impl LlmHttpError {
+    pub fn provider_error(&self) -> ProviderError {
+        ProviderError::parse(self.status.as_u16(), &self.body, self.retry_after)
     }
+}
 
-    pub async fn chat_completion_with_stream(
-        &self,
-        model: &str,
-        messages: Vec<ChatMessage>,
-        temperature: Option<f32>,
-        stream: Option<UnboundedSender<String>>,
-    ) -> Result<CompletionResult> {
-        let url = format!("{}/chat/completions", self.api_base.trim_end_matches('/'));
+// 从 OpenAI / Anthropic / Ollama 等常见错误包装中解析出的上游错误；
+// 识别不了的响应体整段作为 message
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ProviderError {
+    pub status: u16,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+    pub message: String,
+    #[serde(rename = "type", skip_serializing_if = "Option::is_none")]
+    pub kind: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+}
 
-        let request_body = json!({
-            "model": model,
-            "messages": messages,
-            "temperature": temperature,
-            "stream": stream.is_some(),
-        });
+impl ProviderError {
+    pub fn parse(status: u16, body: &str, retry_after: Option<Duration>) -> Self {
+        let mut parsed = ProviderError {
+            status,
+            code: None,
+            message: body.trim().to_string(),
+            kind: None,
+            retry_after_secs: retry_after.map(ceil_secs),
+        };
+        let value = serde_json::from_str::<serde_json::Value>(body).ok();
+        let field = |obj: &serde_json::Map<String, serde_json::Value>, key: &str| {
+            obj.get(key)
+                .and_then(json_value_to_string)
+                .map(|s| s.trim().to_string())
+                .filter(|s| !s.is_empty())
+        };
+        match value.as_ref().and_then(|v| v.get("error")) {
+            // OpenAI: {"error": {"message", "type", "code"}}；Anthropic 外层还有 "type": "error"
+            // Gemini 的 code 是数字状态码，错误类别放在 status 里
+            Some(serde_json::Value::Object(obj)) => {
+                if let Some(message) = field(obj, "message") {
+                    parsed.message = message;
+                }
+                parsed.code = field(obj, "code");
+                parsed.kind = field(obj, "type").or_else(|| field(obj, "status"));
+            }
+            // Ollama: {"error": "..."}
+            Some(serde_json::Value::String(message)) if !message.trim().is_empty() => {
+                parsed.message = message.trim().to_string();
+            }
+            _ => {}
+        }
+        parsed
+    }
 
-        tracing::debug!("Calling LLM API: {} with model: {}", url, model);
-        tracing::debug!(
-            "Request body: {}",
-            serde_json::to_string_pretty(&request_body)?
-        );
+    pub fn is_auth_failure(&self) -> bool {
+        matches!(self.status, 401 | 403)
+    }
 
-        let response = self
-            .client
-            .post(&url)
-            .header("Content-Type", "application/json")
-            .header("Authorization", format!("Bearer {}", self.api_key))
-            .json(&request_body)
-            .send()
-            .await?;
+    pub fn is_context_length_exceeded(&self) -> bool {
+        const MARKERS: &[&str] = &[
+            "context_length_exceeded",
+            "context length",
+            "context window",
+            "maximum context",
+            "prompt is too long",
+        ];
+        let code = self.code.as_deref().unwrap_or_default();
+        let message = self.message.to_ascii_lowercase();
+        MARKERS
+            .iter()
+            .any(|marker| code == *marker || message.contains(marker))
+    }
+}
 
-        if !response.status().is_success() {
-            let status = response.status();
-            let error_text = response.text().await?;
-            return Err(anyhow!(
-                "LLM API request failed with status {}: {}",
-                status,
-                error_text
-            ));
-        }
+// 上游要求等待的时间超出了剩余的阶段超时，不再重试
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "rate_limited: {api_base} asked to retry after {}s, but only {}s of the timeout remain (attempt {attempt})",
+    ceil_secs(*.retry_after),
+    ceil_secs(*.remaining)
+)]
+pub struct UpstreamRateLimited {
+    pub api_base: String,
+    pub retry_after: Duration,
+    pub remaining: Duration,
+    pub attempt: u32,
+}
 
-        if stream.is_some() && response_is_event_stream(&response) {
-            return self.consume_event_stream(response, stream).await;
-        }
+// 上游按安全策略拦截了提示词或回复；不当作空回复交给后续阶段
+#[derive(Debug, thiserror::Error)]
+#[error("LLM provider {api_base} (model {model}) blocked the {target}: {reason}")]
+pub struct ContentBlocked {
+    pub api_base: String,
+    pub model: String,
+    // "prompt" 或 "response"
+    pub target: &'static str,
+    pub reason: String,
+}
 
-        // Be tolerant to different provider response shapes
-        let v: serde_json::Value = response.json().await?;
+// 响应体超出 max_response_bytes 时中止读取，防止上游把内存撑爆
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "LLM provider {api_base} (model {model}) sent more than max_response_bytes ({limit} bytes); aborted reading the response"
+)]
+pub struct ResponseTooLarge {
+    pub api_base: String,
+    pub model: String,
+    pub limit: u64,
+}
 
-        if let Some(content) = extract_completion_text(&v) {
-            if let Some(sender) = stream.as_ref() {
-                let _ = sender.send(content.clone());
-            }
-            return Ok(CompletionResult {
-                content,
-                streamed: false,
-            });
-        }
+// 请求钩子返回错误时整个调用失败，不重试
+#[derive(Debug, thiserror::Error)]
+#[error("request hook '{hook}' failed: {message}")]
+pub struct RequestHookFailed {
+    pub hook: String,
+    pub message: String,
+}
 
-        if let Some(err_msg) = detect_provider_error(&v) {
-            return Err(anyhow!(
-                "LLM provider {} (model {}) returned error: {}",
-                self.api_base,
-                model,
-                err_msg
-            ));
-        }
+// 交给钩子的请求视图：不含鉴权头和 Gemini 的 key 参数，钩子可以追加请求头、改写请求体
+pub struct OutboundRequest {
+    pub url: String,
+    #[allow(dead_code)]
+    pub attempt: u32,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+}
 
-        Err(anyhow!("LLM response missing content field: {}", v))
-    }
+// 内置钩子用不到这些字段，留给审计类钩子
+#[allow(dead_code)]
+pub struct ResponseMeta<'a> {
+    pub url: &'a str,
+    pub attempt: u32,
+    // 连接失败、超时等没有拿到响应时为 None
+    pub status: Option<u16>,
+    pub headers: Option<&'a HeaderMap>,
+    pub elapsed: Duration,
+}
 
-    async fn consume_event_stream(
-        &self,
-        response: reqwest::Response,
-        stream: Option<UnboundedSender<String>>,
-    ) -> Result<CompletionResult> {
-        let mut final_text = String::new();
-        let mut streamed = false;
-        let mut buffer = String::new();
-        let mut byte_stream = response.bytes_stream();
+// 每次发往上游的尝试（含重试）都会依次调用已注册的钩子
+pub trait RequestHook: Send + Sync {
+    fn name(&self) -> &str;
 
-        while let Some(item) = byte_stream.next().await {
-            let chunk = item?;
-            let chunk_str = String::from_utf8_lossy(&chunk);
-            buffer.push_str(&chunk_str);
+    fn on_request(&self, model: &str, request: &mut OutboundRequest) -> Result<()>;
 
-            loop {
-                let Some((idx, sep_len)) = find_event_separator(&buffer) else {
-                    break;
-                };
+    fn on_response(&self, _model: &str, _response: &ResponseMeta<'_>) {}
+}
 
-                let mut event = buffer[..idx].to_string();
-                buffer.drain(..idx + sep_len);
+// 内置钩子：按模型配置的 extra_headers 追加固定请求头
+pub struct HeaderInjection {
+    headers: HeaderMap,
+}
 
-                if event.trim().is_empty() {
-                    continue;
-                }
+impl HeaderInjection {
+    pub fn new<'a>(headers: impl IntoIterator<Item = (&'a String, &'a String)>) -> Result<Self> {
+        let mut map = HeaderMap::new();
+        for (name, value) in headers {
+            let name = HeaderName::from_bytes(name.as_bytes())
+                .with_context(|| format!("invalid header name '{}'", name))?;
+            let value = HeaderValue::from_str(value)
+                .with_context(|| format!("invalid value for header '{}'", name))?;
+            map.insert(name, value);
+        }
+        Ok(Self { headers: map })
+    }
+}
 
-                if event.ends_with('\r') {
-                    event.pop();
-                }
+impl RequestHook for HeaderInjection {
+    fn name(&self) -> &str {
+        "extra_headers"
+    }
 
-                let mut data_payload = String::new();
-                for line in event.lines() {
-                    if let Some(rest) = line.strip_prefix("data:") {
-                        if !data_payload.is_empty() {
-                            data_payload.push('\n');
-                        }
-                        data_payload.push_str(rest.trim_start());
-                    }
-                }
+    fn on_request(&self, _model: &str, request: &mut OutboundRequest) -> Result<()> {
+        for (name, value) in &self.headers {
+            request.headers.insert(name.clone(), value.clone());
+        }
+        Ok(())
+    }
+}
 
-                if data_payload.is_empty() {
-                    continue;
-                }
+pub fn ceil_secs(duration: Duration) -> u64 {
+    duration.as_secs() + u64::from(duration.subsec_nanos() > 0)
+}
 
-                let trimmed = data_payload.trim();
-                if trimmed.is_empty() {
-                    continue;
-                }
+// Retry-After 可以是秒数，也可以是 HTTP 日期；日期已过时按 0 处理
+fn parse_retry_after(value: &str) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+    let at = chrono::DateTime::parse_from_rfc2822(value).ok()?;
+    Some(
+        (at.with_timezone(&chrono::Utc) - chrono::Utc::now())
+            .to_std()
+            .unwrap_or_default(),
+    )
+}
 
-                if trimmed == "[DONE]" {
-                    return Ok(CompletionResult {
-                        content: final_text,
-                        streamed,
-                    });
-                }
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct GenerationParams {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_tokens: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub frequency_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub presence_penalty: Option<f32>,
+    // 配置里的厂商专有参数，原样并入请求体；客户端请求不能设置
+    #[serde(skip)]
+    pub extra_body: serde_json::Map<String, serde_json::Value>,
+}
 
-                match serde_json::from_str::<serde_json::Value>(trimmed) {
-                    Ok(value) => {
-                        if let Some(text) = extract_stream_content(&value)
-                            .or_else(|| extract_completion_text(&value))
-                        {
-                            if let Some(sender) = stream.as_ref() {
-                                let _ = sender.send(text.clone());
-                            }
-                            if !text.is_empty() {
-                                final_text.push_str(&text);
-                                streamed = true;
-                            }
-                        }
+// 由客户端按 api_format 生成，extra_body 不能覆盖
+pub const PROTECTED_BODY_FIELDS: &[&str] = &[
+    "model",
+    "messages",
+    "stream",
+    "contents",
+    "system",
+    "system_instruction",
+];
 
-                        if let Some(reason) = value
-                            .get("choices")
-                            .and_then(|c| c.get(0))
-                            .and_then(|c0| c0.get("finish_reason"))
-                            .and_then(|r| r.as_str())
-                        {
-                            if !reason.is_empty() && reason != "null" {
-                                return Ok(CompletionResult {
-                                    content: final_text,
-                                    streamed,
-                                });
-                            }
-                        }
-                    }
-                    Err(_) => {
-                        if let Some(sender) = stream.as_ref() {
-                            let _ = sender.send(trimmed.to_string());
-                        }
-                        if !trimmed.is_empty() {
-                            final_text.push_str(trimmed);
-                            streamed = true;
-                        }
-                    }
-                }
+impl GenerationParams {
+    // 逐字段合并：自身已设置的值优先，缺失的取 fallback；extra_body 按键合并
+    pub fn or(&self, fallback: &GenerationParams) -> GenerationParams {
+        let mut extra_body = fallback.extra_body.clone();
+        extra_body.extend(self.extra_body.clone());
+        GenerationParams {
+            max_tokens: self.max_tokens.or(fallback.max_tokens),
+            top_p: self.top_p.or(fallback.top_p),
+            top_k: self.top_k.or(fallback.top_k),
+            frequency_penalty: self.frequency_penalty.or(fallback.frequency_penalty),
+            presence_penalty: self.presence_penalty.or(fallback.presence_penalty),
+            extra_body,
+        }
+    }
+
+    // 请求体里已有的字段优先，extra_body 只补充缺失的键
+    fn merge_extra_body(&self, body: &mut serde_json::Value) {
+        let Some(target) = body.as_object_mut() else {
+            return;
+        };
+        let mut merged = Vec::new();
+        for (key, value) in &self.extra_body {
+            if !target.contains_key(key) {
+                target.insert(key.clone(), value.clone());
+                merged.push(key.as_str());
             }
         }
+        if !merged.is_empty() {
+            tracing::debug!("Merged extra_body keys into request: {}", merged.join(", "));
+        }
+    }
 
-        Ok(CompletionResult {
-            content: final_text,
-            streamed,
-        })
+    fn apply_to(&self, body: &mut serde_json::Value) {
+        if let (Some(target), Ok(serde_json::Value::Object(params))) =
+            (body.as_object_mut(), serde_json::to_value(self))
+        {
+            target.extend(params);
+        }
     }
 }
 
-fn response_is_event_stream(response: &reqwest::Response) -> bool {
-    response
-        .headers()
-        .get("content-type")
-        .and_then(|value| value.to_str().ok())
-        .map(|content_type| content_type.contains("text/event-stream"))
-        .unwrap_or(false)
+// 上游接口的协议；默认是 OpenAI 兼容的 /chat/completions
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ApiFormat {
+    #[default]
+    Openai,
+    Ollama,
+    // Azure OpenAI：按部署名路由，api-key 头鉴权，URL 需带 api-version
+    Azure,
+    // Anthropic Messages API（{api_base}/messages），暂不支持流式
+    Anthropic,
+    // Google Gemini generateContent，key 放在查询参数里，暂不支持流式
+    Gemini,
 }
 
-fn find_event_separator(buffer: &str) -> Option<(usize, usize)> {
-    if let Some(idx) = buffer.find("\r\n\r\n") {
-        Some((idx, 4))
-    } else {
-        buffer.find("\n\n").map(|idx| (idx, 2))
-    }
+// 一个上游接口的完整位置；deployment/api_version 只在 Azure 下使用
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Endpoint {
+    pub api_base: String,
+    pub format: ApiFormat,
+    pub deployment: Option<String>,
+    pub api_version: Option<String>,
+    // 替换按 format 推导的默认路径，相对于 api_base
+    pub chat_path: Option<String>,
 }
 
-fn extract_completion_text(value: &serde_json::Value) -> Option<String> {
-    let choice = value.get("choices").and_then(|c| c.get(0));
+impl Endpoint {
+    pub fn new(api_base: impl Into<String>, format: ApiFormat) -> Self {
+        Self {
+            api_base: api_base.into(),
+            format,
+            deployment: None,
+            api_version: None,
+            chat_path: None,
+        }
+    }
 
-    if let Some(choice) = choice {
-        if let Some(message) = choice.get("message") {
-            if let Some(content) = message.get("content") {
-                if let Some(text) = normalize_content_value(content) {
-                    if !text.is_empty() {
-                        return Some(text);
-                    }
-                }
-            }
-            if let Some(reasoning) = message.get("reasoning_content") {
-                if let Some(text) = normalize_content_value(reasoning) {
-                    if !text.is_empty() {
-                        return Some(text);
-                    }
-                }
+    // 不含 Gemini 的 key 参数，可以直接打印
+    pub fn chat_url(&self, model: &str) -> String {
+        let path = match (self.chat_path.as_deref(), self.format) {
+            (Some(path), _) => path.trim_start_matches('/').to_string(),
+            (None, ApiFormat::Openai) => "chat/completions".to_string(),
+            (None, ApiFormat::Ollama) => "api/chat".to_string(),
+            (None, ApiFormat::Anthropic) => "messages".to_string(),
+            (None, ApiFormat::Gemini) => {
+                format!("models/{}:generateContent", encode_component(model))
             }
-            if let Some(reasoning) = message.get("reasoning") {
-                if let Some(text) = normalize_content_value(reasoning) {
-                    if !text.is_empty() {
-                        return Some(text);
-                    }
-                }
+            (None, ApiFormat::Azure) => format!(
+                "openai/deployments/{}/chat/completions",
+                encode_component(self.deployment.as_deref().unwrap_or_default())
+            ),
+        };
+        // base 与 path 之间恰好一个 /，不管两边各自带不带
+        let url = format!("{}/{}", self.api_base.trim_end_matches('/'), path);
+        match (self.format, self.api_version.as_deref()) {
+            (ApiFormat::Azure, Some(version)) => {
+                format!("{}?api-version={}", url, encode_component(version))
             }
+            _ => url,
         }
+    }
+}
 
-        if let Some(text) = choice.get("text").and_then(|t| t.as_str()) {
-            let trimmed = text.trim();
-            if !trimmed.is_empty() {
-                return Some(trimmed.to_string());
-            }
-        }
+// 部署名和版本号原样放进 URL，转义掉会改变路径或查询的字符
+fn encode_component(value: &str) -> String {
+    url::form_urlencoded::byte_serialize(value.as_bytes())
+        .collect::<String>()
+        .replace('+', "%20")
+}
+
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+pub const DEFAULT_MAX_RESPONSE_BYTES: u64 = 8 * 1024 * 1024;
+// candidates[0].finishReason 为这些值时回复被安全策略拦截
+const GEMINI_BLOCKED_FINISH_REASONS: &[&str] = &[
+    "SAFETY",
+    "RECITATION",
+    "BLOCKLIST",
+    "PROHIBITED_CONTENT",
+    "SPII",
+    "IMAGE_SAFETY",
+];
+// Messages API 要求必须给出 max_tokens
+const ANTHROPIC_DEFAULT_MAX_TOKENS: u32 = 4096;
+
+// 上游响应头里的请求编号，按 [telemetry] request_id_headers 的顺序排列；向供应商报障时要用到
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ProviderRequestIds(Vec<(String, String)>);
+
+impl ProviderRequestIds {
+    pub fn from_headers(headers: &HeaderMap, names: &[HeaderName]) -> Self {
+        Self(
+            names
+                .iter()
+                .filter_map(|name| {
+                    let value = headers.get(name)?.to_str().ok()?.trim();
+                    (!value.is_empty()).then(|| (name.as_str().to_string(), value.to_string()))
+                })
+                .collect(),
+        )
     }
 
-    if let Some(text) = value
-        .get("output_text")
-        .and_then(|t| normalize_content_value(t))
-    {
-        if !text.is_empty() {
-            return Some(text);
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    // 列表中第一个出现的头
+    pub fn primary(&self) -> Option<&str> {
+        self.0.first().map(|(_, value)| value.as_str())
+    }
+
+    pub fn to_map(&self) -> BTreeMap<String, String> {
+        self.0.iter().cloned().collect()
+    }
+}
+
+impl std::fmt::Display for ProviderRequestIds {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (index, (name, value)) in self.0.iter().enumerate() {
+            if index > 0 {
+                f.write_str(", ")?;
+            }
+            write!(f, "{}: {}", name, value)?;
         }
+        Ok(())
     }
+}
 
-    if let Some(obj) = value
-        .get("choices")
-        .and_then(|c| c.get(0))
-        .and_then(|c0| c0.get("message"))
-    {
-        return Some(obj.to_string());
+// 上游已经返回 2xx 但读取或解析响应失败时挂在错误上，让错误信息带上请求编号
+#[derive(Debug, thiserror::Error)]
+#[error("upstream response ({ids})")]
+pub struct UpstreamResponseFailed {
+    pub ids: ProviderRequestIds,
+}
+
+// 从错误链中找出上游的请求编号
+pub fn provider_request_ids_of(err: &anyhow::Error) -> Option<&ProviderRequestIds> {
+    if let Some(failed) = err.downcast_ref::<UpstreamResponseFailed>() {
+        return Some(&failed.ids);
     }
+    err.downcast_ref::<LlmHttpError>()
+        .map(|http_err| &http_err.provider_request_ids)
+        .filter(|ids| !ids.is_empty())
+}
 
-    None
+#[derive(Debug, Default)]
+pub struct CompletionResult {
+    pub content: String,
+    pub streamed: bool,
+    pub usage: Option<Usage>,
+    pub finish_reason: Option<String>,
+    // 上游实际使用的模型，可能与请求的名字不同
+    pub model: Option<String>,
+    pub provider_request_ids: ProviderRequestIds,
+    // 因输出达到上限而追加的续写请求次数
+    pub continuations: u32,
 }
 
-fn extract_stream_content(value: &serde_json::Value) -> Option<String> {
-    let choice = value.get("choices")?.get(0)?;
+// 各协议表示“输出达到 max_tokens 被截断”的 finish_reason
+const LENGTH_FINISH_REASONS: &[&str] = &["length", "max_tokens", "MAX_TOKENS"];
 
-    if let Some(delta) = choice.get("delta") {
-        if let Some(content) = delta.get("content") {
-            if let Some(text) = normalize_content_value(content) {
-                if !text.is_empty() {
-                    return Some(text);
-                }
+const CONTINUE_PROMPT: &str =
+    "你的回答因长度限制被截断了。请从中断处继续输出，不要重复已经写过的内容，也不要添加任何说明。";
+
+impl CompletionResult {
+    fn hit_length_limit(&self) -> bool {
+        self.finish_reason
+            .as_deref()
+            .is_some_and(|reason| LENGTH_FINISH_REASONS.contains(&reason))
+    }
+
+    // 拼接续写结果：内容相接、用量累加，其余元数据以最后一次为准
+    fn append(&mut self, next: CompletionResult) {
+        self.content.push_str(&next.content);
+        self.streamed |= next.streamed;
+        self.usage = match (self.usage.take(), next.usage) {
+            (Some(a), Some(b)) => {
+                let add = |x: Option<u32>, y: Option<u32>| match (x, y) {
+                    (None, None) => None,
+                    (x, y) => Some(x.unwrap_or(0) + y.unwrap_or(0)),
+                };
+                Some(Usage {
+                    prompt_tokens: add(a.prompt_tokens, b.prompt_tokens),
+                    completion_tokens: add(a.completion_tokens, b.completion_tokens),
+                    total_tokens: add(a.total_tokens, b.total_tokens),
+                })
             }
+            (a, b) => a.or(b),
+        };
+        self.finish_reason = next.finish_reason;
+        self.model = next.model.or(self.model.take());
+        if !next.provider_request_ids.is_empty() {
+            self.provider_request_ids = next.provider_request_ids;
         }
-        if let Some(reasoning) = delta.get("reasoning_content") {
-            if let Some(text) = normalize_content_value(reasoning) {
-                if !text.is_empty() {
-                    return Some(text);
-                }
-            }
+        self.continuations += 1;
+    }
+
+    // 流式响应逐块调用：后出现的非空字段覆盖先前的值
+    fn record_metadata(&mut self, value: &serde_json::Value, format: ApiFormat) {
+        if let Some(model) = value.get("model").and_then(|m| m.as_str()) {
+            self.model = Some(model.to_string());
         }
-        if let Some(analysis) = delta.get("analysis") {
-            if let Some(text) = normalize_content_value(analysis) {
-                if !text.is_empty() {
-                    return Some(text);
+        let (finish_reason, usage) = match format {
+            ApiFormat::Openai | ApiFormat::Azure => (
+                value
+                    .get("choices")
+                    .and_then(|c| c.get(0))
+                    .and_then(|c0| c0.get("finish_reason")),
+                value
+                    .get("usage")
+                    .filter(|usage| !usage.is_null())
+                    .and_then(|usage| serde_json::from_value::<Usage>(usage.clone()).ok()),
+            ),
+            ApiFormat::Ollama => (
+                value.get("done_reason"),
+                counted_usage(value, "prompt_eval_count", "eval_count"),
+            ),
+            ApiFormat::Anthropic => (
+                value.get("stop_reason"),
+                value
+                    .get("usage")
+                    .and_then(|usage| counted_usage(usage, "input_tokens", "output_tokens")),
+            ),
+            ApiFormat::Gemini => {
+                if let Some(version) = value.get("modelVersion").and_then(|m| m.as_str()) {
+                    self.model = Some(version.to_string());
                 }
+                let usage = value.get("usageMetadata").and_then(|usage| {
+                    let count = |key: &str| {
+                        usage
+                            .get(key)
+                            .and_then(|v| v.as_u64())
+                            .and_then(|v| u32::try_from(v).ok())
+                    };
+                    let usage = Usage {
+                        prompt_tokens: count("promptTokenCount"),
+                        completion_tokens: count("candidatesTokenCount"),
+                        total_tokens: count("totalTokenCount"),
+                    };
+                    (usage != Usage::default()).then_some(usage)
+                });
+                (
+                    value
+                        .get("candidates")
+                        .and_then(|c| c.get(0))
+                        .and_then(|c0| c0.get("finishReason")),
+                    usage,
+                )
             }
+        };
+        if let Some(reason) = finish_reason
+            .and_then(|r| r.as_str())
+            .filter(|r| !r.is_empty() && *r != "null")
+        {
+            self.finish_reason = Some(reason.to_string());
         }
-        if let Some(reasoning) = delta.get("reasoning") {
-            if let Some(text) = normalize_content_value(reasoning) {
-                if !text.is_empty() {
-                    return Some(text);
-                }
-            }
+        if usage.is_some() {
+            self.usage = usage;
         }
     }
+}
 
-    if let Some(text) = choice.get("text").and_then(|t| t.as_str()) {
-        let trimmed = text.trim();
-        if trimmed.is_empty() {
-            None
-        } else {
-            Some(trimmed.to_string())
-        }
-    } else {
-        None
+// 只给出输入、输出两项计数的格式，合计由两者相加
+fn counted_usage(
+    value: &serde_json::Value,
+    prompt_key: &str,
+    completion_key: &str,
+) -> Option<Usage> {
+    let count = |key: &str| {
+        value
+            .get(key)
+            .and_then(|v| v.as_u64())
+            .and_then(|v| u32::try_from(v).ok())
+    };
+    let usage = Usage {
+        prompt_tokens: count(prompt_key),
+        completion_tokens: count(completion_key),
+        total_tokens: None,
+    };
+    (usage != Usage::default()).then(|| Usage {
+        total_tokens: usage
+            .prompt_tokens
+            .zip(usage.completion_tokens)
+            .map(|(prompt, completion)| prompt.saturating_add(completion)),
+        ..usage
+    })
+}
+
+#[derive(Clone)]
+pub struct LLMClient {
+    transport: Arc<dyn HttpTransport>,
+    api_base: String,
+    api_key: String,
+    endpoint: Endpoint,
+    connect_timeout: Duration,
+    retry: RetryPolicy,
+    redactor: Arc<Redactor>,
+    request_id: Option<String>,
+    max_response_bytes: u64,
+    max_continuations: u32,
+    hooks: Arc<Vec<Arc<dyn RequestHook>>>,
+    audit: Option<Arc<AuditLog>>,
+    usage: Option<Arc<UsageTracker>>,
+    workflow_id: Option<u64>,
+    request_id_headers: Arc<Vec<HeaderName>>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RetryPolicy {
+    // 包含第一次请求在内的总次数，1 表示不重试
+    pub max_attempts: u32,
+    pub base_backoff: Duration,
+}
+
+impl RetryPolicy {
+    // 指数退避加等量抖动：一半固定、一半随机，避免多个 worker 同时重试
+    fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self
+            .base_backoff
+            .saturating_mul(1 << attempt.saturating_sub(1).min(16));
+        let half = exponential / 2;
+        half + half.mul_f64(random_fraction())
     }
 }
 
-fn normalize_content_value(value: &serde_json::Value) -> Option<String> {
-    match value {
-        serde_json::Value::Null => None,
-        serde_json::Value::String(s) => {
-            if s.is_empty() {
-                None
-            } else {
-                Some(s.to_string())
-            }
+// RandomState 每次创建都带新的随机种子，抖动用不着引入随机数依赖
+fn random_fraction() -> f64 {
+    use std::hash::{BuildHasher, Hasher};
+    let hash = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (hash >> 11) as f64 / (1u64 << 53) as f64
+}
+
+// 只有这些状态码说明上游暂时不可用，其余 4xx 是请求本身的问题，重试也不会成功
+fn is_retryable_status(status: reqwest::StatusCode) -> bool {
+    matches!(status.as_u16(), 429 | 500 | 502 | 503 | 504)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ProxySetting {
+    // 沿用 reqwest 默认行为（读取 HTTP_PROXY 等环境变量）
+    System,
+    Direct,
+    Url(String),
+}
+
+// 连接池与 keepalive；None 表示沿用 reqwest 默认值
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub struct PoolSettings {
+    pub max_idle_per_host: Option<usize>,
+    pub idle_timeout: Option<Duration>,
+    pub tcp_keepalive: Option<Duration>,
+}
+
+impl PoolSettings {
+    fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        if let Some(max_idle) = self.max_idle_per_host {
+            builder = builder.pool_max_idle_per_host(max_idle);
         }
-        serde_json::Value::Number(n) => Some(n.to_string()),
-        serde_json::Value::Bool(b) => Some(b.to_string()),
-        serde_json::Value::Array(items) => {
-            let mut out = String::new();
-            for item in items {
-                if let Some(piece) = normalize_content_value(item) {
-                    out.push_str(&piece);
-                }
-            }
-            if out.is_empty() {
-                None
-            } else {
-                Some(out)
-            }
+        if let Some(idle_timeout) = self.idle_timeout {
+            builder = builder.pool_idle_timeout(idle_timeout);
         }
-        serde_json::Value::Object(map) => {
-            for key in ["text", "content", "value", "message"] {
-                if let Some(inner) = map.get(key) {
-                    if let Some(text) = normalize_content_value(inner) {
-                        if !text.is_empty() {
-                            return Some(text);
-                        }
-                    }
-                }
+        builder.tcp_keepalive(self.tcp_keepalive)
+    }
+
+    pub fn describe(&self) -> String {
+        let secs = |value: Option<Duration>, default: &str| {
+            value.map_or(default.to_string(), |d| format!("{}s", d.as_secs()))
+        };
+        format!(
+            "max idle per host {}, idle timeout {}, TCP keepalive {}",
+            self.max_idle_per_host
+                .map_or("unlimited".to_string(), |n| n.to_string()),
+            secs(self.idle_timeout, "90s"),
+            secs(self.tcp_keepalive, "off")
+        )
+    }
+}
+
+// 信任设置写死在 reqwest client 里，不同设置的模型各用一个 client
+#[derive(Clone, Default)]
+pub struct TlsSettings {
+    pub extra_roots: Arc<Vec<reqwest::Certificate>>,
+    pub insecure_skip_verify: bool,
+}
+
+impl TlsSettings {
+    fn apply(&self, mut builder: reqwest::ClientBuilder) -> reqwest::ClientBuilder {
+        for cert in self.extra_roots.iter() {
+            builder = builder.add_root_certificate(cert.clone());
+        }
+        builder.danger_accept_invalid_certs(self.insecure_skip_verify)
+    }
+}
+
+impl LLMClient {
+    // resolve 中的主机名不再查询 DNS，直接连到给定地址（端口仍取 URL 中的端口）
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        endpoint: Endpoint,
+        api_key: String,
+        connect_timeout: Duration,
+        retry: RetryPolicy,
+        proxy: &ProxySetting,
+        pool: &PoolSettings,
+        tls: &TlsSettings,
+        resolve: &[(String, SocketAddr)],
+    ) -> Result<Self> {
+        // client 上只设连接超时（含 TLS 握手）；整个请求的时限由调用方按阶段逐次指定
+        let mut builder = tls.apply(pool.apply(Client::builder().connect_timeout(connect_timeout)));
+        for (host, addr) in resolve {
+            builder = builder.resolve(host, *addr);
+        }
+        builder = match proxy {
+            ProxySetting::System => builder,
+            ProxySetting::Direct => builder.no_proxy(),
+            ProxySetting::Url(url) => {
+                builder.proxy(reqwest::Proxy::all(url.as_str()).with_context(|| {
+                    format!("Invalid proxy URL: {}", redact_url_credentials(url))
+                })?)
             }
+        };
+        let client = builder
+            .build()
+            .with_context(|| format!("Failed to build HTTP client for {}", endpoint.api_base))?;
+        Ok(Self::with_transport(
+            endpoint,
+            api_key,
+            connect_timeout,
+            retry,
+            Arc::new(ReqwestTransport::new(client)),
+        ))
+    }
 
-            if let Some(parts) = map.get("parts") {
-                if let Some(text) = normalize_content_value(parts) {
-                    if !text.is_empty() {
-                        return Some(text);
-                    }
-                }
+    // 代理、连接池与 TLS 设置都属于 transport，这里只保留连接超时用于错误信息
+    pub fn with_transport(
+        endpoint: Endpoint,
+        api_key: String,
+        connect_timeout: Duration,
+        retry: RetryPolicy,
+        transport: Arc<dyn HttpTransport>,
+    ) -> Self {
+        // 默认只遮盖自己的 key；由引擎创建时换成覆盖全部已配置 key 的版本
+        let redactor = Arc::new(Redactor::new([api_key.as_str()]));
+        Self {
+            transport,
+            api_base: endpoint.api_base.clone(),
+            api_key,
+            endpoint,
+            connect_timeout,
+            retry,
+            redactor,
+            request_id: None,
+            max_response_bytes: DEFAULT_MAX_RESPONSE_BYTES,
+            max_continuations: 0,
+            hooks: Arc::new(Vec::new()),
+            audit: None,
+            usage: None,
+            workflow_id: None,
+            request_id_headers: Arc::new(
+                crate::config::DEFAULT_REQUEST_ID_HEADERS
+                    .iter()
+                    .map(|name| HeaderName::from_static(name))
+                    .collect(),
+            ),
+        }
+    }
+
+    pub fn with_redactor(mut self, redactor: Arc<Redactor>) -> Self {
+        self.redactor = redactor;
+        self
+    }
+
+    // 随每次请求发给上游，方便向供应商提交工单时对上号
+    pub fn with_request_id(mut self, request_id: Option<String>) -> Self {
+        self.request_id = request_id;
+        self
+    }
+
+    // 上限按模型设置，而 client 按端点缓存，所以和 request id 一样在取出后再设
+    pub fn with_max_response_bytes(mut self, limit: u64) -> Self {
+        self.max_response_bytes = limit;
+        self
+    }
+
+    // 输出因长度被截断时最多自动续写几次，0 表示不续写
+    pub fn with_max_continuations(mut self, max_continuations: u32) -> Self {
+        self.max_continuations = max_continuations;
+        self
+    }
+
+    pub fn with_hooks(mut self, hooks: Vec<Arc<dyn RequestHook>>) -> Self {
+        self.hooks = Arc::new(hooks);
+        self
+    }
+
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn with_usage(mut self, usage: Option<Arc<UsageTracker>>) -> Self {
+        self.usage = usage;
+        self
+    }
+
+    // 采集哪些响应头作为上游请求编号，来自 [telemetry] request_id_headers
+    pub fn with_request_id_headers(mut self, names: Arc<Vec<HeaderName>>) -> Self {
+        self.request_id_headers = names;
+        self
+    }
+
+    fn provider_request_ids(&self, response: &RawResponse) -> ProviderRequestIds {
+        ProviderRequestIds::from_headers(&response.headers, &self.request_id_headers)
+    }
+
+    // 只用于审计记录
+    pub fn with_workflow_id(mut self, workflow_id: Option<u64>) -> Self {
+        self.workflow_id = workflow_id;
+        self
+    }
+
+    fn outbound_request(
+        &self,
+        model: &str,
+        url: &str,
+        attempt: u32,
+        body: &[u8],
+    ) -> Result<OutboundRequest> {
+        let mut headers = HeaderMap::new();
+        headers.insert(
+            reqwest::header::CONTENT_TYPE,
+            HeaderValue::from_static("application/json"),
+        );
+        if let Some(id) = self.request_id.as_deref() {
+            if let Ok(value) = HeaderValue::from_str(id) {
+                headers.insert("x-request-id", value.clone());
+                headers.insert("x-client-request-id", value);
             }
+        }
+        telemetry::inject_current(&mut headers);
+        let mut request = OutboundRequest {
+            url: url.to_string(),
+            attempt,
+            headers,
+            body: body.to_vec(),
+        };
+        for hook in self.hooks.iter() {
+            hook.on_request(model, &mut request)
+                .map_err(|err| RequestHookFailed {
+                    hook: hook.name().to_string(),
+                    message: self.redactor.redact(&format!("{:#}", err)),
+                })?;
+        }
+        Ok(request)
+    }
 
-            if let Some(messages) = map.get("messages") {
-                if let Some(text) = normalize_content_value(messages) {
-                    if !text.is_empty() {
-                        return Some(text);
-                    }
+    fn notify_response(&self, model: &str, response: &ResponseMeta<'_>) {
+        for hook in self.hooks.iter() {
+            hook.on_response(model, response);
+        }
+    }
+
+    // 鉴权信息在钩子之后才加上，钩子看不到也改不了；api_key 为空时不带任何鉴权信息
+    fn build_request(
+        &self,
+        request: OutboundRequest,
+        timeout: Duration,
+    ) -> Result<TransportRequest> {
+        let OutboundRequest {
+            mut url,
+            mut headers,
+            body,
+            ..
+        } = request;
+        if self.endpoint.format == ApiFormat::Anthropic {
+            headers.insert(
+                "anthropic-version",
+                HeaderValue::from_static(ANTHROPIC_VERSION),
+            );
+        }
+        if !self.api_key.is_empty() {
+            let mut auth = |name: &'static str, value: String| -> Result<()> {
+                let mut value = HeaderValue::from_str(&value).map_err(|_| {
+                    anyhow!("API key for {} is not a valid header value", self.api_base)
+                })?;
+                value.set_sensitive(true);
+                headers.insert(name, value);
+                Ok(())
+            };
+            match self.endpoint.format {
+                ApiFormat::Azure => auth("api-key", self.api_key.clone())?,
+                ApiFormat::Anthropic => auth("x-api-key", self.api_key.clone())?,
+                ApiFormat::Gemini => {
+                    let mut parsed = url::Url::parse(&url)
+                        .with_context(|| format!("Invalid upstream URL for {}", self.api_base))?;
+                    parsed.query_pairs_mut().append_pair("key", &self.api_key);
+                    url = parsed.into();
                 }
+                _ => auth("authorization", format!("Bearer {}", self.api_key))?,
             }
-
-            None
         }
+        Ok(TransportRequest {
+            url,
+            headers,
+            body,
+            timeout,
+        })
     }
-}
 
-fn detect_provider_error(value: &serde_json::Value) -> Option<String> {
-    if let Some(error_val) = value.get("error") {
-        if let Some(obj) = error_val.as_object() {
-            let message = ["message", "msg", "error_message", "error_msg", "detail"]
-                .iter()
-                .filter_map(|key| obj.get(*key))
-                .filter_map(json_value_to_string)
-                .map(|s| s.trim().to_string())
-                .find(|s| !s.is_empty());
-            let code = ["code", "status", "type"]
-                .iter()
-                .filter_map(|key| obj.get(*key))
-                .filter_map(json_value_to_string)
-                .map(|s| s.trim().to_string())
-                .find(|s| !s.is_empty());
-            if let Some(msg) = message {
-                if let Some(code_str) = code {
-                    return Some(format!("{}: {}", code_str, msg));
+    // 所有尝试与退避等待共用调用方给出的阶段超时（从发起连接一直算到读完响应体）
+    async fn send_with_retry(
+        &self,
+        model: &str,
+        url: &str,
+        request_body: &serde_json::Value,
+        budget: Duration,
+    ) -> Result<RawResponse> {
+        let body = serde_json::to_vec(request_body)?;
+        let started = Instant::now();
+        let mut attempt = 1;
+        loop {
+            // 每次尝试一个子 span；traceparent 在 span 内生成，上游看到的父节点就是这次尝试
+            let span = tracing::info_span!("attempt", attempt, status = tracing::field::Empty);
+            let request = span.in_scope(|| self.outbound_request(model, url, attempt, &body))?;
+            let attempt_started = Instant::now();
+            let request = self.build_request(request, budget.saturating_sub(started.elapsed()))?;
+            let result = self.transport.send(request).instrument(span.clone()).await;
+            match &result {
+                Ok(response) => span.record("status", response.status.as_u16()),
+                Err(_) => span.record("status", "error"),
+            };
+            if !self.hooks.is_empty() {
+                let (status, headers) = match &result {
+                    Ok(response) => (Some(response.status.as_u16()), Some(&response.headers)),
+                    Err(_) => (None, None),
+                };
+                self.notify_response(
+                    model,
+                    &ResponseMeta {
+                        url,
+                        attempt,
+                        status,
+                        headers,
+                        elapsed: attempt_started.elapsed(),
+                    },
+                );
+            }
+
+            let (retryable, outcome, err, retry_after) = match result {
+                Ok(response) if response.status.is_success() => return Ok(response),
+                Ok(response) => {
+                    let status = response.status;
+                    let retry_after = response
+                        .headers
+                        .get(reqwest::header::RETRY_AFTER)
+                        .and_then(|value| value.to_str().ok())
+                        .and_then(parse_retry_after);
+                    let provider_request_ids = self.provider_request_ids(&response);
+                    // 错误信息只用于展示，超长时截断即可
+                    let (body, _) = self.read_capped(response, budget).await?;
+                    let body = String::from_utf8_lossy(&body);
+                    (
+                        is_retryable_status(status),
+                        format!("status {}", status.as_u16()),
+                        anyhow::Error::from(LlmHttpError {
+                            status,
+                            body: self.redactor.redact(&body),
+                            retry_after,
+                            provider_request_ids,
+                        }),
+                        retry_after,
+                    )
+                }
+                Err(err) => (
+                    err.is_connect() || err.is_timeout(),
+                    if err.is_timeout() {
+                        "timeout".to_string()
+                    } else {
+                        "connection error".to_string()
+                    },
+                    self.transport_error(err, budget),
+                    None,
+                ),
+            };
+
+            let give_up = |err: anyhow::Error| {
+                if attempt > 1 {
+                    err.context(format!("giving up after {} attempts", attempt))
                 } else {
-                    return Some(msg);
+                    err
                 }
-            } else {
-                return Some(error_val.to_string());
+            };
+            if !retryable || attempt >= self.retry.max_attempts {
+                return Err(give_up(err));
             }
-        } else if let Some(text) = json_value_to_string(error_val) {
-            let trimmed = text.trim();
-            if !trimmed.is_empty() {
-                return Some(trimmed.to_string());
+            // 上游给了 Retry-After 时按它等待，否则指数退避
+            let delay = retry_after.unwrap_or_else(|| self.retry.backoff(attempt));
+            let remaining = budget.saturating_sub(started.elapsed());
+            if delay >= remaining {
+                return Err(match retry_after {
+                    Some(retry_after) => err.context(UpstreamRateLimited {
+                        api_base: self.api_base.clone(),
+                        retry_after,
+                        remaining,
+                        attempt,
+                    }),
+                    None => give_up(err),
+                });
             }
+            tracing::warn!(
+                "LLM request to {} failed on attempt {}/{} ({}); retrying in {} ms",
+                url,
+                attempt,
+                self.retry.max_attempts,
+                outcome,
+                delay.as_millis()
+            );
+            tokio::time::sleep(delay).await;
+            attempt += 1;
         }
     }
 
-    if let Some(status_val) = value.get("status") {
-        if let Some(status_str) = interpret_status_like_error(status_val) {
-            let message = extract_message_fields(
-                value,
-                &[
-                    "msg",
-                    "message",
-                    "error_message",
-                    "error_msg",
-                    "cause",
-                    "detail",
-                ],
-            );
-            return Some(match message {
-                Some(msg) => format!("status {}: {}", status_str, msg),
-                None => format!("status {}", status_str),
-            });
+    // 读到上限即停止；第二个返回值表示是否被截断。Content-Length 超限时也照样读前面一段，
+    // 错误响应至少能留下开头的说明
+    async fn read_capped(
+        &self,
+        response: RawResponse,
+        timeout: Duration,
+    ) -> Result<(Vec<u8>, bool)> {
+        let limit = self.max_response_bytes;
+        let mut body = Vec::new();
+        let mut byte_stream = response.body;
+        while let Some(item) = byte_stream.next().await {
+            let chunk = item.map_err(|err| self.transport_error(err, timeout))?;
+            let room = (limit - body.len() as u64) as usize;
+            if chunk.len() > room {
+                body.extend_from_slice(&chunk[..room]);
+                return Ok((body, true));
+            }
+            body.extend_from_slice(&chunk);
         }
+        Ok((body, false))
     }
 
-    if let Some(code_val) = value.get("code") {
-        if let Some(code_str) = interpret_status_like_error(code_val) {
-            let message = extract_message_fields(
-                value,
-                &[
-                    "message",
-                    "msg",
-                    "error_message",
-                    "error_msg",
-                    "cause",
-                    "detail",
-                ],
-            );
-            return Some(match message {
-                Some(msg) => format!("code {}: {}", code_str, msg),
-                None => format!("code {}", code_str),
-            });
+    // 流式响应逐块累计，超过上限时让整个调用失败
+    fn check_streamed_bytes(&self, received: &mut u64, chunk: usize, model: &str) -> Result<()> {
+        *received += chunk as u64;
+        if *received > self.max_response_bytes {
+            return Err(self.too_large(model));
         }
+        Ok(())
     }
 
-    if let Some(success) = value.get("success").and_then(|v| v.as_bool()) {
-        if !success {
-            if let Some(msg) = extract_message_fields(
-                value,
-                &["message", "msg", "error_message", "error_msg", "error"],
-            ) {
-                return Some(msg);
-            }
-            return Some("success flag was false".to_string());
+    fn too_large(&self, model: &str) -> anyhow::Error {
+        ResponseTooLarge {
+            api_base: self.api_base.clone(),
+            model: model.to_string(),
+            limit: self.max_response_bytes,
         }
+        .into()
     }
 
-    if let Some(msg) = extract_message_fields(value, &["message", "msg"]) {
-        let lowered = msg.to_ascii_lowercase();
-        if lowered.contains("error")
-            || lowered.contains("invalid")
-            || lowered.contains("fail")
-            || lowered.contains("denied")
-            || lowered.contains("unauthorized")
-        {
-            return Some(msg);
+    // 超时错误注明是哪一个时限触发的：连不上服务和模型迟迟不返回需要不同的处理
+    fn transport_error(&self, err: TransportError, timeout: Duration) -> anyhow::Error {
+        let err = match self.endpoint.format {
+            ApiFormat::Gemini => err.without_url(),
+            _ => err,
+        };
+        if !err.is_timeout() {
+            return err.into();
         }
+        let message = if err.is_connect() {
+            format!(
+                "connect timeout ({}s) elapsed while connecting to {}",
+                ceil_secs(self.connect_timeout),
+                self.api_base
+            )
+        } else {
+            format!(
+                "request timeout ({}s) elapsed waiting for {} to respond",
+                ceil_secs(timeout),
+                self.api_base
+            )
+        };
+        anyhow::Error::new(err).context(message)
     }
 
-    None
-}
+    #[allow(dead_code)]
+    pub async fn chat_completion(
+        &self,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        temperature: Option<f32>,
+        params: &GenerationParams,
+        timeout: Duration,
+    ) -> Result<String> {
+        let result = self
+            .chat_completion_with_stream(model, messages, temperature, params, timeout, None)
+            .await?;
+        Ok(result.content)
+    }
 
-fn interpret_status_like_error(value: &serde_json::Value) -> Option<String> {
-    match value {
-        serde_json::Value::Number(n) => {
-            if let Some(int) = n.as_i64() {
-                if int != 0 && int != 200 {
-                    return Some(int.to_string());
-                }
-            } else if let Some(f) = n.as_f64() {
-                if f != 0.0 && f != 200.0 {
-                    return Some(f.to_string());
+    // 续写与首次请求共用同一个阶段超时
+    pub async fn chat_completion_with_stream(
+        &self,
+        model: &str,
+        messages: Vec<ChatMessage>,
+        temperature: Option<f32>,
+        params: &GenerationParams,
+        timeout: Duration,
+        stream: Option<UnboundedSender<String>>,
+    ) -> Result<CompletionResult> {
+        let started = Instant::now();
+        let mut result = self
+            .complete_once(
+                model,
+                &messages,
+                temperature,
+                params,
+                timeout,
+                stream.clone(),
+            )
+            .await?;
+
+        while result.hit_length_limit() && result.continuations < self.max_continuations {
+            let remaining = timeout.saturating_sub(started.elapsed());
+            if remaining.is_zero() {
+                break;
+            }
+            let mut follow_up = messages.clone();
+            follow_up.push(ChatMessage {
+                role: "assistant".to_string(),
+                content: result.content.clone(),
+            });
+            follow_up.push(ChatMessage {
+                role: "user".to_string(),
+                content: CONTINUE_PROMPT.to_string(),
+            });
+            tracing::debug!(
+                "LLM output for model {} hit the length limit after {} chars; requesting continuation {}/{}",
+                model,
+                result.content.chars().count(),
+                result.continuations + 1,
+                self.max_continuations
+            );
+            match self
+                .complete_once(
+                    model,
+                    &follow_up,
+                    temperature,
+                    params,
+                    remaining,
+                    stream.clone(),
+                )
+                .await
+            {
+                Ok(next) => result.append(next),
+                // 已拿到的部分可能已经流式发出，续写失败时保留它而不是让整个调用失败
+                Err(err) => {
+                    tracing::warn!(
+                        "Continuation for model {} failed; keeping the truncated output: {:#}",
+                        model,
+                        err
+                    );
+                    break;
                 }
             }
-            None
         }
-        serde_json::Value::String(s) => {
-            let trimmed = s.trim();
-            if trimmed.is_empty() {
-                return None;
+        Ok(result)
+    }
+
+    // 续写也是一次独立的上游调用，各自写一条审计记录并单独计入用量
+    async fn complete_once(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        temperature: Option<f32>,
+        params: &GenerationParams,
+        timeout: Duration,
+        stream: Option<UnboundedSender<String>>,
+    ) -> Result<CompletionResult> {
+        let result = self
+            .request_completion(model, messages, temperature, params, timeout, stream)
+            .await;
+        if let Some(audit) = &self.audit {
+            audit.record(AuditCall {
+                workflow_id: self.workflow_id,
+                request_id: self.request_id.as_deref(),
+                model,
+                api_base: &self.api_base,
+                messages,
+                result: &result,
+                error: result
+                    .as_ref()
+                    .err()
+                    .map(|err| self.redactor.redact(&format!("{:#}", err))),
+            });
+        }
+        if let Some(usage) = &self.usage {
+            let reported = result
+                .as_ref()
+                .ok()
+                .and_then(|result| result.usage.as_ref());
+            usage.record(model, reported, result.is_ok());
+        }
+        result
+    }
+
+    async fn request_completion(
+        &self,
+        model: &str,
+        messages: &[ChatMessage],
+        temperature: Option<f32>,
+        params: &GenerationParams,
+        timeout: Duration,
+        stream: Option<UnboundedSender<String>>,
+    ) -> Result<CompletionResult> {
+        let url = self.endpoint.chat_url(model);
+
+        let mut request_body = match self.endpoint.format {
+            ApiFormat::Openai | ApiFormat::Azure => {
+                build_request_body(model, messages, temperature, params, stream.is_some())
             }
-            if let Ok(int) = trimmed.parse::<i64>() {
-                if int != 0 && int != 200 {
-                    return Some(int.to_string());
-                }
-                return None;
+            ApiFormat::Ollama => {
+                build_ollama_body(model, messages, temperature, params, stream.is_some())
             }
-            let lowered = trimmed.to_ascii_lowercase();
-            if lowered == "ok"
-                || lowered == "success"
-                || lowered == "succeeded"
-                || lowered == "true"
-                || lowered == "0"
-                || lowered == "200"
-            {
-                None
-            } else if lowered.contains("error")
-                || lowered.contains("fail")
-                || lowered.contains("invalid")
-                || lowered.contains("denied")
-                || lowered.contains("unauthorized")
-            {
-                Some(trimmed.to_string())
-            } else {
-                None
+            ApiFormat::Anthropic => build_anthropic_body(model, messages, temperature, params),
+            ApiFormat::Gemini => build_gemini_body(messages, temperature, params),
+        };
+        params.merge_extra_body(&mut request_body);
+
+        tracing::debug!(
+            "Calling LLM API: {} with model: {} (request id {})",
+            url,
+            model,
+            self.request_id.as_deref().unwrap_or("-")
+        );
+        tracing::debug!(
+            "Request body: {}",
+            serde_json::to_string_pretty(&request_body)?
+        );
+
+        let response = self
+            .send_with_retry(model, &url, &request_body, timeout)
+            .await?;
+        let provider_request_ids = self.provider_request_ids(&response);
+
+        let result = match self.endpoint.format {
+            ApiFormat::Ollama if stream.is_some() => {
+                self.consume_ndjson_stream(response, model, stream, timeout)
+                    .await
+            }
+            // 请求了流式但上游仍回普通 JSON 时按非流式处理；其余一律按 SSE 解析，不依赖 content-type
+            ApiFormat::Openai | ApiFormat::Azure if stream.is_some() && !response.is_json() => {
+                self.consume_event_stream(response, model, stream, timeout)
+                    .await
+            }
+            _ => {
+                self.read_json_response(response, model, stream, timeout)
+                    .await
             }
+        };
+        match result {
+            Ok(mut result) => {
+                result.provider_request_ids = provider_request_ids;
+                Ok(result)
+            }
+            Err(err) if !provider_request_ids.is_empty() => {
+                Err(err.context(UpstreamResponseFailed {
+                    ids: provider_request_ids,
+                }))
+            }
+            Err(err) => Err(err),
         }
-        serde_json::Value::Bool(b) => {
-            if *b {
-                None
+    }
+
+    async fn read_json_response(
+        &self,
+        response: RawResponse,
+        model: &str,
+        stream: Option<UnboundedSender<String>>,
+        timeout: Duration,
+    ) -> Result<CompletionResult> {
+        let (body, truncated) = self.read_capped(response, timeout).await?;
+        if truncated {
+            return Err(self.too_large(model));
+        }
+        // Be tolerant to different provider response shapes
+        let v: serde_json::Value = serde_json::from_slice(&body).with_context(|| {
+            format!(
+                "LLM provider {} (model {}) returned a response that is not valid JSON",
+                self.api_base, model
+            )
+        })?;
+
+        let content = match self.endpoint.format {
+            ApiFormat::Openai | ApiFormat::Azure => extract_completion_text(&v),
+            ApiFormat::Ollama => extract_ollama_text(&v),
+            ApiFormat::Anthropic => extract_anthropic_text(&v),
+            ApiFormat::Gemini => {
+                if let Some((target, reason)) = gemini_block_reason(&v) {
+                    return Err(ContentBlocked {
+                        api_base: self.api_base.clone(),
+                        model: model.to_string(),
+                        target,
+                        reason,
+                    }
+                    .into());
+                }
+                extract_gemini_text(&v)
+            }
+        };
+        if let Some(content) = content {
+            if let Some(sender) = stream.as_ref() {
+                let _ = sender.send(content.clone());
+            }
+            // 整段内容已经发给了调用方，标记为已流式输出，避免上层再发一遍
+            let mut result = CompletionResult {
+                content,
+                streamed: stream.is_some(),
+                ..Default::default()
+            };
+            result.record_metadata(&v, self.endpoint.format);
+            return Ok(result);
+        }
+
+        if let Some(err_msg) = detect_provider_error(&v) {
+            return Err(anyhow!(
+                "LLM provider {} (model {}) returned error: {}",
+                self.api_base,
+                model,
+                self.redactor.redact(&err_msg)
+            ));
+        }
+
+        Err(anyhow!(
+            "LLM response missing content field: {}",
+            self.redactor.redact(&v.to_string())
+        ))
+    }
+
+    // Ollama 的流式响应是逐行 JSON，最后一行带 done: true
+    async fn consume_ndjson_stream(
+        &self,
+        response: RawResponse,
+        model: &str,
+        stream: Option<UnboundedSender<String>>,
+        timeout: Duration,
+    ) -> Result<CompletionResult> {
+        let mut result = CompletionResult::default();
+        let mut buffer = Vec::new();
+        let mut received = 0;
+        let mut byte_stream = response.body;
+
+        while let Some(item) = byte_stream.next().await {
+            let chunk = item.map_err(|err| self.transport_error(err, timeout))?;
+            self.check_streamed_bytes(&mut received, chunk.len(), model)?;
+            buffer.extend_from_slice(&chunk);
+
+            while let Some(idx) = buffer.iter().position(|&b| b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=idx).collect();
+                let line = String::from_utf8_lossy(&line);
+                let line = line.trim();
+                if line.is_empty() {
+                    continue;
+                }
+                let value: serde_json::Value = serde_json::from_str(line).with_context(|| {
+                    format!("Invalid Ollama stream line: {}", self.redactor.redact(line))
+                })?;
+                if let Some(err_msg) = detect_provider_error(&value) {
+                    return Err(anyhow!(
+                        "LLM provider {} returned error: {}",
+                        self.api_base,
+                        self.redactor.redact(&err_msg)
+                    ));
+                }
+                if let Some(text) = extract_ollama_text(&value) {
+                    if let Some(sender) = stream.as_ref() {
+                        let _ = sender.send(text.clone());
+                    }
+                    result.content.push_str(&text);
+                }
+                result.record_metadata(&value, ApiFormat::Ollama);
+                if value.get("done").and_then(|d| d.as_bool()) == Some(true) {
+                    break;
+                }
+            }
+        }
+
+        result.streamed = !result.content.is_empty();
+        Ok(result)
+    }
+
+    async fn consume_event_stream(
+        &self,
+        response: RawResponse,
+        model: &str,
+        stream: Option<UnboundedSender<String>>,
+        timeout: Duration,
+    ) -> Result<CompletionResult> {
+        let mut result = CompletionResult::default();
+        let mut parser = SseParser::default();
+        let mut received = 0;
+        let mut byte_stream = response.body;
+
+        while let Some(item) = byte_stream.next().await {
+            let chunk = item.map_err(|err| self.transport_error(err, timeout))?;
+            self.check_streamed_bytes(&mut received, chunk.len(), model)?;
+            for payload in parser.push(&chunk) {
+                if self.apply_stream_event(&payload, stream.as_ref(), &mut result)? {
+                    return Ok(result);
+                }
+            }
+        }
+        // 连接关闭时最后一个事件可能没有空行结尾
+        for payload in parser.finish() {
+            if self.apply_stream_event(&payload, stream.as_ref(), &mut result)? {
+                break;
+            }
+        }
+
+        Ok(result)
+    }
+
+    // 已转发的片段无法撤回，错误帧必须让整个调用失败，而不是当作正常结束
+    fn apply_stream_event(
+        &self,
+        payload: &str,
+        stream: Option<&UnboundedSender<String>>,
+        result: &mut CompletionResult,
+    ) -> Result<bool> {
+        apply_sse_payload(payload, stream, result).map_err(|err_msg| {
+            anyhow!(
+                "LLM provider {} returned error mid-stream after {} chars: {}",
+                self.api_base,
+                result.content.chars().count(),
+                self.redactor.redact(&err_msg)
+            )
+        })
+    }
+}
+
+fn build_request_body(
+    model: &str,
+    messages: &[ChatMessage],
+    temperature: Option<f32>,
+    params: &GenerationParams,
+    stream: bool,
+) -> serde_json::Value {
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+        "temperature": temperature,
+        "stream": stream,
+    });
+    params.apply_to(&mut body);
+    body
+}
+
+// Ollama 把采样参数放在 options 里，max_tokens 对应 num_predict
+fn build_ollama_body(
+    model: &str,
+    messages: &[ChatMessage],
+    temperature: Option<f32>,
+    params: &GenerationParams,
+    stream: bool,
+) -> serde_json::Value {
+    let mut options = serde_json::Map::new();
+    let mut set = |key: &str, value: Option<serde_json::Value>| {
+        if let Some(value) = value {
+            options.insert(key.to_string(), value);
+        }
+    };
+    set("temperature", temperature.map(|t| json!(t)));
+    set("num_predict", params.max_tokens.map(|v| json!(v)));
+    set("top_p", params.top_p.map(|v| json!(v)));
+    set("top_k", params.top_k.map(|v| json!(v)));
+    set(
+        "frequency_penalty",
+        params.frequency_penalty.map(|v| json!(v)),
+    );
+    set(
+        "presence_penalty",
+        params.presence_penalty.map(|v| json!(v)),
+    );
+
+    let mut body = json!({
+        "model": model,
+        "messages": messages,
+        "stream": stream,
+    });
+    if !options.is_empty() {
+        body["options"] = serde_json::Value::Object(options);
+    }
+    body
+}
+
+fn extract_ollama_text(value: &serde_json::Value) -> Option<String> {
+    value
+        .get("message")?
+        .get("content")
+        .and_then(normalize_content_value)
+}
+
+// system 消息放到顶层 system 字段；Messages API 没有的参数丢弃，temperature 上限为 1.0
+fn build_anthropic_body(
+    model: &str,
+    messages: &[ChatMessage],
+    temperature: Option<f32>,
+    params: &GenerationParams,
+) -> serde_json::Value {
+    let (system, turns): (Vec<&ChatMessage>, Vec<&ChatMessage>) =
+        messages.iter().partition(|m| m.role == "system");
+    let mut body = json!({
+        "model": model,
+        "messages": turns,
+        "max_tokens": params.max_tokens.unwrap_or(ANTHROPIC_DEFAULT_MAX_TOKENS),
+    });
+    if !system.is_empty() {
+        let system: Vec<&str> = system.iter().map(|m| m.content.as_str()).collect();
+        body["system"] = json!(system.join("\n\n"));
+    }
+    if let Some(temperature) = temperature {
+        if temperature > 1.0 {
+            tracing::debug!(
+                "Clamping temperature {} to 1.0 for Anthropic model {}",
+                temperature,
+                model
+            );
+        }
+        body["temperature"] = json!(temperature.min(1.0));
+    }
+    if let Some(top_p) = params.top_p {
+        body["top_p"] = json!(top_p);
+    }
+    if let Some(top_k) = params.top_k {
+        body["top_k"] = json!(top_k);
+    }
+    for (name, value) in [
+        ("frequency_penalty", params.frequency_penalty),
+        ("presence_penalty", params.presence_penalty),
+    ] {
+        if value.is_some() {
+            tracing::debug!(
+                "Dropping {} for Anthropic model {}: not supported by the Messages API",
+                name,
+                model
+            );
+        }
+    }
+    body
+}
+
+// 只取 text 块，忽略 thinking / tool_use 等其它块
+fn extract_anthropic_text(value: &serde_json::Value) -> Option<String> {
+    let blocks = value.get("content")?.as_array()?;
+    let text: String = blocks
+        .iter()
+        .filter(|block| block.get("type").and_then(|t| t.as_str()) == Some("text"))
+        .filter_map(|block| block.get("text").and_then(|t| t.as_str()))
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
+
+// system 消息合并进 system_instruction；assistant 在 Gemini 里叫 model
+fn build_gemini_body(
+    messages: &[ChatMessage],
+    temperature: Option<f32>,
+    params: &GenerationParams,
+) -> serde_json::Value {
+    let (system, turns): (Vec<&ChatMessage>, Vec<&ChatMessage>) =
+        messages.iter().partition(|m| m.role == "system");
+    let contents: Vec<serde_json::Value> = turns
+        .iter()
+        .map(|m| {
+            let role = if m.role == "assistant" {
+                "model"
             } else {
-                Some("false".to_string())
+                "user"
+            };
+            json!({"role": role, "parts": [{"text": m.content}]})
+        })
+        .collect();
+    let mut body = json!({ "contents": contents });
+    if !system.is_empty() {
+        let parts: Vec<serde_json::Value> =
+            system.iter().map(|m| json!({"text": m.content})).collect();
+        body["system_instruction"] = json!({ "parts": parts });
+    }
+
+    let mut config = serde_json::Map::new();
+    let mut set = |key: &str, value: Option<serde_json::Value>| {
+        if let Some(value) = value {
+            config.insert(key.to_string(), value);
+        }
+    };
+    set("temperature", temperature.map(|t| json!(t)));
+    set("maxOutputTokens", params.max_tokens.map(|v| json!(v)));
+    set("topP", params.top_p.map(|v| json!(v)));
+    set("topK", params.top_k.map(|v| json!(v)));
+    set(
+        "frequencyPenalty",
+        params.frequency_penalty.map(|v| json!(v)),
+    );
+    set("presencePenalty", params.presence_penalty.map(|v| json!(v)));
+    if !config.is_empty() {
+        body["generationConfig"] = serde_json::Value::Object(config);
+    }
+    body
+}
+
+// 提示词被拦截时没有 candidates，只有 promptFeedback.blockReason
+fn gemini_block_reason(value: &serde_json::Value) -> Option<(&'static str, String)> {
+    if let Some(reason) = value
+        .get("promptFeedback")
+        .and_then(|f| f.get("blockReason"))
+        .and_then(|r| r.as_str())
+    {
+        return Some(("prompt", reason.to_string()));
+    }
+    let candidate = value.get("candidates").and_then(|c| c.get(0))?;
+    let reason = candidate.get("finishReason").and_then(|r| r.as_str())?;
+    (GEMINI_BLOCKED_FINISH_REASONS.contains(&reason) && extract_gemini_text(value).is_none())
+        .then(|| ("response", reason.to_string()))
+}
+
+// 跳过 thought: true 的思考片段
+fn extract_gemini_text(value: &serde_json::Value) -> Option<String> {
+    let parts = value
+        .get("candidates")?
+        .get(0)?
+        .get("content")?
+        .get("parts")?
+        .as_array()?;
+    let text: String = parts
+        .iter()
+        .filter(|part| part.get("thought").and_then(|t| t.as_bool()) != Some(true))
+        .filter_map(|part| part.get("text").and_then(|t| t.as_str()))
+        .collect();
+    (!text.is_empty()).then_some(text)
+}
+
+// 按字节缓存 SSE 数据：TCP 分块既可能把一行（甚至一个 UTF-8 字符）拆开，也可能一次带来多个事件
+#[derive(Default)]
+struct SseParser {
+    buffer: Vec<u8>,
+    data: Option<String>,
+}
+
+impl SseParser {
+    // 返回本次输入中已完整结束的事件的 data；未结束的行留到下一块
+    fn push(&mut self, chunk: &[u8]) -> Vec<String> {
+        self.buffer.extend_from_slice(chunk);
+        let mut events = Vec::new();
+        let mut start = 0;
+        while let Some(offset) = self.buffer[start..].iter().position(|&b| b == b'\n') {
+            let end = start + offset;
+            let line = &self.buffer[start..end];
+            let line = line.strip_suffix(b"\r").unwrap_or(line);
+            let line = String::from_utf8_lossy(line).into_owned();
+            start = end + 1;
+            if let Some(data) = self.process_line(&line) {
+                events.push(data);
+            }
+        }
+        self.buffer.drain(..start);
+        events
+    }
+
+    // 流结束时把没有空行结尾的最后一个事件也交出去
+    fn finish(&mut self) -> Vec<String> {
+        let mut events = Vec::new();
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            let line = rest.strip_suffix(b"\r").unwrap_or(&rest);
+            let line = String::from_utf8_lossy(line).into_owned();
+            if let Some(data) = self.process_line(&line) {
+                events.push(data);
+            }
+        }
+        events.extend(self.data.take());
+        events
+    }
+
+    fn process_line(&mut self, line: &str) -> Option<String> {
+        if line.is_empty() {
+            return self.data.take();
+        }
+        // 同一事件里的多行 data 以换行拼接；注释（以冒号开头）与其它字段忽略
+        if let Some(value) = line.strip_prefix("data") {
+            let value = match value.strip_prefix(':') {
+                Some(value) => value.strip_prefix(' ').unwrap_or(value),
+                None if value.is_empty() => "",
+                None => return None,
+            };
+            match self.data.as_mut() {
+                Some(data) => {
+                    data.push('\n');
+                    data.push_str(value);
+                }
+                None => self.data = Some(value.to_string()),
+            }
+        }
+        None
+    }
+}
+
+// 处理一个事件的 data，返回流是否已经结束；中途出现的错误帧作为 Err 返回
+fn apply_sse_payload(
+    payload: &str,
+    stream: Option<&UnboundedSender<String>>,
+    result: &mut CompletionResult,
+) -> std::result::Result<bool, String> {
+    let trimmed = payload.trim();
+    if trimmed.is_empty() {
+        return Ok(false);
+    }
+    if trimmed == "[DONE]" {
+        return Ok(true);
+    }
+
+    let mut emit = |text: &str| {
+        if let Some(sender) = stream {
+            let _ = sender.send(text.to_string());
+        }
+        if !text.is_empty() {
+            result.content.push_str(text);
+            result.streamed = true;
+        }
+    };
+
+    let Ok(value) = serde_json::from_str::<serde_json::Value>(trimmed) else {
+        emit(trimmed);
+        return Ok(false);
+    };
+    if let Some(err_msg) = detect_provider_error(&value) {
+        return Err(err_msg);
+    }
+    if let Some(text) = extract_stream_content(&value).or_else(|| extract_completion_text(&value)) {
+        emit(&text);
+    }
+    // 带 finish_reason 的块即最后一块，部分供应商把 usage 一并放在这里
+    result.record_metadata(&value, ApiFormat::Openai);
+    Ok(value
+        .get("choices")
+        .and_then(|c| c.get(0))
+        .and_then(|c0| c0.get("finish_reason"))
+        .and_then(|r| r.as_str())
+        .is_some_and(|reason| !reason.is_empty() && reason != "null"))
+}
+
+fn extract_completion_text(value: &serde_json::Value) -> Option<String> {
+    let choice = value.get("choices").and_then(|c| c.get(0));
+
+    if let Some(choice) = choice {
+        if let Some(message) = choice.get("message") {
+            if let Some(content) = message.get("content") {
+                if let Some(text) = normalize_content_value(content) {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+            }
+            if let Some(reasoning) = message.get("reasoning_content") {
+                if let Some(text) = normalize_content_value(reasoning) {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+            }
+            if let Some(reasoning) = message.get("reasoning") {
+                if let Some(text) = normalize_content_value(reasoning) {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+            }
+        }
+
+        if let Some(text) = choice.get("text").and_then(|t| t.as_str()) {
+            let trimmed = text.trim();
+            if !trimmed.is_empty() {
+                return Some(trimmed.to_string());
+            }
+        }
+    }
+
+    if let Some(text) = value.get("output_text").and_then(normalize_content_value) {
+        if !text.is_empty() {
+            return Some(text);
+        }
+    }
+
+    if let Some(obj) = value
+        .get("choices")
+        .and_then(|c| c.get(0))
+        .and_then(|c0| c0.get("message"))
+    {
+        return Some(obj.to_string());
+    }
+
+    None
+}
+
+fn extract_stream_content(value: &serde_json::Value) -> Option<String> {
+    let choice = value.get("choices")?.get(0)?;
+
+    if let Some(delta) = choice.get("delta") {
+        if let Some(content) = delta.get("content") {
+            if let Some(text) = normalize_content_value(content) {
+                if !text.is_empty() {
+                    return Some(text);
+                }
+            }
+        }
+        if let Some(reasoning) = delta.get("reasoning_content") {
+            if let Some(text) = normalize_content_value(reasoning) {
+                if !text.is_empty() {
+                    return Some(text);
+                }
             }
         }
-        _ => None,
+        if let Some(analysis) = delta.get("analysis") {
+            if let Some(text) = normalize_content_value(analysis) {
+                if !text.is_empty() {
+                    return Some(text);
+                }
+            }
+        }
+        if let Some(reasoning) = delta.get("reasoning") {
+            if let Some(text) = normalize_content_value(reasoning) {
+                if !text.is_empty() {
+                    return Some(text);
+                }
+            }
+        }
+    }
+
+    if let Some(text) = choice.get("text").and_then(|t| t.as_str()) {
+        let trimmed = text.trim();
+        if trimmed.is_empty() {
+            None
+        } else {
+            Some(trimmed.to_string())
+        }
+    } else {
+        None
+    }
+}
+
+fn normalize_content_value(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => {
+            if s.is_empty() {
+                None
+            } else {
+                Some(s.to_string())
+            }
+        }
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Array(items) => {
+            let mut out = String::new();
+            for item in items {
+                if let Some(piece) = normalize_content_value(item) {
+                    out.push_str(&piece);
+                }
+            }
+            if out.is_empty() {
+                None
+            } else {
+                Some(out)
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for key in ["text", "content", "value", "message"] {
+                if let Some(inner) = map.get(key) {
+                    if let Some(text) = normalize_content_value(inner) {
+                        if !text.is_empty() {
+                            return Some(text);
+                        }
+                    }
+                }
+            }
+
+            if let Some(parts) = map.get("parts") {
+                if let Some(text) = normalize_content_value(parts) {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+            }
+
+            if let Some(messages) = map.get("messages") {
+                if let Some(text) = normalize_content_value(messages) {
+                    if !text.is_empty() {
+                        return Some(text);
+                    }
+                }
+            }
+
+            None
+        }
+    }
+}
+
+fn detect_provider_error(value: &serde_json::Value) -> Option<String> {
+    if let Some(error_val) = value.get("error") {
+        if let Some(obj) = error_val.as_object() {
+            let message = ["message", "msg", "error_message", "error_msg", "detail"]
+                .iter()
+                .filter_map(|key| obj.get(*key))
+                .filter_map(json_value_to_string)
+                .map(|s| s.trim().to_string())
+                .find(|s| !s.is_empty());
+            let code = ["code", "status", "type"]
+                .iter()
+                .filter_map(|key| obj.get(*key))
+                .filter_map(json_value_to_string)
+                .map(|s| s.trim().to_string())
+                .find(|s| !s.is_empty());
+            if let Some(msg) = message {
+                if let Some(code_str) = code {
+                    return Some(format!("{}: {}", code_str, msg));
+                } else {
+                    return Some(msg);
+                }
+            } else {
+                return Some(error_val.to_string());
+            }
+        } else if let Some(text) = json_value_to_string(error_val) {
+            let trimmed = text.trim();
+            if !trimmed.is_empty() {
+                return Some(trimmed.to_string());
+            }
+        }
+    }
+
+    if let Some(status_val) = value.get("status") {
+        if let Some(status_str) = interpret_status_like_error(status_val) {
+            let message = extract_message_fields(
+                value,
+                &[
+                    "msg",
+                    "message",
+                    "error_message",
+                    "error_msg",
+                    "cause",
+                    "detail",
+                ],
+            );
+            return Some(match message {
+                Some(msg) => format!("status {}: {}", status_str, msg),
+                None => format!("status {}", status_str),
+            });
+        }
+    }
+
+    if let Some(code_val) = value.get("code") {
+        if let Some(code_str) = interpret_status_like_error(code_val) {
+            let message = extract_message_fields(
+                value,
+                &[
+                    "message",
+                    "msg",
+                    "error_message",
+                    "error_msg",
+                    "cause",
+                    "detail",
+                ],
+            );
+            return Some(match message {
+                Some(msg) => format!("code {}: {}", code_str, msg),
+                None => format!("code {}", code_str),
+            });
+        }
+    }
+
+    if let Some(success) = value.get("success").and_then(|v| v.as_bool()) {
+        if !success {
+            if let Some(msg) = extract_message_fields(
+                value,
+                &["message", "msg", "error_message", "error_msg", "error"],
+            ) {
+                return Some(msg);
+            }
+            return Some("success flag was false".to_string());
+        }
+    }
+
+    if let Some(msg) = extract_message_fields(value, &["message", "msg"]) {
+        let lowered = msg.to_ascii_lowercase();
+        if lowered.contains("error")
+            || lowered.contains("invalid")
+            || lowered.contains("fail")
+            || lowered.contains("denied")
+            || lowered.contains("unauthorized")
+        {
+            return Some(msg);
+        }
+    }
+
+    None
+}
+
+fn interpret_status_like_error(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Number(n) => {
+            if let Some(int) = n.as_i64() {
+                if int != 0 && int != 200 {
+                    return Some(int.to_string());
+                }
+            } else if let Some(f) = n.as_f64() {
+                if f != 0.0 && f != 200.0 {
+                    return Some(f.to_string());
+                }
+            }
+            None
+        }
+        serde_json::Value::String(s) => {
+            let trimmed = s.trim();
+            if trimmed.is_empty() {
+                return None;
+            }
+            if let Ok(int) = trimmed.parse::<i64>() {
+                if int != 0 && int != 200 {
+                    return Some(int.to_string());
+                }
+                return None;
+            }
+            let lowered = trimmed.to_ascii_lowercase();
+            if lowered == "ok"
+                || lowered == "success"
+                || lowered == "succeeded"
+                || lowered == "true"
+                || lowered == "0"
+                || lowered == "200"
+            {
+                None
+            } else if lowered.contains("error")
+                || lowered.contains("fail")
+                || lowered.contains("invalid")
+                || lowered.contains("denied")
+                || lowered.contains("unauthorized")
+            {
+                Some(trimmed.to_string())
+            } else {
+                None
+            }
+        }
+        serde_json::Value::Bool(b) => {
+            if *b {
+                None
+            } else {
+                Some("false".to_string())
+            }
+        }
+        _ => None,
+    }
+}
+
+fn extract_message_fields(value: &serde_json::Value, fields: &[&str]) -> Option<String> {
+    for key in fields {
+        if let Some(inner) = value.get(*key) {
+            if let Some(text) = json_value_to_string(inner) {
+                let trimmed = text.trim();
+                if !trimmed.is_empty() {
+                    return Some(trimmed.to_string());
+                }
+            }
+        }
+    }
+    None
+}
+
+fn json_value_to_string(value: &serde_json::Value) -> Option<String> {
+    match value {
+        serde_json::Value::Null => None,
+        serde_json::Value::String(s) => Some(s.clone()),
+        serde_json::Value::Number(n) => Some(n.to_string()),
+        serde_json::Value::Bool(b) => Some(b.to_string()),
+        serde_json::Value::Array(items) => {
+            let mut parts = Vec::new();
+            for item in items {
+                if let Some(text) = json_value_to_string(item) {
+                    let trimmed = text.trim();
+                    if !trimmed.is_empty() {
+                        parts.push(trimmed.to_string());
+                    }
+                }
+            }
+            if parts.is_empty() {
+                None
+            } else {
+                Some(parts.join(" "))
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for key in &[
+                "message",
+                "msg",
+                "error_message",
+                "error_msg",
+                "detail",
+                "description",
+            ] {
+                if let Some(inner) = map.get(*key) {
+                    if let Some(text) = json_value_to_string(inner) {
+                        let trimmed = text.trim();
+                        if !trimmed.is_empty() {
+                            return Some(trimmed.to_string());
+                        }
+                    }
+                }
+            }
+            Some(value.to_string())
+        }
+    }
+}
+
+pub fn parse_temperature_from_response(response: &str) -> f32 {
+    if let Some(value) = parse_temperature_from_json(response) {
+        return clamp_temperature(value);
+    }
+
+    for line in response.lines() {
+        if line.to_lowercase().contains("temperature") {
+            if let Some(value_part) = line.split_once(':').map(|x| x.1) {
+                if let Some(value) = parse_numeric_fragment(value_part) {
+                    return clamp_temperature(value);
+                }
+            }
+
+            if let Some(value) = parse_numeric_fragment(line) {
+                return clamp_temperature(value);
+            }
+        }
+    }
+
+    1.4
+}
+
+fn parse_temperature_from_json(response: &str) -> Option<f32> {
+    let json_value = serde_json::from_str::<serde_json::Value>(response).ok()?;
+    extract_temperature_from_json(&json_value)
+}
+
+fn extract_temperature_from_json(value: &serde_json::Value) -> Option<f32> {
+    match value {
+        serde_json::Value::Object(map) => {
+            if let Some(temp_value) = map.get("temperature") {
+                return match temp_value {
+                    serde_json::Value::Number(n) => n.as_f64().map(|v| v as f32),
+                    serde_json::Value::String(s) => parse_numeric_fragment(s),
+                    _ => None,
+                };
+            }
+
+            map.values().find_map(extract_temperature_from_json)
+        }
+        serde_json::Value::Array(items) => items.iter().find_map(extract_temperature_from_json),
+        _ => None,
+    }
+}
+
+fn parse_numeric_fragment(input: &str) -> Option<f32> {
+    let trimmed = input.trim().trim_matches(|c| c == '"' || c == '\'');
+    if let Ok(value) = trimmed.parse::<f32>() {
+        return Some(value);
+    }
+
+    trimmed
+        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .find(|segment| !segment.is_empty())
+        .and_then(|segment| segment.parse::<f32>().ok())
+}
+
+fn clamp_temperature(value: f32) -> f32 {
+    value.clamp(0.0, 2.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transport::{Scripted, ScriptedTransport};
+
+    const NO_RETRY: RetryPolicy = RetryPolicy {
+        max_attempts: 1,
+        base_backoff: Duration::ZERO,
+    };
+
+    fn test_client(endpoint: Endpoint, api_key: &str) -> LLMClient {
+        LLMClient::new(
+            endpoint,
+            api_key.to_string(),
+            Duration::from_secs(5),
+            NO_RETRY,
+            &ProxySetting::Direct,
+            &PoolSettings::default(),
+            &TlsSettings::default(),
+            &[],
+        )
+        .unwrap()
+    }
+
+    // 在随机端口上启动 app；返回 http://地址 和指向其 /v1 的 OpenAI 格式 client
+    async fn spawn_upstream(app: axum::Router) -> (String, LLMClient) {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let base = format!("http://{}", addr);
+        let client = test_client(
+            Endpoint::new(format!("{}/v1", base), ApiFormat::Openai),
+            "k",
+        );
+        (base, client)
+    }
+
+    #[test]
+    fn parses_temperature_from_json_string_value() {
+        let response = r#"{"temperature":"0.65","reasoning":"ok"}"#;
+        let value = parse_temperature_from_response(response);
+        assert!((value - 0.65).abs() < 1e-6);
+    }
+
+    #[test]
+    fn parses_temperature_from_text_fragment() {
+        let response = "Temperature: \"0.42\", reasoning: details";
+        let value = parse_temperature_from_response(response);
+        assert!((value - 0.42).abs() < 1e-6);
+    }
+
+    #[test]
+    fn clamps_out_of_range_values() {
+        let response = r#"{"temperature": 3.5}"#;
+        assert_eq!(parse_temperature_from_response(response), 2.0);
+    }
+
+    #[test]
+    fn detects_status_based_error_message() {
+        let value = serde_json::json!({
+            "status": "434",
+            "msg": "Invalid apiKey"
+        });
+        let err = detect_provider_error(&value).expect("expected error");
+        assert!(err.contains("434"));
+        assert!(err.to_ascii_lowercase().contains("invalid"));
+    }
+
+    #[test]
+    fn detects_error_object_code() {
+        let value = serde_json::json!({
+            "error": {
+                "code": "invalid_api_key",
+                "message": "No API key provided"
+            }
+        });
+        let err = detect_provider_error(&value).expect("expected error");
+        assert!(err.to_ascii_lowercase().contains("invalid_api_key"));
+        assert!(err.contains("No API key provided"));
+    }
+
+    #[test]
+    fn detects_success_flag_false_message() {
+        let value = serde_json::json!({
+            "success": false,
+            "message": "Request failed"
+        });
+        let err = detect_provider_error(&value).expect("expected error");
+        assert!(err.contains("Request failed"));
+    }
+
+    #[test]
+    fn ignores_successful_status_values() {
+        let value = serde_json::json!({
+            "status": 0,
+            "msg": "ok"
+        });
+        assert!(detect_provider_error(&value).is_none());
+    }
+
+    #[test]
+    fn request_body_includes_only_set_generation_params() {
+        let params = GenerationParams {
+            max_tokens: Some(2048),
+            top_p: Some(0.9),
+            ..Default::default()
+        };
+        let body = build_request_body("m1", &[], Some(0.7), &params, false);
+        assert_eq!(body["max_tokens"], 2048);
+        assert!((body["top_p"].as_f64().unwrap() - 0.9).abs() < 1e-6);
+        assert!(body.get("top_k").is_none());
+        assert!(body.get("frequency_penalty").is_none());
+
+        let body = build_request_body("m1", &[], None, &GenerationParams::default(), true);
+        assert_eq!(
+            body.as_object().unwrap().len(),
+            4,
+            "no generation params should be added when none are set"
+        );
+    }
+
+    #[test]
+    fn extra_body_never_overrides_request_fields() {
+        let params = GenerationParams {
+            max_tokens: Some(64),
+            extra_body: json!({"max_tokens": 9999, "model": "other", "enable_thinking": false})
+                .as_object()
+                .cloned()
+                .unwrap(),
+            ..Default::default()
+        };
+        let mut body = build_request_body("m1", &[], Some(0.7), &params, false);
+        params.merge_extra_body(&mut body);
+        assert_eq!(body["max_tokens"], 64);
+        assert_eq!(body["model"], "m1");
+        assert_eq!(body["enable_thinking"], false);
+
+        // 客户端请求里的同名字段不会被当作 extra_body 读进来
+        let request: GenerationParams =
+            serde_json::from_value(json!({"extra_body": {"stream": true}})).unwrap();
+        assert!(request.extra_body.is_empty());
+    }
+
+    #[test]
+    fn generation_params_merge_field_by_field() {
+        let request = GenerationParams {
+            max_tokens: Some(100),
+            ..Default::default()
+        };
+        let node = GenerationParams {
+            max_tokens: Some(500),
+            top_p: Some(0.5),
+            ..Default::default()
+        };
+        let model = GenerationParams {
+            top_p: Some(0.9),
+            frequency_penalty: Some(0.3),
+            ..Default::default()
+        };
+        let merged = request.or(&node.or(&model));
+        assert_eq!(merged.max_tokens, Some(100));
+        assert_eq!(merged.top_p, Some(0.5));
+        assert_eq!(merged.frequency_penalty, Some(0.3));
+        assert_eq!(merged.top_k, None);
+    }
+
+    #[test]
+    fn client_builds_with_each_proxy_setting() {
+        for proxy in [
+            ProxySetting::System,
+            ProxySetting::Direct,
+            ProxySetting::Url("http://proxy.internal:3128".to_string()),
+            ProxySetting::Url("socks5h://127.0.0.1:1080".to_string()),
+        ] {
+            LLMClient::new(
+                Endpoint::new("https://api.example.com/v1".to_string(), ApiFormat::Openai),
+                "k".to_string(),
+                Duration::from_secs(2),
+                NO_RETRY,
+                &proxy,
+                &PoolSettings::default(),
+                &TlsSettings::default(),
+                &[],
+            )
+            .unwrap_or_else(|err| panic!("{:?} should build: {}", proxy, err));
+        }
+    }
+
+    #[tokio::test]
+    async fn slow_responses_report_the_request_timeout() {
+        // 接受连接但从不回复：连接很快建立，触发的是总时限
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut held = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                held.push(socket);
+            }
+        });
+
+        let client = test_client(
+            Endpoint::new(format!("http://{}/v1", addr), ApiFormat::Openai),
+            "k",
+        );
+        let err = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(1),
+            )
+            .await
+            .unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("request timeout (1s) elapsed waiting for http://"),
+            "{}",
+            message
+        );
+        assert!(err.downcast_ref::<TransportError>().unwrap().is_timeout());
+    }
+
+    // 按顺序返回给定的状态码（带上可选的 Retry-After），之后一律成功；返回地址和请求计数
+    async fn spawn_flaky_upstream(
+        failures: Vec<u16>,
+        retry_after: Option<&'static str>,
+    ) -> (String, std::sync::Arc<std::sync::atomic::AtomicUsize>) {
+        use axum::{
+            http::{header, StatusCode},
+            response::IntoResponse,
+            routing::post,
+            Json, Router,
+        };
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let calls = Arc::new(AtomicUsize::new(0));
+        let counter = calls.clone();
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move || {
+                let call = counter.fetch_add(1, Ordering::SeqCst);
+                let failure = failures.get(call).copied();
+                async move {
+                    match failure {
+                        Some(code) => {
+                            let mut response = (
+                                StatusCode::from_u16(code).unwrap(),
+                                Json(json!({"error": {"message": "upstream busy"}})),
+                            )
+                                .into_response();
+                            if let Some(value) = retry_after {
+                                response
+                                    .headers_mut()
+                                    .insert(header::RETRY_AFTER, value.parse().unwrap());
+                            }
+                            response
+                        }
+                        None => Json(json!({"choices": [{"message": {"content": "ok"}}]}))
+                            .into_response(),
+                    }
+                }
+            }),
+        );
+        let (base, _) = spawn_upstream(app).await;
+        (format!("{}/v1", base), calls)
+    }
+
+    // 模拟网关签名：对请求体算一个摘要放进请求头，并记下每次看到的请求与响应
+    #[derive(Default)]
+    struct SigningHook {
+        seen: std::sync::Mutex<Vec<String>>,
+        fail: bool,
+    }
+
+    impl RequestHook for SigningHook {
+        fn name(&self) -> &str {
+            "signer"
+        }
+
+        fn on_request(&self, model: &str, request: &mut OutboundRequest) -> Result<()> {
+            if self.fail {
+                anyhow::bail!("signing key for sk-secret is unavailable");
+            }
+            assert!(request.headers.get("authorization").is_none());
+            let digest: u32 = request.body.iter().map(|&b| b as u32).sum();
+            request
+                .headers
+                .insert("x-signature", HeaderValue::from(digest));
+            self.seen
+                .lock()
+                .unwrap()
+                .push(format!("request {} #{}", model, request.attempt));
+            Ok(())
+        }
+
+        fn on_response(&self, model: &str, response: &ResponseMeta<'_>) {
+            self.seen.lock().unwrap().push(format!(
+                "response {} #{} {:?}",
+                model, response.attempt, response.status
+            ));
+        }
+    }
+
+    #[tokio::test]
+    async fn hooks_run_for_every_attempt_and_can_fail_the_call() {
+        let (api_base, calls) = spawn_flaky_upstream(vec![503], None).await;
+        let hook = Arc::new(SigningHook::default());
+        let client = retrying_client(api_base.clone(), 2).with_hooks(vec![hook.clone()]);
+        let content = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+        assert_eq!(
+            *hook.seen.lock().unwrap(),
+            vec![
+                "request m1 #1",
+                "response m1 #1 Some(503)",
+                "request m1 #2",
+                "response m1 #2 Some(200)",
+            ]
+        );
+
+        // 钩子失败时请求不会发出，错误信息里的 key 被遮盖
+        let failing = Arc::new(SigningHook {
+            fail: true,
+            ..Default::default()
+        });
+        let client = retrying_client(api_base, 2)
+            .with_redactor(Arc::new(Redactor::new(["sk-secret"])))
+            .with_hooks(vec![failing]);
+        let err = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap_err();
+        let failed = err.downcast_ref::<RequestHookFailed>().unwrap();
+        assert_eq!(failed.hook, "signer");
+        assert!(!failed.message.contains("sk-secret"), "{}", failed.message);
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn injected_headers_go_out_alongside_the_auth_header() {
+        let headers = std::collections::BTreeMap::from([(
+            "X-Gateway-Tenant".to_string(),
+            "team-a".to_string(),
+        )]);
+        let client = retrying_client("https://api.example.com/v1".to_string(), 1).with_hooks(vec![
+            Arc::new(HeaderInjection::new(&headers).unwrap()),
+            Arc::new(SigningHook::default()),
+        ]);
+        let outbound = client
+            .outbound_request(
+                "m1",
+                "https://api.example.com/v1/chat/completions",
+                1,
+                b"{}",
+            )
+            .unwrap();
+        let request = client
+            .build_request(outbound, Duration::from_secs(5))
+            .unwrap();
+        assert_eq!(request.headers["x-gateway-tenant"], "team-a");
+        assert_eq!(request.headers["x-signature"], "248");
+        assert_eq!(request.headers["authorization"], "Bearer k");
+        assert_eq!(request.body, b"{}");
+
+        let broken =
+            std::collections::BTreeMap::from([("bad header".to_string(), "v".to_string())]);
+        assert!(HeaderInjection::new(&broken).is_err());
+    }
+
+    fn keyless_client(endpoint: Endpoint) -> LLMClient {
+        LLMClient::new(
+            endpoint,
+            String::new(),
+            Duration::from_secs(5),
+            RetryPolicy {
+                max_attempts: 1,
+                base_backoff: Duration::from_millis(10),
+            },
+            &ProxySetting::Direct,
+            &PoolSettings::default(),
+            &TlsSettings::default(),
+            &[],
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn keyless_models_send_no_credentials() {
+        for format in [
+            ApiFormat::Openai,
+            ApiFormat::Ollama,
+            ApiFormat::Azure,
+            ApiFormat::Anthropic,
+            ApiFormat::Gemini,
+        ] {
+            let client = keyless_client(Endpoint::new("http://127.0.0.1:8080/v1", format));
+            let url = "http://127.0.0.1:8080/v1/chat/completions";
+            let outbound = client.outbound_request("m1", url, 1, b"{}").unwrap();
+            let request = client
+                .build_request(outbound, Duration::from_secs(5))
+                .unwrap();
+            for name in ["authorization", "api-key", "x-api-key"] {
+                assert!(
+                    request.headers.get(name).is_none(),
+                    "{:?} sent {}",
+                    format,
+                    name
+                );
+            }
+            assert_eq!(request.url, url, "{:?}", format);
+        }
+    }
+
+    #[tokio::test]
+    async fn keyless_calls_reach_local_servers_without_authorization() {
+        use axum::http::HeaderMap;
+        use axum::{routing::post, Json, Router};
+
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|headers: HeaderMap| async move {
+                let content = if headers.contains_key("authorization") {
+                    "unexpected authorization header"
+                } else {
+                    "ok"
+                };
+                Json(json!({"choices": [{"message": {"content": content}}]}))
+            }),
+        );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = keyless_client(Endpoint::new(format!("{}/v1", base), ApiFormat::Openai));
+        let content = client
+            .chat_completion(
+                "local-model",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+    }
+
+    #[tokio::test]
+    async fn static_resolve_routes_fake_host_names_to_the_given_address() {
+        let (api_base, calls) = spawn_flaky_upstream(Vec::new(), None).await;
+        let port = url::Url::parse(&api_base).unwrap().port().unwrap();
+        let client = LLMClient::new(
+            Endpoint::new(
+                format!("http://upstream.chorus.test:{}/v1", port),
+                ApiFormat::Openai,
+            ),
+            "k".to_string(),
+            Duration::from_secs(5),
+            RetryPolicy {
+                max_attempts: 1,
+                base_backoff: Duration::from_millis(10),
+            },
+            &ProxySetting::Direct,
+            &PoolSettings::default(),
+            &TlsSettings::default(),
+            // 端口以 URL 为准，这里写的端口不起作用
+            &[(
+                "upstream.chorus.test".to_string(),
+                "127.0.0.1:1".parse().unwrap(),
+            )],
+        )
+        .unwrap();
+        let content = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+    }
+
+    fn retrying_client(api_base: String, max_attempts: u32) -> LLMClient {
+        LLMClient::new(
+            Endpoint::new(api_base, ApiFormat::Openai),
+            "k".to_string(),
+            Duration::from_secs(5),
+            RetryPolicy {
+                max_attempts,
+                base_backoff: Duration::from_millis(10),
+            },
+            &ProxySetting::Direct,
+            &PoolSettings::default(),
+            &TlsSettings::default(),
+            &[],
+        )
+        .unwrap()
+    }
+
+    // 依次返回 replies 里的 (内容, finish_reason)，用完后返回 500；记录每次的请求体
+    async fn spawn_truncating_upstream(
+        replies: Vec<(&'static str, &'static str)>,
+    ) -> (
+        String,
+        std::sync::Arc<std::sync::Mutex<Vec<serde_json::Value>>>,
+    ) {
+        use axum::{http::StatusCode, response::IntoResponse, routing::post, Json, Router};
+        use std::sync::{Arc, Mutex};
+
+        let bodies = Arc::new(Mutex::new(Vec::new()));
+        let seen = bodies.clone();
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move |Json(body): Json<serde_json::Value>| {
+                let mut seen = seen.lock().unwrap();
+                let reply = replies.get(seen.len()).copied();
+                seen.push(body);
+                async move {
+                    match reply {
+                        Some((content, finish_reason)) => Json(json!({
+                            "choices": [{
+                                "message": {"content": content},
+                                "finish_reason": finish_reason,
+                            }],
+                            "usage": {"prompt_tokens": 10, "completion_tokens": 4, "total_tokens": 14},
+                        }))
+                        .into_response(),
+                        None => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+                    }
+                }
+            }),
+        );
+        let (base, _) = spawn_upstream(app).await;
+        (format!("{}/v1", base), bodies)
+    }
+
+    #[tokio::test]
+    async fn truncated_completions_are_continued_and_stitched() {
+        let question = vec![ChatMessage {
+            role: "user".to_string(),
+            content: "say hello".to_string(),
+        }];
+        let replies = vec![("Hello, ", "length"), ("wor", "length"), ("ld.", "stop")];
+
+        let (api_base, bodies) = spawn_truncating_upstream(replies.clone()).await;
+        let result = retrying_client(api_base, 1)
+            .with_max_continuations(5)
+            .chat_completion_with_stream(
+                "m1",
+                question.clone(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello, world.");
+        assert_eq!(result.continuations, 2);
+        assert_eq!(result.finish_reason.as_deref(), Some("stop"));
+        let usage = result.usage.unwrap();
+        assert_eq!(usage.completion_tokens, Some(12));
+        assert_eq!(usage.total_tokens, Some(42));
+
+        // 续写请求带上原问题、已生成的部分和续写指令
+        let messages = bodies.lock().unwrap()[2]["messages"].clone();
+        let messages = messages.as_array().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[0]["content"], "say hello");
+        assert_eq!(messages[1]["role"], "assistant");
+        assert_eq!(messages[1]["content"], "Hello, wor");
+        assert_eq!(messages[2]["content"], CONTINUE_PROMPT);
+        assert_eq!(bodies.lock().unwrap().len(), 3);
+
+        // 达到上限后停下，返回的仍是截断状态
+        let (api_base, bodies) = spawn_truncating_upstream(replies.clone()).await;
+        let result = retrying_client(api_base, 1)
+            .with_max_continuations(1)
+            .chat_completion_with_stream(
+                "m1",
+                question.clone(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello, wor");
+        assert_eq!(result.continuations, 1);
+        assert_eq!(result.finish_reason.as_deref(), Some("length"));
+        assert_eq!(bodies.lock().unwrap().len(), 2);
+
+        // 续写失败时保留已经拿到的内容
+        let (api_base, _) = spawn_truncating_upstream(replies[..1].to_vec()).await;
+        let result = retrying_client(api_base, 1)
+            .with_max_continuations(3)
+            .chat_completion_with_stream(
+                "m1",
+                question,
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello, ");
+        assert_eq!(result.continuations, 0);
+        assert_eq!(result.finish_reason.as_deref(), Some("length"));
+    }
+
+    #[tokio::test]
+    async fn transient_failures_are_retried_until_success() {
+        let (api_base, calls) = spawn_flaky_upstream(vec![502, 503], None).await;
+        let client = retrying_client(api_base, 3);
+        let content = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn validation_errors_are_not_retried_and_attempts_are_reported() {
+        let (api_base, calls) = spawn_flaky_upstream(vec![400], None).await;
+        let client = retrying_client(api_base, 3);
+        let err = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        assert_eq!(err.downcast_ref::<LlmHttpError>().unwrap().status, 400);
+
+        let (api_base, calls) = spawn_flaky_upstream(vec![429, 500, 500], None).await;
+        let client = retrying_client(api_base, 2);
+        let err = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("giving up after 2 attempts: "),
+            "{}",
+            message
+        );
+        assert_eq!(err.downcast_ref::<LlmHttpError>().unwrap().status, 500);
+    }
+
+    #[test]
+    fn backoff_grows_exponentially_with_jitter() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_backoff: Duration::from_millis(100),
+        };
+        for (attempt, full) in [(1, 100), (2, 200), (3, 400)] {
+            let delay = policy.backoff(attempt).as_millis();
+            assert!(
+                (full / 2..=full).contains(&delay),
+                "attempt {}: {}",
+                attempt,
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn retry_after_accepts_seconds_and_http_dates() {
+        assert_eq!(parse_retry_after(" 7 "), Some(Duration::from_secs(7)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT"),
+            Some(Duration::ZERO)
+        );
+        let future = (chrono::Utc::now() + chrono::Duration::seconds(30)).to_rfc2822();
+        let parsed = parse_retry_after(&future).unwrap();
+        assert!((28..=30).contains(&parsed.as_secs()), "{:?}", parsed);
+        assert_eq!(parse_retry_after("soon"), None);
+    }
+
+    #[tokio::test]
+    async fn retry_after_is_honored_when_it_fits_the_budget() {
+        let (api_base, calls) = spawn_flaky_upstream(vec![429], Some("1")).await;
+        let client = retrying_client(api_base, 3);
+        let started = Instant::now();
+        let content = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "ok");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 2);
+        assert!(started.elapsed() >= Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn retry_after_beyond_the_budget_gives_up_as_rate_limited() {
+        let (api_base, calls) = spawn_flaky_upstream(vec![429], Some("60")).await;
+        let client = retrying_client(api_base, 3);
+        let err = client
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 1);
+        let limited = err.downcast_ref::<UpstreamRateLimited>().unwrap();
+        assert_eq!(limited.retry_after, Duration::from_secs(60));
+        assert!(err.to_string().starts_with("rate_limited: "), "{}", err);
+        assert_eq!(
+            err.downcast_ref::<LlmHttpError>().unwrap().retry_after,
+            Some(Duration::from_secs(60))
+        );
+    }
+
+    #[test]
+    fn request_timeout_comes_from_the_caller() {
+        // client 上不再有固定总时限，150 秒的阶段超时不会在 120 秒处被截断
+        let client = test_client(
+            Endpoint::new("https://api.example.com/v1".to_string(), ApiFormat::Openai),
+            "k",
+        );
+        let outbound = client
+            .outbound_request(
+                "m1",
+                "https://api.example.com/v1/chat/completions",
+                1,
+                b"{}",
+            )
+            .unwrap();
+        let request = client
+            .build_request(outbound, Duration::from_secs(150))
+            .unwrap();
+        assert_eq!(request.timeout, Duration::from_secs(150));
+    }
+
+    #[test]
+    fn ollama_body_moves_sampling_params_into_options() {
+        let params = GenerationParams {
+            max_tokens: Some(256),
+            top_k: Some(40),
+            ..Default::default()
+        };
+        let body = build_ollama_body("llama3", &[], Some(0.5), &params, true);
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["options"]["num_predict"], 256);
+        assert_eq!(body["options"]["top_k"], 40);
+        assert!((body["options"]["temperature"].as_f64().unwrap() - 0.5).abs() < 1e-6);
+        assert!(body.get("max_tokens").is_none());
+
+        let body = build_ollama_body("llama3", &[], None, &GenerationParams::default(), false);
+        assert!(body.get("options").is_none());
+    }
+
+    #[tokio::test]
+    async fn ollama_format_uses_api_chat_and_reads_ndjson_streams() {
+        use axum::{routing::post, Json, Router};
+
+        let app = Router::new().route(
+            "/api/chat",
+            post(|Json(body): Json<serde_json::Value>| async move {
+                if body["stream"] == true {
+                    let lines = [
+                        json!({"message": {"role": "assistant", "content": "Hel"}, "done": false}),
+                        json!({"message": {"role": "assistant", "content": "lo"}, "done": false}),
+                        json!({"message": {"role": "assistant", "content": ""}, "done": true}),
+                    ];
+                    let body: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+                    ([("content-type", "application/x-ndjson")], body)
+                } else {
+                    let body = json!({
+                        "model": body["model"],
+                        "message": {"role": "assistant", "content": "Hello"},
+                        "done": true,
+                    });
+                    ([("content-type", "application/json")], body.to_string())
+                }
+            }),
+        );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = test_client(Endpoint::new(base, ApiFormat::Ollama), "");
+        let content = client
+            .chat_completion(
+                "llama3",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "Hello");
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let result = client
+            .chat_completion_with_stream(
+                "llama3",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                Some(sender),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello");
+        assert!(result.streamed);
+        let mut pieces = Vec::new();
+        while let Ok(piece) = receiver.try_recv() {
+            pieces.push(piece);
+        }
+        assert_eq!(pieces, ["Hel", "lo"]);
+    }
+
+    #[test]
+    fn provider_error_envelopes_are_parsed() {
+        // OpenAI
+        let openai = ProviderError::parse(
+            400,
+            r#"{"error":{"message":"This model's maximum context length is 8192 tokens.","type":"invalid_request_error","param":"messages","code":"context_length_exceeded"}}"#,
+            None,
+        );
+        assert_eq!(openai.code.as_deref(), Some("context_length_exceeded"));
+        assert_eq!(openai.kind.as_deref(), Some("invalid_request_error"));
+        assert!(openai.is_context_length_exceeded());
+
+        // Anthropic
+        let anthropic = ProviderError::parse(
+            429,
+            r#"{"type":"error","error":{"type":"rate_limit_error","message":"Number of request tokens has exceeded your rate limit."}}"#,
+            Some(Duration::from_millis(1_500)),
+        );
+        assert_eq!(
+            anthropic,
+            ProviderError {
+                status: 429,
+                code: None,
+                message: "Number of request tokens has exceeded your rate limit.".to_string(),
+                kind: Some("rate_limit_error".to_string()),
+                retry_after_secs: Some(2),
+            }
+        );
+        let too_long = ProviderError::parse(
+            400,
+            r#"{"type":"error","error":{"type":"invalid_request_error","message":"prompt is too long: 210000 tokens > 200000 maximum"}}"#,
+            None,
+        );
+        assert!(too_long.is_context_length_exceeded());
+
+        // Ollama
+        let ollama = ProviderError::parse(404, r#"{"error":"model 'llama9' not found"}"#, None);
+        assert_eq!(ollama.message, "model 'llama9' not found");
+        assert_eq!((ollama.code, ollama.kind), (None, None));
+
+        // Gemini 兼容接口：code 是数字
+        let gemini = ProviderError::parse(
+            401,
+            r#"{"error":{"code":401,"message":"API key not valid.","status":"UNAUTHENTICATED"}}"#,
+            None,
+        );
+        assert_eq!(gemini.code.as_deref(), Some("401"));
+        assert_eq!(gemini.kind.as_deref(), Some("UNAUTHENTICATED"));
+        assert!(gemini.is_auth_failure());
+
+        // 识别不了时保留原始响应体
+        let html = ProviderError::parse(502, " <html>Bad Gateway</html>\n", None);
+        assert_eq!(html.message, "<html>Bad Gateway</html>");
+        assert!(!html.is_context_length_exceeded());
+    }
+
+    #[test]
+    fn chat_path_replaces_the_default_path_with_one_slash() {
+        let endpoint = |base: &str, path: Option<&str>| Endpoint {
+            chat_path: path.map(str::to_string),
+            ..Endpoint::new(base, ApiFormat::Openai)
+        };
+        assert_eq!(
+            endpoint("https://gw.example.com/v1/", None).chat_url("m1"),
+            "https://gw.example.com/v1/chat/completions"
+        );
+        for (base, path) in [
+            ("https://gw.example.com", "/api/v1/chat/completions"),
+            ("https://gw.example.com/", "api/v1/chat/completions"),
+            ("https://gw.example.com//", "//api/v1/chat/completions"),
+        ] {
+            assert_eq!(
+                endpoint(base, Some(path)).chat_url("m1"),
+                "https://gw.example.com/api/v1/chat/completions"
+            );
+        }
+
+        let azure = Endpoint {
+            api_version: Some("2024-06-01".to_string()),
+            chat_path: Some("/custom/chat".to_string()),
+            ..Endpoint::new("https://az.example.com", ApiFormat::Azure)
+        };
+        assert_eq!(
+            azure.chat_url("gpt-4o"),
+            "https://az.example.com/custom/chat?api-version=2024-06-01"
+        );
     }
-}
 
-fn extract_message_fields(value: &serde_json::Value, fields: &[&str]) -> Option<String> {
-    for key in fields {
-        if let Some(inner) = value.get(*key) {
-            if let Some(text) = json_value_to_string(inner) {
-                let trimmed = text.trim();
-                if !trimmed.is_empty() {
-                    return Some(trimmed.to_string());
-                }
-            }
-        }
+    #[test]
+    fn anthropic_body_lifts_system_and_drops_unsupported_params() {
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: "Be brief".to_string(),
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: "hi".to_string(),
+            },
+        ];
+        let params = GenerationParams {
+            top_k: Some(40),
+            frequency_penalty: Some(0.5),
+            ..Default::default()
+        };
+        let body = build_anthropic_body("claude-sonnet-4-5", &messages, Some(1.4), &params);
+        assert_eq!(
+            body,
+            json!({
+                "model": "claude-sonnet-4-5",
+                "system": "Be brief",
+                "messages": [{"role": "user", "content": "hi"}],
+                "max_tokens": ANTHROPIC_DEFAULT_MAX_TOKENS,
+                "temperature": 1.0,
+                "top_k": 40,
+            })
+        );
     }
-    None
-}
 
-fn json_value_to_string(value: &serde_json::Value) -> Option<String> {
-    match value {
-        serde_json::Value::Null => None,
-        serde_json::Value::String(s) => Some(s.clone()),
-        serde_json::Value::Number(n) => Some(n.to_string()),
-        serde_json::Value::Bool(b) => Some(b.to_string()),
-        serde_json::Value::Array(items) => {
-            let mut parts = Vec::new();
-            for item in items {
-                if let Some(text) = json_value_to_string(item) {
-                    let trimmed = text.trim();
-                    if !trimmed.is_empty() {
-                        parts.push(trimmed.to_string());
-                    }
-                }
-            }
-            if parts.is_empty() {
-                None
-            } else {
-                Some(parts.join(" "))
-            }
-        }
-        serde_json::Value::Object(map) => {
-            for key in &[
-                "message",
-                "msg",
-                "error_message",
-                "error_msg",
-                "detail",
-                "description",
-            ] {
-                if let Some(inner) = map.get(*key) {
-                    if let Some(text) = json_value_to_string(inner) {
-                        let trimmed = text.trim();
-                        if !trimmed.is_empty() {
-                            return Some(trimmed.to_string());
-                        }
-                    }
-                }
-            }
-            Some(value.to_string())
-        }
+    #[tokio::test]
+    async fn anthropic_format_uses_messages_api_headers_and_content_blocks() {
+        use axum::http::HeaderMap;
+        use axum::{routing::post, Json, Router};
+
+        let app = Router::new().route(
+            "/v1/messages",
+            post(
+                |headers: HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                    assert_eq!(headers["x-api-key"], "ant-key");
+                    assert_eq!(headers["anthropic-version"], ANTHROPIC_VERSION);
+                    assert!(headers.get("authorization").is_none());
+                    assert_eq!(body["max_tokens"], 256);
+                    assert!(body.get("stream").is_none());
+                    (
+                        [("request-id", "req_011")],
+                        Json(json!({
+                            "id": "msg_01",
+                            "type": "message",
+                            "role": "assistant",
+                            "model": "claude-sonnet-4-5-20250929",
+                            "content": [
+                                {"type": "thinking", "thinking": "hmm", "signature": "sig"},
+                                {"type": "text", "text": "Hello"},
+                                {"type": "text", "text": ", world"},
+                            ],
+                            "stop_reason": "end_turn",
+                            "usage": {"input_tokens": 12, "output_tokens": 4},
+                        })),
+                    )
+                },
+            ),
+        );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = test_client(
+            Endpoint::new(format!("{}/v1", base), ApiFormat::Anthropic),
+            "ant-key",
+        );
+        let params = GenerationParams {
+            max_tokens: Some(256),
+            ..Default::default()
+        };
+        // 请求流式时整段转发一次，并标记为已流式输出
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let result = client
+            .chat_completion_with_stream(
+                "claude-sonnet-4-5",
+                Vec::new(),
+                Some(0.7),
+                &params,
+                Duration::from_secs(10),
+                Some(sender),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello, world");
+        assert!(result.streamed);
+        assert_eq!(receiver.try_recv().unwrap(), "Hello, world");
+        assert!(receiver.try_recv().is_err());
+        assert_eq!(result.finish_reason.as_deref(), Some("end_turn"));
+        assert_eq!(result.model.as_deref(), Some("claude-sonnet-4-5-20250929"));
+        assert_eq!(result.provider_request_ids.primary(), Some("req_011"));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                prompt_tokens: Some(12),
+                completion_tokens: Some(4),
+                total_tokens: Some(16),
+            })
+        );
     }
-}
 
-pub fn parse_temperature_from_response(response: &str) -> f32 {
-    if let Some(value) = parse_temperature_from_json(response) {
-        return clamp_temperature(value);
+    // 按 Gemini generateContent 的实际响应整理（responseId 等字段已缩短）
+    const GEMINI_RESPONSE: &str = r#"{
+  "candidates": [
+    {
+      "content": {
+        "parts": [
+          {"text": "**Considering the greeting**", "thought": true},
+          {"text": "Hello"},
+          {"text": ", world"}
+        ],
+        "role": "model"
+      },
+      "finishReason": "STOP",
+      "index": 0
     }
+  ],
+  "usageMetadata": {
+    "promptTokenCount": 8,
+    "candidatesTokenCount": 3,
+    "totalTokenCount": 27,
+    "thoughtsTokenCount": 16
+  },
+  "modelVersion": "gemini-2.5-flash",
+  "responseId": "mT1Aab"
+}"#;
 
-    for line in response.lines() {
-        if line.to_lowercase().contains("temperature") {
-            if let Some(value_part) = line.split_once(':').map(|x| x.1) {
-                if let Some(value) = parse_numeric_fragment(value_part) {
-                    return clamp_temperature(value);
-                }
-            }
+    const GEMINI_BLOCKED_PROMPT: &str = r#"{
+  "promptFeedback": {
+    "blockReason": "SAFETY",
+    "safetyRatings": [
+      {"category": "HARM_CATEGORY_DANGEROUS_CONTENT", "probability": "HIGH", "blocked": true}
+    ]
+  },
+  "usageMetadata": {"promptTokenCount": 9, "totalTokenCount": 9},
+  "modelVersion": "gemini-2.5-flash"
+}"#;
 
-            if let Some(value) = parse_numeric_fragment(line) {
-                return clamp_temperature(value);
-            }
-        }
+    const GEMINI_BLOCKED_RESPONSE: &str = r#"{
+  "candidates": [
+    {
+      "finishReason": "SAFETY",
+      "index": 0,
+      "safetyRatings": [
+        {"category": "HARM_CATEGORY_HARASSMENT", "probability": "MEDIUM", "blocked": true}
+      ]
     }
+  ],
+  "usageMetadata": {"promptTokenCount": 11, "totalTokenCount": 11},
+  "modelVersion": "gemini-2.5-flash"
+}"#;
 
-    1.4
-}
+    #[test]
+    fn gemini_body_maps_system_instruction_roles_and_generation_config() {
+        let messages = vec![
+            ChatMessage {
+                role: "system".to_string(),
+                content: "Be brief".to_string(),
+            },
+            ChatMessage {
+                role: "user".to_string(),
+                content: "hi".to_string(),
+            },
+            ChatMessage {
+                role: "assistant".to_string(),
+                content: "hello".to_string(),
+            },
+        ];
+        let params = GenerationParams {
+            max_tokens: Some(64),
+            top_k: Some(40),
+            ..Default::default()
+        };
+        assert_eq!(
+            build_gemini_body(&messages, Some(0.5), &params),
+            json!({
+                "system_instruction": {"parts": [{"text": "Be brief"}]},
+                "contents": [
+                    {"role": "user", "parts": [{"text": "hi"}]},
+                    {"role": "model", "parts": [{"text": "hello"}]},
+                ],
+                "generationConfig": {"temperature": 0.5, "maxOutputTokens": 64, "topK": 40},
+            })
+        );
+    }
 
-fn parse_temperature_from_json(response: &str) -> Option<f32> {
-    let json_value = serde_json::from_str::<serde_json::Value>(response).ok()?;
-    extract_temperature_from_json(&json_value)
-}
+    async fn gemini_client(fixture: &'static str) -> LLMClient {
+        use axum::extract::{Path, Query};
+        use axum::{routing::post, Router};
+        use std::collections::HashMap;
 
-fn extract_temperature_from_json(value: &serde_json::Value) -> Option<f32> {
-    match value {
-        serde_json::Value::Object(map) => {
-            if let Some(temp_value) = map.get("temperature") {
-                return match temp_value {
-                    serde_json::Value::Number(n) => n.as_f64().map(|v| v as f32),
-                    serde_json::Value::String(s) => parse_numeric_fragment(s),
-                    _ => None,
-                };
-            }
+        let app =
+            Router::new().route(
+                "/v1beta/models/:action",
+                post(
+                    move |Path(action): Path<String>,
+                          Query(query): Query<HashMap<String, String>>| async move {
+                        assert_eq!(action, "gemini-2.5-flash:generateContent");
+                        assert_eq!(query["key"], "AIzaSecretKey");
+                        ([("content-type", "application/json")], fixture)
+                    },
+                ),
+            );
+        let (base, _) = spawn_upstream(app).await;
 
-            map.values().find_map(extract_temperature_from_json)
+        test_client(
+            Endpoint::new(format!("{}/v1beta", base), ApiFormat::Gemini),
+            "AIzaSecretKey",
+        )
+    }
+
+    async fn gemini_completion(client: &LLMClient) -> Result<CompletionResult> {
+        client
+            .chat_completion_with_stream(
+                "gemini-2.5-flash",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                None,
+            )
+            .await
+    }
+
+    #[tokio::test]
+    async fn gemini_fixture_responses_map_text_usage_and_blocks() {
+        let result = gemini_completion(&gemini_client(GEMINI_RESPONSE).await)
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello, world");
+        assert_eq!(result.finish_reason.as_deref(), Some("STOP"));
+        assert_eq!(result.model.as_deref(), Some("gemini-2.5-flash"));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                prompt_tokens: Some(8),
+                completion_tokens: Some(3),
+                total_tokens: Some(27),
+            })
+        );
+
+        for (fixture, target) in [
+            (GEMINI_BLOCKED_PROMPT, "prompt"),
+            (GEMINI_BLOCKED_RESPONSE, "response"),
+        ] {
+            let err = gemini_completion(&gemini_client(fixture).await)
+                .await
+                .unwrap_err();
+            let blocked = err.downcast_ref::<ContentBlocked>().unwrap();
+            assert_eq!(
+                (blocked.target, blocked.reason.as_str()),
+                (target, "SAFETY")
+            );
         }
-        serde_json::Value::Array(items) => items.iter().find_map(extract_temperature_from_json),
-        _ => None,
     }
-}
 
-fn parse_numeric_fragment(input: &str) -> Option<f32> {
-    let trimmed = input.trim().trim_matches(|c| c == '"' || c == '\'');
-    if let Ok(value) = trimmed.parse::<f32>() {
-        return Some(value);
+    #[tokio::test]
+    async fn gemini_key_stays_out_of_urls_and_transport_errors() {
+        let endpoint = Endpoint::new("http://127.0.0.1:1/v1beta", ApiFormat::Gemini);
+        assert_eq!(
+            endpoint.chat_url("gemini-2.5-flash"),
+            "http://127.0.0.1:1/v1beta/models/gemini-2.5-flash:generateContent"
+        );
+        let client = test_client(endpoint, "AIzaSecretKey");
+        let err = gemini_completion(&client).await.unwrap_err();
+        assert!(!format!("{:#}", err).contains("AIzaSecretKey"), "{:#}", err);
     }
 
-    trimmed
-        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
-        .find(|segment| !segment.is_empty())
-        .and_then(|segment| segment.parse::<f32>().ok())
-}
+    #[tokio::test]
+    async fn azure_format_routes_by_deployment_and_sends_api_key_header() {
+        use axum::extract::{Path, Query};
+        use axum::http::HeaderMap;
+        use axum::{routing::post, Json, Router};
+        use std::collections::HashMap;
 
-fn clamp_temperature(value: f32) -> f32 {
-    value.clamp(0.0, 2.0)
-}
+        let app = Router::new().route(
+            "/openai/deployments/:deployment/chat/completions",
+            post(
+                |Path(deployment): Path<String>,
+                 Query(query): Query<HashMap<String, String>>,
+                 headers: HeaderMap| async move {
+                    let seen = json!({
+                        "deployment": deployment,
+                        "api_version": query.get("api-version"),
+                        "api_key": headers.get("api-key").and_then(|v| v.to_str().ok()),
+                        "authorization": headers.contains_key("authorization"),
+                    });
+                    Json(json!({
+                        "choices": [{"message": {"content": seen.to_string()}, "finish_reason": "stop"}],
+                    }))
+                },
+            ),
+        );
+        let (base, _) = spawn_upstream(app).await;
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        let endpoint = Endpoint {
+            deployment: Some("gpt-4o prod".to_string()),
+            api_version: Some("2024-06-01".to_string()),
+            ..Endpoint::new(format!("{}/", base), ApiFormat::Azure)
+        };
+        assert_eq!(
+            endpoint.chat_url("gpt-4o"),
+            format!(
+                "{}/openai/deployments/gpt-4o%20prod/chat/completions?api-version=2024-06-01",
+                base
+            )
+        );
+        let client = test_client(endpoint, "azure-key");
+        let content = client
+            .chat_completion(
+                "gpt-4o",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        let seen: serde_json::Value = serde_json::from_str(&content).unwrap();
+        assert_eq!(
+            seen,
+            json!({
+                "deployment": "gpt-4o prod",
+                "api_version": "2024-06-01",
+                "api_key": "azure-key",
+                "authorization": false,
+            })
+        );
+    }
 
-    #[test]
-    fn parses_temperature_from_json_string_value() {
-        let response = r#"{"temperature":"0.65","reasoning":"ok"}"#;
-        let value = parse_temperature_from_response(response);
-        assert!((value - 0.65).abs() < 1e-6);
+    // 把同一段字节流按给定的位置切块喂给解析器
+    fn parse_in_chunks(raw: &[u8], cuts: &[usize]) -> Vec<String> {
+        let mut parser = SseParser::default();
+        let mut events = Vec::new();
+        let mut start = 0;
+        for &cut in cuts.iter().chain(std::iter::once(&raw.len())) {
+            events.extend(parser.push(&raw[start..cut]));
+            start = cut;
+        }
+        events.extend(parser.finish());
+        events
     }
 
     #[test]
-    fn parses_temperature_from_text_fragment() {
-        let response = "Temperature: \"0.42\", reasoning: details";
-        let value = parse_temperature_from_response(response);
-        assert!((value - 0.42).abs() < 1e-6);
+    fn sse_events_survive_every_chunk_boundary() {
+        let raw = "data: {\"a\":\"你好\"}\n\n: keep-alive\r\n\r\ndata: first\r\ndata: second\r\n\r\nevent: ping\ndata:x\n\ndata: [DONE]\n\n".as_bytes();
+        let expected = ["{\"a\":\"你好\"}", "first\nsecond", "x", "[DONE]"];
+
+        assert_eq!(parse_in_chunks(raw, &[]), expected);
+        // 每个字节单独成块，中文字符的 UTF-8 编码也会被拆开
+        let every_byte: Vec<usize> = (1..raw.len()).collect();
+        assert_eq!(parse_in_chunks(raw, &every_byte), expected);
+        for cut in 1..raw.len() {
+            assert_eq!(parse_in_chunks(raw, &[cut]), expected, "cut at {}", cut);
+        }
     }
 
     #[test]
-    fn clamps_out_of_range_values() {
-        let response = r#"{"temperature": 3.5}"#;
-        assert_eq!(parse_temperature_from_response(response), 2.0);
+    fn sse_trailing_event_without_blank_line_is_flushed() {
+        assert_eq!(
+            parse_in_chunks(b"data: a\n\ndata: b", &[3]),
+            ["a".to_string(), "b".to_string()]
+        );
+        assert!(parse_in_chunks(b"id: 1\nretry: 10\n\n", &[]).is_empty());
     }
 
     #[test]
-    fn detects_status_based_error_message() {
-        let value = serde_json::json!({
-            "status": "434",
-            "msg": "Invalid apiKey"
-        });
-        let err = detect_provider_error(&value).expect("expected error");
-        assert!(err.contains("434"));
-        assert!(err.to_ascii_lowercase().contains("invalid"));
+    fn sse_payloads_accumulate_until_finish_reason() {
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let mut result = CompletionResult::default();
+        let payloads = [
+            r#"{"choices":[{"delta":{"content":"Hel"}}]}"#,
+            r#"{"choices":[{"delta":{"content":"lo"},"finish_reason":null}]}"#,
+            r#"{"choices":[{"delta":{},"finish_reason":"stop"}]}"#,
+        ];
+        let finished: Vec<bool> = payloads
+            .iter()
+            .map(|payload| apply_sse_payload(payload, Some(&sender), &mut result).unwrap())
+            .collect();
+        assert_eq!(finished, [false, false, true]);
+        assert_eq!(result.content, "Hello");
+        assert!(result.streamed);
+        assert_eq!(receiver.try_recv().unwrap(), "Hel");
+        assert_eq!(receiver.try_recv().unwrap(), "lo");
     }
 
-    #[test]
-    fn detects_error_object_code() {
-        let value = serde_json::json!({
-            "error": {
-                "code": "invalid_api_key",
-                "message": "No API key provided"
-            }
-        });
-        let err = detect_provider_error(&value).expect("expected error");
-        assert!(err.to_ascii_lowercase().contains("invalid_api_key"));
-        assert!(err.contains("No API key provided"));
+    #[tokio::test]
+    async fn error_frames_mid_stream_fail_the_call() {
+        use axum::{routing::post, Router};
+
+        let body = "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n\
+data: {\"error\":{\"type\":\"overloaded_error\",\"message\":\"Overloaded\"}}\n\n";
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move || async move { ([("content-type", "text/event-stream")], body) }),
+        );
+        let (_, client) = spawn_upstream(app).await;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let err = client
+            .chat_completion_with_stream(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                Some(sender),
+            )
+            .await
+            .unwrap_err();
+        assert_eq!(receiver.try_recv().unwrap(), "Hel");
+        assert!(
+            err.to_string()
+                .ends_with("returned error mid-stream after 3 chars: overloaded_error: Overloaded"),
+            "{}",
+            err
+        );
     }
 
-    #[test]
-    fn detects_success_flag_false_message() {
-        let value = serde_json::json!({
-            "success": false,
-            "message": "Request failed"
+    #[tokio::test]
+    async fn oversized_responses_are_aborted() {
+        use axum::{routing::post, Router};
+
+        let app = Router::new()
+            .route(
+                "/json/chat/completions",
+                post(|| async {
+                    axum::Json(json!({"choices": [{"message": {"content": "x".repeat(4096)}}]}))
+                }),
+            )
+            .route(
+                "/sse/chat/completions",
+                post(|| async { ([("content-type", "text/event-stream")], OPENAI_STREAM) }),
+            )
+            .route(
+                "/error/chat/completions",
+                post(|| async {
+                    (
+                        axum::http::StatusCode::INTERNAL_SERVER_ERROR,
+                        format!("quota exhausted for project{}", " ".repeat(4096)),
+                    )
+                }),
+            );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = |path: &str, limit: u64| {
+            test_client(
+                Endpoint::new(format!("{}/{}", base, path), ApiFormat::Openai),
+                "k",
+            )
+            .with_max_response_bytes(limit)
+        };
+        let call = |client: LLMClient, stream: bool| async move {
+            let (sender, _receiver) = tokio::sync::mpsc::unbounded_channel();
+            client
+                .chat_completion_with_stream(
+                    "m1",
+                    Vec::new(),
+                    None,
+                    &GenerationParams::default(),
+                    Duration::from_secs(10),
+                    stream.then_some(sender),
+                )
+                .await
+        };
+
+        let err = call(client("json", 1024), false).await.unwrap_err();
+        let too_large = err.downcast_ref::<ResponseTooLarge>().unwrap();
+        assert_eq!(too_large.model, "m1");
+        assert_eq!(too_large.limit, 1024);
+        assert!(err.to_string().contains("max_response_bytes (1024 bytes)"));
+        let ok = call(client("json", 8192), false).await.unwrap();
+        assert_eq!(ok.content.len(), 4096);
+
+        // 流式按累计字节计算，单个事件再小也会被拦下
+        let err = call(client("sse", 400), true).await.unwrap_err();
+        assert!(
+            err.downcast_ref::<ResponseTooLarge>().is_some(),
+            "{:#}",
+            err
+        );
+        let ok = call(client("sse", 8192), true).await.unwrap();
+        assert_eq!(ok.content, "Hello there!");
+
+        // Content-Length 超限的错误响应截断到上限，而不是丢掉整个响应体
+        let err = call(client("error", 1024), false).await.unwrap_err();
+        let http_err = err.downcast_ref::<LlmHttpError>().unwrap();
+        assert!(
+            http_err.body.starts_with("quota exhausted for project"),
+            "{:?}",
+            http_err.body
+        );
+        assert_eq!(http_err.body.len(), 1024);
+    }
+
+    fn gzip_chunks(parts: &[&str]) -> Vec<bytes::Bytes> {
+        use flate2::{write::GzEncoder, Compression};
+        use std::io::Write;
+
+        // 每段之后 flush，让解码端不必等到整个流结束就能拿到这一段
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        let mut chunks = Vec::new();
+        for part in parts {
+            encoder.write_all(part.as_bytes()).unwrap();
+            encoder.flush().unwrap();
+            chunks.push(bytes::Bytes::from(std::mem::take(encoder.get_mut())));
+        }
+        chunks.push(bytes::Bytes::from(encoder.finish().unwrap()));
+        chunks
+    }
+
+    #[tokio::test]
+    async fn gzip_responses_are_decoded_and_streams_stay_incremental() {
+        use axum::{body::Body, http::HeaderMap, routing::post, Router};
+        use std::convert::Infallible;
+
+        let json_body = gzip_chunks(&[&json!({
+            "choices": [{"message": {"content": "compressed hello"}}]
+        })
+        .to_string()])
+        .concat();
+        let mut stream_chunks = gzip_chunks(&[
+            "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\n",
+            "data: {\"choices\":[{\"delta\":{\"content\":\"lo\"}}]}\n\ndata: [DONE]\n\n",
+        ])
+        .into_iter();
+        let first = stream_chunks.next().unwrap();
+        let rest: Vec<_> = stream_chunks.collect();
+        let release = Arc::new(tokio::sync::Notify::new());
+        let (seen_tx, mut seen_rx) = tokio::sync::mpsc::unbounded_channel();
+
+        let server_release = release.clone();
+        let app = Router::new()
+            .route(
+                "/json/chat/completions",
+                post(move |headers: HeaderMap| async move {
+                    let _ = seen_tx.send(headers.get("accept-encoding").cloned());
+                    ([("content-encoding", "gzip")], json_body)
+                }),
+            )
+            .route(
+                "/sse/chat/completions",
+                post(move || async move {
+                    // 第二段要等客户端收到第一段之后才发，缓冲整个流的实现会卡在这里
+                    let (tx, rx) =
+                        tokio::sync::mpsc::channel::<Result<bytes::Bytes, Infallible>>(4);
+                    tokio::spawn(async move {
+                        tx.send(Ok(first)).await.unwrap();
+                        server_release.notified().await;
+                        for chunk in rest {
+                            tx.send(Ok(chunk)).await.unwrap();
+                        }
+                    });
+                    (
+                        [
+                            ("content-type", "text/event-stream"),
+                            ("content-encoding", "gzip"),
+                        ],
+                        Body::from_stream(tokio_stream::wrappers::ReceiverStream::new(rx)),
+                    )
+                }),
+            );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = |path: &str| {
+            test_client(
+                Endpoint::new(format!("{}/{}", base, path), ApiFormat::Openai),
+                "k",
+            )
+        };
+
+        let content = client("json")
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "compressed hello");
+        let accept = seen_rx.recv().await.unwrap().unwrap();
+        let accept = accept.to_str().unwrap();
+        assert!(
+            accept.contains("gzip") && accept.contains("br"),
+            "{}",
+            accept
+        );
+
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let sse = client("sse");
+        let call = tokio::spawn(async move {
+            sse.chat_completion_with_stream(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                Some(sender),
+            )
+            .await
         });
-        let err = detect_provider_error(&value).expect("expected error");
-        assert!(err.contains("Request failed"));
+        let piece = tokio::time::timeout(Duration::from_secs(5), receiver.recv())
+            .await
+            .expect("first streamed piece should arrive before the stream ends");
+        assert_eq!(piece.as_deref(), Some("Hel"));
+        release.notify_one();
+        let result = call.await.unwrap().unwrap();
+        assert_eq!(result.content, "Hello");
+    }
+
+    // 按 OpenAI 与通义千问兼容接口的流式响应格式整理（id 等字段已缩短）：首块只带 role，末块带 finish_reason
+    const OPENAI_STREAM: &str = "data: {\"id\":\"chatcmpl-9x\",\"object\":\"chat.completion.chunk\",\"created\":1718000000,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_0a\",\"choices\":[{\"index\":0,\"delta\":{\"role\":\"assistant\",\"content\":\"\",\"refusal\":null},\"logprobs\":null,\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"chatcmpl-9x\",\"object\":\"chat.completion.chunk\",\"created\":1718000000,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_0a\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Hello\"},\"logprobs\":null,\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"chatcmpl-9x\",\"object\":\"chat.completion.chunk\",\"created\":1718000000,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_0a\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\" there!\"},\"logprobs\":null,\"finish_reason\":null}]}\n\n\
+data: {\"id\":\"chatcmpl-9x\",\"object\":\"chat.completion.chunk\",\"created\":1718000000,\"model\":\"gpt-4o-mini-2024-07-18\",\"system_fingerprint\":\"fp_0a\",\"choices\":[{\"index\":0,\"delta\":{},\"logprobs\":null,\"finish_reason\":\"stop\"}]}\n\n\
+data: [DONE]\n\n";
+
+    const QWEN_STREAM: &str = "data: {\"choices\":[{\"delta\":{\"content\":\"\",\"role\":\"assistant\"},\"index\":0,\"logprobs\":null,\"finish_reason\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1718000000,\"system_fingerprint\":null,\"model\":\"qwen3-max\",\"id\":\"chatcmpl-4b\"}\r\n\r\n\
+data: {\"choices\":[{\"finish_reason\":null,\"delta\":{\"content\":\"你好\"},\"index\":0,\"logprobs\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1718000000,\"system_fingerprint\":null,\"model\":\"qwen3-max\",\"id\":\"chatcmpl-4b\"}\r\n\r\n\
+data: {\"choices\":[{\"finish_reason\":null,\"delta\":{\"content\":\"，世界\"},\"index\":0,\"logprobs\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1718000000,\"system_fingerprint\":null,\"model\":\"qwen3-max\",\"id\":\"chatcmpl-4b\"}\r\n\r\n\
+data: {\"choices\":[{\"finish_reason\":\"stop\",\"delta\":{\"content\":\"\"},\"index\":0,\"logprobs\":null}],\"object\":\"chat.completion.chunk\",\"usage\":null,\"created\":1718000000,\"system_fingerprint\":null,\"model\":\"qwen3-max\",\"id\":\"chatcmpl-4b\"}\r\n\r\n\
+data: {\"choices\":[],\"object\":\"chat.completion.chunk\",\"usage\":{\"prompt_tokens\":9,\"completion_tokens\":4,\"total_tokens\":13},\"created\":1718000000,\"system_fingerprint\":null,\"model\":\"qwen3-max\",\"id\":\"chatcmpl-4b\"}\r\n\r\n\
+data: [DONE]\r\n\r\n";
+
+    async fn stream_from_fixture(
+        fixture: &'static str,
+        content_type: &'static str,
+    ) -> (String, Vec<String>) {
+        use axum::{routing::post, Router};
+
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(move || async move { ([("content-type", content_type)], fixture) }),
+        );
+        let (_, client) = spawn_upstream(app).await;
+        let (sender, mut receiver) = tokio::sync::mpsc::unbounded_channel();
+        let result = client
+            .chat_completion_with_stream(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                Some(sender),
+            )
+            .await
+            .unwrap();
+        assert!(result.streamed);
+        let mut pieces = Vec::new();
+        while let Ok(piece) = receiver.try_recv() {
+            pieces.push(piece);
+        }
+        (result.content, pieces)
+    }
+
+    #[tokio::test]
+    async fn openai_stream_fixtures_yield_only_delta_content() {
+        let (content, pieces) =
+            stream_from_fixture(OPENAI_STREAM, "text/event-stream; charset=utf-8").await;
+        assert_eq!(content, "Hello there!");
+        assert_eq!(pieces, ["Hello", " there!"]);
+
+        // 解析方式由 api_format 决定：content-type 不对也按 SSE 处理
+        let (content, pieces) = stream_from_fixture(QWEN_STREAM, "text/plain").await;
+        assert_eq!(content, "你好，世界");
+        assert_eq!(pieces, ["你好", "，世界"]);
+    }
+
+    fn scripted_client(transport: Arc<ScriptedTransport>, max_attempts: u32) -> LLMClient {
+        LLMClient::with_transport(
+            Endpoint::new("http://upstream.test/v1", ApiFormat::Openai),
+            "k".to_string(),
+            Duration::from_secs(5),
+            RetryPolicy {
+                max_attempts,
+                base_backoff: Duration::from_millis(10),
+            },
+            transport,
+        )
+    }
+
+    #[tokio::test]
+    async fn scripted_connect_errors_are_retried() {
+        let transport = Arc::new(
+            ScriptedTransport::default()
+                .on("upstream.test", Scripted::connect_error())
+                .on("upstream.test", Scripted::completion("second try")),
+        );
+        let content = scripted_client(transport.clone(), 2)
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(5),
+            )
+            .await
+            .unwrap();
+        assert_eq!(content, "second try");
+        assert_eq!(transport.requests().len(), 2);
+    }
+
+    #[tokio::test]
+    async fn scripted_event_stream_split_across_chunks() {
+        let transport = Arc::new(
+            ScriptedTransport::default().on(
+                "upstream.test",
+                Scripted::event_stream(&[
+                    "data: {\"choices\":[{\"delta\":{\"content\":\"Hel\"}}]}\n\nda",
+                    "ta: {\"choices\":[{\"delta\":{\"content\":\"lo\"},\"finish_reason\":\"stop\"}]}\n\n",
+                    "data: [DONE]\n\n",
+                ])
+                .header("x-request-id", "req_stream"),
+            ),
+        );
+        let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+        let result = scripted_client(transport.clone(), 1)
+            .chat_completion_with_stream(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(5),
+                Some(tx),
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "Hello");
+        assert_eq!(result.provider_request_ids.primary(), Some("req_stream"));
+        let mut pieces = Vec::new();
+        while let Ok(piece) = rx.try_recv() {
+            pieces.push(piece);
+        }
+        assert_eq!(pieces, ["Hel", "lo"]);
+        assert_eq!(transport.requests()[0].body["stream"], true);
+    }
+
+    #[tokio::test]
+    async fn completion_metadata_comes_from_body_and_headers() {
+        use axum::{routing::post, Json, Router};
+
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                (
+                    [("x-request-id", "req_abc123")],
+                    Json(json!({
+                        "model": "gpt-4o-mini-2024-07-18",
+                        "choices": [{"message": {"content": "ok"}, "finish_reason": "length"}],
+                        "usage": {"prompt_tokens": 12, "completion_tokens": 3, "total_tokens": 15}
+                    })),
+                )
+            }),
+        );
+        let (_, client) = spawn_upstream(app).await;
+        let result = client
+            .chat_completion_with_stream(
+                "gpt-4o-mini",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+                None,
+            )
+            .await
+            .unwrap();
+        assert_eq!(result.content, "ok");
+        assert_eq!(result.model.as_deref(), Some("gpt-4o-mini-2024-07-18"));
+        assert_eq!(result.finish_reason.as_deref(), Some("length"));
+        assert_eq!(result.provider_request_ids.primary(), Some("req_abc123"));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                prompt_tokens: Some(12),
+                completion_tokens: Some(3),
+                total_tokens: Some(15),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn configured_request_id_headers_reach_results_and_errors() {
+        use axum::{http::StatusCode, routing::post, Json, Router};
+
+        let app = Router::new()
+            .route(
+                "/ok/chat/completions",
+                post(|| async {
+                    (
+                        [("x-request-id", "req_1"), ("x-trace", "tr_1")],
+                        Json(json!({"choices": [{"message": {"content": "ok"}}]})),
+                    )
+                }),
+            )
+            .route(
+                "/fail/chat/completions",
+                post(|| async {
+                    (
+                        StatusCode::INTERNAL_SERVER_ERROR,
+                        [("x-request-id", "req_2"), ("x-trace", "tr_2")],
+                        "overloaded",
+                    )
+                }),
+            );
+        let (base, _) = spawn_upstream(app).await;
+
+        let client = |path: &str| {
+            test_client(
+                Endpoint::new(format!("{}/{}", base, path), ApiFormat::Openai),
+                "k",
+            )
+            .with_request_id_headers(Arc::new(vec![
+                HeaderName::from_static("x-trace"),
+                HeaderName::from_static("x-request-id"),
+            ]))
+        };
+        let call = |client: LLMClient| async move {
+            client
+                .chat_completion_with_stream(
+                    "m1",
+                    Vec::new(),
+                    None,
+                    &GenerationParams::default(),
+                    Duration::from_secs(10),
+                    None,
+                )
+                .await
+        };
+
+        let result = call(client("ok")).await.unwrap();
+        // 按配置的顺序，第一个出现的是 x-trace
+        assert_eq!(result.provider_request_ids.primary(), Some("tr_1"));
+        assert_eq!(
+            result.provider_request_ids.to_string(),
+            "x-trace: tr_1, x-request-id: req_1"
+        );
+
+        let err = call(client("fail")).await.unwrap_err();
+        assert!(
+            err.to_string()
+                .ends_with("overloaded (x-trace: tr_2, x-request-id: req_2)"),
+            "{}",
+            err
+        );
+        assert_eq!(
+            provider_request_ids_of(&err).and_then(ProviderRequestIds::primary),
+            Some("tr_2")
+        );
     }
 
     #[test]
-    fn ignores_successful_status_values() {
-        let value = serde_json::json!({
-            "status": 0,
-            "msg": "ok"
-        });
-        assert!(detect_provider_error(&value).is_none());
+    fn stream_chunks_and_ollama_counts_fill_in_metadata() {
+        let mut result = CompletionResult::default();
+        let final_chunk = r#"{"model":"deepseek-chat","choices":[{"delta":{"content":""},"finish_reason":"stop"}],"usage":{"prompt_tokens":5,"completion_tokens":2,"total_tokens":7}}"#;
+        assert_eq!(apply_sse_payload(final_chunk, None, &mut result), Ok(true));
+        assert_eq!(result.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(result.usage.unwrap().total_tokens, Some(7));
+        assert_eq!(result.model.as_deref(), Some("deepseek-chat"));
+
+        let mut result = CompletionResult::default();
+        result.record_metadata(
+            &json!({"model": "llama3", "done": true, "done_reason": "stop", "prompt_eval_count": 26, "eval_count": 290}),
+            ApiFormat::Ollama,
+        );
+        assert_eq!(result.finish_reason.as_deref(), Some("stop"));
+        assert_eq!(
+            result.usage,
+            Some(Usage {
+                prompt_tokens: Some(26),
+                completion_tokens: Some(290),
+                total_tokens: Some(316),
+            })
+        );
+    }
+
+    #[tokio::test]
+    async fn self_signed_upstreams_need_the_ca_or_an_explicit_opt_out() {
+        use crate::config::TlsConfig;
+        use crate::tls::{load_ca_certificates, serve, TlsCertificates};
+        use axum::{routing::post, Json, Router};
+
+        let dir = crate::test_util::temp_dir("llm_tls");
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let tls = TlsConfig {
+            cert_path: dir.join("upstream.crt").to_string_lossy().into_owned(),
+            key_path: dir.join("upstream.key").to_string_lossy().into_owned(),
+        };
+        std::fs::write(&tls.cert_path, cert.cert.pem()).unwrap();
+        std::fs::write(&tls.key_path, cert.key_pair.serialize_pem()).unwrap();
+
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async { Json(json!({"choices": [{"message": {"content": "secure"}}]})) }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let certs = Arc::new(TlsCertificates::load(&tls).unwrap());
+        tokio::spawn(serve(listener, certs, app, std::future::pending()));
+
+        let call = |tls: TlsSettings| async move {
+            LLMClient::new(
+                Endpoint::new(format!("https://localhost:{}/v1", port), ApiFormat::Openai),
+                "k".to_string(),
+                Duration::from_secs(5),
+                NO_RETRY,
+                &ProxySetting::Direct,
+                &PoolSettings::default(),
+                &tls,
+                &[],
+            )
+            .unwrap()
+            .chat_completion(
+                "m1",
+                Vec::new(),
+                None,
+                &GenerationParams::default(),
+                Duration::from_secs(10),
+            )
+            .await
+        };
+
+        assert!(call(TlsSettings::default()).await.is_err());
+        let trusted = TlsSettings {
+            extra_roots: Arc::new(load_ca_certificates(&tls.cert_path).unwrap()),
+            insecure_skip_verify: false,
+        };
+        assert_eq!(call(trusted).await.unwrap(), "secure");
+        let insecure = TlsSettings {
+            insecure_skip_verify: true,
+            ..Default::default()
+        };
+        assert_eq!(call(insecure).await.unwrap(), "secure");
+
+        let err = load_ca_certificates(&tls.key_path).unwrap_err();
+        assert!(format!("{:#}", err).contains("upstream.key"), "{:#}", err);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 }
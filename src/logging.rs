@@ -0,0 +1,343 @@
+use crate::config::{AccessLogConfig, LogFormat, LogRotation, LoggingConfig, TelemetryConfig};
+use crate::telemetry;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local, NaiveDate};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use tracing_subscriber::filter::filter_fn;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::MakeWriter;
+use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt, EnvFilter, Layer};
+
+// 访问日志事件的 target，由单独的输出层处理
+pub const ACCESS_TARGET: &str = "chorus::access";
+
+// RUST_LOG 优先，其次是 [logging] level，最后才是调用方给的默认值
+pub fn init(
+    config: &LoggingConfig,
+    telemetry: Option<&TelemetryConfig>,
+    access_log: Option<&AccessLogConfig>,
+    default_filter: &str,
+) -> Result<()> {
+    let mut filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::try_new(config.level.as_deref().unwrap_or(default_filter))
+            .with_context(|| "Invalid logging.level")?,
+    };
+    // 访问日志只由 [access_log] 控制，不受日志级别影响
+    filter = filter.add_directive(format!("{}=info", ACCESS_TARGET).parse()?);
+
+    let file = config
+        .file
+        .as_ref()
+        .map(|path| RotatingFile::open(Path::new(path), config).map(Arc::new))
+        .transpose()?;
+    let (writer, ansi) = match &file {
+        Some(file) => (BoxMakeWriter::new(SharedFile(file.clone())), false),
+        None => (BoxMakeWriter::new(io::stdout), true),
+    };
+
+    // 不需要 span 字段时对输出层隐藏 span，文本格式也不再带 `workflow{...}:` 前缀
+    let include_spans = config.include_spans;
+    let spans =
+        filter_fn(move |meta| (include_spans || !meta.is_span()) && meta.target() != ACCESS_TARGET);
+    let layer = match config.format {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .with_filter(spans)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(include_spans)
+            .with_span_list(include_spans)
+            .with_writer(writer)
+            .with_filter(spans)
+            .boxed(),
+    };
+
+    let access = access_layer(config, access_log, file)?;
+
+    // OTLP 导出不受 include_spans 影响，span 总是完整导出
+    let otel = telemetry
+        .filter(|telemetry| telemetry.endpoint.is_some())
+        .map(telemetry::layer)
+        .transpose()
+        .with_context(|| "Failed to set up OpenTelemetry export")?;
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(layer)
+        .with(access)
+        .with(otel)
+        .try_init()
+        .with_context(|| "Failed to install the log subscriber")?;
+    Ok(())
+}
+
+// 访问日志单独一层：可以写到自己的文件、用自己的格式，只输出 chorus::access 事件
+fn access_layer<S>(
+    logging: &LoggingConfig,
+    access_log: Option<&AccessLogConfig>,
+    logging_file: Option<Arc<RotatingFile>>,
+) -> Result<Option<Box<dyn Layer<S> + Send + Sync>>>
+where
+    S: tracing::Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let Some(access_log) = access_log else {
+        return Ok(None);
+    };
+    // 没有单独的文件时与应用日志共用同一个文件，切分也一起进行
+    let (writer, ansi) = match (&access_log.file, logging_file) {
+        (Some(path), _) => (
+            BoxMakeWriter::new(RotatingFile::open(Path::new(path), logging)?),
+            false,
+        ),
+        (None, Some(file)) => (BoxMakeWriter::new(SharedFile(file)), false),
+        (None, None) => (BoxMakeWriter::new(io::stdout), true),
+    };
+    let only_access = filter_fn(|meta| meta.target() == ACCESS_TARGET);
+    let layer = match access_log.format.unwrap_or(logging.format) {
+        LogFormat::Text => tracing_subscriber::fmt::layer()
+            .with_ansi(ansi)
+            .with_writer(writer)
+            .with_filter(only_access)
+            .boxed(),
+        LogFormat::Json => tracing_subscriber::fmt::layer()
+            .json()
+            .with_current_span(false)
+            .with_span_list(false)
+            .with_writer(writer)
+            .with_filter(only_access)
+            .boxed(),
+    };
+    Ok(Some(layer))
+}
+
+// 追加写入日志文件，按天或按大小切分；旧文件以 `<name>.<时间>` 命名并只保留 max_files 个
+pub struct RotatingFile {
+    state: Mutex<RotatingState>,
+}
+
+struct RotatingState {
+    path: PathBuf,
+    file: File,
+    size: u64,
+    opened_on: NaiveDate,
+    rotation: LogRotation,
+    max_bytes: u64,
+    max_files: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: &Path, config: &LoggingConfig) -> Result<Self> {
+        Self::open_with(
+            path,
+            config.rotation,
+            config.max_size_bytes(),
+            config.max_files(),
+        )
+    }
+
+    pub fn open_with(
+        path: &Path,
+        rotation: LogRotation,
+        max_bytes: u64,
+        max_files: usize,
+    ) -> Result<Self> {
+        if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)
+                .with_context(|| format!("Failed to create log dir: {}", dir.display()))?;
+        }
+        let file = open_append(path)
+            .with_context(|| format!("Failed to open log file: {}", path.display()))?;
+        let metadata = file.metadata()?;
+        // 按文件最后修改日期判断，跨天重启后第一条日志就会切分
+        let opened_on = metadata
+            .modified()
+            .map(|time| DateTime::<Local>::from(time).date_naive())
+            .unwrap_or_else(|_| Local::now().date_naive());
+
+        Ok(Self {
+            state: Mutex::new(RotatingState {
+                path: path.to_path_buf(),
+                file,
+                size: metadata.len(),
+                opened_on,
+                rotation,
+                max_bytes,
+                max_files,
+            }),
+        })
+    }
+
+    // 审计日志按 fsync 策略把数据落盘
+    pub fn sync(&self) -> io::Result<()> {
+        let state = self.lock();
+        state.file.sync_data()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, RotatingState> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+impl<'a> MakeWriter<'a> for RotatingFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        RotatingWriter(self.lock())
+    }
+}
+
+struct SharedFile(Arc<RotatingFile>);
+
+impl<'a> MakeWriter<'a> for SharedFile {
+    type Writer = RotatingWriter<'a>;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.0.make_writer()
+    }
+}
+
+pub struct RotatingWriter<'a>(MutexGuard<'a, RotatingState>);
+
+impl Write for RotatingWriter<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let state = &mut *self.0;
+        if let Some(suffix) = state.rotation_suffix(buf.len() as u64) {
+            state.rotate(&suffix)?;
+        }
+        let written = state.file.write(buf)?;
+        state.size += written as u64;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.file.flush()
+    }
+}
+
+impl RotatingState {
+    fn rotation_suffix(&self, incoming: u64) -> Option<String> {
+        match self.rotation {
+            LogRotation::Never => None,
+            LogRotation::Daily => {
+                let today = Local::now().date_naive();
+                (today != self.opened_on).then(|| self.opened_on.format("%Y-%m-%d").to_string())
+            }
+            LogRotation::Size => (self.size > 0 && self.size + incoming > self.max_bytes)
+                .then(|| Local::now().format("%Y-%m-%d-%H%M%S%.3f").to_string()),
+        }
+    }
+
+    fn rotate(&mut self, suffix: &str) -> io::Result<()> {
+        self.file.flush()?;
+        let rotated = PathBuf::from(format!("{}.{}", self.path.display(), suffix));
+        fs::rename(&self.path, &rotated)?;
+        self.file = open_append(&self.path)?;
+        self.size = 0;
+        self.opened_on = Local::now().date_naive();
+        self.prune();
+        Ok(())
+    }
+
+    // 时间后缀按字典序即按时间排序，删掉最旧的
+    fn prune(&self) {
+        let (Some(dir), Some(name)) = (self.path.parent(), self.path.file_name()) else {
+            return;
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let prefix = format!("{}.", name.to_string_lossy());
+        let Ok(entries) = fs::read_dir(dir) else {
+            return;
+        };
+        let mut rotated: Vec<PathBuf> = entries
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.file_name().to_string_lossy().starts_with(&prefix))
+            .map(|entry| entry.path())
+            .collect();
+        rotated.sort();
+        let excess = rotated.len().saturating_sub(self.max_files);
+        for old in &rotated[..excess] {
+            let _ = fs::remove_file(old);
+        }
+    }
+}
+
+fn open_append(path: &Path) -> io::Result<File> {
+    OpenOptions::new().create(true).append(true).open(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 日志放在尚不存在的子目录里，顺带覆盖自动建目录
+    fn temp_log(tag: &str) -> PathBuf {
+        crate::test_util::temp_dir(&format!("logging_{}", tag))
+            .join("logs")
+            .join("chorus.log")
+    }
+
+    fn dir_entries(path: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(path.parent().unwrap())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn size_rotation_keeps_max_files() {
+        let path = temp_log("size");
+        let config = LoggingConfig {
+            rotation: LogRotation::Size,
+            max_files: Some(2),
+            ..Default::default()
+        };
+        let file = RotatingFile::open(&path, &config).unwrap();
+        file.lock().max_bytes = 10;
+
+        for line in ["first line\n", "second line\n", "third line\n", "fourth\n"] {
+            file.make_writer().write_all(line.as_bytes()).unwrap();
+            // 保证时间后缀互不相同
+            std::thread::sleep(std::time::Duration::from_millis(5));
+        }
+
+        let names = dir_entries(&path);
+        assert_eq!(names.len(), 3, "{:?}", names);
+        assert_eq!(fs::read_to_string(&path).unwrap(), "fourth\n");
+        let newest = path.with_file_name(names[2].clone());
+        assert_eq!(fs::read_to_string(newest).unwrap(), "third line\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn daily_rotation_uses_previous_date_as_suffix() {
+        let path = temp_log("daily");
+        let config = LoggingConfig {
+            rotation: LogRotation::Daily,
+            ..Default::default()
+        };
+        let file = RotatingFile::open(&path, &config).unwrap();
+        file.make_writer().write_all(b"yesterday\n").unwrap();
+        let yesterday = Local::now().date_naive().pred_opt().unwrap();
+        file.lock().opened_on = yesterday;
+
+        file.make_writer().write_all(b"today\n").unwrap();
+        let rotated = path.with_file_name(format!("chorus.log.{}", yesterday.format("%Y-%m-%d")));
+        assert_eq!(fs::read_to_string(rotated).unwrap(), "yesterday\n");
+        assert_eq!(fs::read_to_string(&path).unwrap(), "today\n");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
@@ -1,33 +1,163 @@
+mod audit;
 mod config;
+mod config_keys;
+mod config_migrations;
+mod env_overrides;
+mod history;
+mod inflight;
+mod init;
+mod latency;
 mod llm;
+mod logging;
+mod ratelimit;
+mod redaction;
+mod reload;
 mod server;
+mod show;
+mod summary;
+mod telemetry;
+mod tls;
+mod tokens;
+mod transport;
+#[cfg(unix)]
+mod unix_socket;
+mod usage;
+mod validate;
 mod workflow;
+mod workflow_toml;
 
 #[cfg(test)]
 mod config_tests;
+#[cfg(test)]
+mod test_util;
 
 use anyhow::Result;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use std::io::IsTerminal;
+use std::path::PathBuf;
 use std::sync::Arc;
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 
+#[derive(Parser)]
+#[command(name = "chorus", version, about = "Chorus LLM aggregation server")]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+    #[command(flatten)]
+    migrate: MigrateArgs,
+    /// Apply the named [profile.<name>] section on top of the base config
+    #[arg(long, global = true, env = "CHORUS_PROFILE")]
+    profile: Option<String>,
+}
+
+#[derive(Args, Clone, Copy)]
+struct MigrateArgs {
+    /// Print the migrated config to stderr instead of rewriting it (same as CHORUS_MIGRATE_DRY_RUN=1)
+    #[arg(long, global = true)]
+    migrate_dry_run: bool,
+    /// Rewrite an embedded `json = """..."""` workflow as inline TOML tables (same as CHORUS_MIGRATE_INLINE_WORKFLOW=1)
+    #[arg(long, global = true)]
+    migrate_inline_workflow: bool,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Start the HTTP server (default)
+    Serve,
+    /// Check a configuration file without starting the server or calling any model
+    Validate {
+        /// Config file to check (defaults to CHORUS_CONFIG, then ~/.config/chorus/config.toml)
+        #[arg(long, short)]
+        config: Option<PathBuf>,
+        /// Print the result as JSON
+        #[arg(long)]
+        json: bool,
+    },
+    /// Write a starter config with one model wired into a minimal workflow
+    Init(InitArgs),
+    /// Inspect the configuration the server would run with
+    #[command(subcommand)]
+    Config(ConfigCommand),
+}
+
+#[derive(Subcommand)]
+enum ConfigCommand {
+    /// Print the fully resolved configuration (API keys masked)
+    Show {
+        /// Config file to load (defaults to CHORUS_CONFIG, then ~/.config/chorus/config.toml)
+        #[arg(long, short)]
+        config: Option<PathBuf>,
+        /// Output format
+        #[arg(long, value_enum, default_value_t = OutputFormat::Text)]
+        format: OutputFormat,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum OutputFormat {
+    Text,
+    Json,
+}
+
+#[derive(Args)]
+struct InitArgs {
+    /// Where to write the config (defaults to CHORUS_CONFIG, then ~/.config/chorus/config.toml)
+    #[arg(long, short)]
+    config: Option<PathBuf>,
+    /// Do not prompt; the API base, API key and model must all be given
+    #[arg(long)]
+    non_interactive: bool,
+    /// Provider API base URL, e.g. https://api.openai.com/v1
+    #[arg(long)]
+    api_base: Option<String>,
+    /// Provider API key; visible in the process list, prefer CHORUS_API_KEY or --api-key-stdin
+    #[arg(long, env = "CHORUS_API_KEY", hide_env_values = true)]
+    api_key: Option<String>,
+    /// Read the provider API key from the first line of stdin
+    #[arg(long)]
+    api_key_stdin: bool,
+    /// Model name to use for every workflow role
+    #[arg(long)]
+    model: Option<String>,
+    /// Overwrite an existing config file
+    #[arg(long)]
+    force: bool,
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
-    // 初始化日志
-    tracing_subscriber::registry()
-        .with(
-            tracing_subscriber::EnvFilter::try_from_default_env()
-                .unwrap_or_else(|_| "chorus=debug,tower_http=debug".into()),
-        )
-        .with(tracing_subscriber::fmt::layer())
-        .init();
+    let cli = Cli::parse();
+
+    let profile = cli.profile.as_deref();
+    match cli.command.unwrap_or(Command::Serve) {
+        Command::Serve => serve(cli.migrate, profile).await,
+        Command::Validate { config, json } => validate(config, json, profile),
+        Command::Init(args) => init(args),
+        Command::Config(ConfigCommand::Show { config, format }) => {
+            config_show(config, format, cli.migrate, profile)
+        }
+    }
+}
+
+async fn serve(migrate: MigrateArgs, profile: Option<&str>) -> Result<()> {
+    // 初始化日志：加载配置的过程本身也要打日志，所以先单独读取 [logging]
+    logging::init(
+        &config::Config::peek_logging(profile),
+        config::Config::peek_telemetry(profile).as_ref(),
+        config::Config::peek_access_log(profile).as_ref(),
+        "chorus=debug,tower_http=debug",
+    )?;
 
-    // 自动加载配置（env > ~/.config/chorus/config.toml）
-    let config = config::Config::load_auto()?;
-    let host = config.server.host.clone();
-    let port = config.server.port;
+    let (config, config_path) = load_runtime_config(None, migrate, profile)?;
+    if let Some(profile) = config.profile_name() {
+        tracing::info!("Using config profile '{}'", profile);
+    }
     let worker_labels = config.workflow_integration.worker_labels();
 
-    tracing::info!("Starting Chorus server on {}:{}", host, port);
+    tracing::info!(
+        "Starting Chorus server on {}",
+        config.server.describe_listeners()
+    );
     tracing::info!(
         "Analyzer model: {}",
         config.workflow_integration.analyzer.model
@@ -45,7 +175,115 @@ async fn main() -> Result<()> {
     }
 
     // 启动服务器
-    server::start_server(Arc::new(config)).await?;
+    let result = server::start_server(Arc::new(config), config_path).await;
+    telemetry::shutdown();
+    result
+}
+
+// serve 与 config show 共用同一条加载路径（env > ~/.config/chorus/config.toml）
+fn load_runtime_config(
+    path: Option<PathBuf>,
+    migrate: MigrateArgs,
+    profile: Option<&str>,
+) -> Result<(config::Config, PathBuf)> {
+    if let Some(path) = path {
+        let config = config::Config::load_profile(&path.to_string_lossy(), profile)?;
+        return Ok((config, path));
+    }
+    let mut migration = config::MigrationOptions::from_env();
+    migration.dry_run |= migrate.migrate_dry_run;
+    migration.inline_workflow |= migrate.migrate_inline_workflow;
+    config::Config::load_auto(&migration, profile)
+}
+
+// 日志输出到 stderr，避免污染 JSON 结果
+fn init_cli_logging() {
+    tracing_subscriber::registry()
+        .with(
+            tracing_subscriber::EnvFilter::try_from_default_env()
+                .unwrap_or_else(|_| "chorus=warn".into()),
+        )
+        .with(tracing_subscriber::fmt::layer().with_writer(std::io::stderr))
+        .init();
+}
+
+fn validate(config: Option<PathBuf>, json: bool, profile: Option<&str>) -> Result<()> {
+    init_cli_logging();
+
+    let path = match config {
+        Some(path) => path,
+        None => config::Config::resolve_auto_path()?,
+    };
+    let report = validate::validate_config_file(&path, profile);
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print!("{}", report.render_text());
+    }
+
+    if !report.valid {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn config_show(
+    config: Option<PathBuf>,
+    format: OutputFormat,
+    migrate: MigrateArgs,
+    profile: Option<&str>,
+) -> Result<()> {
+    init_cli_logging();
+
+    let (config, path) = load_runtime_config(config, migrate, profile)?;
+    // 与 serve 一样构建 AppState，服务端会拒绝的配置这里同样报错
+    let state = server::AppState::new(config)?;
+    let resolved = show::ResolvedConfig::from_config(state.config(), &path)?;
+
+    match format {
+        OutputFormat::Text => print!("{}", resolved.render_text()),
+        OutputFormat::Json => println!("{}", serde_json::to_string_pretty(&resolved)?),
+    }
+    Ok(())
+}
+
+fn init(args: InitArgs) -> Result<()> {
+    let path = match args.config {
+        Some(path) => path,
+        None => match std::env::var_os("CHORUS_CONFIG") {
+            Some(path) => PathBuf::from(path),
+            None => config::Config::user_config_path()?,
+        },
+    };
+    if path.exists() && !args.force {
+        anyhow::bail!(
+            "{} already exists; pass --force to overwrite it",
+            path.display()
+        );
+    }
+
+    let mut stdin = std::io::stdin().lock();
+    let api_key = if args.api_key_stdin {
+        Some(init::read_api_key(&mut stdin)?)
+    } else {
+        args.api_key
+    };
+    let answers = init::InitAnswers {
+        api_base: args.api_base,
+        api_key,
+        model: args.model,
+    };
+    let provider = if args.non_interactive {
+        answers.into_provider()?
+    } else {
+        let terminal = stdin.is_terminal();
+        answers.prompt(&mut stdin, &mut std::io::stderr(), terminal)?
+    };
 
+    let content = init::render_starter_config(&provider)?;
+    init::write_config(&path, &content, args.force)?;
+    println!("Wrote {}", path.display());
+    println!("Check it with: chorus validate --config {}", path.display());
     Ok(())
 }
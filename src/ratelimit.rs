@@ -0,0 +1,554 @@
+use serde::Serialize;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub rpm: Option<u32>,
+    pub tpm: Option<u32>,
+}
+
+impl RateLimits {
+    fn is_unlimited(&self) -> bool {
+        self.rpm.is_none() && self.tpm.is_none()
+    }
+}
+
+// 一次调用的总时限：限流等待、排队与上游请求共用同一个截止时间，每一步只能用剩下的部分
+#[derive(Debug, Clone, Copy)]
+pub struct Deadline {
+    at: Instant,
+    timeout: Duration,
+}
+
+impl Deadline {
+    pub fn after(timeout: Duration) -> Self {
+        Self {
+            at: Instant::now() + timeout,
+            timeout,
+        }
+    }
+
+    pub fn remaining(&self) -> Duration {
+        self.at.saturating_duration_since(Instant::now())
+    }
+
+    pub fn timeout_secs(&self) -> u64 {
+        self.timeout.as_secs()
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "Rate limit budget for model '{model}' did not free up within {timeout_secs}s (waited {waited_ms} ms)"
+)]
+pub struct RateLimitExceeded {
+    pub model: String,
+    pub waited_ms: u64,
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error(
+    "No request slot for model '{model}' (max_concurrent_requests = {max}) freed up within {timeout_secs}s (waited {waited_ms} ms)"
+)]
+pub struct ConcurrencyLimitExceeded {
+    pub model: String,
+    pub max: u32,
+    pub waited_ms: u64,
+    pub timeout_secs: u64,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct RateLimitStats {
+    pub model: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_rpm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_tpm: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_requests: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub available_tokens: Option<f64>,
+    pub granted: u64,
+    pub delayed: u64,
+    pub total_wait_ms: u64,
+    pub rejected: u64,
+}
+
+// max_concurrent_requests 的占用情况，供 /api/status 展示排队深度
+#[derive(Debug, Clone, Serialize)]
+pub struct RequestSlotStats {
+    pub model: String,
+    pub max_concurrent_requests: u32,
+    pub in_use: u32,
+    pub waiting: usize,
+}
+
+struct Slots {
+    max: u32,
+    semaphore: Arc<Semaphore>,
+    waiting: Arc<AtomicUsize>,
+}
+
+impl Slots {
+    fn new(max: u32) -> Self {
+        Self {
+            max,
+            semaphore: Arc::new(Semaphore::new(max as usize)),
+            waiting: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+}
+
+// 请求被取消时 future 直接被丢弃，排队计数放在 Drop 里减回去
+struct Queued(Arc<AtomicUsize>);
+
+impl Queued {
+    fn enter(waiting: Arc<AtomicUsize>) -> Self {
+        waiting.fetch_add(1, Ordering::Relaxed);
+        Self(waiting)
+    }
+}
+
+impl Drop for Queued {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn per_minute(limit: u32, now: Instant) -> Self {
+        let capacity = f64::from(limit.max(1));
+        Self {
+            capacity,
+            available: capacity,
+            refill_per_sec: capacity / 60.0,
+            updated: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    fn wait_for(&self, amount: f64) -> Duration {
+        if self.available >= amount {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((amount - self.available) / self.refill_per_sec)
+        }
+    }
+}
+
+struct ModelState {
+    limits: RateLimits,
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    granted: u64,
+    delayed: u64,
+    total_wait_ms: u64,
+    rejected: u64,
+}
+
+impl ModelState {
+    fn new(limits: RateLimits, now: Instant) -> Self {
+        Self {
+            limits,
+            requests: limits.rpm.map(|rpm| Bucket::per_minute(rpm, now)),
+            tokens: limits.tpm.map(|tpm| Bucket::per_minute(tpm, now)),
+            granted: 0,
+            delayed: 0,
+            total_wait_ms: 0,
+            rejected: 0,
+        }
+    }
+
+    fn reconfigure(&mut self, limits: RateLimits, now: Instant) {
+        if self.limits != limits {
+            self.limits = limits;
+            self.requests = limits.rpm.map(|rpm| Bucket::per_minute(rpm, now));
+            self.tokens = limits.tpm.map(|tpm| Bucket::per_minute(tpm, now));
+        }
+    }
+
+    // 返回还需等待的时长；为零时已扣除本次预算
+    fn try_take(&mut self, tokens: u32, now: Instant) -> Duration {
+        let mut wait = Duration::ZERO;
+        if let Some(bucket) = self.requests.as_mut() {
+            bucket.refill(now);
+            wait = wait.max(bucket.wait_for(1.0));
+        }
+        if let Some(bucket) = self.tokens.as_mut() {
+            bucket.refill(now);
+            // 单次估算超过整桶容量时按满桶计，避免永远等不到
+            let needed = f64::from(tokens).min(bucket.capacity);
+            wait = wait.max(bucket.wait_for(needed));
+        }
+
+        if wait.is_zero() {
+            if let Some(bucket) = self.requests.as_mut() {
+                bucket.available -= 1.0;
+            }
+            if let Some(bucket) = self.tokens.as_mut() {
+                bucket.available -= f64::from(tokens).min(bucket.capacity);
+            }
+        }
+        wait
+    }
+}
+
+// 按模型名维护令牌桶，由同一个 WorkflowEngine 上的所有并发工作流共享
+#[derive(Default)]
+pub struct RateLimiter {
+    models: Mutex<HashMap<String, ModelState>>,
+    // 按模型名限制同时在途的请求数；上限变化时换新的信号量
+    slots: Mutex<HashMap<String, Slots>>,
+}
+
+impl RateLimiter {
+    pub fn configure(&self, model: &str, limits: RateLimits) {
+        let now = Instant::now();
+        let mut models = self.lock();
+        if limits.is_unlimited() {
+            models.remove(model);
+            return;
+        }
+        models
+            .entry(model.to_string())
+            .or_insert_with(|| ModelState::new(limits, now))
+            .reconfigure(limits, now);
+    }
+
+    pub async fn acquire(
+        &self,
+        model: &str,
+        limits: RateLimits,
+        estimated_tokens: u32,
+        deadline: Deadline,
+    ) -> Result<Duration, RateLimitExceeded> {
+        if limits.is_unlimited() {
+            return Ok(Duration::ZERO);
+        }
+
+        let started = Instant::now();
+        loop {
+            let now = Instant::now();
+            let wait = {
+                let mut models = self.lock();
+                let state = models
+                    .entry(model.to_string())
+                    .or_insert_with(|| ModelState::new(limits, now));
+                state.reconfigure(limits, now);
+                let wait = state.try_take(estimated_tokens, now);
+                if wait.is_zero() {
+                    let waited = started.elapsed();
+                    state.granted += 1;
+                    if !waited.is_zero() {
+                        state.delayed += 1;
+                        state.total_wait_ms += waited.as_millis() as u64;
+                    }
+                    return Ok(waited);
+                }
+
+                if wait > deadline.remaining() {
+                    state.rejected += 1;
+                    return Err(RateLimitExceeded {
+                        model: model.to_string(),
+                        waited_ms: started.elapsed().as_millis() as u64,
+                        timeout_secs: deadline.timeout_secs(),
+                    });
+                }
+                wait
+            };
+
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    // 返回的 permit 需持有到这次上游调用结束；未配置上限时不排队
+    pub async fn acquire_slot(
+        &self,
+        model: &str,
+        max: Option<u32>,
+        deadline: Deadline,
+    ) -> Result<(Option<OwnedSemaphorePermit>, Duration), ConcurrencyLimitExceeded> {
+        let Some(max) = max else {
+            return Ok((None, Duration::ZERO));
+        };
+        let (semaphore, waiting) = {
+            let mut slots = self.slots.lock().unwrap_or_else(|p| p.into_inner());
+            let entry = slots
+                .entry(model.to_string())
+                .or_insert_with(|| Slots::new(max));
+            if entry.max != max {
+                *entry = Slots::new(max);
+            }
+            (entry.semaphore.clone(), entry.waiting.clone())
+        };
+
+        let started = Instant::now();
+        let queued = Queued::enter(waiting);
+        let acquired = tokio::time::timeout_at(deadline.at, semaphore.acquire_owned()).await;
+        drop(queued);
+        match acquired {
+            Ok(Ok(permit)) => Ok((Some(permit), started.elapsed())),
+            // 信号量从不关闭，这里只可能是超时
+            _ => Err(ConcurrencyLimitExceeded {
+                model: model.to_string(),
+                max,
+                waited_ms: started.elapsed().as_millis() as u64,
+                timeout_secs: deadline.timeout_secs(),
+            }),
+        }
+    }
+
+    // 调用完成后按实际输出补扣 token 预算（估算值，允许透支到下一分钟）
+    pub fn record_tokens(&self, model: &str, tokens: u32) {
+        let mut models = self.lock();
+        if let Some(bucket) = models.get_mut(model).and_then(|s| s.tokens.as_mut()) {
+            bucket.refill(Instant::now());
+            bucket.available = (bucket.available - f64::from(tokens)).max(-bucket.capacity);
+        }
+    }
+
+    pub fn stats(&self) -> Vec<RateLimitStats> {
+        let now = Instant::now();
+        let mut models = self.lock();
+        let mut stats: Vec<_> = models
+            .iter_mut()
+            .map(|(name, state)| {
+                let available = |bucket: &mut Option<Bucket>| {
+                    bucket.as_mut().map(|b| {
+                        b.refill(now);
+                        (b.available * 100.0).round() / 100.0
+                    })
+                };
+                RateLimitStats {
+                    model: name.clone(),
+                    rate_limit_rpm: state.limits.rpm,
+                    rate_limit_tpm: state.limits.tpm,
+                    available_requests: available(&mut state.requests),
+                    available_tokens: available(&mut state.tokens),
+                    granted: state.granted,
+                    delayed: state.delayed,
+                    total_wait_ms: state.total_wait_ms,
+                    rejected: state.rejected,
+                }
+            })
+            .collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+
+    pub fn slot_stats(&self) -> Vec<RequestSlotStats> {
+        let slots = self.slots.lock().unwrap_or_else(|p| p.into_inner());
+        let mut stats: Vec<_> = slots
+            .iter()
+            .map(|(name, slots)| RequestSlotStats {
+                model: name.clone(),
+                max_concurrent_requests: slots.max,
+                in_use: slots
+                    .max
+                    .saturating_sub(slots.semaphore.available_permits() as u32),
+                waiting: slots.waiting.load(Ordering::Relaxed),
+            })
+            .collect();
+        stats.sort_by(|a, b| a.model.cmp(&b.model));
+        stats
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, ModelState>> {
+        self.models
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test(start_paused = true)]
+    async fn requests_wait_for_refill_instead_of_failing() {
+        let limiter = RateLimiter::default();
+        let limits = RateLimits {
+            rpm: Some(60),
+            tpm: None,
+        };
+        let timeout = Duration::from_secs(30);
+
+        for _ in 0..60 {
+            let waited = limiter
+                .acquire("m1", limits, 0, Deadline::after(timeout))
+                .await
+                .unwrap();
+            assert!(waited.is_zero());
+        }
+
+        // 每秒补充一个请求额度
+        let waited = limiter
+            .acquire("m1", limits, 0, Deadline::after(timeout))
+            .await
+            .unwrap();
+        assert!(waited >= Duration::from_millis(900), "{:?}", waited);
+
+        let stats = limiter.stats();
+        assert_eq!(stats[0].granted, 61);
+        assert_eq!(stats[0].delayed, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn acquire_gives_up_when_wait_exceeds_timeout() {
+        let limiter = RateLimiter::default();
+        let limits = RateLimits {
+            rpm: Some(1),
+            tpm: None,
+        };
+
+        limiter
+            .acquire("m1", limits, 0, Deadline::after(Duration::from_secs(5)))
+            .await
+            .unwrap();
+        let err = limiter
+            .acquire("m1", limits, 0, Deadline::after(Duration::from_secs(5)))
+            .await
+            .unwrap_err();
+        assert_eq!(err.model, "m1");
+        assert_eq!(limiter.stats()[0].rejected, 1);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn token_budget_is_shared_per_model() {
+        let limiter = RateLimiter::default();
+        let limits = RateLimits {
+            rpm: None,
+            tpm: Some(1_000),
+        };
+        let timeout = Duration::from_secs(120);
+
+        limiter
+            .acquire("m1", limits, 600, Deadline::after(timeout))
+            .await
+            .unwrap();
+        limiter.record_tokens("m1", 400);
+        // 桶已耗尽，需要等待补充
+        let waited = limiter
+            .acquire("m1", limits, 300, Deadline::after(timeout))
+            .await
+            .unwrap();
+        assert!(waited >= Duration::from_secs(17), "{:?}", waited);
+
+        // 其它模型不受影响
+        let waited = limiter
+            .acquire("m2", limits, 300, Deadline::after(timeout))
+            .await
+            .unwrap();
+        assert!(waited.is_zero());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn request_slots_queue_and_time_out() {
+        let limiter = Arc::new(RateLimiter::default());
+        let timeout = Duration::from_secs(30);
+
+        let (first, queued) = limiter
+            .acquire_slot("m1", Some(1), Deadline::after(timeout))
+            .await
+            .unwrap();
+        assert!(first.is_some());
+        assert!(queued.is_zero());
+
+        // 唯一的空位被占用时排队，直到前一个请求结束
+        let waiter = {
+            let limiter = limiter.clone();
+            tokio::spawn(async move {
+                limiter
+                    .acquire_slot("m1", Some(1), Deadline::after(timeout))
+                    .await
+            })
+        };
+        tokio::time::sleep(Duration::from_secs(10)).await;
+        let stats = limiter.slot_stats();
+        assert_eq!((stats[0].in_use, stats[0].waiting), (1, 1));
+        drop(first);
+        let (second, queued) = waiter.await.unwrap().unwrap();
+        assert!(queued >= Duration::from_secs(10), "{:?}", queued);
+        let stats = limiter.slot_stats();
+        assert_eq!((stats[0].in_use, stats[0].waiting), (1, 0));
+
+        let err = limiter
+            .acquire_slot("m1", Some(1), Deadline::after(timeout))
+            .await
+            .unwrap_err();
+        assert_eq!(err.max, 1);
+        assert_eq!(err.timeout_secs, 30);
+        drop(second);
+
+        // 未配置上限或其它模型不受影响
+        let (permit, _) = limiter
+            .acquire_slot("m1", None, Deadline::after(timeout))
+            .await
+            .unwrap();
+        assert!(permit.is_none());
+        limiter
+            .acquire_slot("m2", Some(1), Deadline::after(timeout))
+            .await
+            .unwrap();
+    }
+
+    #[test]
+    fn unlimited_models_are_not_tracked() {
+        let limiter = RateLimiter::default();
+        let waited = futures::executor::block_on(limiter.acquire(
+            "m1",
+            RateLimits::default(),
+            10,
+            Deadline::after(Duration::from_secs(1)),
+        ))
+        .unwrap();
+        assert!(waited.is_zero());
+        assert!(limiter.stats().is_empty());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn rate_limit_wait_and_slot_queue_share_one_deadline() {
+        let limiter = RateLimiter::default();
+        let limits = RateLimits {
+            rpm: Some(6),
+            tpm: None,
+        };
+        let deadline = Deadline::after(Duration::from_secs(15));
+        for _ in 0..6 {
+            limiter.acquire("m1", limits, 0, deadline).await.unwrap();
+        }
+        // 限流等待约 10s，剩下的时间不够排队 10s
+        let waited = limiter.acquire("m1", limits, 0, deadline).await.unwrap();
+        assert!(waited >= Duration::from_secs(9), "{:?}", waited);
+
+        let (_held, _) = limiter
+            .acquire_slot("m1", Some(1), Deadline::after(Duration::from_secs(60)))
+            .await
+            .unwrap();
+        let err = limiter
+            .acquire_slot("m1", Some(1), deadline)
+            .await
+            .unwrap_err();
+        assert_eq!(err.timeout_secs, 15);
+        assert!(err.waited_ms <= 6_000, "{}", err.waited_ms);
+        assert!(deadline.remaining().is_zero());
+    }
+}
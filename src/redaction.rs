@@ -0,0 +1,236 @@
+use crate::config::{Config, ModelConfig};
+use anyhow::{anyhow, Context, Result};
+use regex::Regex;
+
+// 内置规则：名称、正则、匹配内容至少包含的数字个数；自定义规则统一标记为 custom
+const BUILTIN_PATTERNS: &[(&str, &str, usize)] = &[
+    (
+        "email",
+        r"[A-Za-z0-9._%+-]+@[A-Za-z0-9-]+(?:\.[A-Za-z0-9-]+)*\.[A-Za-z]{2,}",
+        0,
+    ),
+    // 允许 +、空格、连字符与括号分隔，至少 8 位数字才算电话号码
+    ("phone", r"\+?\(?\d[\d ()-]{5,}\d", 8),
+];
+
+pub const BUILTIN_NAMES: &[&str] = &["email", "phone"];
+
+struct Rule {
+    name: String,
+    pattern: Regex,
+    min_digits: usize,
+}
+
+// 按规则顺序把匹配内容替换为 [REDACTED:<规则名>]
+pub struct TextRedactor {
+    rules: Vec<Rule>,
+}
+
+impl TextRedactor {
+    pub fn new(builtin: &[String], custom: &[String]) -> Result<Self> {
+        let mut rules = Vec::new();
+        for name in builtin {
+            let (_, pattern, min_digits) = BUILTIN_PATTERNS
+                .iter()
+                .find(|(builtin, _, _)| builtin == name)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "Unknown redaction rule '{}' (expected one of: {})",
+                        name,
+                        BUILTIN_NAMES.join(", ")
+                    )
+                })?;
+            rules.push(Rule {
+                name: name.clone(),
+                pattern: Regex::new(pattern).expect("builtin pattern compiles"),
+                min_digits: *min_digits,
+            });
+        }
+        for pattern in custom {
+            rules.push(Rule {
+                name: "custom".to_string(),
+                pattern: compile(pattern)?,
+                min_digits: 0,
+            });
+        }
+        Ok(Self { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for rule in &self.rules {
+            let replacement = format!("[REDACTED:{}]", rule.name);
+            let replaced = rule.pattern.replace_all(&text, |caps: &regex::Captures| {
+                let matched = &caps[0];
+                if matched.chars().filter(char::is_ascii_digit).count() >= rule.min_digits {
+                    replacement.clone()
+                } else {
+                    matched.to_string()
+                }
+            });
+            if let std::borrow::Cow::Owned(replaced) = replaced {
+                text = replaced;
+            }
+        }
+        text
+    }
+}
+
+pub fn compile(pattern: &str) -> Result<Regex> {
+    Regex::new(pattern).with_context(|| format!("Invalid redaction pattern '{}'", pattern))
+}
+
+const REDACTED: &str = "***";
+// 过短的 key 遮盖起来会误伤正常文本
+const MIN_REDACTED_KEY_CHARS: usize = 8;
+const MIN_TOKEN_CHARS: usize = 16;
+
+// 上游错误信息可能回显请求里的密钥；写入日志或返回给客户端之前统一遮盖
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    secrets: Vec<String>,
+}
+
+impl Redactor {
+    pub fn from_config(config: &Config) -> Self {
+        Self::new(config.models.iter().map(ModelConfig::api_key))
+    }
+
+    pub fn new<'a>(keys: impl IntoIterator<Item = &'a str>) -> Self {
+        let mut secrets: Vec<String> = keys
+            .into_iter()
+            .map(|key| key.trim().to_string())
+            .filter(|key| key.chars().count() >= MIN_REDACTED_KEY_CHARS)
+            .collect();
+        // 先替换较长的 key，避免互为前缀时只遮盖一半
+        secrets.sort_by(|a, b| b.len().cmp(&a.len()).then_with(|| a.cmp(b)));
+        secrets.dedup();
+        Self { secrets }
+    }
+
+    pub fn redact(&self, text: &str) -> String {
+        let mut text = text.to_string();
+        for secret in &self.secrets {
+            if text.contains(secret.as_str()) {
+                text = text.replace(secret.as_str(), REDACTED);
+            }
+        }
+        redact_tokens(&text)
+    }
+}
+
+// 不依赖配置的兜底：`Bearer xxx` 与 `sk-` 开头的长串
+pub fn redact_tokens(text: &str) -> String {
+    let is_token_byte = |b: u8| b.is_ascii_alphanumeric() || b"-._~+/=".contains(&b);
+    let token_len = |rest: &[u8]| rest.iter().take_while(|&&b| is_token_byte(b)).count();
+
+    let bytes = text.as_bytes();
+    let mut out = String::with_capacity(text.len());
+    let mut copied = 0;
+    let mut i = 0;
+    while i < bytes.len() {
+        let at_word_start =
+            i == 0 || !(bytes[i - 1].is_ascii_alphanumeric() || b"-_".contains(&bytes[i - 1]));
+        let rest = &bytes[i..];
+        // (保留的前缀长度, token 长度, 最短长度)
+        let (keep, len, min) = if !at_word_start {
+            (0, 0, usize::MAX)
+        } else if rest.len() > 7 && rest[..7].eq_ignore_ascii_case(b"bearer ") {
+            (7, token_len(&rest[7..]), MIN_REDACTED_KEY_CHARS)
+        } else if rest.starts_with(b"sk-") {
+            (0, token_len(rest), MIN_TOKEN_CHARS)
+        } else {
+            (0, 0, usize::MAX)
+        };
+        if len >= min {
+            // 前缀都是 ASCII，切片位置一定落在字符边界上
+            out.push_str(&text[copied..i + keep]);
+            out.push_str(REDACTED);
+            i += keep + len;
+            copied = i;
+        } else {
+            i += 1;
+        }
+    }
+    out.push_str(&text[copied..]);
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn names(names: &[&str]) -> Vec<String> {
+        names.iter().map(|name| name.to_string()).collect()
+    }
+
+    #[test]
+    fn builtin_rules_mask_emails_and_phone_numbers() {
+        let redactor = TextRedactor::new(&names(&["email", "phone"]), &[]).unwrap();
+        assert_eq!(
+            redactor.redact("联系 alice.w@example.co.uk 或 +86 138-0013-8000"),
+            "联系 [REDACTED:email] 或 [REDACTED:phone]"
+        );
+        assert_eq!(
+            redactor.redact("call (555) 123-4567 today"),
+            "call [REDACTED:phone] today"
+        );
+        // 短数字、版本号和年份保持原样
+        assert_eq!(
+            redactor.redact("version 1.2.0 released in 2024 with 42 000 items"),
+            "version 1.2.0 released in 2024 with 42 000 items"
+        );
+    }
+
+    #[test]
+    fn custom_patterns_apply_after_builtin_rules() {
+        let redactor = TextRedactor::new(&names(&["email"]), &names(&[r"\bACCT-\d{6}\b"])).unwrap();
+        assert_eq!(
+            redactor.redact("ACCT-123456 belongs to bob@example.com"),
+            "[REDACTED:custom] belongs to [REDACTED:email]"
+        );
+    }
+
+    #[test]
+    fn unknown_rules_and_bad_patterns_are_rejected() {
+        let err = TextRedactor::new(&names(&["ssn"]), &[]).err().unwrap();
+        assert_eq!(
+            err.to_string(),
+            "Unknown redaction rule 'ssn' (expected one of: email, phone)"
+        );
+        let err = TextRedactor::new(&[], &names(&["(unclosed"]))
+            .err()
+            .unwrap();
+        assert!(err
+            .to_string()
+            .starts_with("Invalid redaction pattern '(unclosed'"));
+        assert!(TextRedactor::new(&[], &[]).unwrap().is_empty());
+    }
+
+    #[test]
+    fn redactor_masks_configured_keys_and_token_shapes() {
+        let redactor = Redactor::new(["sk-secret-value-1234"]);
+        assert_eq!(
+            redactor.redact("bad key sk-secret-value-1234 for 模型"),
+            "bad key *** for 模型"
+        );
+        assert_eq!(
+            redact_tokens("Authorization: bearer abcdefgh.ijk, retry"),
+            "Authorization: bearer ***, retry"
+        );
+        assert_eq!(
+            redact_tokens("key=sk-proj-AbCdEf0123456789xyz!"),
+            "key=***!"
+        );
+        // 太短的串、单词中间的 sk- 不算
+        assert_eq!(
+            redact_tokens("sk-short, task-sk-0123456789abcdefgh"),
+            "sk-short, task-sk-0123456789abcdefgh"
+        );
+        assert_eq!(Redactor::new(["k"]).redact("keep k"), "keep k");
+    }
+}
@@ -0,0 +1,291 @@
+use crate::config::Config;
+use crate::server::{AppState, LiveState};
+use anyhow::{Context, Result};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::time::MissedTickBehavior;
+
+pub(crate) const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+pub fn spawn_config_watcher(path: PathBuf, state: Arc<LiveState>) {
+    tokio::spawn(async move {
+        let mut watched = watched_files(&path, &state);
+        let mut last_modified = modified_times(&watched);
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut hangup = HangupListener::new();
+
+        tracing::info!("Watching {} for configuration changes", path.display());
+
+        loop {
+            let reason = tokio::select! {
+                _ = ticker.tick() => {
+                    if modified_times(&watched) == last_modified {
+                        continue;
+                    }
+                    "file change"
+                }
+                _ = hangup.recv() => "SIGHUP",
+            };
+
+            let result = reload_config(&state, &path);
+            // 重新加载后 json_file、include 与 api_key_file 都可能换了路径，按新配置重新登记
+            watched = watched_files(&path, &state);
+            last_modified = modified_times(&watched);
+            match result {
+                Ok(()) => tracing::info!(
+                    "Reloaded configuration from {} ({})",
+                    path.display(),
+                    reason
+                ),
+                Err(err) => tracing::error!(
+                    "Keeping previous configuration; reload from {} failed ({}): {:#}",
+                    path.display(),
+                    reason,
+                    err
+                ),
+            }
+        }
+    });
+}
+
+pub fn reload_config(state: &LiveState, path: &Path) -> Result<()> {
+    let current = state.snapshot();
+    // 热加载沿用启动时选中的 profile
+    let mut next = Config::load_profile(&path.to_string_lossy(), current.config().profile_name())?;
+    let running = &current.config().server;
+
+    if next.server != *running {
+        tracing::warn!(
+            "Ignoring server listener change to {}; restart Chorus to listen on it (still serving {})",
+            next.server.describe_listeners(),
+            running.describe_listeners()
+        );
+        next.server = running.clone();
+    }
+
+    let next_state =
+        AppState::reloaded(next, &current).context("Reloaded configuration is invalid")?;
+    state.replace(next_state);
+    Ok(())
+}
+
+// 主配置文件之外，还要监视 json_file、include 进来的文件与 api_key_file
+fn watched_files(path: &Path, state: &LiveState) -> Vec<PathBuf> {
+    let mut files = vec![path.to_path_buf()];
+    files.extend(state.snapshot().config().source_files());
+    files
+}
+
+pub(crate) fn modified_times(paths: &[PathBuf]) -> Vec<Option<SystemTime>> {
+    paths
+        .iter()
+        .map(|path| std::fs::metadata(path).and_then(|m| m.modified()).ok())
+        .collect()
+}
+
+#[cfg(unix)]
+pub(crate) struct HangupListener(Option<tokio::signal::unix::Signal>);
+
+#[cfg(unix)]
+impl HangupListener {
+    pub(crate) fn new() -> Self {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::hangup()) {
+            Ok(sig) => Self(Some(sig)),
+            Err(err) => {
+                tracing::warn!("Failed to install SIGHUP handler: {}", err);
+                Self(None)
+            }
+        }
+    }
+
+    pub(crate) async fn recv(&mut self) {
+        match self.0.as_mut() {
+            Some(sig) => {
+                sig.recv().await;
+            }
+            None => std::future::pending().await,
+        }
+    }
+}
+
+#[cfg(not(unix))]
+pub(crate) struct HangupListener;
+
+#[cfg(not(unix))]
+impl HangupListener {
+    pub(crate) fn new() -> Self {
+        Self
+    }
+
+    pub(crate) async fn recv(&mut self) {
+        std::future::pending().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn config_toml(port: u16, models: &[&str], worker: &str) -> String {
+        let mut out = format!("[server]\nhost = \"127.0.0.1\"\nport = {}\n\n", port);
+        for name in models {
+            out.push_str(&format!(
+                "[[model]]\napi_base = \"https://api.example.com/v1\"\napi_key = \"k\"\nname = \"{}\"\n\n",
+                name
+            ));
+        }
+        out.push_str(&format!(
+            "[workflow-integration]\njson = \"\"\"{{\"analyzer\": {{\"ref\": \"m1\"}}, \"workers\": [{{\"name\": \"{}\"}}], \"synthesizer\": {{\"ref\": \"m1\"}}}}\"\"\"\n",
+            worker
+        ));
+        out.push_str("\n[workflow.timeouts]\nanalyzer_timeout_secs = 3\nworker_timeout_secs = 6\nsynthesizer_timeout_secs = 9\n");
+        out
+    }
+
+    fn live_state_from(path: &Path) -> LiveState {
+        let config = Config::load(&path.to_string_lossy()).unwrap();
+        LiveState::new(AppState::new(config).unwrap())
+    }
+
+    fn temp_config_path(tag: &str) -> PathBuf {
+        crate::test_util::temp_dir(&format!("reload_{}", tag)).join("config.toml")
+    }
+
+    #[test]
+    fn valid_config_replaces_snapshot() {
+        let path = temp_config_path("valid");
+        fs::write(&path, config_toml(11435, &["m1"], "m1")).unwrap();
+        let state = live_state_from(&path);
+        let before = state.snapshot();
+
+        fs::write(&path, config_toml(11435, &["m1", "m2"], "m2")).unwrap();
+        reload_config(&state, &path).expect("reload should succeed");
+
+        let after = state.snapshot();
+        assert_eq!(after.config().models.len(), 2);
+        // 旧快照仍然可用，进行中的请求不受影响
+        assert_eq!(before.config().models.len(), 1);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn invalid_config_keeps_previous_snapshot() {
+        let path = temp_config_path("invalid");
+        fs::write(&path, config_toml(11435, &["m1"], "m1")).unwrap();
+        let state = live_state_from(&path);
+
+        fs::write(&path, config_toml(11435, &["m1"], "missing")).unwrap();
+        let err = reload_config(&state, &path).expect_err("unknown model should be rejected");
+        assert!(format!("{:#}", err).contains("unknown model 'missing'"));
+
+        fs::write(&path, "not = [valid toml").unwrap();
+        assert!(reload_config(&state, &path).is_err());
+
+        let current = state.snapshot();
+        assert_eq!(current.config().models.len(), 1);
+        assert_eq!(current.config().models[0].name, "m1");
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn server_address_change_is_ignored() {
+        let path = temp_config_path("address");
+        fs::write(&path, config_toml(11435, &["m1"], "m1")).unwrap();
+        let state = live_state_from(&path);
+
+        fs::write(&path, config_toml(9999, &["m1", "m2"], "m1")).unwrap();
+        reload_config(&state, &path).expect("reload should succeed");
+
+        let current = state.snapshot();
+        assert_eq!(current.config().server.port, Some(11435));
+        assert_eq!(current.config().models.len(), 2);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn external_workflow_json_is_watched_and_reloaded() {
+        let path = temp_config_path("json_file");
+        let json_path = path.with_extension("workflow.json");
+        let workflow = |worker: &str| {
+            format!(
+                "{{\"analyzer\": {{\"ref\": \"m1\"}}, \"workers\": [{{\"name\": \"{}\"}}], \"synthesizer\": {{\"ref\": \"m1\"}}}}",
+                worker
+            )
+        };
+        let inline = config_toml(11435, &["m1", "m2"], "m1");
+        let (head, _) = inline.split_once("json = ").unwrap();
+        let (_, timeouts) = inline.split_once("\n\n[workflow.timeouts]").unwrap();
+        let content = format!(
+            "{}json_file = \"{}\"\n\n[workflow.timeouts]{}",
+            head,
+            json_path.file_name().unwrap().to_string_lossy(),
+            timeouts
+        );
+        fs::write(&path, content).unwrap();
+        fs::write(&json_path, workflow("m1")).unwrap();
+        let state = live_state_from(&path);
+        assert_eq!(
+            watched_files(&path, &state),
+            vec![path.clone(), json_path.clone()]
+        );
+
+        fs::write(&json_path, workflow("m2")).unwrap();
+        reload_config(&state, &path).expect("reload should succeed");
+        assert_eq!(
+            state
+                .snapshot()
+                .config()
+                .workflow_integration
+                .worker_labels(),
+            vec!["m2"]
+        );
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+
+    #[test]
+    fn included_files_and_api_key_files_are_watched() {
+        let path = temp_config_path("sources");
+        let dir = path.with_extension("d");
+        fs::create_dir_all(&dir).unwrap();
+        let include = dir.join("models.toml");
+        // api_key_file 按主配置文件所在目录解析，include 进来的模型也一样
+        let key = path.with_extension("key");
+        let main = config_toml(11435, &["m1"], "m1");
+        fs::write(
+            &path,
+            format!(
+                "include = [\"{}/*.toml\"]\n{}",
+                dir.file_name().unwrap().to_string_lossy(),
+                main
+            ),
+        )
+        .unwrap();
+        fs::write(
+            &include,
+            format!(
+                "[[model]]\napi_base = \"https://api.example.com/v1\"\napi_key_file = \"{}\"\nname = \"m2\"\n",
+                key.file_name().unwrap().to_string_lossy()
+            ),
+        )
+        .unwrap();
+        fs::write(&key, "first-key\n").unwrap();
+
+        let state = live_state_from(&path);
+        assert_eq!(
+            watched_files(&path, &state),
+            vec![path.clone(), include.clone(), key.clone()]
+        );
+
+        fs::write(&key, "second-key\n").unwrap();
+        reload_config(&state, &path).expect("reload should succeed");
+        let snapshot = state.snapshot();
+        let m2 = snapshot.config().models.iter().find(|m| m.name == "m2");
+        assert_eq!(m2.unwrap().api_key(), "second-key");
+
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+    }
+}
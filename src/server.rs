@@ -1,35 +1,220 @@
-use crate::config::Config;
-use crate::workflow::{StreamCallback, WorkflowEngine, WorkflowExecutionDetails};
-use anyhow::Result;
+use crate::audit::AuditLog;
+use crate::config::{Config, IpRange, ServerConfig};
+use crate::history::{
+    prompt_hash, HistoryQuery, HistoryRecord, HistoryStatus, HistoryStore, DEFAULT_LIST_LIMIT,
+    MAX_LIST_LIMIT,
+};
+use crate::inflight::MAX_LISTED_WORKFLOWS;
+use crate::llm::{
+    ceil_secs, provider_request_ids_of, ChatMessage, GenerationParams, LlmHttpError, ProviderError,
+    UpstreamRateLimited,
+};
+use crate::logging::ACCESS_TARGET;
+use crate::redaction::redact_tokens;
+use crate::show::redact_url_credentials;
+use crate::summary::WorkflowSummary;
+use crate::telemetry;
+use crate::transport::HttpTransport;
+use crate::usage::{self, GroupBy, UsageTracker};
+use crate::workflow::{
+    retry_after_hint, NoEnabledWorkers, RequestOptions, StreamCallback, WorkflowEngine,
+    WorkflowExecutionDetails,
+};
+use anyhow::{Context, Result};
 use axum::{
-    extract::State,
-    http::StatusCode,
+    extract::{ConnectInfo, MatchedPath, Path, Query, Request, State},
+    http::{header, HeaderValue, StatusCode},
+    middleware::{self, Next},
     response::{
         sse::{Event, Sse},
         IntoResponse, Response,
     },
     routing::{get, post},
-    Json, Router,
+    Extension, Json, Router,
 };
+use bytes::Bytes;
 use futures::{stream, StreamExt};
 
 use serde::{Deserialize, Serialize};
 use serde_json::Value;
-use std::sync::atomic::{AtomicUsize, Ordering};
-use std::sync::Arc;
-use tokio::sync::{mpsc, oneshot};
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::sync::{mpsc, oneshot, watch};
 use tokio_stream::wrappers::UnboundedReceiverStream;
 
+use hyper_util::rt::TokioIo;
+use hyper_util::server::graceful::Watcher;
+use hyper_util::service::TowerToHyperService;
 use std::convert::Infallible;
+use std::future::Future;
 use tower_http::cors::CorsLayer;
+use tracing::Instrument;
+
+static NEXT_WORKFLOW_ID: AtomicU64 = AtomicU64::new(1);
+
+tokio::task_local! {
+    // 供 AppError 把请求 ID 写进错误响应体
+    static CURRENT_REQUEST_ID: String;
+}
+
+const REQUEST_ID_HEADER: &str = "x-request-id";
+const MAX_REQUEST_ID_LEN: usize = 128;
+
+// 每个入站请求的 ID：沿用客户端的 X-Request-Id，没有或不合法时生成一个
+#[derive(Debug, Clone)]
+struct RequestId(String);
 
-type SharedState = Arc<AppState>;
+// 命中的路由模板，如 /v1/chat/completions；写进工作流汇总日志
+#[derive(Debug, Clone)]
+struct RequestRoute(String);
+
+type SharedState = Arc<LiveState>;
 
 const STREAM_CHUNK_SIZE: usize = 120;
 
 pub struct AppState {
     config: Config,
     workflow_engine: WorkflowEngine,
+    history: Option<Arc<HistoryStore>>,
+}
+
+impl AppState {
+    pub fn new(config: Config) -> Result<Self> {
+        Self::with_transport(config, None)
+    }
+
+    // 传入 transport 时所有上游请求都经由它发出，测试用它代替真实的 HTTP 客户端
+    pub fn with_transport(
+        config: Config,
+        transport: Option<Arc<dyn HttpTransport>>,
+    ) -> Result<Self> {
+        let audit = match &config.audit {
+            Some(audit) => {
+                let log = AuditLog::open(audit)?;
+                tracing::info!("Auditing upstream calls to {}", audit.path);
+                Some(Arc::new(log))
+            }
+            None => None,
+        };
+        let history = match &config.history {
+            Some(history) => {
+                let store = HistoryStore::open(history)?;
+                NEXT_WORKFLOW_ID.fetch_max(store.next_workflow_id(), Ordering::Relaxed);
+                tracing::info!("Recording workflow history to {}", history.path);
+                Some(Arc::new(store))
+            }
+            None => None,
+        };
+        // 启用历史记录时用量汇总也存进同一个库，重启后接着累计
+        let mut usage = UsageTracker::new(config.stats.usage_retention());
+        if let Some(store) = &history {
+            usage = usage.with_store(store.clone())?;
+        }
+        let workflow_engine = WorkflowEngine::new(config.clone())?
+            .with_audit(audit)
+            .with_usage(Arc::new(usage))
+            .with_transport(transport);
+        Ok(Self {
+            config,
+            workflow_engine,
+            history,
+        })
+    }
+
+    // 热加载时复用旧引擎的限流状态、延迟统计、用量汇总、在途工作流、审计日志与历史记录库
+    pub fn reloaded(config: Config, previous: &AppState) -> Result<Self> {
+        let workflow_engine = WorkflowEngine::with_rate_limiter(
+            config.clone(),
+            previous.workflow_engine.rate_limiter(),
+        )?
+        .with_latency(previous.workflow_engine.latency())
+        .with_audit(previous.workflow_engine.audit())
+        .with_inflight(previous.workflow_engine.inflight())
+        .with_usage(previous.workflow_engine.usage())
+        .with_transport(previous.workflow_engine.transport());
+        if config.history != previous.config.history {
+            tracing::warn!("[history] changes take effect after a restart");
+        }
+        if config.audit != previous.config.audit {
+            tracing::warn!("[audit] changes take effect after a restart");
+        }
+        // 输出层在启动时建立；enabled、exclude_paths 与 trusted_proxies 随热加载生效
+        let access_output = |config: &Config| {
+            config
+                .access_log
+                .as_ref()
+                .map(|access_log| (access_log.format, access_log.file.clone()))
+        };
+        if access_output(&config) != access_output(&previous.config) {
+            tracing::warn!(
+                "Adding or removing [access_log], or changing its format or file, takes effect after a restart"
+            );
+        }
+        Ok(Self {
+            config,
+            workflow_engine,
+            history: previous.history.clone(),
+        })
+    }
+
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    // 已配置的模型之外，每个工作流预设也以模型名的形式列出
+    fn listed_model_names(&self) -> Vec<String> {
+        self.config
+            .models
+            .iter()
+            .filter(|m| m.is_enabled())
+            .map(|m| m.name.clone())
+            .chain(self.config.workflow_integration.preset_model_names())
+            .collect()
+    }
+
+    fn preset_for(&self, model: &str) -> Option<String> {
+        let preset = self.config.workflow_integration.resolve_preset(model);
+        if let Some(name) = &preset {
+            tracing::debug!("Model '{}' selects workflow preset '{}'", model, name);
+        }
+        preset
+    }
+}
+
+// 新请求取当前快照；热加载时整体替换，进行中的请求继续持有旧快照
+pub struct LiveState {
+    current: RwLock<Arc<AppState>>,
+    // 进程启动时间，热加载不重置
+    started_at: chrono::DateTime<chrono::Utc>,
+    started: std::time::Instant,
+}
+
+impl LiveState {
+    pub fn new(state: AppState) -> Self {
+        Self {
+            current: RwLock::new(Arc::new(state)),
+            started_at: chrono::Utc::now(),
+            started: std::time::Instant::now(),
+        }
+    }
+
+    pub fn snapshot(&self) -> Arc<AppState> {
+        self.current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .clone()
+    }
+
+    pub fn replace(&self, state: AppState) {
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(state);
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,20 +223,18 @@ pub struct GenerateRequest {
     pub prompt: String,
     pub stream: Option<bool>,
     pub include_workflow: Option<bool>,
+    #[serde(flatten)]
+    pub generation: GenerationParams,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub struct ChatRequest {
     pub model: Option<String>,
-    pub messages: Vec<Message>,
+    pub messages: Vec<ChatMessage>,
     pub stream: Option<bool>,
     pub include_workflow: Option<bool>,
-}
-
-#[derive(Debug, Serialize, Deserialize)]
-pub struct Message {
-    pub role: String,
-    pub content: String,
+    #[serde(flatten)]
+    pub generation: GenerationParams,
 }
 
 #[derive(Debug, Deserialize)]
@@ -76,6 +259,8 @@ pub struct CompletionRequest {
     pub prompt: PromptInput,
     pub stream: Option<bool>,
     pub include_workflow: Option<bool>,
+    #[serde(flatten)]
+    pub generation: GenerationParams,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -92,7 +277,7 @@ pub struct GenerateResponse {
 pub struct ChatResponse {
     pub model: String,
     pub created_at: String,
-    pub message: Message,
+    pub message: ChatMessage,
     pub done: bool,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub workflow: Option<WorkflowExecutionDetails>,
@@ -125,7 +310,7 @@ fn chunk_text(content: &str, max_len: usize) -> Vec<String> {
     chunks
 }
 
-fn build_prompt_from_messages(messages: &[Message]) -> String {
+fn build_prompt_from_messages(messages: &[ChatMessage]) -> String {
     messages
         .iter()
         .map(|m| format!("{}: {}", m.role, m.content))
@@ -133,25 +318,128 @@ fn build_prompt_from_messages(messages: &[Message]) -> String {
         .join("\n")
 }
 
+// 每次执行在 /api/status 中登记，结束（包括出错与客户端断开）时输出一行汇总日志
 async fn execute_workflow(
     state: &AppState,
     prompt: String,
     include_workflow: bool,
+    mut options: RequestOptions,
     stream: Option<StreamCallback>,
 ) -> Result<(String, Option<WorkflowExecutionDetails>), AppError> {
-    if include_workflow {
-        let result = state
-            .workflow_engine
-            .process_with_details_stream(prompt, stream)
-            .await?;
-        Ok((result.final_response, Some(result.execution_details)))
-    } else {
-        let response = state
-            .workflow_engine
-            .process_with_stream(prompt, stream)
-            .await?;
-        Ok((response, None))
+    // 同一次请求内 analyzer / worker / synthesizer 的日志共享 workflow_id；request_id 在外层的 request span 上
+    let workflow_id = NEXT_WORKFLOW_ID.fetch_add(1, Ordering::Relaxed);
+    options.workflow_id = Some(workflow_id);
+    let registration = state.workflow_engine.inflight().register(
+        workflow_id,
+        options.request_id.clone(),
+        options.preset.clone(),
+    );
+    let summary = WorkflowSummary::begin(
+        registration,
+        state.config.logging.workflow_summary(),
+        workflow_id,
+        options.request_id.clone(),
+        options.route.clone(),
+        options.preset.clone(),
+    );
+    let result = run_workflow(
+        state,
+        prompt,
+        include_workflow,
+        options,
+        stream,
+        workflow_id,
+    )
+    .await;
+    summary.finish(result.is_ok());
+    result
+}
+
+async fn run_workflow(
+    state: &AppState,
+    prompt: String,
+    include_workflow: bool,
+    options: RequestOptions,
+    stream: Option<StreamCallback>,
+    workflow_id: u64,
+) -> Result<(String, Option<WorkflowExecutionDetails>), AppError> {
+    let hash = prompt_hash(&prompt);
+    let span = tracing::info_span!(
+        "workflow",
+        workflow_id,
+        prompt_hash = %hash,
+        preset = options.preset.as_deref().unwrap_or("default")
+    );
+    let Some(history) = state.history.as_ref() else {
+        return async move {
+            if include_workflow {
+                let result = state
+                    .workflow_engine
+                    .process_with_details_stream(prompt, options, stream)
+                    .await?;
+                Ok((result.final_response, Some(result.execution_details)))
+            } else {
+                let response = state
+                    .workflow_engine
+                    .process_with_stream(prompt, options, stream)
+                    .await?;
+                Ok((response, None))
+            }
+        }
+        .instrument(span)
+        .await;
+    };
+
+    // 记录历史时总是取完整的执行详情；写入在后台线程完成
+    let mut record = HistoryRecord {
+        workflow_id,
+        request_id: options.request_id.clone(),
+        started_at: chrono::Utc::now(),
+        duration_ms: 0,
+        status: HistoryStatus::Success,
+        error: None,
+        preset: options.preset.clone(),
+        prompt_hash: hash,
+        prompt: history.store_prompts().then(|| prompt.clone()),
+        final_response: None,
+        details: None,
+    };
+    let started = std::time::Instant::now();
+    let result = state
+        .workflow_engine
+        .process_with_details_stream(prompt, options, stream)
+        .instrument(span)
+        .await;
+    record.duration_ms = started.elapsed().as_millis() as u64;
+    match &result {
+        Ok(result) => {
+            record.final_response = Some(result.final_response.clone());
+            record.details = Some(result.execution_details.clone());
+        }
+        Err(err) => {
+            record.status = HistoryStatus::Error;
+            record.error = Some(redact_tokens(&format!("{:#}", err)));
+        }
+    }
+    history.record(record);
+
+    let result = result?;
+    Ok((
+        result.final_response,
+        include_workflow.then_some(result.execution_details),
+    ))
+}
+
+// Responses API 使用 max_output_tokens，其余采样参数与 chat.completions 同名
+fn generation_params_from_responses_body(body: &Value) -> GenerationParams {
+    let mut params: GenerationParams = serde_json::from_value(body.clone()).unwrap_or_default();
+    if params.max_tokens.is_none() {
+        params.max_tokens = body
+            .get("max_output_tokens")
+            .and_then(Value::as_u64)
+            .and_then(|v| u32::try_from(v).ok());
     }
+    params
 }
 
 fn insert_workflow_field(
@@ -167,15 +455,38 @@ fn insert_workflow_field(
     }
 }
 
-pub async fn start_server(config: Arc<Config>) -> Result<()> {
-    let workflow_engine = WorkflowEngine::new((*config).clone());
+pub async fn start_server(config: Arc<Config>, config_path: PathBuf) -> Result<()> {
+    let state = Arc::new(LiveState::new(AppState::new((*config).clone())?));
+    crate::reload::spawn_config_watcher(config_path, state.clone());
+    tracing::info!(
+        "Upstream connections: {}, connect timeout {}s",
+        config.network.pool_settings().describe(),
+        config.workflow.timeouts.connect_timeout_secs
+    );
+    for (host, address) in &config.network.resolve {
+        tracing::info!("Static resolve: {} -> {}", host, address);
+    }
+    for model in config.models.iter().filter(|model| model.is_enabled()) {
+        for (host, address) in &model.resolve {
+            tracing::info!(
+                "Static resolve for model '{}': {} -> {}",
+                model.name,
+                host,
+                address
+            );
+        }
+        tracing::info!(
+            "Model '{}' chat endpoint: {}",
+            model.name,
+            redact_url_credentials(&model.endpoint().chat_url(&model.name))
+        );
+    }
 
-    let state = Arc::new(AppState {
-        config: (*config).clone(),
-        workflow_engine,
-    });
+    serve(&config.server, router(state), shutdown_signal()).await
+}
 
-    let app = Router::new()
+fn router(state: SharedState) -> Router {
+    Router::new()
         .route("/", get(health_check))
         // API v0 style
         .route("/api/generate", post(generate))
@@ -190,20 +501,352 @@ pub async fn start_server(config: Arc<Config>) -> Result<()> {
         .route("/v1/models", get(list_models_openai))
         .route("/v1/tags", get(list_models))
         .route("/v1/responses", post(responses))
+        .route("/api/status", get(status))
+        .route("/api/stats/rate-limits", get(rate_limit_stats))
+        .route("/api/stats/models", get(model_stats))
+        .route("/api/usage", get(usage_summary))
+        .route("/metrics", get(metrics))
+        .route("/api/workflow/plan", get(workflow_plan))
         .layer(CorsLayer::permissive())
-        .with_state(state);
+        // 历史记录含完整的提示词与回答，重置统计会改动服务端状态：不开放跨域访问
+        .merge(
+            Router::new()
+                .route("/api/stats/models/reset", post(reset_model_stats))
+                .route("/api/workflows", get(list_workflows))
+                .route("/api/workflows/:workflow_id", get(get_workflow))
+                .layer(middleware::from_fn(reject_cross_origin)),
+        )
+        .layer(middleware::from_fn_with_state(state.clone(), access_log))
+        .layer(middleware::from_fn(assign_request_id))
+        .with_state(state)
+}
+
+// 不带 CORS 头时浏览器读不到响应，但简单 POST 仍会被发出；带 Origin 的请求都来自浏览器页面，直接拒绝
+async fn reject_cross_origin(request: Request, next: Next) -> Response {
+    if request.headers().contains_key(header::ORIGIN) {
+        return AppError::new(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!("Cross-origin requests are not allowed on this endpoint"),
+        )
+        .into_response();
+    }
+    next.run(request).await
+}
+
+async fn assign_request_id(mut request: Request, next: Next) -> Response {
+    let id = request
+        .headers()
+        .get(REQUEST_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::trim)
+        .filter(|id| is_valid_request_id(id))
+        .map(str::to_string)
+        .unwrap_or_else(generate_request_id);
+    request.extensions_mut().insert(RequestId(id.clone()));
+    let route = request.extensions().get::<MatchedPath>().map_or_else(
+        || request.uri().path().to_string(),
+        |path| path.as_str().to_string(),
+    );
+    request.extensions_mut().insert(RequestRoute(route));
+
+    // 整个请求的日志都带上 request_id；流式响应的后台任务由 spawn_in_current_span 继承
+    let span = tracing::info_span!(
+        "request",
+        request_id = %id,
+        method = %request.method(),
+        path = %request.uri().path()
+    );
+    telemetry::set_remote_parent(&span, request.headers());
+    let mut response = CURRENT_REQUEST_ID
+        .scope(id.clone(), next.run(request))
+        .instrument(span)
+        .await;
+    if let Ok(value) = HeaderValue::from_str(&id) {
+        response.headers_mut().insert(REQUEST_ID_HEADER, value);
+    }
+    response
+}
+
+// 访问日志在响应体发送完毕（或连接中途断开）时才写，SSE 的耗时因此覆盖整个流
+async fn access_log(State(live): State<SharedState>, request: Request, next: Next) -> Response {
+    let path = request.uri().path().to_string();
+    let entry = {
+        let state = live.snapshot();
+        match state.config.access_log.as_ref() {
+            Some(config) if config.enabled() && !config.excludes(&path) => Some(AccessEntry {
+                method: request.method().to_string(),
+                path,
+                request_id: request
+                    .extensions()
+                    .get::<RequestId>()
+                    .map(|id| id.0.clone()),
+                client_ip: client_ip(&request, &config.trusted_proxies()),
+                status: 0,
+                started: std::time::Instant::now(),
+            }),
+            _ => None,
+        }
+    };
+    let response = next.run(request).await;
+    let Some(mut entry) = entry else {
+        return response;
+    };
+    let (parts, body) = response.into_parts();
+    entry.status = parts.status.as_u16();
+    Response::from_parts(
+        parts,
+        axum::body::Body::new(LoggedBody {
+            inner: body,
+            bytes: 0,
+            entry: Some(entry),
+        }),
+    )
+}
+
+// 直连地址是可信代理时，从 X-Forwarded-For 右侧往左跳过可信代理，第一个不可信的地址即客户端；
+// Unix socket 连接没有地址
+fn client_ip(request: &Request, trusted: &[IpRange]) -> Option<IpAddr> {
+    let peer = request
+        .extensions()
+        .get::<ConnectInfo<SocketAddr>>()?
+        .0
+        .ip();
+    let is_trusted = |ip: IpAddr| trusted.iter().any(|range| range.contains(ip));
+    let mut client = peer;
+    if !is_trusted(peer) {
+        return Some(client);
+    }
+    let forwarded: Vec<&str> = request
+        .headers()
+        .get_all("x-forwarded-for")
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(','))
+        .collect();
+    for hop in forwarded.into_iter().rev() {
+        let Ok(ip) = hop.trim().parse::<IpAddr>() else {
+            break;
+        };
+        client = ip;
+        if !is_trusted(ip) {
+            break;
+        }
+    }
+    Some(client)
+}
+
+struct AccessEntry {
+    method: String,
+    path: String,
+    request_id: Option<String>,
+    client_ip: Option<IpAddr>,
+    status: u16,
+    started: std::time::Instant,
+}
+
+// 统计实际发出的响应体字节数，释放时写访问日志
+struct LoggedBody {
+    inner: axum::body::Body,
+    bytes: u64,
+    entry: Option<AccessEntry>,
+}
+
+impl http_body::Body for LoggedBody {
+    type Data = Bytes;
+    type Error = axum::Error;
+
+    fn poll_frame(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<Result<http_body::Frame<Self::Data>, Self::Error>>> {
+        let polled = std::pin::Pin::new(&mut self.inner).poll_frame(cx);
+        if let std::task::Poll::Ready(Some(Ok(frame))) = &polled {
+            if let Some(data) = frame.data_ref() {
+                self.bytes += data.len() as u64;
+            }
+        }
+        polled
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.inner.is_end_stream()
+    }
+
+    fn size_hint(&self) -> http_body::SizeHint {
+        self.inner.size_hint()
+    }
+}
+
+impl Drop for LoggedBody {
+    fn drop(&mut self) {
+        let Some(entry) = self.entry.take() else {
+            return;
+        };
+        let client_ip = entry.client_ip.map(|ip| ip.to_string());
+        tracing::info!(
+            target: ACCESS_TARGET,
+            method = %entry.method,
+            path = %entry.path,
+            status = entry.status,
+            latency_ms = entry.started.elapsed().as_millis() as u64,
+            bytes = self.bytes,
+            request_id = entry.request_id.as_deref(),
+            client_ip = client_ip.as_deref(),
+            "{} {} {}",
+            entry.method,
+            entry.path,
+            entry.status
+        );
+    }
+}
+
+// ID 会原样转发给上游并写进日志，只接受不含空白的可见 ASCII
+fn is_valid_request_id(id: &str) -> bool {
+    !id.is_empty() && id.len() <= MAX_REQUEST_ID_LEN && id.bytes().all(|b| b.is_ascii_graphic())
+}
 
-    let addr = format!("{}:{}", config.server.host, config.server.port);
+// UUID v4 格式；RandomState 自带随机种子，不必为此引入 uuid 依赖
+fn generate_request_id() -> String {
+    use std::hash::{BuildHasher, Hasher};
+    let random = || {
+        std::collections::hash_map::RandomState::new()
+            .build_hasher()
+            .finish()
+    };
+    let (high, low) = (random(), random());
+    format!(
+        "{:08x}-{:04x}-4{:03x}-{:04x}-{:012x}",
+        high >> 32,
+        (high >> 16) & 0xffff,
+        high & 0x0fff,
+        (low >> 48) & 0x3fff | 0x8000,
+        low & 0xffff_ffff_ffff
+    )
+}
 
-    let listener = tokio::net::TcpListener::bind(&addr).await?;
+// 流式响应在后台任务里跑工作流，任务要留在当前请求的 span 里
+fn spawn_in_current_span<F>(future: F)
+where
+    F: Future<Output = ()> + Send + 'static,
+{
+    tokio::spawn(future.in_current_span());
+}
 
-    tracing::info!("Chorus server listening on http://{}", addr);
+// TCP 与 Unix socket 共用同一个关闭信号，任一监听失败时整体退出
+async fn serve(
+    server: &ServerConfig,
+    app: Router,
+    shutdown: impl Future<Output = ()> + Send + 'static,
+) -> Result<()> {
+    let (stop_tx, stop_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown.await;
+        let _ = stop_tx.send(true);
+    });
+    let stopped = |mut rx: watch::Receiver<bool>| async move {
+        let _ = rx.wait_for(|stop| *stop).await;
+    };
 
-    axum::serve(listener, app).await?;
+    let tcp = async {
+        let Some(addr) = server.tcp_address() else {
+            return Ok(());
+        };
+        // 证书有问题时在监听端口之前就报错退出
+        let certs = match &server.tls {
+            Some(tls) => Some(Arc::new(crate::tls::TlsCertificates::load(tls)?)),
+            None => None,
+        };
+        let listener = tokio::net::TcpListener::bind(&addr)
+            .await
+            .with_context(|| format!("Failed to listen on {}", addr))?;
+        tracing::info!(
+            "Chorus server listening on {}://{}",
+            server.tcp_scheme(),
+            addr
+        );
+        match certs {
+            Some(certs) => {
+                crate::tls::spawn_certificate_reloader(certs.clone());
+                crate::tls::serve(listener, certs, app.clone(), stopped(stop_rx.clone())).await?;
+            }
+            None => {
+                axum::serve(
+                    listener,
+                    app.clone()
+                        .into_make_service_with_connect_info::<SocketAddr>(),
+                )
+                .with_graceful_shutdown(stopped(stop_rx.clone()))
+                .await?;
+            }
+        }
+        Ok::<(), anyhow::Error>(())
+    };
+
+    let unix = async {
+        #[cfg(unix)]
+        if let Some(path) = &server.unix_socket {
+            let socket =
+                crate::unix_socket::bind(std::path::Path::new(path), server.unix_socket_mode())?;
+            tracing::info!("Chorus server listening on unix:{}", path);
+            crate::unix_socket::serve(socket, app.clone(), stopped(stop_rx.clone())).await?;
+        }
+        Ok::<(), anyhow::Error>(())
+    };
 
+    tokio::try_join!(tcp, unix)?;
+    tracing::info!("Chorus server stopped");
     Ok(())
 }
 
+// Unix socket 与 TLS 监听自行 accept 连接，再交给同一个 Router 处理；
+// 对端地址和 axum::serve 一样以 ConnectInfo 提供给访问日志
+pub(crate) async fn serve_http1<I>(io: I, peer: Option<SocketAddr>, app: Router, watcher: Watcher)
+where
+    I: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+{
+    let app = tower::ServiceExt::map_request(app, move |mut request: hyper::Request<_>| {
+        if let Some(peer) = peer {
+            request.extensions_mut().insert(ConnectInfo(peer));
+        }
+        request
+    });
+    let conn = hyper::server::conn::http1::Builder::new()
+        .serve_connection(TokioIo::new(io), TowerToHyperService::new(app));
+    if let Err(err) = watcher.watch(conn).await {
+        tracing::debug!("Connection closed with error: {}", err);
+    }
+}
+
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::warn!("Failed to listen for Ctrl-C: {}", err);
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        match tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate()) {
+            Ok(mut sig) => {
+                sig.recv().await;
+            }
+            Err(err) => {
+                tracing::warn!("Failed to install SIGTERM handler: {}", err);
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutting down");
+}
+
 async fn health_check() -> impl IntoResponse {
     Json(serde_json::json!({
         "status": "ok",
@@ -213,14 +856,18 @@ async fn health_check() -> impl IntoResponse {
 }
 
 async fn generate(
-    State(state): State<SharedState>,
+    State(live): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(route): Extension<RequestRoute>,
     Json(req): Json<GenerateRequest>,
 ) -> Result<Response, AppError> {
+    let state = live.snapshot();
     let GenerateRequest {
         model,
         prompt,
         stream,
         include_workflow,
+        generation,
     } = req;
 
     tracing::info!(
@@ -232,18 +879,27 @@ async fn generate(
     let model_name = model.unwrap_or_else(|| "chorus".to_string());
     let stream_enabled = stream.unwrap_or(false);
     let include_workflow_details = include_workflow.unwrap_or(false);
+    let options = RequestOptions {
+        generation,
+        preset: state.preset_for(&model_name),
+        request_id: Some(request_id.0),
+        route: Some(route.0),
+        ..Default::default()
+    };
 
     if stream_enabled {
+        state.workflow_engine.ensure_enabled_workers(&options)?;
         let created_at = chrono::Utc::now().to_rfc3339();
         let (chunk_tx, chunk_rx) = mpsc::unbounded_channel::<String>();
         let (result_tx, result_rx) = oneshot::channel();
 
         let state_clone = state.clone();
-        tokio::spawn(async move {
+        spawn_in_current_span(async move {
             let result = execute_workflow(
                 &state_clone,
                 prompt,
                 include_workflow_details,
+                options,
                 Some(chunk_tx.clone()),
             )
             .await;
@@ -293,7 +949,7 @@ async fn generate(
                         Ok::<Event, Infallible>(Event::default().json_data(payload).unwrap())
                     }
                     Ok(Err(err)) => {
-                        let error_message = err.error.to_string();
+                        let error_message = err.message();
                         let payload = serde_json::json!({
                             "model": model_name.clone(),
                             "created_at": created_at.clone(),
@@ -320,7 +976,7 @@ async fn generate(
     }
 
     let (response_text, workflow_details) =
-        execute_workflow(&state, prompt, include_workflow_details, None).await?;
+        execute_workflow(&state, prompt, include_workflow_details, options, None).await?;
 
     Ok(Json(GenerateResponse {
         model: model_name,
@@ -333,9 +989,12 @@ async fn generate(
 }
 
 async fn chat(
-    State(state): State<SharedState>,
+    State(live): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(route): Extension<RequestRoute>,
     Json(req): Json<ChatRequest>,
 ) -> Result<Response, AppError> {
+    let state = live.snapshot();
     tracing::info!(
         "Received chat request with {} messages, stream: {:?}, include_workflow: {:?}",
         req.messages.len(),
@@ -348,18 +1007,27 @@ async fn chat(
     let model_name = req.model.unwrap_or_else(|| "chorus".to_string());
     let stream_enabled = req.stream.unwrap_or(false);
     let include_workflow_details = req.include_workflow.unwrap_or(false);
+    let options = RequestOptions {
+        generation: req.generation,
+        preset: state.preset_for(&model_name),
+        request_id: Some(request_id.0),
+        route: Some(route.0),
+        ..Default::default()
+    };
 
     if stream_enabled {
+        state.workflow_engine.ensure_enabled_workers(&options)?;
         let created_at = chrono::Utc::now().to_rfc3339();
         let (chunk_tx, chunk_rx) = mpsc::unbounded_channel::<String>();
         let (result_tx, result_rx) = oneshot::channel();
 
         let state_clone = state.clone();
-        tokio::spawn(async move {
+        spawn_in_current_span(async move {
             let result = execute_workflow(
                 &state_clone,
                 prompt,
                 include_workflow_details,
+                options,
                 Some(chunk_tx.clone()),
             )
             .await;
@@ -415,7 +1083,7 @@ async fn chat(
                         Ok::<Event, Infallible>(Event::default().json_data(payload).unwrap())
                     }
                     Ok(Err(err)) => {
-                        let error_message = err.error.to_string();
+                        let error_message = err.message();
                         let payload = serde_json::json!({
                             "model": model_name.clone(),
                             "created_at": created_at.clone(),
@@ -450,12 +1118,12 @@ async fn chat(
     }
 
     let (response_text, workflow_details) =
-        execute_workflow(&state, prompt, include_workflow_details, None).await?;
+        execute_workflow(&state, prompt, include_workflow_details, options, None).await?;
 
     Ok(Json(ChatResponse {
         model: model_name,
         created_at: chrono::Utc::now().to_rfc3339(),
-        message: Message {
+        message: ChatMessage {
             role: "assistant".to_string(),
             content: response_text,
         },
@@ -467,9 +1135,12 @@ async fn chat(
 
 // OpenAI Chat Completions compatible endpoint
 async fn openai_chat_completions(
-    State(state): State<SharedState>,
+    State(live): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(route): Extension<RequestRoute>,
     Json(req): Json<ChatRequest>,
 ) -> Result<Response, AppError> {
+    let state = live.snapshot();
     tracing::info!(
         "Received OpenAI chat.completions request with {} messages, stream: {:?}",
         req.messages.len(),
@@ -481,8 +1152,16 @@ async fn openai_chat_completions(
     let model_name = req.model.unwrap_or_else(|| "chorus".to_string());
     let stream_enabled = req.stream.unwrap_or(false);
     let include_workflow_details = req.include_workflow.unwrap_or(false);
+    let options = RequestOptions {
+        generation: req.generation,
+        preset: state.preset_for(&model_name),
+        request_id: Some(request_id.0),
+        route: Some(route.0),
+        ..Default::default()
+    };
 
     if stream_enabled {
+        state.workflow_engine.ensure_enabled_workers(&options)?;
         let now = chrono::Utc::now();
         let created = now.timestamp();
         let id = format!("chatcmpl_{}", now.timestamp_millis());
@@ -490,11 +1169,12 @@ async fn openai_chat_completions(
         let (result_tx, result_rx) = oneshot::channel();
 
         let state_clone = state.clone();
-        tokio::spawn(async move {
+        spawn_in_current_span(async move {
             let result = execute_workflow(
                 &state_clone,
                 prompt,
                 include_workflow_details,
+                options,
                 Some(chunk_tx.clone()),
             )
             .await;
@@ -573,7 +1253,7 @@ async fn openai_chat_completions(
                         Ok::<Event, Infallible>(Event::default().json_data(payload).unwrap())
                     }
                     Ok(Err(err)) => {
-                        let error_message = err.error.to_string();
+                        let error_message = err.message();
                         let payload = serde_json::json!({
                             "id": id.clone(),
                             "object": "chat.completion.chunk",
@@ -620,7 +1300,7 @@ async fn openai_chat_completions(
     }
 
     let (response_text, workflow_details) =
-        execute_workflow(&state, prompt, include_workflow_details, None).await?;
+        execute_workflow(&state, prompt, include_workflow_details, options, None).await?;
     let now = chrono::Utc::now();
     let created = now.timestamp();
     let id = format!("chatcmpl_{}", now.timestamp_millis());
@@ -644,9 +1324,12 @@ async fn openai_chat_completions(
 }
 
 async fn openai_completions(
-    State(state): State<SharedState>,
+    State(live): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(route): Extension<RequestRoute>,
     Json(req): Json<CompletionRequest>,
 ) -> Result<Response, AppError> {
+    let state = live.snapshot();
     tracing::info!(
         "Received OpenAI completions request, stream: {:?}",
         req.stream
@@ -656,8 +1339,16 @@ async fn openai_completions(
     let model_name = req.model.unwrap_or_else(|| "chorus".to_string());
     let stream_enabled = req.stream.unwrap_or(false);
     let include_workflow_details = req.include_workflow.unwrap_or(false);
+    let options = RequestOptions {
+        generation: req.generation,
+        preset: state.preset_for(&model_name),
+        request_id: Some(request_id.0),
+        route: Some(route.0),
+        ..Default::default()
+    };
 
     if stream_enabled {
+        state.workflow_engine.ensure_enabled_workers(&options)?;
         let now = chrono::Utc::now();
         let created = now.timestamp();
         let id = format!("cmpl_{}", now.timestamp_millis());
@@ -665,11 +1356,12 @@ async fn openai_completions(
         let (result_tx, result_rx) = oneshot::channel();
 
         let state_clone = state.clone();
-        tokio::spawn(async move {
+        spawn_in_current_span(async move {
             let result = execute_workflow(
                 &state_clone,
                 prompt,
                 include_workflow_details,
+                options,
                 Some(chunk_tx.clone()),
             )
             .await;
@@ -731,7 +1423,7 @@ async fn openai_completions(
                         Ok::<Event, Infallible>(Event::default().json_data(payload).unwrap())
                     }
                     Ok(Err(err)) => {
-                        let error_message = err.error.to_string();
+                        let error_message = err.message();
                         let payload = serde_json::json!({
                             "id": id.clone(),
                             "object": "text_completion",
@@ -776,7 +1468,7 @@ async fn openai_completions(
     }
 
     let (response_text, workflow_details) =
-        execute_workflow(&state, prompt, include_workflow_details, None).await?;
+        execute_workflow(&state, prompt, include_workflow_details, options, None).await?;
 
     let now = chrono::Utc::now();
     let created = now.timestamp();
@@ -864,10 +1556,7 @@ fn extract_text_value(value: &Value) -> Option<String> {
 
 fn extract_message_text(value: &Value) -> Option<String> {
     if let Value::Object(map) = value {
-        let role = match map.get("role").and_then(|v| v.as_str()) {
-            Some(role) => role,
-            None => return None,
-        };
+        let role = map.get("role").and_then(|v| v.as_str())?;
         if let Some(content) = map.get("content") {
             if let Some(text) = extract_text_value(content) {
                 if text.is_empty() {
@@ -959,9 +1648,12 @@ fn extract_prompt_from_responses_body(payload: &Value) -> Option<String> {
 }
 
 async fn responses(
-    State(state): State<SharedState>,
+    State(live): State<SharedState>,
+    Extension(request_id): Extension<RequestId>,
+    Extension(route): Extension<RequestRoute>,
     Json(req): Json<Value>,
 ) -> Result<Response, AppError> {
+    let state = live.snapshot();
     let model_name = req
         .get("model")
         .and_then(|v| v.as_str())
@@ -992,8 +1684,16 @@ async fn responses(
     })?;
 
     let prompt_len = prompt.len();
+    let options = RequestOptions {
+        generation: generation_params_from_responses_body(&req),
+        preset: state.preset_for(&model_name),
+        request_id: Some(request_id.0),
+        route: Some(route.0),
+        ..Default::default()
+    };
 
     if stream_requested {
+        state.workflow_engine.ensure_enabled_workers(&options)?;
         let now = chrono::Utc::now();
         let created = now.timestamp();
         let resp_id = format!("resp_{}", now.timestamp_millis());
@@ -1003,11 +1703,12 @@ async fn responses(
 
         let state_clone = state.clone();
         let prompt_for_stream = prompt.clone();
-        tokio::spawn(async move {
+        spawn_in_current_span(async move {
             let result = execute_workflow(
                 &state_clone,
                 prompt_for_stream,
                 include_workflow_details,
+                options,
                 Some(chunk_tx.clone()),
             )
             .await;
@@ -1019,7 +1720,6 @@ async fn responses(
             let resp_id = resp_id.clone();
             let msg_id = msg_id.clone();
             let model_name = model_name.clone();
-            let created = created;
             async move {
                 let payload = serde_json::json!({
                     "id": resp_id,
@@ -1050,12 +1750,10 @@ async fn responses(
             UnboundedReceiverStream::new(chunk_rx).flat_map({
                 let resp_id = resp_id.clone();
                 let model_name = model_name.clone();
-                let created = created;
                 let content_index = content_index.clone();
                 move |segment| {
                     let resp_id = resp_id.clone();
                     let model_name = model_name.clone();
-                    let created = created;
                     let content_index = content_index.clone();
                     let pieces = if segment.is_empty() {
                         vec![String::new()]
@@ -1090,8 +1788,6 @@ async fn responses(
             let resp_id = resp_id.clone();
             let msg_id = msg_id.clone();
             let model_name = model_name.clone();
-            let prompt_len = prompt_len;
-            let created = created;
             async move {
                 match result_rx.await {
                     Ok(Ok((response_text, workflow_details))) => {
@@ -1123,7 +1819,7 @@ async fn responses(
                         )
                     }
                     Ok(Err(err)) => {
-                        let error_message = err.error.to_string();
+                        let error_message = err.message();
                         tracing::error!(
                             "Responses stream failed after prompt {} bytes: {}",
                             prompt_len,
@@ -1181,7 +1877,7 @@ async fn responses(
     }
 
     let (response_text, workflow_details) =
-        execute_workflow(&state, prompt, include_workflow_details, None).await?;
+        execute_workflow(&state, prompt, include_workflow_details, options, None).await?;
 
     tracing::debug!(
         "Generated responses payload (prompt {} bytes, response {} bytes)",
@@ -1211,15 +1907,15 @@ async fn responses(
     Ok(Json(resp).into_response())
 }
 
-async fn list_models_openai(State(state): State<SharedState>) -> impl IntoResponse {
+async fn list_models_openai(State(live): State<SharedState>) -> impl IntoResponse {
+    let state = live.snapshot();
     let created = chrono::Utc::now().timestamp();
     let data: Vec<_> = state
-        .config
-        .models
-        .iter()
-        .map(|m| {
+        .listed_model_names()
+        .into_iter()
+        .map(|name| {
             serde_json::json!({
-                "id": m.name,
+                "id": name,
                 "object": "model",
                 "created": created,
                 "owned_by": "chorus",
@@ -1234,15 +1930,293 @@ async fn list_models_openai(State(state): State<SharedState>) -> impl IntoRespon
     }))
 }
 
-async fn list_models(State(state): State<SharedState>) -> impl IntoResponse {
+// 服务当前在做什么：只读取登记表的快照，不等待正在执行的工作流
+async fn status(State(live): State<SharedState>) -> impl IntoResponse {
+    let state = live.snapshot();
+    let config = state.config();
+    let engine = &state.workflow_engine;
+    let (total, workflows) = engine.inflight().snapshot(MAX_LISTED_WORKFLOWS);
+    Json(serde_json::json!({
+        "started_at": live.started_at.to_rfc3339(),
+        "uptime_secs": live.started.elapsed().as_secs(),
+        "config": {
+            "models": config.models.len(),
+            "enabled_models": config.models.iter().filter(|m| m.is_enabled()).count(),
+            "workers": config.workflow_integration.plan_for_preset(None).worker_labels(),
+            "presets": config.workflow_integration.presets.keys().collect::<Vec<_>>(),
+        },
+        "in_flight": {
+            "total": total,
+            "workflows": workflows,
+        },
+        "request_slots": engine.rate_limiter().slot_stats(),
+    }))
+}
+
+async fn rate_limit_stats(State(live): State<SharedState>) -> impl IntoResponse {
+    let state = live.snapshot();
+    Json(serde_json::json!({
+        "models": state.workflow_engine.rate_limiter().stats()
+    }))
+}
+
+// 滑动窗口内各模型、各阶段的延迟分位数与错误率
+async fn model_stats(State(live): State<SharedState>) -> impl IntoResponse {
+    let state = live.snapshot();
+    let latency = state.workflow_engine.latency();
+    Json(serde_json::json!({
+        "window_secs": latency.window().as_secs(),
+        "models": latency.stats(),
+        "final_response_chars": latency.final_response_stats(),
+    }))
+}
+
+async fn reset_model_stats(State(live): State<SharedState>) -> Result<StatusCode, AppError> {
+    let state = live.snapshot();
+    if !state.config.stats.allow_reset {
+        return Err(AppError::new(
+            StatusCode::FORBIDDEN,
+            anyhow::anyhow!("Resetting model stats is disabled; set [stats] allow_reset = true"),
+        ));
+    }
+    state.workflow_engine.latency().reset();
+    tracing::info!("Model latency stats reset");
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UsageQuery {
+    pub from: Option<String>,
+    pub to: Option<String>,
+    pub group_by: Option<String>,
+}
+
+const DEFAULT_USAGE_RANGE_DAYS: i64 = 7;
+
+// 各模型或各天的上游请求数、错误数与 token 合计；默认最近 7 天、按模型分组
+async fn usage_summary(
+    State(live): State<SharedState>,
+    Query(query): Query<UsageQuery>,
+) -> Result<Json<Value>, AppError> {
+    let group_by = match query.group_by.as_deref() {
+        None | Some("model") => GroupBy::Model,
+        Some("day") => GroupBy::Day,
+        Some(other) => {
+            return Err(AppError::bad_request(anyhow::anyhow!(
+                "group_by must be \"model\" or \"day\", got \"{}\"",
+                other
+            )))
+        }
+    };
+    let to = match query.to.as_deref() {
+        Some(to) => parse_usage_time("to", to, true).map_err(AppError::bad_request)?,
+        None => chrono::Utc::now(),
+    };
+    let from = match query.from.as_deref() {
+        Some(from) => parse_usage_time("from", from, false).map_err(AppError::bad_request)?,
+        None => to - chrono::Duration::days(DEFAULT_USAGE_RANGE_DAYS),
+    };
+    if from >= to {
+        return Err(AppError::bad_request(anyhow::anyhow!(
+            "from must be earlier than to"
+        )));
+    }
+    let snapshot = live.snapshot();
+    let rows =
+        snapshot
+            .workflow_engine
+            .usage()
+            .query(from, to, group_by, &snapshot.config.pricing());
+    Ok(Json(serde_json::json!({
+        "from": from.to_rfc3339(),
+        "to": to.to_rfc3339(),
+        "total": usage::total(&rows),
+        "rows": rows,
+    })))
+}
+
+// RFC 3339 时间或 YYYY-MM-DD（UTC）；作为 to 时日期包含当天
+fn parse_usage_time(
+    name: &str,
+    value: &str,
+    end_of_day: bool,
+) -> Result<chrono::DateTime<chrono::Utc>> {
+    if let Ok(time) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Ok(time.into());
+    }
+    if let Ok(date) = chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d") {
+        let date = if end_of_day {
+            date.succ_opt().unwrap_or(date)
+        } else {
+            date
+        };
+        return Ok(date.and_time(chrono::NaiveTime::MIN).and_utc());
+    }
+    Err(anyhow::anyhow!(
+        "{} must be an RFC 3339 timestamp or a YYYY-MM-DD date, got \"{}\"",
+        name,
+        value
+    ))
+}
+
+async fn metrics(State(live): State<SharedState>) -> impl IntoResponse {
+    (
+        [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+        live.snapshot()
+            .workflow_engine
+            .latency()
+            .render_prometheus(),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowListQuery {
+    pub limit: Option<usize>,
+    pub before: Option<u64>,
+    pub status: Option<String>,
+}
+
+// 按 workflow_id 倒序分页；next_before 传给下一次请求的 before
+async fn list_workflows(
+    State(live): State<SharedState>,
+    Query(query): Query<WorkflowListQuery>,
+) -> Result<Json<Value>, AppError> {
+    let history = live
+        .snapshot()
+        .history
+        .clone()
+        .ok_or_else(history_disabled)?;
+    let status = match query.status.as_deref() {
+        None => None,
+        Some(status) => Some(HistoryStatus::parse(status).ok_or_else(|| {
+            AppError::bad_request(anyhow::anyhow!(
+                "status must be \"success\" or \"error\", got \"{}\"",
+                status
+            ))
+        })?),
+    };
+    let query = HistoryQuery {
+        limit: query
+            .limit
+            .unwrap_or(DEFAULT_LIST_LIMIT)
+            .clamp(1, MAX_LIST_LIMIT),
+        before: query.before,
+        status,
+    };
+    let limit = query.limit;
+    let workflows = tokio::task::spawn_blocking(move || history.list(&query))
+        .await
+        .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?
+        .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let next_before = (workflows.len() == limit)
+        .then(|| workflows.last().map(|row| row.workflow_id))
+        .flatten();
+    Ok(Json(serde_json::json!({
+        "workflows": workflows,
+        "next_before": next_before,
+    })))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct WorkflowQuery {
+    pub include_outputs: Option<bool>,
+}
+
+async fn get_workflow(
+    State(live): State<SharedState>,
+    Path(workflow_id): Path<String>,
+    Query(query): Query<WorkflowQuery>,
+) -> Result<Json<Value>, AppError> {
+    let history = live
+        .snapshot()
+        .history
+        .clone()
+        .ok_or_else(history_disabled)?;
+    let workflow_id: u64 = workflow_id.parse().map_err(|_| {
+        AppError::bad_request(anyhow::anyhow!(
+            "workflow_id must be a positive integer, got \"{}\"",
+            workflow_id
+        ))
+    })?;
+    let (entry, missing) = tokio::task::spawn_blocking(move || -> Result<_> {
+        Ok(match history.get(workflow_id)? {
+            Some(entry) => (Some(entry), None),
+            None => (None, Some(history.missing_reason(workflow_id)?)),
+        })
+    })
+    .await
+    .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?
+    .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    let Some(entry) = entry else {
+        return Err(AppError::new(
+            StatusCode::NOT_FOUND,
+            anyhow::anyhow!(missing.unwrap_or_default()),
+        ));
+    };
+    let entry = if query.include_outputs.unwrap_or(true) {
+        entry
+    } else {
+        entry.without_outputs()
+    };
+    Ok(Json(serde_json::to_value(entry)?))
+}
+
+fn history_disabled() -> AppError {
+    AppError::new(
+        StatusCode::NOT_FOUND,
+        anyhow::anyhow!("Workflow history is not enabled; add a [history] section to the config"),
+    )
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PlanQuery {
+    pub model: Option<String>,
+}
+
+// 只展示展开后的工作流结构，不调用任何模型
+async fn workflow_plan(
+    State(live): State<SharedState>,
+    Query(query): Query<PlanQuery>,
+) -> Result<Json<Value>, AppError> {
+    let state = live.snapshot();
+    let preset = query
+        .model
+        .as_deref()
+        .and_then(|model| state.preset_for(model));
+    let preview = plan_preview(state.config(), preset.as_deref())
+        .map_err(|err| AppError::new(StatusCode::INTERNAL_SERVER_ERROR, err))?;
+    Ok(Json(preview))
+}
+
+fn plan_preview(config: &Config, preset: Option<&str>) -> Result<Value> {
+    let plan = config.workflow_integration.plan_for_preset(preset);
+    let mut preview = serde_json::json!({
+        "preset": preset,
+        "label": plan.label(),
+        "workers": plan.worker_labels(),
+        "plan": plan.to_json_value()?,
+    });
+    // 展开后的节点带有 group 字段，这里再列出各组的成员
+    if !config.model_groups.is_empty() {
+        let groups: serde_json::Map<String, Value> = config
+            .model_groups
+            .iter()
+            .map(|(name, group)| (name.clone(), serde_json::json!(group.models)))
+            .collect();
+        preview["model_groups"] = Value::Object(groups);
+    }
+    Ok(preview)
+}
+
+async fn list_models(State(live): State<SharedState>) -> impl IntoResponse {
+    let state = live.snapshot();
     let models: Vec<_> = state
-        .config
-        .models
-        .iter()
-        .map(|m| {
+        .listed_model_names()
+        .into_iter()
+        .map(|name| {
             serde_json::json!({
-                "name": m.name,
-                "model": m.name,
+                "name": name,
+                "model": name,
                 "modified_at": chrono::Utc::now().to_rfc3339(),
             })
         })
@@ -1253,39 +2227,154 @@ async fn list_models(State(state): State<SharedState>) -> impl IntoResponse {
     }))
 }
 
-#[cfg(test)]
-mod responses_tests {
-    use super::extract_prompt_from_responses_body;
-    use serde_json::json;
+// 错误处理
+pub struct AppError {
+    status: StatusCode,
+    error: anyhow::Error,
+    retry_after: Option<Duration>,
+    // 上游返回的 HTTP 错误，解析后随响应一起返回给客户端
+    provider: Option<ProviderError>,
+}
 
-    #[test]
-    fn extract_prompt_prefers_instructions_and_input() {
-        let payload = json!({
-            "instructions": "Be helpful",
-            "input": "Say hello"
-        });
-        assert_eq!(
-            extract_prompt_from_responses_body(&payload).unwrap(),
-            "system: Be helpful\nSay hello"
-        );
+impl AppError {
+    pub fn new(status: StatusCode, err: impl Into<anyhow::Error>) -> Self {
+        Self {
+            status,
+            error: err.into(),
+            retry_after: None,
+            provider: None,
+        }
     }
 
-    #[test]
-    fn extract_prompt_handles_message_arrays() {
-        let payload = json!({
-            "input": [
-                {
-                    "role": "user",
-                    "content": [
-                        {"type": "text", "text": "Hi there"}
-                    ]
-                },
-                {
-                    "role": "assistant",
-                    "content": [
-                        {"type": "text", "text": "Hello!"}
-                    ]
-                }
+    pub fn bad_request(err: impl Into<anyhow::Error>) -> Self {
+        Self::new(StatusCode::BAD_REQUEST, err)
+    }
+
+    // 上游回显的 key 已在 LLM 客户端按配置遮盖，这里再兜底遮盖 token 形态的字符串
+    pub fn message(&self) -> String {
+        match &self.provider {
+            // 不把上游的 401 原样转给客户端，否则看起来像是调用 Chorus 的凭据有问题
+            Some(provider) if provider.is_auth_failure() => redact_tokens(&format!(
+                "provider auth failed (upstream status {}): {}",
+                provider.status, provider.message
+            )),
+            // 带有上游请求编号时输出整条错误链，编号挂在上下文里，只取最外层会丢掉原因
+            _ if provider_request_ids_of(&self.error).is_some() => {
+                redact_tokens(&format!("{:#}", self.error))
+            }
+            _ => redact_tokens(&self.error.to_string()),
+        }
+    }
+}
+
+// 上游错误对应给客户端的状态码：鉴权失败是网关问题，限流与上下文超长原样反映给客户端；
+// 其余返回 None，按通用规则处理
+fn provider_status(provider: &ProviderError) -> Option<StatusCode> {
+    if provider.is_auth_failure() {
+        Some(StatusCode::BAD_GATEWAY)
+    } else if provider.status == 429 {
+        Some(StatusCode::TOO_MANY_REQUESTS)
+    } else if provider.is_context_length_exceeded() {
+        Some(StatusCode::BAD_REQUEST)
+    } else {
+        None
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        let message = self.message();
+        tracing::error!(
+            status = %self.status,
+            error = %message,
+            "Application error"
+        );
+
+        let mut body = serde_json::json!({ "error": message });
+        if let Ok(request_id) = CURRENT_REQUEST_ID.try_with(String::clone) {
+            body["request_id"] = Value::String(request_id);
+        }
+        if let Some(provider) = &self.provider {
+            if let Ok(mut value) = serde_json::to_value(provider) {
+                if let Some(message) = value.get_mut("message") {
+                    *message = Value::String(redact_tokens(&provider.message));
+                }
+                body["provider_error"] = value;
+            }
+        }
+        let mut response = (self.status, Json(body)).into_response();
+        if let Some(retry_after) = self.retry_after {
+            response.headers_mut().insert(
+                header::RETRY_AFTER,
+                HeaderValue::from(ceil_secs(retry_after)),
+            );
+        }
+        response
+    }
+}
+
+impl<E> From<E> for AppError
+where
+    E: Into<anyhow::Error>,
+{
+    fn from(err: E) -> Self {
+        let err = err.into();
+        let retry_after = retry_after_hint(&err);
+        let provider = err
+            .downcast_ref::<LlmHttpError>()
+            .map(LlmHttpError::provider_error);
+        let status = if err.downcast_ref::<UpstreamRateLimited>().is_some() {
+            StatusCode::TOO_MANY_REQUESTS
+        } else if let Some(status) = provider.as_ref().and_then(provider_status) {
+            status
+        } else if err.downcast_ref::<NoEnabledWorkers>().is_some() || retry_after.is_some() {
+            StatusCode::SERVICE_UNAVAILABLE
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        };
+        Self {
+            retry_after,
+            provider,
+            ..Self::new(status, err)
+        }
+    }
+}
+
+#[cfg(test)]
+mod responses_tests {
+    use super::{
+        extract_prompt_from_responses_body, generation_params_from_responses_body, plan_preview,
+    };
+    use serde_json::json;
+
+    #[test]
+    fn extract_prompt_prefers_instructions_and_input() {
+        let payload = json!({
+            "instructions": "Be helpful",
+            "input": "Say hello"
+        });
+        assert_eq!(
+            extract_prompt_from_responses_body(&payload).unwrap(),
+            "system: Be helpful\nSay hello"
+        );
+    }
+
+    #[test]
+    fn extract_prompt_handles_message_arrays() {
+        let payload = json!({
+            "input": [
+                {
+                    "role": "user",
+                    "content": [
+                        {"type": "text", "text": "Hi there"}
+                    ]
+                },
+                {
+                    "role": "assistant",
+                    "content": [
+                        {"type": "text", "text": "Hello!"}
+                    ]
+                }
             ]
         });
         assert_eq!(
@@ -1349,50 +2438,1488 @@ mod responses_tests {
         let response = super::AppError::bad_request(anyhow::anyhow!("bad request")).into_response();
         assert_eq!(response.status(), StatusCode::BAD_REQUEST);
     }
-}
 
-// 错误处理
-pub struct AppError {
-    status: StatusCode,
-    error: anyhow::Error,
+    #[test]
+    fn upstream_retry_after_is_forwarded_to_the_client() {
+        use crate::llm::{LlmHttpError, UpstreamRateLimited};
+        use axum::http::{header, StatusCode};
+        use axum::response::IntoResponse;
+        use std::time::Duration;
+
+        let http_err = |status, retry_after| LlmHttpError {
+            status,
+            body: "slow down".to_string(),
+            retry_after,
+            provider_request_ids: Default::default(),
+        };
+        let too_many = reqwest::StatusCode::TOO_MANY_REQUESTS;
+        let unavailable = reqwest::StatusCode::SERVICE_UNAVAILABLE;
+        let limited = anyhow::Error::from(http_err(too_many, Some(Duration::from_millis(7_200))))
+            .context(UpstreamRateLimited {
+                api_base: "https://api.example.com/v1".to_string(),
+                retry_after: Duration::from_millis(7_200),
+                remaining: Duration::from_secs(3),
+                attempt: 1,
+            });
+        let response = super::AppError::from(limited).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "8");
+
+        // 重试次数用完但上游给过 Retry-After：上游 429 原样返回，其余为 503，并转发等待时间
+        let exhausted = anyhow::Error::from(http_err(too_many, Some(Duration::from_secs(2))));
+        let response = super::AppError::from(exhausted).into_response();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+        let exhausted = anyhow::Error::from(http_err(unavailable, Some(Duration::from_secs(2))));
+        let response = super::AppError::from(exhausted).into_response();
+        assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(response.headers()[header::RETRY_AFTER], "2");
+
+        let response =
+            super::AppError::from(anyhow::Error::from(http_err(unavailable, None))).into_response();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(header::RETRY_AFTER).is_none());
+    }
+
+    #[tokio::test]
+    async fn provider_errors_map_to_client_statuses() {
+        use crate::llm::LlmHttpError;
+        use axum::http::StatusCode;
+        use axum::response::IntoResponse;
+        use http_body_util::BodyExt;
+
+        let respond = |status: u16, body: &str| {
+            let err = anyhow::Error::from(LlmHttpError {
+                status: reqwest::StatusCode::from_u16(status).unwrap(),
+                body: body.to_string(),
+                retry_after: None,
+                provider_request_ids: Default::default(),
+            });
+            super::AppError::from(err).into_response()
+        };
+        let json_body = |response: axum::response::Response| async move {
+            let bytes = response.into_body().collect().await.unwrap().to_bytes();
+            serde_json::from_slice::<serde_json::Value>(&bytes).unwrap()
+        };
+
+        let response = respond(
+            401,
+            r#"{"error":{"message":"Incorrect API key provided","type":"invalid_request_error","code":"invalid_api_key"}}"#,
+        );
+        assert_eq!(response.status(), StatusCode::BAD_GATEWAY);
+        let body = json_body(response).await;
+        assert_eq!(
+            body["error"],
+            "provider auth failed (upstream status 401): Incorrect API key provided"
+        );
+        assert_eq!(body["provider_error"]["code"], "invalid_api_key");
+
+        let response = respond(
+            400,
+            r#"{"error":{"message":"maximum context length is 8192 tokens","code":"context_length_exceeded"}}"#,
+        );
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        let response = respond(429, r#"{"error":"too many requests"}"#);
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = json_body(response).await;
+        assert_eq!(
+            body["provider_error"],
+            json!({"status": 429, "message": "too many requests"})
+        );
+
+        let response = respond(400, "not json");
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = json_body(response).await;
+        assert_eq!(body["provider_error"]["message"], "not json");
+    }
+
+    #[test]
+    fn responses_body_generation_params_accept_max_output_tokens() {
+        let payload = json!({
+            "input": "hi",
+            "max_output_tokens": 300,
+            "top_p": 0.8
+        });
+        let params = generation_params_from_responses_body(&payload);
+        assert_eq!(params.max_tokens, Some(300));
+        assert_eq!(params.top_p, Some(0.8));
+
+        let payload = json!({"input": "hi", "max_tokens": 10, "max_output_tokens": 300});
+        assert_eq!(
+            generation_params_from_responses_body(&payload).max_tokens,
+            Some(10)
+        );
+    }
+
+    #[test]
+    fn chat_request_accepts_generation_params() {
+        let req: super::ChatRequest = serde_json::from_value(json!({
+            "messages": [{"role": "user", "content": "hi"}],
+            "max_tokens": 128,
+            "frequency_penalty": 0.1
+        }))
+        .unwrap();
+        assert_eq!(req.generation.max_tokens, Some(128));
+        assert_eq!(req.generation.frequency_penalty, Some(0.1));
+        assert_eq!(req.generation.top_p, None);
+    }
+
+    #[test]
+    fn plan_preview_shows_per_node_replication() {
+        let cfg: crate::config::Config = toml::from_str(
+            r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "m1"},
+  "workers": [
+    {"name": "m1"},
+    {"analyzer": {"ref": "m1"}, "workers": [{"name": "m1"}], "nested_worker_depth": 2}
+  ],
+  "synthesizer": {"ref": "m1"}
+}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+"#,
+        )
+        .unwrap();
+
+        let preview = plan_preview(&cfg, None).unwrap();
+        assert_eq!(preview["preset"], serde_json::Value::Null);
+        assert_eq!(
+            preview["workers"],
+            json!(["m1", "workflow:m1[workflow:m1[m1, m1]]"])
+        );
+        let nested = &preview["plan"]["workers"][1]["workers"][0]["workers"];
+        assert_eq!(nested.as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn plan_preview_lists_expanded_model_groups() {
+        let dir = crate::test_util::temp_dir("plan_groups");
+        let path = dir.join("config.toml");
+        std::fs::write(
+            &path,
+            r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m2"
+
+[model-group.fast]
+models = ["m1", "m2"]
+
+[workflow-integration]
+analyzer = { ref = "m1" }
+workers = [{ group = "fast", temperature = 0.3 }]
+synthesizer = { ref = "m1" }
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+"#,
+        )
+        .unwrap();
+        let cfg = crate::config::Config::load(&path.to_string_lossy()).unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let preview = plan_preview(&cfg, None).unwrap();
+        assert_eq!(preview["workers"], json!(["m1", "m2"]));
+        assert_eq!(preview["model_groups"], json!({"fast": ["m1", "m2"]}));
+        let worker = &preview["plan"]["workers"][1];
+        assert_eq!(worker["name"], "m2");
+        assert_eq!(worker["group"], "fast");
+        assert!((worker["temperature"].as_f64().unwrap() - 0.3).abs() < 1e-6);
+    }
 }
 
-impl AppError {
-    pub fn new(status: StatusCode, err: impl Into<anyhow::Error>) -> Self {
-        Self {
+#[cfg(test)]
+mod listener_tests {
+    use super::{router, serve, AppState, LiveState};
+    use crate::config::{Config, HistoryConfig, TlsConfig};
+    use crate::test_util::temp_dir;
+    use crate::tls::{load_server_config, TlsCertificates};
+    use axum::{routing::post, Json, Router};
+    use http_body_util::{BodyExt, Full};
+    use hyper::body::Bytes;
+    use hyper::Request;
+    use hyper_util::rt::TokioIo;
+    use serde_json::json;
+    use std::path::Path;
+    use std::sync::Arc;
+    use tokio::io::{AsyncRead, AsyncWrite};
+    use tokio::sync::oneshot;
+
+    struct Reply {
+        status: u16,
+        content_type: String,
+        request_id: String,
+        frames: usize,
+        body: String,
+    }
+
+    // 模拟上游 OpenAI 兼容接口，固定返回一段回复
+    async fn spawn_upstream() -> String {
+        let app = Router::new().route(
+            "/v1/chat/completions",
+            post(|| async {
+                Json(json!({
+                    "choices": [{"message": {"role": "assistant", "content": "hello from upstream"}}]
+                }))
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        format!("http://{}/v1", addr)
+    }
+
+    fn test_config(api_base: &str, server: &str) -> Config {
+        toml::from_str(&format!(
+            r#"
+[server]
+{}
+
+[[model]]
+api_base = "{}"
+api_key = "k"
+name = "m1"
+proxy = "direct"
+
+[workflow-integration]
+json = """{{"analyzer": {{"ref": "m1"}}, "workers": [{{"name": "m1"}}], "synthesizer": {{"ref": "m1"}}}}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 5
+worker_timeout_secs = 5
+synthesizer_timeout_secs = 5
+"#,
+            server, api_base
+        ))
+        .unwrap()
+    }
+
+    fn app_for(config: Config) -> Router {
+        router(Arc::new(LiveState::new(AppState::new(config).unwrap())))
+    }
+
+    async fn request<S>(stream: S, req: Request<Full<Bytes>>) -> Reply
+    where
+        S: AsyncRead + AsyncWrite + Unpin + Send + 'static,
+    {
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        let response = sender.send_request(req).await.unwrap();
+        let status = response.status().as_u16();
+        let content_type = response
+            .headers()
+            .get("content-type")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+        let request_id = response
+            .headers()
+            .get("x-request-id")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default()
+            .to_string();
+
+        let mut body = response.into_body();
+        let mut frames = 0;
+        let mut bytes = Vec::new();
+        while let Some(frame) = body.frame().await {
+            if let Ok(data) = frame.unwrap().into_data() {
+                frames += 1;
+                bytes.extend_from_slice(&data);
+            }
+        }
+        Reply {
             status,
-            error: err.into(),
+            content_type,
+            request_id,
+            frames,
+            body: String::from_utf8_lossy(&bytes).into_owned(),
         }
     }
 
-    pub fn bad_request(err: impl Into<anyhow::Error>) -> Self {
-        Self::new(StatusCode::BAD_REQUEST, err)
+    fn models_request() -> Request<Full<Bytes>> {
+        Request::get("/v1/models")
+            .header("host", "localhost")
+            .body(Full::default())
+            .unwrap()
     }
-}
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        tracing::error!(
-            status = %self.status,
-            error = %self.error,
-            "Application error"
+    fn streaming_chat_request() -> Request<Full<Bytes>> {
+        let payload = json!({
+            "model": "chorus",
+            "stream": true,
+            "messages": [{"role": "user", "content": "hi"}]
+        });
+        Request::post("/v1/chat/completions")
+            .header("host", "localhost")
+            .header("content-type", "application/json")
+            .body(Full::new(Bytes::from(payload.to_string())))
+            .unwrap()
+    }
+
+    fn assert_streamed(reply: &Reply) {
+        assert_eq!(reply.status, 200);
+        assert!(
+            reply.content_type.starts_with("text/event-stream"),
+            "{}",
+            reply.content_type
+        );
+        assert!(reply.body.contains("hello from upstream"), "{}", reply.body);
+        assert!(reply.body.contains("data: [DONE]"), "{}", reply.body);
+        // 事件应逐个送达，而不是被缓冲成一整块
+        assert!(reply.frames > 1, "{} frames", reply.frames);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn routes_and_streaming_work_over_unix_socket() {
+        use tokio::net::UnixStream;
+
+        let dir = temp_dir("unix");
+        let socket = dir.join("nested").join("chorus.sock");
+
+        // 残留的 socket 文件在启动时被清理
+        std::fs::create_dir_all(socket.parent().unwrap()).unwrap();
+        drop(std::os::unix::net::UnixListener::bind(&socket).unwrap());
+        assert!(socket.exists());
+
+        let config = test_config(
+            &spawn_upstream().await,
+            &format!(
+                "unix_socket = \"{}\"\nunix_socket_mode = 0o600",
+                socket.display()
+            ),
         );
+        let server_config = config.server.clone();
+        let app = app_for(config);
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(async move {
+            serve(&server_config, app, async {
+                let _ = stop_rx.await;
+            })
+            .await
+        });
+
+        for _ in 0..50 {
+            if UnixStream::connect(&socket).await.is_ok() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+        }
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&socket).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+            // bind 用的临时目录已删除，目录里只剩 socket
+            let names: Vec<_> = std::fs::read_dir(socket.parent().unwrap())
+                .unwrap()
+                .map(|entry| entry.unwrap().file_name())
+                .collect();
+            assert_eq!(names, vec![socket.file_name().unwrap().to_os_string()]);
+        }
 
+        let reply = request(
+            UnixStream::connect(&socket).await.unwrap(),
+            models_request(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+        assert!(reply.body.contains("\"m1\""), "{}", reply.body);
+
+        let reply = request(
+            UnixStream::connect(&socket).await.unwrap(),
+            streaming_chat_request(),
+        )
+        .await;
+        assert_streamed(&reply);
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        assert!(!socket.exists(), "socket should be removed on shutdown");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn refuses_to_replace_regular_file() {
+        let dir = temp_dir("regular");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("chorus.sock");
+        std::fs::write(&path, "not a socket").unwrap();
+
+        let err = crate::unix_socket::bind(&path, 0o660).err().unwrap();
+        assert!(err.to_string().contains("is not a socket"), "{}", err);
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "not a socket");
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // 生成 localhost 自签名证书，返回写入后的 TLS 配置与 PEM 证书
+    fn write_certificate(dir: &Path, tag: &str) -> (TlsConfig, String) {
+        std::fs::create_dir_all(dir).unwrap();
+        let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+        let cert_pem = cert.cert.pem();
+        let cert_path = dir.join(format!("{}.crt", tag));
+        let key_path = dir.join(format!("{}.key", tag));
+        std::fs::write(&cert_path, &cert_pem).unwrap();
+        std::fs::write(&key_path, cert.key_pair.serialize_pem()).unwrap();
         (
-            self.status,
-            Json(serde_json::json!({
-                "error": self.error.to_string()
-            })),
+            TlsConfig {
+                cert_path: cert_path.to_string_lossy().into_owned(),
+                key_path: key_path.to_string_lossy().into_owned(),
+            },
+            cert_pem,
         )
-            .into_response()
     }
-}
 
-impl<E> From<E> for AppError
-where
-    E: Into<anyhow::Error>,
-{
-    fn from(err: E) -> Self {
-        Self::new(StatusCode::INTERNAL_SERVER_ERROR, err)
+    async fn connect_tls(
+        addr: std::net::SocketAddr,
+        trusted_pem: &str,
+    ) -> std::io::Result<tokio_rustls::client::TlsStream<tokio::net::TcpStream>> {
+        let mut roots = rustls::RootCertStore::empty();
+        for cert in rustls_pemfile::certs(&mut trusted_pem.as_bytes()) {
+            roots.add(cert.unwrap()).unwrap();
+        }
+        let provider = Arc::new(rustls::crypto::ring::default_provider());
+        let config = rustls::ClientConfig::builder_with_provider(provider)
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let connector = tokio_rustls::TlsConnector::from(Arc::new(config));
+        let tcp = tokio::net::TcpStream::connect(addr).await?;
+        let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+        connector.connect(name, tcp).await
+    }
+
+    #[tokio::test]
+    async fn sse_streams_over_tls_and_certificates_reload() {
+        let dir = temp_dir("tls");
+        let (tls, first_pem) = write_certificate(&dir, "server");
+        let certs = Arc::new(TlsCertificates::load(&tls).unwrap());
+
+        let config = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (stop_tx, stop_rx) = oneshot::channel::<()>();
+        let server = tokio::spawn(crate::tls::serve(
+            listener,
+            certs.clone(),
+            app_for(config),
+            async {
+                let _ = stop_rx.await;
+            },
+        ));
+
+        let reply = request(
+            connect_tls(addr, &first_pem).await.unwrap(),
+            models_request(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+        let reply = request(
+            connect_tls(addr, &first_pem).await.unwrap(),
+            streaming_chat_request(),
+        )
+        .await;
+        assert_streamed(&reply);
+
+        // 模拟证书续期：覆盖文件后重新加载，新连接使用新证书
+        let (_, renewed_pem) = write_certificate(&dir, "server");
+        certs.reload().unwrap();
+        assert!(connect_tls(addr, &first_pem).await.is_err());
+        let reply = request(
+            connect_tls(addr, &renewed_pem).await.unwrap(),
+            models_request(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+
+        stop_tx.send(()).unwrap();
+        server.await.unwrap().unwrap();
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn unreadable_or_mismatched_certificates_fail_clearly() {
+        let dir = temp_dir("tls_errors");
+        let (first, _) = write_certificate(&dir, "first");
+        let (second, _) = write_certificate(&dir, "second");
+
+        let missing = TlsConfig {
+            cert_path: dir.join("missing.crt").to_string_lossy().into_owned(),
+            key_path: first.key_path.clone(),
+        };
+        let err = format!("{:#}", load_server_config(&missing).unwrap_err());
+        assert!(err.contains("Failed to read TLS certificate"), "{}", err);
+        assert!(err.contains("missing.crt"), "{}", err);
+
+        let swapped = TlsConfig {
+            cert_path: first.cert_path.clone(),
+            key_path: first.cert_path.clone(),
+        };
+        let err = format!("{:#}", load_server_config(&swapped).unwrap_err());
+        assert!(err.contains("No PEM private key found"), "{}", err);
+
+        let mismatched = TlsConfig {
+            cert_path: first.cert_path.clone(),
+            key_path: second.key_path.clone(),
+        };
+        let err = format!("{:#}", load_server_config(&mismatched).unwrap_err());
+        assert!(err.contains("cannot be used with key"), "{}", err);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+
+    // 收集测试期间所有日志输出
+    #[derive(Clone, Default)]
+    struct CapturedLogs(Arc<std::sync::Mutex<Vec<u8>>>);
+
+    impl std::io::Write for CapturedLogs {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn upstream_errors_echoing_the_api_key_are_redacted() {
+        const KEY: &str = "live-key-0123456789abcdef";
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::TRACE)
+                .with_writer(move || writer.clone())
+                .finish(),
+        );
+
+        // 上游把收到的 Authorization 原样写进错误信息
+        let upstream = Router::new().route(
+            "/v1/chat/completions",
+            post(|headers: axum::http::HeaderMap| async move {
+                let auth = headers["authorization"].to_str().unwrap().to_string();
+                (
+                    axum::http::StatusCode::UNAUTHORIZED,
+                    Json(json!({"error": {"message": format!("invalid key {} ({})", KEY, auth)}})),
+                )
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let mut config = test_config(
+            &format!("http://{}/v1", upstream_addr),
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.models[0].api_key = KEY.to_string();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap(),
+        )
+        .await;
+
+        assert_eq!(reply.status, 500);
+        assert!(reply.body.contains("invalid key ***"), "{}", reply.body);
+        assert!(!reply.body.contains(KEY), "{}", reply.body);
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        assert!(logs.contains("invalid key ***"), "{}", logs);
+        assert!(!logs.contains(KEY), "{}", logs);
+    }
+
+    #[tokio::test]
+    async fn streaming_requests_without_enabled_workers_get_503() {
+        let api_base = spawn_upstream().await;
+        let config: Config = toml::from_str(&format!(
+            r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "{0}"
+api_key = "k"
+name = "m1"
+proxy = "direct"
+
+[[model]]
+api_base = "{0}"
+api_key = "k"
+name = "w1"
+proxy = "direct"
+enabled = false
+
+[workflow-integration]
+json = """{{"analyzer": {{"ref": "m1"}}, "workers": [{{"name": "w1"}}], "synthesizer": {{"ref": "m1"}}}}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 5
+worker_timeout_secs = 5
+synthesizer_timeout_secs = 5
+"#,
+            api_base
+        ))
+        .unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let post = |path: &str, payload: serde_json::Value| {
+            Request::post(path)
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap()
+        };
+        let messages = json!([{"role": "user", "content": "hi"}]);
+
+        for req in [
+            streaming_chat_request(),
+            post("/api/generate", json!({"prompt": "hi", "stream": true})),
+            post("/api/chat", json!({"messages": messages, "stream": true})),
+            post("/v1/completions", json!({"prompt": "hi", "stream": true})),
+            post("/v1/responses", json!({"input": "hi", "stream": true})),
+        ] {
+            let path = req.uri().path().to_string();
+            let reply = request(tokio::net::TcpStream::connect(addr).await.unwrap(), req).await;
+            assert_eq!(reply.status, 503, "{}: {}", path, reply.body);
+            assert!(
+                reply.content_type.starts_with("application/json"),
+                "{}: {}",
+                path,
+                reply.content_type
+            );
+            assert!(
+                reply.body.contains("No enabled worker models"),
+                "{}: {}",
+                path,
+                reply.body
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn chat_completions_run_against_an_injected_transport() {
+        use crate::transport::{Scripted, ScriptedTransport};
+        use tower::ServiceExt;
+
+        let transport = Arc::new(
+            ScriptedTransport::default().on("m1.test", Scripted::completion("hello from the fake")),
+        );
+        let config = test_config("http://m1.test/v1", "host = \"127.0.0.1\"\nport = 11435");
+        let state = AppState::with_transport(config, Some(transport.clone())).unwrap();
+        let app = router(Arc::new(LiveState::new(state)));
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let response = app
+            .oneshot(
+                Request::post("/v1/chat/completions")
+                    .header("content-type", "application/json")
+                    .body(axum::body::Body::from(payload.to_string()))
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 200);
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&bytes).unwrap();
+        assert_eq!(
+            body["choices"][0]["message"]["content"],
+            "hello from the fake"
+        );
+
+        // worker 与 synthesizer 各调用一次，都带着配置里的 key
+        let requests = transport.requests();
+        assert_eq!(requests.len(), 2);
+        for sent in &requests {
+            assert_eq!(sent.url, "http://m1.test/v1/chat/completions");
+            assert_eq!(sent.headers["authorization"], "Bearer k");
+            assert_eq!(sent.body["model"], "m1");
+        }
+    }
+
+    #[tokio::test]
+    async fn stats_reset_is_disabled_unless_configured() {
+        use tower::ServiceExt;
+
+        let config = test_config("http://m1.test/v1", "host = \"127.0.0.1\"\nport = 11435");
+        let response = app_for(config)
+            .oneshot(
+                Request::post("/api/stats/models/reset")
+                    .body(axum::body::Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), 403);
+        let bytes = response.into_body().collect().await.unwrap().to_bytes();
+        let body = String::from_utf8_lossy(&bytes);
+        assert!(body.contains("allow_reset = true"), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn admin_endpoints_are_not_open_to_cross_origin_requests() {
+        let mut config = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.stats.allow_reset = true;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let send = |req: Request<Full<Bytes>>| async move {
+            let stream = tokio::net::TcpStream::connect(addr).await.unwrap();
+            let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+                .await
+                .unwrap();
+            tokio::spawn(conn);
+            sender.send_request(req).await.unwrap()
+        };
+        let with_origin = |method: &str, path: &str| {
+            Request::builder()
+                .method(method)
+                .uri(path)
+                .header("host", "localhost")
+                .header("origin", "https://evil.example")
+                .body(Full::default())
+                .unwrap()
+        };
+
+        let response = send(with_origin("GET", "/v1/models")).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["access-control-allow-origin"], "*");
+
+        for (method, path) in [
+            ("POST", "/api/stats/models/reset"),
+            ("GET", "/api/workflows"),
+            ("GET", "/api/workflows/1"),
+        ] {
+            let response = send(with_origin(method, path)).await;
+            assert_eq!(response.status(), 403, "{} {}", method, path);
+            assert!(response
+                .headers()
+                .get("access-control-allow-origin")
+                .is_none());
+        }
+
+        // 不带 Origin 的命令行调用不受影响
+        let response = send(
+            Request::post("/api/stats/models/reset")
+                .header("host", "localhost")
+                .body(Full::default())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(response.status(), 204);
+    }
+
+    #[tokio::test]
+    async fn workflow_history_is_recorded_and_listed() {
+        let dir = temp_dir("server_history");
+        let mut config = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.history = Some(HistoryConfig {
+            path: dir.join("history.db").to_string_lossy().into_owned(),
+            ..Default::default()
+        });
+        let state = AppState::new(config).unwrap();
+        let history = state.history.clone().unwrap();
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = router(Arc::new(LiveState::new(state)));
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let send = |req: Request<Full<Bytes>>| async move {
+            request(tokio::net::TcpStream::connect(addr).await.unwrap(), req).await
+        };
+        let get = |path: &str| {
+            Request::get(path)
+                .header("host", "localhost")
+                .body(Full::default())
+                .unwrap()
+        };
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let reply = send(
+            Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .header("x-request-id", "hist-1")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+        // 执行详情只写进历史，不出现在未请求的响应里
+        assert!(reply.body.contains("\"workflow\":null"), "{}", reply.body);
+        history.flush();
+
+        let listed: serde_json::Value =
+            serde_json::from_str(&send(get("/api/workflows?status=success")).await.body).unwrap();
+        let rows = listed["workflows"].as_array().unwrap();
+        assert_eq!(rows.len(), 1, "{}", listed);
+        assert_eq!(rows[0]["request_id"], "hist-1");
+        assert_eq!(rows[0]["status"], "success");
+        assert_eq!(rows[0]["models"], json!(["m1"]));
+        assert_eq!(
+            rows[0]["prompt_hash"],
+            crate::history::prompt_hash("user: hi")
+        );
+        assert!(rows[0].get("prompt").is_none());
+        assert_eq!(listed["next_before"], serde_json::Value::Null);
+
+        let errors: serde_json::Value =
+            serde_json::from_str(&send(get("/api/workflows?status=error")).await.body).unwrap();
+        assert_eq!(errors["workflows"], json!([]));
+        assert_eq!(send(get("/api/workflows?status=bogus")).await.status, 400);
+
+        let id = rows[0]["workflow_id"].as_u64().unwrap();
+        let full: serde_json::Value =
+            serde_json::from_str(&send(get(&format!("/api/workflows/{}", id))).await.body).unwrap();
+        assert_eq!(full["request_id"], "hist-1");
+        assert_eq!(full["final_response"], "hello from upstream");
+        assert_eq!(
+            full["details"]["workers"][0]["response"],
+            "hello from upstream"
+        );
+        let trimmed: serde_json::Value = serde_json::from_str(
+            &send(get(&format!("/api/workflows/{}?include_outputs=false", id)))
+                .await
+                .body,
+        )
+        .unwrap();
+        assert!(trimmed["details"]["workers"][0]["response"].is_null());
+        assert_eq!(trimmed["final_response"], "hello from upstream");
+
+        let missing = send(get(&format!("/api/workflows/{}", id + 100))).await;
+        assert_eq!(missing.status, 404);
+        assert!(
+            missing.body.contains("has not been recorded"),
+            "{}",
+            missing.body
+        );
+        assert!(missing.body.contains("\"request_id\""), "{}", missing.body);
+        assert_eq!(send(get("/api/workflows/abc")).await.status, 400);
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let disabled = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(disabled);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            get("/api/workflows"),
+        )
+        .await;
+        assert_eq!(reply.status, 404);
+        assert!(reply.body.contains("[history]"), "{}", reply.body);
+    }
+
+    #[tokio::test]
+    async fn status_lists_in_flight_workflows() {
+        // 上游在放行前一直挂起，工作流停在 worker 阶段
+        let gate = Arc::new(tokio::sync::Semaphore::new(0));
+        let upstream = Router::new().route(
+            "/v1/chat/completions",
+            post({
+                let gate = gate.clone();
+                move || async move {
+                    let _permit = gate.acquire().await.unwrap();
+                    Json(json!({
+                        "choices": [{"message": {"role": "assistant", "content": "done"}}]
+                    }))
+                }
+            }),
+        );
+        let upstream_listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = upstream_listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(upstream_listener, upstream).await.unwrap() });
+
+        let config = test_config(
+            &format!("http://{}/v1", upstream_addr),
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let status = || async move {
+            let reply = request(
+                tokio::net::TcpStream::connect(addr).await.unwrap(),
+                Request::get("/api/status")
+                    .header("host", "localhost")
+                    .body(Full::default())
+                    .unwrap(),
+            )
+            .await;
+            serde_json::from_str::<serde_json::Value>(&reply.body).unwrap()
+        };
+
+        let idle = status().await;
+        assert_eq!(idle["in_flight"], json!({"total": 0, "workflows": []}));
+        assert_eq!(idle["config"]["models"], 1);
+        assert_eq!(idle["config"]["workers"], json!(["m1"]));
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let pending = tokio::spawn(async move {
+            request(
+                tokio::net::TcpStream::connect(addr).await.unwrap(),
+                Request::post("/v1/chat/completions")
+                    .header("host", "localhost")
+                    .header("content-type", "application/json")
+                    .header("x-request-id", "busy-1")
+                    .body(Full::new(Bytes::from(payload.to_string())))
+                    .unwrap(),
+            )
+            .await
+        });
+        let mut busy = status().await;
+        for _ in 0..100 {
+            if busy["in_flight"]["workflows"][0]["upstream_calls"][0].is_object() {
+                break;
+            }
+            tokio::time::sleep(std::time::Duration::from_millis(20)).await;
+            busy = status().await;
+        }
+        assert_eq!(busy["in_flight"]["total"], 1, "{}", busy);
+        let workflow = &busy["in_flight"]["workflows"][0];
+        assert_eq!(workflow["request_id"], "busy-1");
+        assert_eq!(workflow["phase"], "worker");
+        assert_eq!(workflow["upstream_calls"][0]["model"], "m1");
+        assert_eq!(workflow["upstream_calls"][0]["phase"], "worker");
+
+        gate.add_permits(10);
+        assert_eq!(pending.await.unwrap().status, 200);
+        assert_eq!(status().await["in_flight"]["total"], 0);
+    }
+
+    #[tokio::test]
+    async fn usage_summary_counts_upstream_calls() {
+        let mut config = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.models[0].input_price_per_1k = Some(0.5);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let send = |req: Request<Full<Bytes>>| async move {
+            request(tokio::net::TcpStream::connect(addr).await.unwrap(), req).await
+        };
+        let get = |path: &str| {
+            Request::get(path)
+                .header("host", "localhost")
+                .body(Full::default())
+                .unwrap()
+        };
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let reply = send(
+            Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+
+        // worker 与 synthesizer 各一次上游调用；测试上游不报告 token 用量
+        let usage: serde_json::Value =
+            serde_json::from_str(&send(get("/api/usage")).await.body).unwrap();
+        assert_eq!(
+            usage["rows"],
+            json!([{
+                "model": "m1",
+                "requests": 2,
+                "errors": 0,
+                "prompt_tokens": 0,
+                "completion_tokens": 0,
+                "total_tokens": 0,
+                "estimated_cost": 0.0,
+            }])
+        );
+        assert_eq!(usage["total"]["requests"], 2);
+
+        let today = chrono::Utc::now().format("%Y-%m-%d").to_string();
+        let by_day: serde_json::Value = serde_json::from_str(
+            &send(get(&format!(
+                "/api/usage?group_by=day&from={}&to={}",
+                today, today
+            )))
+            .await
+            .body,
+        )
+        .unwrap();
+        assert_eq!(by_day["rows"][0]["day"], today.as_str());
+        assert_eq!(by_day["rows"][0]["requests"], 2);
+
+        let empty: serde_json::Value = serde_json::from_str(
+            &send(get("/api/usage?from=2020-01-01&to=2020-01-31"))
+                .await
+                .body,
+        )
+        .unwrap();
+        assert_eq!(empty["rows"], json!([]));
+        assert_eq!(empty["total"]["requests"], 0);
+
+        assert_eq!(send(get("/api/usage?group_by=hour")).await.status, 400);
+        assert_eq!(send(get("/api/usage?from=yesterday")).await.status, 400);
+        assert_eq!(
+            send(get("/api/usage?from=2026-02-01&to=2026-01-01"))
+                .await
+                .status,
+            400
+        );
+    }
+
+    #[tokio::test]
+    async fn model_stats_and_metrics_report_upstream_latency() {
+        let mut config = test_config(
+            &spawn_upstream().await,
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.stats.allow_reset = true;
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let send = |req: Request<Full<Bytes>>| async move {
+            request(tokio::net::TcpStream::connect(addr).await.unwrap(), req).await
+        };
+        let get = |path: &str| {
+            Request::get(path)
+                .header("host", "localhost")
+                .body(Full::default())
+                .unwrap()
+        };
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let reply = send(
+            Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+
+        let stats: serde_json::Value =
+            serde_json::from_str(&send(get("/api/stats/models")).await.body).unwrap();
+        assert_eq!(stats["window_secs"], 900);
+        let phases: Vec<_> = stats["models"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|entry| {
+                assert_eq!(entry["model"], "m1");
+                assert_eq!(entry["requests"], 1);
+                assert_eq!(entry["error_rate"], 0.0);
+                assert!(entry["p95_ms"].is_u64(), "{}", entry);
+                // 上游没报告用量，token 数来自本地估算
+                assert_eq!(entry["completion_tokens"]["count"], 1);
+                assert!(entry["prompt_tokens"]["max"].as_u64().unwrap() > 0);
+                assert_eq!(entry["response_chars"]["max"], 19);
+                entry["phase"].as_str().unwrap().to_string()
+            })
+            .collect();
+        // 模型没开 auto_temperature，analyzer 不会调用上游
+        assert_eq!(phases, ["worker", "synthesizer"]);
+        assert_eq!(stats["final_response_chars"]["p50"], 19);
+
+        let metrics = send(get("/metrics")).await;
+        assert!(metrics.content_type.starts_with("text/plain"));
+        assert!(
+            metrics.body.contains(
+                "chorus_upstream_latency_seconds_count{model=\"m1\",phase=\"worker\"} 1\n"
+            ),
+            "{}",
+            metrics.body
+        );
+        assert!(metrics.body.contains(
+            "chorus_upstream_response_chars_bucket{model=\"m1\",phase=\"worker\",le=\"100\"} 1\n"
+        ));
+        assert!(metrics
+            .body
+            .contains("chorus_final_response_chars_count 1\n"));
+
+        let reset = send(
+            Request::post("/api/stats/models/reset")
+                .header("host", "localhost")
+                .body(Full::default())
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(reset.status, 204);
+        let stats: serde_json::Value =
+            serde_json::from_str(&send(get("/api/stats/models")).await.body).unwrap();
+        assert_eq!(stats["models"], json!([]));
+        assert_eq!(stats["final_response_chars"], json!(null));
+    }
+
+    #[tokio::test]
+    async fn request_ids_reach_error_bodies_and_logs() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .finish(),
+        );
+
+        let config = test_config(
+            "http://127.0.0.1:1/v1",
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            Request::post("/v1/responses")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .header("x-request-id", "ticket-7")
+                .body(Full::new(Bytes::from("{}")))
+                .unwrap(),
+        )
+        .await;
+        assert_eq!(reply.status, 400);
+        assert_eq!(reply.request_id, "ticket-7");
+        let body: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(body["request_id"], "ticket-7");
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let error_line = logs
+            .lines()
+            .find(|line| line.contains("Application error"))
+            .unwrap_or_else(|| panic!("no error log in:\n{}", logs));
+        assert!(error_line.contains("request_id=ticket-7"), "{}", error_line);
+    }
+
+    #[tokio::test]
+    async fn worker_logs_are_nested_under_workflow_and_phase_spans() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .finish(),
+        );
+
+        // 上游不可达，worker 调用失败并打印警告
+        let config = test_config(
+            "http://127.0.0.1:1/v1",
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let payload = json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json")
+                .header("x-request-id", "span-1")
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap(),
+        )
+        .await;
+        assert_ne!(reply.status, 200);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let line = logs
+            .lines()
+            .find(|line| line.contains("Worker call failed"))
+            .unwrap_or_else(|| panic!("no worker failure in:\n{}", logs));
+        let hash = crate::history::prompt_hash("user: hi");
+        for expected in [
+            "request{request_id=span-1 ",
+            "}:workflow{workflow_id=",
+            &format!("prompt_hash={} preset=\"default\"}}", hash),
+            "phase{phase=\"worker\"}",
+            "worker{index=1 model=\"m1\" temperature=",
+        ] {
+            assert!(line.contains(expected), "{} not in {}", expected, line);
+        }
+    }
+
+    #[tokio::test]
+    async fn access_log_records_requests_with_the_forwarded_client() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .finish(),
+        );
+
+        let mut config = test_config(
+            "http://127.0.0.1:1/v1",
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        config.access_log = Some(crate::config::AccessLogConfig {
+            exclude_paths: vec!["/".to_string()],
+            trusted_proxies: vec!["127.0.0.0/8".to_string(), "10.0.0.0/8".to_string()],
+            ..Default::default()
+        });
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config).into_make_service_with_connect_info::<std::net::SocketAddr>();
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        let send = |path: &str, forwarded: &str| {
+            let req = Request::get(path)
+                .header("host", "localhost")
+                .header("x-request-id", "access-1")
+                .header("x-forwarded-for", forwarded)
+                .body(Full::default())
+                .unwrap();
+            async move { request(tokio::net::TcpStream::connect(addr).await.unwrap(), req).await }
+        };
+
+        let health = send("/", "198.51.100.1").await;
+        assert_eq!(health.status, 200);
+        // 最右侧的 10.0.0.5 是可信代理，客户端是它左边的地址
+        let models = send("/v1/models", "198.51.100.1, 203.0.113.7, 10.0.0.5").await;
+        assert_eq!(models.status, 200);
+        let missing = send("/nope", "not-an-ip").await;
+        assert_eq!(missing.status, 404);
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<&str> = logs
+            .lines()
+            .filter(|line| line.contains("chorus::access"))
+            .collect();
+        assert_eq!(lines.len(), 2, "{}", logs);
+        let bytes = format!("bytes={} ", models.body.len());
+        for expected in [
+            "GET /v1/models 200",
+            "method=GET",
+            "path=/v1/models",
+            "status=200",
+            "latency_ms=",
+            bytes.as_str(),
+            "request_id=\"access-1\"",
+            "client_ip=\"203.0.113.7\"",
+        ] {
+            assert!(
+                lines[0].contains(expected),
+                "{} not in {}",
+                expected,
+                lines[0]
+            );
+        }
+        // X-Forwarded-For 无法解析时停在直连地址
+        assert!(lines[1].contains("status=404"), "{}", lines[1]);
+        assert!(lines[1].contains("client_ip=\"127.0.0.1\""), "{}", lines[1]);
+    }
+
+    #[tokio::test]
+    async fn each_workflow_logs_one_summary_line() {
+        let logs = CapturedLogs::default();
+        let writer = logs.clone();
+        let _guard = tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_ansi(false)
+                .with_writer(move || writer.clone())
+                .finish(),
+        );
+
+        let upstream = spawn_upstream().await;
+        let mut quiet = test_config(&upstream, "host = \"127.0.0.1\"\nport = 11435");
+        quiet.logging.workflow_summary = Some(false);
+        for (config, request_id) in [
+            (
+                test_config(&upstream, "host = \"127.0.0.1\"\nport = 11435"),
+                "summary-1",
+            ),
+            (quiet, "summary-2"),
+        ] {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            let app = app_for(config);
+            tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+            let payload =
+                json!({"model": "chorus", "messages": [{"role": "user", "content": "hi"}]});
+            let reply = request(
+                tokio::net::TcpStream::connect(addr).await.unwrap(),
+                Request::post("/v1/chat/completions")
+                    .header("host", "localhost")
+                    .header("content-type", "application/json")
+                    .header("x-request-id", request_id)
+                    .body(Full::new(Bytes::from(payload.to_string())))
+                    .unwrap(),
+            )
+            .await;
+            assert_eq!(reply.status, 200);
+        }
+
+        let logs = String::from_utf8(logs.0.lock().unwrap().clone()).unwrap();
+        let summaries: Vec<&str> = logs
+            .lines()
+            .filter(|line| line.contains("chorus::summary"))
+            .collect();
+        assert_eq!(summaries.len(), 1, "{}", logs);
+        let line = summaries[0];
+        for expected in [
+            "request_id=\"summary-1\"",
+            "route=\"/v1/chat/completions\"",
+            "outcome=\"success\"",
+            "analyzer_ms=",
+            "workers_ms=",
+            "synthesizer_ms=",
+            "slowest_worker=\"m1\"",
+            "workers_ok=1 workers_failed=0 total_tokens=0",
+            "selector_fallback=false degraded=false",
+        ] {
+            assert!(line.contains(expected), "{} not in {}", expected, line);
+        }
+        // 没有选择器的计划不输出 selector_ms，默认工作流不输出 preset
+        assert!(!line.contains("selector_ms="), "{}", line);
+        assert!(!line.contains("preset="), "{}", line);
+    }
+
+    #[tokio::test]
+    async fn request_ids_are_forwarded_to_upstream_calls_per_phase() {
+        let seen = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let recorded = seen.clone();
+        let upstream = Router::new().route(
+            "/v1/chat/completions",
+            post(move |headers: axum::http::HeaderMap| {
+                let recorded = recorded.clone();
+                async move {
+                    let header = |name: &str| headers[name].to_str().unwrap().to_string();
+                    assert_eq!(header("x-request-id"), header("x-client-request-id"));
+                    recorded.lock().unwrap().push(header("x-request-id"));
+                    Json(json!({"choices": [{"message": {"content": "hello"}}]}))
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, upstream).await.unwrap() });
+
+        let config = test_config(
+            &format!("http://{}/v1", upstream_addr),
+            "host = \"127.0.0.1\"\nport = 11435",
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let app = app_for(config);
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+
+        let chat = |request_id: Option<&str>| {
+            let payload = json!({
+                "model": "chorus",
+                "messages": [{"role": "user", "content": "hi"}],
+                "include_workflow": true,
+            });
+            let mut builder = Request::post("/v1/chat/completions")
+                .header("host", "localhost")
+                .header("content-type", "application/json");
+            if let Some(id) = request_id {
+                builder = builder.header("x-request-id", id);
+            }
+            builder
+                .body(Full::new(Bytes::from(payload.to_string())))
+                .unwrap()
+        };
+
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            chat(Some("ticket-42")),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.request_id, "ticket-42");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            ["ticket-42/worker-1", "ticket-42/synthesizer"]
+        );
+        let body: serde_json::Value = serde_json::from_str(&reply.body).unwrap();
+        assert_eq!(body["workflow"]["request_id"], "ticket-42");
+        assert_eq!(
+            body["workflow"]["workers"][0]["attempts"][0]["request_id"],
+            "ticket-42/worker-1"
+        );
+
+        // 不合法的 ID 不转发，换成服务端生成的
+        seen.lock().unwrap().clear();
+        let reply = request(
+            tokio::net::TcpStream::connect(addr).await.unwrap(),
+            chat(Some("has spaces")),
+        )
+        .await;
+        assert_eq!(reply.status, 200);
+        assert_eq!(reply.request_id.len(), 36, "{}", reply.request_id);
+        assert_ne!(reply.request_id, "has spaces");
+        assert_eq!(
+            *seen.lock().unwrap(),
+            [
+                format!("{}/worker-1", reply.request_id),
+                format!("{}/synthesizer", reply.request_id),
+            ]
+        );
     }
 }
@@ -0,0 +1,343 @@
+use crate::config::{
+    Config, LogFormat, LoggingConfig, ModelConfig, NetworkConfig, ServerConfig, TelemetryConfig,
+    TimeoutConfig,
+};
+use crate::redaction::redact_tokens;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use serde_json::Value as JsonValue;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+pub struct ResolvedConfig {
+    pub config_path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub server: ServerConfig,
+    pub models: Vec<JsonValue>,
+    pub workflow: JsonValue,
+    pub timeouts: BTreeMap<String, TimeoutConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub network: Option<NetworkConfig>,
+    pub logging: LoggingConfig,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub telemetry: Option<TelemetryConfig>,
+}
+
+impl ResolvedConfig {
+    // 只接受已经通过服务端同一套加载与校验流程的配置
+    pub fn from_config(config: &Config, path: &Path) -> Result<Self> {
+        let models = config
+            .models
+            .iter()
+            .map(|model| resolved_model(config, model))
+            .collect::<Result<Vec<_>>>()?;
+        let workflow_json = config.workflow_integration.to_json_string()?;
+        let workflow = serde_json::from_str(&workflow_json)
+            .context("Failed to re-read serialized workflow JSON")?;
+        let network = (config.network != NetworkConfig::default()).then(|| NetworkConfig {
+            proxy: config.network.proxy.as_deref().map(redact_url_credentials),
+            ..config.network.clone()
+        });
+
+        Ok(Self {
+            config_path: path.display().to_string(),
+            profile: config.profile_name().map(str::to_string),
+            server: config.server.clone(),
+            models,
+            workflow,
+            timeouts: domain_timeouts(config),
+            network,
+            logging: config.logging.clone(),
+            telemetry: config.telemetry.as_ref().map(|telemetry| TelemetryConfig {
+                endpoint: telemetry.endpoint.as_deref().map(redact_url_credentials),
+                ..telemetry.clone()
+            }),
+        })
+    }
+
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        out.push_str(&format!("Config: {}\n", self.config_path));
+        if let Some(profile) = &self.profile {
+            out.push_str(&format!("Profile: {}\n", profile));
+        }
+        out.push_str(&format!("Server: {}\n", self.server.describe_listeners()));
+        if self.server.unix_socket.is_some() {
+            out.push_str(&format!(
+                "  unix_socket_mode: {:o}\n",
+                self.server.unix_socket_mode()
+            ));
+        }
+        if let Some(network) = &self.network {
+            out.push_str(&format!(
+                "Network proxy: {}\n",
+                network.proxy.as_deref().unwrap_or("(system)")
+            ));
+            if !network.no_proxy.is_empty() {
+                out.push_str(&format!("  no_proxy: {}\n", network.no_proxy.join(", ")));
+            }
+            out.push_str(&format!(
+                "  connections: {}\n",
+                network.pool_settings().describe()
+            ));
+            if let Some(ca) = &network.ca_certificate {
+                out.push_str(&format!("  ca_certificate: {}\n", ca));
+            }
+            for (host, address) in &network.resolve {
+                out.push_str(&format!("  resolve: {} -> {}\n", host, address));
+            }
+        }
+
+        let logging = &self.logging;
+        out.push_str(&format!(
+            "Logging: {} to {}\n",
+            match logging.format {
+                LogFormat::Text => "text",
+                LogFormat::Json => "json",
+            },
+            logging.file.as_deref().unwrap_or("stdout")
+        ));
+        if let Some(level) = &logging.level {
+            out.push_str(&format!("  level: {}\n", level));
+        }
+        if let Some(telemetry) = &self.telemetry {
+            if let Some(endpoint) = telemetry.traces_endpoint() {
+                out.push_str(&format!(
+                    "Telemetry: OTLP to {} as '{}' (sample ratio {})\n",
+                    endpoint,
+                    telemetry.service_name(),
+                    telemetry.sample_ratio()
+                ));
+            }
+            if let Some(headers) = &telemetry.request_id_headers {
+                out.push_str(&format!(
+                    "Provider request id headers: {}\n",
+                    headers.join(", ")
+                ));
+            }
+        }
+
+        out.push_str(&format!("Models ({}):\n", self.models.len()));
+        for model in &self.models {
+            let Some(fields) = model.as_object() else {
+                continue;
+            };
+            let name = fields.get("name").and_then(JsonValue::as_str).unwrap_or("");
+            out.push_str(&format!("  {}\n", name));
+            for (key, value) in fields {
+                if key == "name" {
+                    continue;
+                }
+                let rendered = match value {
+                    JsonValue::String(s) => s.clone(),
+                    _ if key == "effective_timeouts" => {
+                        let secs = |field: &str| value[field].to_string();
+                        format!(
+                            "{}/{}/{}, connect {}",
+                            secs("analyzer_timeout_secs"),
+                            secs("worker_timeout_secs"),
+                            secs("synthesizer_timeout_secs"),
+                            secs("connect_timeout_secs")
+                        )
+                    }
+                    other => other.to_string(),
+                };
+                out.push_str(&format!("    {}: {}\n", key, rendered));
+            }
+        }
+
+        out.push_str("Workflow:\n");
+        let workflow = serde_json::to_string_pretty(&self.workflow).unwrap_or_default();
+        for line in workflow.lines() {
+            out.push_str(&format!("  {}\n", line));
+        }
+
+        out.push_str("Timeouts (analyzer/worker/synthesizer secs):\n");
+        for (domain, t) in &self.timeouts {
+            out.push_str(&format!(
+                "  {}: {}/{}/{}, connect {}\n",
+                domain,
+                t.analyzer_timeout_secs,
+                t.worker_timeout_secs,
+                t.synthesizer_timeout_secs,
+                t.connect_timeout_secs
+            ));
+        }
+        out
+    }
+}
+
+pub fn domain_timeouts(config: &Config) -> BTreeMap<String, TimeoutConfig> {
+    let mut timeouts = BTreeMap::new();
+    timeouts.insert(
+        "default".to_string(),
+        config.effective_timeouts_for_domain(None),
+    );
+    for domain in config.workflow.domains.keys() {
+        timeouts.insert(
+            domain.clone(),
+            config.effective_timeouts_for_domain(Some(domain)),
+        );
+    }
+    timeouts
+}
+
+fn resolved_model(config: &Config, model: &ModelConfig) -> Result<JsonValue> {
+    let mut value = serde_json::to_value(model)
+        .with_context(|| format!("Failed to serialize model '{}'", model.name))?;
+    if let Some(fields) = value.as_object_mut() {
+        fields.retain(|_, value| !value.is_null());
+        fields.insert(
+            "api_key".to_string(),
+            JsonValue::String(mask_api_key(model.api_key())),
+        );
+        if let Some(proxy) = &model.proxy {
+            fields.insert(
+                "proxy".to_string(),
+                JsonValue::String(redact_url_credentials(proxy)),
+            );
+        }
+        // 请求头里常放网关凭据，和 api_key 一样只显示末尾几位
+        if !model.extra_headers.is_empty() {
+            let headers = model
+                .extra_headers
+                .iter()
+                .map(|(name, value)| (name.clone(), JsonValue::String(mask_api_key(value))))
+                .collect();
+            fields.insert("extra_headers".to_string(), JsonValue::Object(headers));
+        }
+        fields.insert(
+            "effective_timeouts".to_string(),
+            serde_json::to_value(config.effective_timeouts_for(model))?,
+        );
+    }
+    Ok(value)
+}
+
+// 只保留末尾 4 位；过短的 key 全部遮盖
+pub fn mask_api_key(key: &str) -> String {
+    let chars: Vec<char> = key.chars().collect();
+    if chars.is_empty() {
+        return String::new();
+    }
+    if chars.len() <= 8 {
+        return "****".to_string();
+    }
+    let tail: String = chars[chars.len() - 4..].iter().collect();
+    format!("****{}", tail)
+}
+
+// 代理地址里可能带有 `user:password@`，展示前遮盖密码
+pub fn redact_url_credentials(raw: &str) -> String {
+    match url::Url::parse(raw) {
+        Ok(mut url) if url.password().is_some() => {
+            let _ = url.set_password(Some("****"));
+            url.to_string()
+        }
+        _ => raw.to_string(),
+    }
+}
+
+// 打印配置原文（迁移预览、测试输出）前逐行遮盖：api_key 只留末 4 位，URL 去掉密码，再兜底遮盖 token
+pub fn redact_config_text(text: &str) -> String {
+    let mut out: String = text
+        .lines()
+        .map(|line| {
+            let Some((key, value)) = line.split_once('=') else {
+                return line.to_string();
+            };
+            let quoted = value.trim();
+            let Some(inner) = quoted
+                .strip_prefix('"')
+                .and_then(|rest| rest.strip_suffix('"'))
+            else {
+                return line.to_string();
+            };
+            let masked = if key.trim() == "api_key" {
+                mask_api_key(inner)
+            } else {
+                redact_url_credentials(inner)
+            };
+            format!("{}= \"{}\"", key, masked)
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    if text.ends_with('\n') {
+        out.push('\n');
+    }
+    redact_tokens(&out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CFG: &str = r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "sk-secret-value-1234"
+name = "m1"
+worker_timeout_secs = 20
+
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "m1"},
+  "workers": [{"name": "m1"}],
+  "synthesizer": {"ref": "m1"}
+}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+
+[workflow.domains."api.example.com"]
+analyzer_timeout_secs = 5
+"#;
+
+    #[test]
+    fn masks_all_but_last_four_characters() {
+        assert_eq!(mask_api_key(""), "");
+        assert_eq!(mask_api_key("short"), "****");
+        assert_eq!(mask_api_key("sk-secret-value-1234"), "****1234");
+    }
+
+    #[test]
+    fn resolved_config_masks_keys_and_includes_effective_values() {
+        let config: Config = toml::from_str(CFG).unwrap();
+        let resolved = ResolvedConfig::from_config(&config, Path::new("config.toml")).unwrap();
+
+        let json = serde_json::to_value(&resolved).unwrap();
+        assert_eq!(json["models"][0]["api_key"], "****1234");
+        assert_eq!(
+            json["models"][0]["effective_timeouts"]["analyzer_timeout_secs"],
+            5
+        );
+        assert_eq!(
+            json["models"][0]["effective_timeouts"]["worker_timeout_secs"],
+            20
+        );
+        assert_eq!(json["workflow"]["analyzer"]["ref"], "m1");
+        assert_eq!(
+            json["timeouts"]["api.example.com"]["worker_timeout_secs"],
+            6
+        );
+        assert!(json.get("network").is_none());
+
+        let text = resolved.render_text();
+        assert!(!text.contains("sk-secret"), "{}", text);
+        assert!(text.contains("api_key: ****1234"));
+        assert!(text.contains("effective_timeouts: 5/20/9"));
+        assert!(!text.contains("temperature"));
+        assert!(text.contains("Server: http://127.0.0.1:11435"));
+        assert!(text.contains("Logging: text to stdout"));
+        assert!(text.contains("  api.example.com: 5/6/9"));
+        assert!(text.contains("\"analyzer\""));
+    }
+}
@@ -0,0 +1,105 @@
+use crate::inflight::{Registration, WorkflowTally};
+use crate::latency::Phase;
+
+// 每个工作流请求结束时输出一条 info 级日志（target 为 chorus::summary），字段固定如下，
+// 可以据此建告警；没有值的字段不输出：
+//   workflow_id        工作流编号
+//   request_id         入站请求的 ID
+//   route              命中的 HTTP 路由，如 /v1/chat/completions
+//   preset             工作流预设名，默认工作流不输出
+//   outcome            success / error / cancelled（客户端断开，工作流被中止）
+//   total_ms           从开始执行到结束的总耗时
+//   analyzer_ms        各阶段耗时（含限流与排队等待），未进入的阶段不输出
+//   workers_ms
+//   selector_ms
+//   synthesizer_ms
+//   slowest_worker     最慢一次 worker 调用的模型名及其耗时 slowest_worker_ms
+//   workers_ok         成功与失败的 worker 数（不含被禁用而跳过的）
+//   workers_failed
+//   total_tokens       所有阶段上游报告的 token 之和
+//   selector_fallback  选择器没给出结果，改用 worker 的回答
+//   degraded           有 worker 失败或发生了 selector_fallback
+pub struct WorkflowSummary {
+    registration: Option<Registration>,
+    enabled: bool,
+    workflow_id: u64,
+    request_id: Option<String>,
+    route: Option<String>,
+    preset: Option<String>,
+    started: std::time::Instant,
+}
+
+impl WorkflowSummary {
+    // 持有在途登记，结束时从中取出累计数据；未调用 finish 就被释放时按 cancelled 输出
+    pub fn begin(
+        registration: Registration,
+        enabled: bool,
+        workflow_id: u64,
+        request_id: Option<String>,
+        route: Option<String>,
+        preset: Option<String>,
+    ) -> Self {
+        Self {
+            registration: Some(registration),
+            enabled,
+            workflow_id,
+            request_id,
+            route,
+            preset,
+            started: std::time::Instant::now(),
+        }
+    }
+
+    pub fn finish(mut self, success: bool) {
+        self.emit(if success { "success" } else { "error" });
+    }
+
+    fn emit(&mut self, outcome: &'static str) {
+        let Some(registration) = self.registration.take() else {
+            return;
+        };
+        let tally = registration.finish();
+        if !self.enabled {
+            return;
+        }
+        let WorkflowTally {
+            phase_ms,
+            slowest_worker,
+            workers_ok,
+            workers_failed,
+            total_tokens,
+            selector_fallback,
+        } = tally;
+        let phase = |phase: Phase| phase_ms.get(&phase).copied();
+        let (slowest_worker, slowest_worker_ms) = slowest_worker.unzip();
+        tracing::info!(
+            target: "chorus::summary",
+            workflow_id = self.workflow_id,
+            request_id = self.request_id.as_deref(),
+            route = self.route.as_deref(),
+            preset = self.preset.as_deref(),
+            outcome,
+            total_ms = self.started.elapsed().as_millis() as u64,
+            analyzer_ms = phase(Phase::Analyzer),
+            workers_ms = phase(Phase::Worker),
+            selector_ms = phase(Phase::Selector),
+            synthesizer_ms = phase(Phase::Synthesizer),
+            slowest_worker = slowest_worker.as_deref(),
+            slowest_worker_ms,
+            workers_ok,
+            workers_failed,
+            total_tokens,
+            selector_fallback,
+            degraded = selector_fallback || workers_failed > 0,
+            "Workflow {} finished: {}",
+            self.workflow_id,
+            outcome
+        );
+    }
+}
+
+impl Drop for WorkflowSummary {
+    fn drop(&mut self) {
+        self.emit("cancelled");
+    }
+}
@@ -0,0 +1,149 @@
+use crate::config::TelemetryConfig;
+use anyhow::{Context as _, Result};
+use opentelemetry::propagation::{Extractor, Injector, TextMapPropagator};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::Context;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::propagation::TraceContextPropagator;
+use opentelemetry_sdk::trace::{Sampler, SdkTracer, SdkTracerProvider};
+use opentelemetry_sdk::Resource;
+use std::sync::OnceLock;
+use tracing::Span;
+use tracing_opentelemetry::{OpenTelemetryLayer, OpenTelemetrySpanExt};
+use tracing_subscriber::registry::LookupSpan;
+
+// 只有配置了 [telemetry] 才会设置；未设置时下面的函数都直接返回
+static PROVIDER: OnceLock<SdkTracerProvider> = OnceLock::new();
+
+pub fn enabled() -> bool {
+    PROVIDER.get().is_some()
+}
+
+// 把现有的 tracing span 导出为 OTLP 链路；批量导出在独立线程上进行，不占用 tokio 运行时
+pub fn layer<S>(config: &TelemetryConfig) -> Result<OpenTelemetryLayer<S, SdkTracer>>
+where
+    S: tracing::Subscriber + for<'span> LookupSpan<'span>,
+{
+    let endpoint = config
+        .traces_endpoint()
+        .context("telemetry.endpoint is not set")?;
+    let exporter = opentelemetry_otlp::SpanExporter::builder()
+        .with_http()
+        .with_endpoint(&endpoint)
+        .build()
+        .with_context(|| format!("Failed to create OTLP exporter for {}", endpoint))?;
+    let provider = SdkTracerProvider::builder()
+        .with_batch_exporter(exporter)
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(
+            config.sample_ratio(),
+        ))))
+        .with_resource(
+            Resource::builder()
+                .with_service_name(config.service_name().to_string())
+                .build(),
+        )
+        .build();
+    let tracer = provider.tracer("chorus");
+    PROVIDER
+        .set(provider)
+        .map_err(|_| anyhow::anyhow!("OpenTelemetry export is already initialized"))?;
+    Ok(tracing_opentelemetry::layer().with_tracer(tracer))
+}
+
+// 退出前把尚未导出的 span 发送出去
+pub fn shutdown() {
+    if let Some(provider) = PROVIDER.get() {
+        if let Err(err) = provider.shutdown() {
+            eprintln!("Failed to flush OpenTelemetry spans: {}", err);
+        }
+    }
+}
+
+// 入站请求带 traceparent 时，把 span 挂到调用方的链路下
+pub fn set_remote_parent(span: &Span, headers: &axum::http::HeaderMap) {
+    if enabled() {
+        let _ = span.set_parent(extract(headers));
+    }
+}
+
+// 出站请求带上当前 span 的 traceparent，让上游服务接上同一条链路
+pub fn inject_current(headers: &mut reqwest::header::HeaderMap) {
+    if enabled() {
+        inject(&Span::current().context(), headers);
+    }
+}
+
+fn extract(headers: &axum::http::HeaderMap) -> Context {
+    TraceContextPropagator::new().extract(&InboundHeaders(headers))
+}
+
+fn inject(context: &Context, headers: &mut reqwest::header::HeaderMap) {
+    TraceContextPropagator::new().inject_context(context, &mut OutboundHeaders(headers));
+}
+
+// axum 与 reqwest 0.11 依赖的 http 版本不同，各自实现一份
+struct InboundHeaders<'a>(&'a axum::http::HeaderMap);
+
+impl Extractor for InboundHeaders<'_> {
+    fn get(&self, key: &str) -> Option<&str> {
+        self.0.get(key).and_then(|value| value.to_str().ok())
+    }
+
+    fn keys(&self) -> Vec<&str> {
+        self.0.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+struct OutboundHeaders<'a>(&'a mut reqwest::header::HeaderMap);
+
+impl Injector for OutboundHeaders<'_> {
+    fn set(&mut self, key: &str, value: String) {
+        if let (Ok(name), Ok(value)) = (
+            reqwest::header::HeaderName::from_bytes(key.as_bytes()),
+            reqwest::header::HeaderValue::from_str(&value),
+        ) {
+            self.0.insert(name, value);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tracing_subscriber::layer::SubscriberExt;
+
+    const TRACEPARENT: &str = "00-4bf92f3577b34da6a3ce929d0e0e4736-00f067aa0ba902b7-01";
+
+    #[test]
+    fn inbound_traceparent_is_forwarded_as_the_parent_trace() {
+        // 不导出，只验证同一进程内的上下文传递
+        let provider = SdkTracerProvider::builder().build();
+        let subscriber = tracing_subscriber::registry()
+            .with(tracing_opentelemetry::layer().with_tracer(provider.tracer("test")));
+        let _guard = tracing::subscriber::set_default(subscriber);
+
+        let mut inbound = axum::http::HeaderMap::new();
+        inbound.insert("traceparent", TRACEPARENT.parse().unwrap());
+        let span = tracing::info_span!("request");
+        span.set_parent(extract(&inbound)).unwrap();
+
+        let mut outbound = reqwest::header::HeaderMap::new();
+        inject(&span.context(), &mut outbound);
+        let forwarded = outbound["traceparent"].to_str().unwrap();
+        assert!(
+            forwarded.starts_with("00-4bf92f3577b34da6a3ce929d0e0e4736-"),
+            "{}",
+            forwarded
+        );
+        // 父 span 换成了我们自己的 span，采样标志沿用上游
+        assert!(!forwarded.contains("00f067aa0ba902b7"), "{}", forwarded);
+        assert!(forwarded.ends_with("-01"), "{}", forwarded);
+    }
+
+    #[test]
+    fn nothing_is_injected_without_telemetry() {
+        let mut outbound = reqwest::header::HeaderMap::new();
+        inject_current(&mut outbound);
+        assert!(outbound.is_empty());
+    }
+}
@@ -5,7 +5,9 @@ use std::path::PathBuf;
 
 // 引入 config 模块
 mod config;
+mod show;
 use config::Config;
+use show::redact_config_text;
 
 fn main() -> anyhow::Result<()> {
     // 初始化日志
@@ -37,7 +39,7 @@ fn main() -> anyhow::Result<()> {
     // 显示旧配置内容
     println!("旧配置文件内容（前10行）:");
     println!("---");
-    let old_content = fs::read_to_string(&old_config_path)?;
+    let old_content = redact_config_text(&fs::read_to_string(&old_config_path)?);
     for (i, line) in old_content.lines().enumerate() {
         if i >= 10 {
             println!("...");
@@ -75,7 +77,7 @@ fn main() -> anyhow::Result<()> {
     }
 
     // 检查新配置内容
-    let new_content = fs::read_to_string(&old_config_path)?;
+    let new_content = redact_config_text(&fs::read_to_string(&old_config_path)?);
     if new_content.contains("[workflow-integration]") && new_content.contains("json = \"\"\"") {
         println!("✓ 新配置已迁移到 workflow json 格式");
         println!();
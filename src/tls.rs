@@ -0,0 +1,175 @@
+use crate::config::TlsConfig;
+use crate::reload::{modified_times, HangupListener, POLL_INTERVAL};
+use anyhow::{anyhow, Context, Result};
+use axum::Router;
+use hyper_util::server::graceful::GracefulShutdown;
+use rustls::ServerConfig;
+use std::fs;
+use std::future::Future;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+use tokio::net::TcpListener;
+use tokio::time::MissedTickBehavior;
+use tokio_rustls::TlsAcceptor;
+
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10);
+
+// 当前生效的证书；续期后替换，已建立的连接继续使用旧证书
+pub struct TlsCertificates {
+    config: TlsConfig,
+    current: RwLock<Arc<ServerConfig>>,
+}
+
+impl TlsCertificates {
+    pub fn load(config: &TlsConfig) -> Result<Self> {
+        Ok(Self {
+            config: config.clone(),
+            current: RwLock::new(Arc::new(load_server_config(config)?)),
+        })
+    }
+
+    pub fn reload(&self) -> Result<()> {
+        let next = load_server_config(&self.config)?;
+        *self
+            .current
+            .write()
+            .unwrap_or_else(|poisoned| poisoned.into_inner()) = Arc::new(next);
+        Ok(())
+    }
+
+    fn acceptor(&self) -> TlsAcceptor {
+        let current = self
+            .current
+            .read()
+            .unwrap_or_else(|poisoned| poisoned.into_inner());
+        TlsAcceptor::from(current.clone())
+    }
+
+    fn files(&self) -> Vec<PathBuf> {
+        vec![
+            PathBuf::from(&self.config.cert_path),
+            PathBuf::from(&self.config.key_path),
+        ]
+    }
+}
+
+pub fn load_server_config(config: &TlsConfig) -> Result<ServerConfig> {
+    let cert_pem = fs::read(&config.cert_path)
+        .with_context(|| format!("Failed to read TLS certificate {}", config.cert_path))?;
+    let certs = rustls_pemfile::certs(&mut cert_pem.as_slice())
+        .collect::<std::result::Result<Vec<_>, _>>()
+        .with_context(|| format!("Failed to parse TLS certificate {}", config.cert_path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No PEM certificate found in {}", config.cert_path));
+    }
+
+    let key_pem = fs::read(&config.key_path)
+        .with_context(|| format!("Failed to read TLS private key {}", config.key_path))?;
+    let key = rustls_pemfile::private_key(&mut key_pem.as_slice())
+        .with_context(|| format!("Failed to parse TLS private key {}", config.key_path))?
+        .ok_or_else(|| anyhow!("No PEM private key found in {}", config.key_path))?;
+
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let mut server_config = ServerConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(certs, key)
+        .map_err(|err| {
+            anyhow!(
+                "TLS certificate {} cannot be used with key {}: {}",
+                config.cert_path,
+                config.key_path,
+                err
+            )
+        })?;
+    server_config.alpn_protocols = vec![b"http/1.1".to_vec()];
+    Ok(server_config)
+}
+
+// 出站请求额外信任的 CA；文件里可以有多张证书
+pub fn load_ca_certificates(path: &str) -> Result<Vec<reqwest::Certificate>> {
+    let pem = fs::read(path).with_context(|| format!("Failed to read CA certificate {}", path))?;
+    let certs = reqwest::Certificate::from_pem_bundle(&pem)
+        .with_context(|| format!("Failed to parse CA certificate {}", path))?;
+    if certs.is_empty() {
+        return Err(anyhow!("No PEM certificate found in {}", path));
+    }
+    Ok(certs)
+}
+
+// 证书文件变化或收到 SIGHUP 时重新加载，方便 Let's Encrypt 续期后无需重启
+pub fn spawn_certificate_reloader(certs: Arc<TlsCertificates>) {
+    tokio::spawn(async move {
+        let files = certs.files();
+        let mut last_modified = modified_times(&files);
+        let mut ticker = tokio::time::interval(POLL_INTERVAL);
+        ticker.set_missed_tick_behavior(MissedTickBehavior::Delay);
+        let mut hangup = HangupListener::new();
+
+        loop {
+            tokio::select! {
+                _ = ticker.tick() => {
+                    let modified = modified_times(&files);
+                    if modified == last_modified {
+                        continue;
+                    }
+                    last_modified = modified;
+                }
+                _ = hangup.recv() => {
+                    last_modified = modified_times(&files);
+                }
+            }
+
+            match certs.reload() {
+                Ok(()) => {
+                    tracing::info!("Reloaded TLS certificate from {}", certs.config.cert_path)
+                }
+                // 证书与私钥分两次写入时可能短暂不匹配，下次变化时会再试
+                Err(err) => {
+                    tracing::error!("Keeping previous TLS certificate; reload failed: {:#}", err)
+                }
+            }
+        }
+    });
+}
+
+pub async fn serve(
+    listener: TcpListener,
+    certs: Arc<TlsCertificates>,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, peer) = match accepted {
+                    Ok(accepted) => accepted,
+                    Err(err) => {
+                        tracing::error!("Failed to accept TCP connection: {}", err);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                let acceptor = certs.acceptor();
+                let app = app.clone();
+                let watcher = graceful.watcher();
+                tokio::spawn(async move {
+                    match tokio::time::timeout(HANDSHAKE_TIMEOUT, acceptor.accept(stream)).await {
+                        Ok(Ok(tls)) => crate::server::serve_http1(tls, Some(peer), app, watcher).await,
+                        Ok(Err(err)) => tracing::debug!("TLS handshake with {} failed: {}", peer, err),
+                        Err(_) => tracing::debug!("TLS handshake with {} timed out", peer),
+                    }
+                });
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    drop(listener);
+    graceful.shutdown().await;
+    Ok(())
+}
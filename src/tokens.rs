@@ -0,0 +1,204 @@
+use crate::config::ModelConfig;
+use std::collections::HashMap;
+use tiktoken_rs::tokenizer::{get_tokenizer, Tokenizer as TiktokenTokenizer};
+use tiktoken_rs::CoreBPE;
+
+// 可在 `tokenizer = "..."` 中指定的 BPE 编码
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Cl100k,
+    O200k,
+    P50k,
+    R50k,
+}
+
+impl Encoding {
+    pub const NAMES: &'static [&'static str] =
+        &["cl100k_base", "o200k_base", "p50k_base", "r50k_base"];
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "cl100k_base" => Some(Self::Cl100k),
+            "o200k_base" => Some(Self::O200k),
+            "p50k_base" => Some(Self::P50k),
+            "r50k_base" => Some(Self::R50k),
+            _ => None,
+        }
+    }
+
+    // 编码表首次使用时加载，之后整个进程共用
+    fn bpe(self) -> &'static CoreBPE {
+        match self {
+            Self::Cl100k => tiktoken_rs::cl100k_base_singleton(),
+            Self::O200k => tiktoken_rs::o200k_base_singleton(),
+            Self::P50k => tiktoken_rs::p50k_base_singleton(),
+            Self::R50k => tiktoken_rs::r50k_base_singleton(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Tokenizer {
+    Bpe(Encoding),
+    CharsPerToken(f32),
+    // 未知模型：中日韩字符按一字一个 token，其余按 4 个字符一个 token
+    Heuristic,
+}
+
+impl Tokenizer {
+    // 配置优先；未配置时按模型名推断 OpenAI 系列的编码
+    pub fn for_model(model: &ModelConfig) -> Self {
+        if let Some(encoding) = model.tokenizer.as_deref().and_then(Encoding::from_name) {
+            return Self::Bpe(encoding);
+        }
+        if let Some(chars_per_token) = model.chars_per_token {
+            return Self::CharsPerToken(chars_per_token);
+        }
+        match get_tokenizer(&model.name) {
+            Some(TiktokenTokenizer::O200kBase) => Self::Bpe(Encoding::O200k),
+            Some(TiktokenTokenizer::Cl100kBase) => Self::Bpe(Encoding::Cl100k),
+            Some(TiktokenTokenizer::P50kBase) => Self::Bpe(Encoding::P50k),
+            Some(TiktokenTokenizer::R50kBase) => Self::Bpe(Encoding::R50k),
+            _ => Self::Heuristic,
+        }
+    }
+
+    pub fn count(self, text: &str) -> usize {
+        match self {
+            Self::Bpe(encoding) => encoding.bpe().encode_ordinary(text).len(),
+            Self::CharsPerToken(chars_per_token) => {
+                (text.chars().count() as f32 / chars_per_token).ceil() as usize
+            }
+            Self::Heuristic => {
+                let (cjk, other) = text.chars().fold((0, 0), |(cjk, other), c| {
+                    if is_cjk(c) {
+                        (cjk + 1, other)
+                    } else {
+                        (cjk, other + 1)
+                    }
+                });
+                cjk + (other as usize).div_ceil(4)
+            }
+        }
+    }
+}
+
+fn is_cjk(c: char) -> bool {
+    matches!(c,
+        '\u{3000}'..='\u{303f}'   // 中文标点
+        | '\u{3040}'..='\u{30ff}' // 假名
+        | '\u{3400}'..='\u{4dbf}'
+        | '\u{4e00}'..='\u{9fff}'
+        | '\u{ac00}'..='\u{d7af}' // 谚文
+        | '\u{ff00}'..='\u{ffef}' // 全角符号
+    )
+}
+
+// 按模型名查分词方式；每个引擎创建时按配置建一份
+#[derive(Debug, Default)]
+pub struct TokenEstimator {
+    tokenizers: HashMap<String, Tokenizer>,
+}
+
+impl TokenEstimator {
+    pub fn new(models: &[ModelConfig]) -> Self {
+        Self {
+            tokenizers: models
+                .iter()
+                .map(|model| (model.name.clone(), Tokenizer::for_model(model)))
+                .collect(),
+        }
+    }
+
+    pub fn estimate_tokens(&self, model_name: &str, text: &str) -> usize {
+        self.tokenizers
+            .get(model_name)
+            .copied()
+            .unwrap_or(Tokenizer::Heuristic)
+            .count(text)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ENGLISH: &str = "The quick brown fox jumps over the lazy dog. Large language models are trained on vast amounts of text and can answer questions, write code, and summarize documents in many languages.";
+    const CHINESE: &str = "大型语言模型通过海量文本进行训练，能够回答问题、编写代码，并用多种语言总结文档。今天天气很好，我们一起去公园散步吧。";
+    const CODE: &str = "fn main() {\n    let mut total = 0u64;\n    for i in 0..10 {\n        total += i * i;\n    }\n    println!(\"{}\", total);\n}\n";
+
+    fn assert_near(actual: usize, expected: usize, tolerance: f32) {
+        let diff = (actual as f32 - expected as f32).abs() / expected as f32;
+        assert!(
+            diff <= tolerance,
+            "estimate {} is more than {:.0}% away from {}",
+            actual,
+            tolerance * 100.0,
+            expected
+        );
+    }
+
+    fn model(name: &str) -> ModelConfig {
+        ModelConfig {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn bpe_counts_match_tiktoken() {
+        let cl100k = Tokenizer::Bpe(Encoding::Cl100k);
+        assert_eq!(cl100k.count(ENGLISH), 35);
+        assert_eq!(cl100k.count(CHINESE), 62);
+        assert_eq!(cl100k.count(CODE), 38);
+        assert_eq!(Tokenizer::Bpe(Encoding::O200k).count(CHINESE), 39);
+    }
+
+    #[test]
+    fn heuristic_stays_close_to_bpe() {
+        // 以 cl100k_base 的结果为基准
+        assert_near(Tokenizer::Heuristic.count(ENGLISH), 35, 0.35);
+        assert_near(Tokenizer::Heuristic.count(CHINESE), 62, 0.1);
+        assert_near(Tokenizer::Heuristic.count(CODE), 38, 0.25);
+        assert_eq!(Tokenizer::Heuristic.count(""), 0);
+    }
+
+    #[test]
+    fn tokenizer_follows_config_then_model_name() {
+        assert_eq!(
+            Tokenizer::for_model(&model("gpt-4o")),
+            Tokenizer::Bpe(Encoding::O200k)
+        );
+        assert_eq!(
+            Tokenizer::for_model(&model("gpt-4-turbo")),
+            Tokenizer::Bpe(Encoding::Cl100k)
+        );
+        assert_eq!(
+            Tokenizer::for_model(&model("qwen3-max")),
+            Tokenizer::Heuristic
+        );
+
+        let configured = ModelConfig {
+            tokenizer: Some("cl100k_base".to_string()),
+            ..model("gpt-4o")
+        };
+        assert_eq!(
+            Tokenizer::for_model(&configured),
+            Tokenizer::Bpe(Encoding::Cl100k)
+        );
+        let calibrated = ModelConfig {
+            chars_per_token: Some(3.2),
+            ..model("qwen3-max")
+        };
+        assert_eq!(Tokenizer::for_model(&calibrated).count(CODE), 37);
+    }
+
+    #[test]
+    fn estimator_looks_up_models_by_name() {
+        let estimator = TokenEstimator::new(&[model("gpt-4o"), model("qwen3-max")]);
+        assert_eq!(estimator.estimate_tokens("gpt-4o", CHINESE), 39);
+        assert_eq!(estimator.estimate_tokens("qwen3-max", CHINESE), 58);
+        // 未配置的模型按启发式估算
+        assert_eq!(estimator.estimate_tokens("unknown", "abcd"), 1);
+    }
+}
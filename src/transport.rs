@@ -0,0 +1,308 @@
+use bytes::Bytes;
+use futures::future::BoxFuture;
+use futures::stream::BoxStream;
+use futures::{StreamExt, TryStreamExt};
+use reqwest::header::HeaderMap;
+use reqwest::StatusCode;
+use std::error::Error as StdError;
+use std::fmt;
+use std::time::Duration;
+
+// 发往上游的一次 HTTP 请求：鉴权信息已经加好，timeout 是这次尝试剩下的时限
+pub struct TransportRequest {
+    pub url: String,
+    pub headers: HeaderMap,
+    pub body: Vec<u8>,
+    pub timeout: Duration,
+}
+
+// 响应头已经到达，响应体按块读取
+pub struct RawResponse {
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub body: BoxStream<'static, Result<Bytes, TransportError>>,
+}
+
+impl RawResponse {
+    pub fn is_json(&self) -> bool {
+        self.headers
+            .get("content-type")
+            .and_then(|value| value.to_str().ok())
+            .map(|content_type| content_type.contains("application/json"))
+            .unwrap_or(false)
+    }
+}
+
+// 连不上、超时等没有拿到完整响应的错误；显示内容与来源链都沿用底层错误
+#[derive(Debug)]
+pub struct TransportError {
+    connect: bool,
+    timeout: bool,
+    inner: Box<dyn StdError + Send + Sync>,
+}
+
+// 供 reqwest 以外的实现构造错误
+impl TransportError {
+    #[allow(dead_code)]
+    pub fn connect(err: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self {
+            connect: true,
+            timeout: false,
+            inner: err.into(),
+        }
+    }
+
+    #[allow(dead_code)]
+    pub fn timeout(err: impl Into<Box<dyn StdError + Send + Sync>>) -> Self {
+        Self {
+            connect: false,
+            timeout: true,
+            inner: err.into(),
+        }
+    }
+
+    pub fn is_connect(&self) -> bool {
+        self.connect
+    }
+
+    pub fn is_timeout(&self) -> bool {
+        self.timeout
+    }
+
+    // reqwest 的错误信息带完整 URL，Gemini 的 key 就在查询参数里
+    pub fn without_url(self) -> Self {
+        match self.inner.downcast::<reqwest::Error>() {
+            Ok(err) => Self {
+                inner: Box::new(err.without_url()),
+                ..self
+            },
+            Err(inner) => Self { inner, ..self },
+        }
+    }
+}
+
+impl From<reqwest::Error> for TransportError {
+    fn from(err: reqwest::Error) -> Self {
+        Self {
+            connect: err.is_connect(),
+            timeout: err.is_timeout(),
+            inner: Box::new(err),
+        }
+    }
+}
+
+impl fmt::Display for TransportError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.inner.fmt(f)
+    }
+}
+
+impl StdError for TransportError {
+    fn source(&self) -> Option<&(dyn StdError + 'static)> {
+        self.inner.source()
+    }
+}
+
+// LLMClient 发请求的唯一出口；测试中换成脚本化的实现，不需要真的起 HTTP 服务
+pub trait HttpTransport: Send + Sync {
+    fn send(&self, request: TransportRequest)
+        -> BoxFuture<'_, Result<RawResponse, TransportError>>;
+}
+
+pub struct ReqwestTransport {
+    client: reqwest::Client,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: reqwest::Client) -> Self {
+        Self { client }
+    }
+}
+
+impl HttpTransport for ReqwestTransport {
+    fn send(
+        &self,
+        request: TransportRequest,
+    ) -> BoxFuture<'_, Result<RawResponse, TransportError>> {
+        let builder = self
+            .client
+            .post(&request.url)
+            .headers(request.headers)
+            .body(request.body)
+            .timeout(request.timeout);
+        Box::pin(async move {
+            let response = builder.send().await?;
+            Ok(RawResponse {
+                status: response.status(),
+                headers: response.headers().clone(),
+                body: response
+                    .bytes_stream()
+                    .map_err(TransportError::from)
+                    .boxed(),
+            })
+        })
+    }
+}
+
+#[cfg(test)]
+pub use scripted::{Scripted, ScriptedTransport};
+
+#[cfg(test)]
+mod scripted {
+    use super::*;
+    use reqwest::header::HeaderValue;
+    use std::sync::Mutex;
+
+    // 一条脚本化的回复：状态码、响应头、分块的响应体，以及到达前的延迟
+    #[derive(Clone)]
+    pub struct Scripted {
+        status: StatusCode,
+        headers: HeaderMap,
+        chunks: Vec<Bytes>,
+        latency: Duration,
+        connect_error: bool,
+    }
+
+    impl Scripted {
+        pub fn json(value: serde_json::Value) -> Self {
+            let mut headers = HeaderMap::new();
+            headers.insert("content-type", HeaderValue::from_static("application/json"));
+            Self {
+                status: StatusCode::OK,
+                headers,
+                chunks: vec![Bytes::from(value.to_string())],
+                latency: Duration::ZERO,
+                connect_error: false,
+            }
+        }
+
+        // OpenAI 格式的一次完整回复
+        pub fn completion(content: &str) -> Self {
+            Self::json(serde_json::json!({
+                "choices": [{"message": {"content": content}, "finish_reason": "stop"}]
+            }))
+        }
+
+        pub fn status(status: u16, message: &str) -> Self {
+            Self {
+                status: StatusCode::from_u16(status).unwrap(),
+                ..Self::json(serde_json::json!({"error": {"message": message}}))
+            }
+        }
+
+        // SSE 响应，每一项作为一个网络分块到达
+        pub fn event_stream(chunks: &[&str]) -> Self {
+            let mut headers = HeaderMap::new();
+            headers.insert(
+                "content-type",
+                HeaderValue::from_static("text/event-stream"),
+            );
+            Self {
+                status: StatusCode::OK,
+                headers,
+                chunks: chunks
+                    .iter()
+                    .map(|chunk| Bytes::from(chunk.to_string()))
+                    .collect(),
+                latency: Duration::ZERO,
+                connect_error: false,
+            }
+        }
+
+        pub fn connect_error() -> Self {
+            Self {
+                connect_error: true,
+                ..Self::json(serde_json::Value::Null)
+            }
+        }
+
+        // 超过这次请求剩余时限的延迟表现为超时
+        pub fn after(mut self, latency: Duration) -> Self {
+            self.latency = latency;
+            self
+        }
+
+        pub fn header(mut self, name: &'static str, value: &'static str) -> Self {
+            self.headers.insert(name, HeaderValue::from_static(value));
+            self
+        }
+    }
+
+    #[derive(Debug, Clone)]
+    pub struct RecordedRequest {
+        pub url: String,
+        pub headers: HeaderMap,
+        pub body: serde_json::Value,
+    }
+
+    // 按 URL 片段匹配回复；同一片段登记多条时按顺序使用，最后一条重复使用
+    #[derive(Default)]
+    pub struct ScriptedTransport {
+        routes: Mutex<Vec<(String, Vec<Scripted>)>>,
+        requests: Mutex<Vec<RecordedRequest>>,
+    }
+
+    impl ScriptedTransport {
+        pub fn on(self, pattern: &str, reply: Scripted) -> Self {
+            {
+                let mut routes = self.routes.lock().unwrap();
+                match routes.iter_mut().find(|(existing, _)| existing == pattern) {
+                    Some((_, replies)) => replies.push(reply),
+                    None => routes.push((pattern.to_string(), vec![reply])),
+                }
+            }
+            self
+        }
+
+        pub fn requests(&self) -> Vec<RecordedRequest> {
+            self.requests.lock().unwrap().clone()
+        }
+
+        fn next_reply(&self, url: &str) -> Option<Scripted> {
+            let mut routes = self.routes.lock().unwrap();
+            let (_, replies) = routes
+                .iter_mut()
+                .find(|(pattern, _)| url.contains(pattern.as_str()))?;
+            Some(if replies.len() > 1 {
+                replies.remove(0)
+            } else {
+                replies[0].clone()
+            })
+        }
+    }
+
+    impl HttpTransport for ScriptedTransport {
+        fn send(
+            &self,
+            request: TransportRequest,
+        ) -> BoxFuture<'_, Result<RawResponse, TransportError>> {
+            self.requests.lock().unwrap().push(RecordedRequest {
+                url: request.url.clone(),
+                headers: request.headers.clone(),
+                body: serde_json::from_slice(&request.body).unwrap_or_default(),
+            });
+            let reply = self.next_reply(&request.url);
+            Box::pin(async move {
+                let Some(reply) = reply else {
+                    return Err(TransportError::connect(format!(
+                        "no scripted response for {}",
+                        request.url
+                    )));
+                };
+                if reply.latency >= request.timeout {
+                    tokio::time::sleep(request.timeout).await;
+                    return Err(TransportError::timeout("operation timed out"));
+                }
+                tokio::time::sleep(reply.latency).await;
+                if reply.connect_error {
+                    return Err(TransportError::connect("connection refused"));
+                }
+                Ok(RawResponse {
+                    status: reply.status,
+                    headers: reply.headers,
+                    body: futures::stream::iter(reply.chunks.into_iter().map(Ok)).boxed(),
+                })
+            })
+        }
+    }
+}
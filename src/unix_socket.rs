@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Context, Result};
+use axum::Router;
+use hyper_util::server::graceful::GracefulShutdown;
+use std::fs;
+use std::future::Future;
+use std::io::ErrorKind;
+use std::os::unix::fs::{DirBuilderExt, FileTypeExt, PermissionsExt};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+use tokio::net::UnixListener;
+
+// 监听结束（包括出错提前返回）时删除 socket 文件
+pub struct UnixSocketListener {
+    listener: UnixListener,
+    path: PathBuf,
+}
+
+impl Drop for UnixSocketListener {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_file(&self.path) {
+            if err.kind() != ErrorKind::NotFound {
+                tracing::warn!(
+                    "Failed to remove Unix socket {}: {}",
+                    self.path.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+pub fn bind(path: &Path, mode: u32) -> Result<UnixSocketListener> {
+    if let Some(dir) = path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create socket dir: {}", dir.display()))?;
+    }
+    remove_stale_socket(path)?;
+
+    // 先在同目录下只有自己可进入的临时目录里 bind 并设好权限，再移到目标路径；
+    // 直接 bind 时 socket 会按 umask 的权限短暂暴露
+    let staging = staging_dir(path)?;
+    let result = bind_in(&staging, path, mode);
+    let _ = fs::remove_dir_all(&staging);
+    result
+}
+
+fn staging_dir(path: &Path) -> Result<PathBuf> {
+    let name = path
+        .file_name()
+        .ok_or_else(|| anyhow!("{} is not a valid socket path", path.display()))?;
+    let dir = path.with_file_name(format!(
+        ".{}.{}.bind",
+        name.to_string_lossy(),
+        std::process::id()
+    ));
+    let _ = fs::remove_dir_all(&dir);
+    fs::DirBuilder::new()
+        .mode(0o700)
+        .create(&dir)
+        .with_context(|| format!("Failed to create socket staging dir: {}", dir.display()))?;
+    Ok(dir)
+}
+
+fn bind_in(staging: &Path, path: &Path, mode: u32) -> Result<UnixSocketListener> {
+    let staged = staging.join("socket");
+    let listener = UnixListener::bind(&staged)
+        .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+    fs::set_permissions(&staged, fs::Permissions::from_mode(mode))
+        .with_context(|| format!("Failed to set permissions {:o} on {}", mode, path.display()))?;
+    fs::rename(&staged, path)
+        .with_context(|| format!("Failed to move Unix socket into {}", path.display()))?;
+    Ok(UnixSocketListener {
+        listener,
+        path: path.to_path_buf(),
+    })
+}
+
+// 上次异常退出留下的 socket 文件直接删除；仍有进程在监听或不是 socket 时拒绝启动
+fn remove_stale_socket(path: &Path) -> Result<()> {
+    let metadata = match fs::symlink_metadata(path) {
+        Ok(metadata) => metadata,
+        Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+        Err(err) => {
+            return Err(err).with_context(|| format!("Failed to inspect {}", path.display()))
+        }
+    };
+    if !metadata.file_type().is_socket() {
+        return Err(anyhow!(
+            "{} exists and is not a socket; refusing to replace it",
+            path.display()
+        ));
+    }
+    if std::os::unix::net::UnixStream::connect(path).is_ok() {
+        return Err(anyhow!(
+            "{} is already in use by another process",
+            path.display()
+        ));
+    }
+
+    fs::remove_file(path)
+        .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+    tracing::info!("Removed stale Unix socket {}", path.display());
+    Ok(())
+}
+
+pub async fn serve(
+    socket: UnixSocketListener,
+    app: Router,
+    shutdown: impl Future<Output = ()>,
+) -> Result<()> {
+    let graceful = GracefulShutdown::new();
+    tokio::pin!(shutdown);
+
+    loop {
+        tokio::select! {
+            accepted = socket.listener.accept() => {
+                let stream = match accepted {
+                    Ok((stream, _)) => stream,
+                    Err(err) => {
+                        // 与 axum::serve 一致：多为文件描述符耗尽，稍后重试
+                        tracing::error!("Failed to accept Unix socket connection: {}", err);
+                        tokio::time::sleep(Duration::from_secs(1)).await;
+                        continue;
+                    }
+                };
+                tokio::spawn(crate::server::serve_http1(stream, None, app.clone(), graceful.watcher()));
+            }
+            _ = &mut shutdown => break,
+        }
+    }
+
+    // 先删除 socket 文件不再接受新连接，再等进行中的请求完成
+    drop(socket);
+    graceful.shutdown().await;
+    Ok(())
+}
@@ -0,0 +1,367 @@
+use crate::history::HistoryStore;
+use crate::llm::Usage;
+use anyhow::Result;
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use serde::{Deserialize, Serialize, Serializer};
+use std::collections::{BTreeMap, HashMap};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+const HOUR_SECS: i64 = 3600;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct UsageCounts {
+    pub requests: u64,
+    pub errors: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub total_tokens: u64,
+    // 查询时按当前配置的价格逐个（模型, 小时）桶折算，不落库；没有任何桶配置价格时省略
+    #[serde(
+        skip_serializing_if = "Option::is_none",
+        serialize_with = "serialize_cost"
+    )]
+    pub estimated_cost: Option<f64>,
+}
+
+// 每 1000 个 token 的价格
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pricing {
+    pub input_per_1k: f64,
+    pub output_per_1k: f64,
+}
+
+impl Pricing {
+    pub fn cost(&self, counts: &UsageCounts) -> f64 {
+        (counts.prompt_tokens as f64 * self.input_per_1k
+            + counts.completion_tokens as f64 * self.output_per_1k)
+            / 1000.0
+    }
+}
+
+// 浮点累加的尾数没有意义，保留 6 位小数
+fn serialize_cost<S: Serializer>(cost: &Option<f64>, serializer: S) -> Result<S::Ok, S::Error> {
+    match cost {
+        Some(cost) => serializer.serialize_f64((cost * 1e6).round() / 1e6),
+        None => serializer.serialize_none(),
+    }
+}
+
+impl UsageCounts {
+    // 一次上游调用；上游没报告用量时只计请求数
+    pub fn of_call(usage: Option<&Usage>, ok: bool) -> Self {
+        let tokens = |count: Option<u32>| u64::from(count.unwrap_or(0));
+        Self {
+            requests: 1,
+            errors: u64::from(!ok),
+            prompt_tokens: tokens(usage.and_then(|usage| usage.prompt_tokens)),
+            completion_tokens: tokens(usage.and_then(|usage| usage.completion_tokens)),
+            total_tokens: tokens(usage.and_then(|usage| usage.total_tokens)),
+            estimated_cost: None,
+        }
+    }
+
+    fn add(&mut self, other: &UsageCounts) {
+        self.requests += other.requests;
+        self.errors += other.errors;
+        self.prompt_tokens += other.prompt_tokens;
+        self.completion_tokens += other.completion_tokens;
+        self.total_tokens += other.total_tokens;
+        self.estimated_cost = match (self.estimated_cost, other.estimated_cost) {
+            (Some(a), Some(b)) => Some(a + b),
+            (cost, None) | (None, cost) => cost,
+        };
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum GroupBy {
+    #[default]
+    Model,
+    Day,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct UsageRow {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub model: Option<String>,
+    // UTC 日期，YYYY-MM-DD
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub day: Option<String>,
+    #[serde(flatten)]
+    pub counts: UsageCounts,
+}
+
+struct Buckets {
+    // (整点的 Unix 秒, 模型) -> 该小时的累计
+    hours: BTreeMap<(i64, String), UsageCounts>,
+    retention: Duration,
+}
+
+// 按小时累计每个模型的上游调用与 token；查询只汇总这些桶，不扫描历史记录
+pub struct UsageTracker {
+    buckets: Mutex<Buckets>,
+    store: Option<Arc<HistoryStore>>,
+}
+
+impl UsageTracker {
+    pub fn new(retention: Duration) -> Self {
+        Self {
+            buckets: Mutex::new(Buckets {
+                hours: BTreeMap::new(),
+                retention,
+            }),
+            store: None,
+        }
+    }
+
+    // 启用历史记录时从库里恢复保留期内的用量，之后的增量也写回库中
+    pub fn with_store(mut self, store: Arc<HistoryStore>) -> Result<Self> {
+        let cutoff = {
+            let mut buckets = self.lock();
+            let cutoff = hour_start(Utc::now() - buckets.retention);
+            for (hour, model, counts) in store.load_usage(cutoff)? {
+                buckets.hours.entry((hour, model)).or_default().add(&counts);
+            }
+            cutoff
+        };
+        store.prune_usage(cutoff);
+        self.store = Some(store);
+        Ok(self)
+    }
+
+    pub fn configure(&self, retention: Duration) {
+        self.lock().retention = retention;
+    }
+
+    pub fn record(&self, model: &str, usage: Option<&Usage>, ok: bool) {
+        self.record_at(Utc::now(), model, UsageCounts::of_call(usage, ok));
+    }
+
+    fn record_at(&self, now: DateTime<Utc>, model: &str, counts: UsageCounts) {
+        let hour = hour_start(now);
+        let pruned = {
+            let mut buckets = self.lock();
+            buckets
+                .hours
+                .entry((hour, model.to_string()))
+                .or_default()
+                .add(&counts);
+            let cutoff = hour_start(now - buckets.retention);
+            let expired = buckets
+                .hours
+                .first_key_value()
+                .is_some_and(|((oldest, _), _)| *oldest < cutoff);
+            if expired {
+                buckets.hours = buckets.hours.split_off(&(cutoff, String::new()));
+            }
+            expired.then_some(cutoff)
+        };
+        if let Some(store) = &self.store {
+            store.record_usage(hour, model, counts);
+            if let Some(cutoff) = pruned {
+                store.prune_usage(cutoff);
+            }
+        }
+    }
+
+    // 汇总 [from, to) 内的小时桶；from 向下取整到整点。按模型分组时以模型名排序，按天分组时以日期排序。
+    // 没有配置价格的模型不计入 estimated_cost
+    pub fn query(
+        &self,
+        from: DateTime<Utc>,
+        to: DateTime<Utc>,
+        group_by: GroupBy,
+        pricing: &HashMap<String, Pricing>,
+    ) -> Vec<UsageRow> {
+        let first = hour_start(from);
+        let last = to.timestamp();
+        let mut groups: BTreeMap<String, UsageCounts> = BTreeMap::new();
+        let buckets = self.lock();
+        for ((hour, model), counts) in buckets.hours.range((first, String::new())..) {
+            if *hour >= last {
+                break;
+            }
+            let key = match group_by {
+                GroupBy::Model => model.clone(),
+                GroupBy::Day => day_of(*hour),
+            };
+            let counts = UsageCounts {
+                estimated_cost: pricing.get(model).map(|price| price.cost(counts)),
+                ..*counts
+            };
+            groups.entry(key).or_default().add(&counts);
+        }
+        groups
+            .into_iter()
+            .map(|(key, counts)| match group_by {
+                GroupBy::Model => UsageRow {
+                    model: Some(key),
+                    day: None,
+                    counts,
+                },
+                GroupBy::Day => UsageRow {
+                    model: None,
+                    day: Some(key),
+                    counts,
+                },
+            })
+            .collect()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Buckets> {
+        self.buckets
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
+
+pub fn total(rows: &[UsageRow]) -> UsageCounts {
+    let mut total = UsageCounts::default();
+    for row in rows {
+        total.add(&row.counts);
+    }
+    total
+}
+
+fn hour_start(at: DateTime<Utc>) -> i64 {
+    at.timestamp().div_euclid(HOUR_SECS) * HOUR_SECS
+}
+
+fn day_of(hour: i64) -> String {
+    Utc.timestamp_opt(hour, 0)
+        .single()
+        .unwrap_or_default()
+        .format("%Y-%m-%d")
+        .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(timestamp: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(timestamp).unwrap().into()
+    }
+
+    fn usage(prompt: u32, completion: u32) -> Usage {
+        Usage {
+            prompt_tokens: Some(prompt),
+            completion_tokens: Some(completion),
+            total_tokens: Some(prompt + completion),
+        }
+    }
+
+    #[test]
+    fn calls_roll_up_by_model_and_day() {
+        let tracker = UsageTracker::new(Duration::days(35));
+        let call = |now: &str, model: &str, usage: Option<&Usage>, ok: bool| {
+            tracker.record_at(at(now), model, UsageCounts::of_call(usage, ok));
+        };
+        call("2026-10-12T09:15:00Z", "m1", Some(&usage(10, 5)), true);
+        call("2026-10-12T09:45:00Z", "m1", Some(&usage(20, 5)), true);
+        call("2026-10-12T23:59:00Z", "m2", None, false);
+        call("2026-10-13T00:10:00Z", "m1", Some(&usage(1, 1)), true);
+
+        let rows = tracker.query(
+            at("2026-10-12T09:30:00Z"),
+            at("2026-10-14T00:00:00Z"),
+            GroupBy::Model,
+            &HashMap::new(),
+        );
+        assert_eq!(
+            rows.iter()
+                .map(|row| (row.model.as_deref().unwrap(), row.counts))
+                .collect::<Vec<_>>(),
+            vec![
+                (
+                    "m1",
+                    UsageCounts {
+                        requests: 3,
+                        errors: 0,
+                        prompt_tokens: 31,
+                        completion_tokens: 11,
+                        total_tokens: 42,
+                        estimated_cost: None,
+                    }
+                ),
+                (
+                    "m2",
+                    UsageCounts {
+                        requests: 1,
+                        errors: 1,
+                        ..Default::default()
+                    }
+                ),
+            ]
+        );
+
+        let days = tracker.query(
+            at("2026-10-01T00:00:00Z"),
+            at("2026-10-13T00:00:00Z"),
+            GroupBy::Day,
+            &HashMap::new(),
+        );
+        assert_eq!(days.len(), 1);
+        assert_eq!(days[0].day.as_deref(), Some("2026-10-12"));
+        assert_eq!(days[0].counts.requests, 3);
+        assert_eq!(total(&days).prompt_tokens, 30);
+    }
+
+    #[test]
+    fn buckets_older_than_the_retention_are_dropped() {
+        let tracker = UsageTracker::new(Duration::days(1));
+        let counts = UsageCounts::of_call(Some(&usage(1, 1)), true);
+        tracker.record_at(at("2026-10-10T08:00:00Z"), "m1", counts);
+        tracker.record_at(at("2026-10-11T07:30:00Z"), "m1", counts);
+        tracker.record_at(at("2026-10-11T09:00:00Z"), "m1", counts);
+
+        let rows = tracker.query(
+            at("2026-10-01T00:00:00Z"),
+            at("2026-10-12T00:00:00Z"),
+            GroupBy::Model,
+            &HashMap::new(),
+        );
+        assert_eq!(rows[0].counts.requests, 2);
+    }
+
+    #[test]
+    fn estimated_cost_uses_per_model_prices() {
+        let tracker = UsageTracker::new(Duration::days(35));
+        tracker.record_at(
+            at("2026-10-12T09:15:00Z"),
+            "m1",
+            UsageCounts::of_call(Some(&usage(2_000, 500)), true),
+        );
+        tracker.record_at(
+            at("2026-10-12T10:15:00Z"),
+            "m1",
+            UsageCounts::of_call(Some(&usage(1_000, 1_000)), true),
+        );
+        tracker.record_at(
+            at("2026-10-12T10:30:00Z"),
+            "m2",
+            UsageCounts::of_call(Some(&usage(1_000, 1_000)), true),
+        );
+        let pricing = HashMap::from([(
+            "m1".to_string(),
+            Pricing {
+                input_per_1k: 0.5,
+                output_per_1k: 1.5,
+            },
+        )]);
+        let range = (at("2026-10-12T00:00:00Z"), at("2026-10-13T00:00:00Z"));
+
+        let rows = tracker.query(range.0, range.1, GroupBy::Model, &pricing);
+        // 1.0 + 0.75，再加 0.5 + 1.5
+        assert_eq!(rows[0].counts.estimated_cost, Some(3.75));
+        assert_eq!(rows[1].counts.estimated_cost, None);
+        let json = serde_json::to_value(&rows[1]).unwrap();
+        assert!(json.get("estimated_cost").is_none());
+
+        // 按天汇总时只累计配置了价格的模型
+        let days = tracker.query(range.0, range.1, GroupBy::Day, &pricing);
+        assert_eq!(days[0].counts.estimated_cost, Some(3.75));
+        assert_eq!(total(&rows).estimated_cost, Some(3.75));
+    }
+}
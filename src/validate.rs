@@ -0,0 +1,368 @@
+use crate::config::{Config, TimeoutConfig, WorkflowPlan};
+use crate::show::domain_timeouts;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Debug, Serialize)]
+pub struct ValidationReport {
+    pub config_path: String,
+    pub valid: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub problems: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub summary: Option<ConfigSummary>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct ConfigSummary {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub profile: Option<String>,
+    pub models: Vec<String>,
+    pub workers: Vec<String>,
+    pub analyzer: String,
+    pub synthesizer: Option<String>,
+    pub selector: Option<String>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub presets: Vec<String>,
+    pub timeouts: BTreeMap<String, TimeoutConfig>,
+}
+
+impl ConfigSummary {
+    fn from_config(config: &Config) -> Self {
+        let plan: &WorkflowPlan = &config.workflow_integration;
+        Self {
+            profile: config.profile_name().map(str::to_string),
+            models: config.models.iter().map(|m| m.name.clone()).collect(),
+            workers: plan.worker_labels(),
+            analyzer: plan.analyzer.model.clone(),
+            synthesizer: plan.synthesizer.as_ref().map(|t| t.model.clone()),
+            selector: plan.selector.as_ref().map(|t| t.model.clone()),
+            presets: plan.preset_model_names(),
+            timeouts: domain_timeouts(config),
+        }
+    }
+}
+
+// 只做本地解析与校验，不会发起任何网络请求
+pub fn validate_config_file(path: &Path, profile: Option<&str>) -> ValidationReport {
+    let config_path = path.display().to_string();
+
+    let config = match Config::load_profile(&path.to_string_lossy(), profile) {
+        Ok(config) => config,
+        Err(err) => {
+            // 整体加载只报出第一个错误，再逐段检查一遍补上其余问题
+            let load_error = format!("{:#}", err);
+            let mut problems = vec![load_error.clone()];
+            for problem in Config::section_problems(path, profile) {
+                if !load_error.contains(&problem.message) {
+                    problems.push(problem.to_string());
+                }
+            }
+            return ValidationReport {
+                config_path,
+                valid: false,
+                problems,
+                summary: None,
+            };
+        }
+    };
+
+    match config.validate_workflow() {
+        Ok(()) => ValidationReport {
+            config_path,
+            valid: true,
+            problems: Vec::new(),
+            summary: Some(ConfigSummary::from_config(&config)),
+        },
+        Err(err) => ValidationReport {
+            config_path,
+            valid: false,
+            problems: err.problems,
+            summary: None,
+        },
+    }
+}
+
+impl ValidationReport {
+    pub fn render_text(&self) -> String {
+        let mut out = String::new();
+        if !self.valid {
+            out.push_str(&format!("Configuration invalid: {}\n", self.config_path));
+            for problem in &self.problems {
+                out.push_str(&format!("  - {}\n", problem));
+            }
+            return out;
+        }
+
+        out.push_str(&format!("Configuration OK: {}\n", self.config_path));
+        if let Some(summary) = &self.summary {
+            if let Some(profile) = &summary.profile {
+                out.push_str(&format!("  Profile: {}\n", profile));
+            }
+            out.push_str(&format!(
+                "  Models ({}): {}\n",
+                summary.models.len(),
+                summary.models.join(", ")
+            ));
+            out.push_str(&format!(
+                "  Workers ({}): {}\n",
+                summary.workers.len(),
+                summary.workers.join(", ")
+            ));
+            out.push_str(&format!("  Analyzer: {}\n", summary.analyzer));
+            out.push_str(&format!(
+                "  Synthesizer: {}\n",
+                summary.synthesizer.as_deref().unwrap_or("(none)")
+            ));
+            out.push_str(&format!(
+                "  Selector: {}\n",
+                summary.selector.as_deref().unwrap_or("(none)")
+            ));
+            if !summary.presets.is_empty() {
+                out.push_str(&format!("  Presets: {}\n", summary.presets.join(", ")));
+            }
+            out.push_str("  Timeouts (analyzer/worker/synthesizer secs):\n");
+            for (domain, t) in &summary.timeouts {
+                out.push_str(&format!(
+                    "    {}: {}/{}/{}, connect {}\n",
+                    domain,
+                    t.analyzer_timeout_secs,
+                    t.worker_timeout_secs,
+                    t.synthesizer_timeout_secs,
+                    t.connect_timeout_secs
+                ));
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    const CFG_VALID: &str = r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m2"
+
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "m1"},
+  "workers": [{"name": "m1"}, {"name": "m2"}],
+  "selector": {"ref": "m2"}
+}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+
+[workflow.domains."app.example.com"]
+worker_timeout_secs = 12
+"#;
+
+    const CFG_BROKEN: &str = r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "ghost"},
+  "workers": [{"name": "missing"}],
+  "synthesizer": {"ref": "m1"}
+}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 0
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+
+[workflow.domains."app.example.com"]
+worker_timeout_secs = 0
+"#;
+
+    fn write_temp(tag: &str, content: &str) -> PathBuf {
+        let path = crate::test_util::temp_dir(&format!("validate_{}", tag)).join("config.toml");
+        fs::write(&path, content).unwrap();
+        path
+    }
+
+    #[test]
+    fn valid_config_produces_summary() {
+        let path = write_temp("valid", CFG_VALID);
+        let report = validate_config_file(&path, None);
+
+        assert!(report.valid, "{:?}", report.problems);
+        let summary = report.summary.expect("summary");
+        assert_eq!(summary.models, vec!["m1", "m2"]);
+        assert_eq!(summary.workers.len(), 2);
+        assert_eq!(summary.analyzer, "m1");
+        assert_eq!(summary.synthesizer, None);
+        assert_eq!(summary.selector.as_deref(), Some("m2"));
+        let domain = &summary.timeouts["app.example.com"];
+        assert_eq!(domain.analyzer_timeout_secs, 3);
+        assert_eq!(domain.worker_timeout_secs, 12);
+
+        let text = report_text(&path, CFG_VALID);
+        assert!(text.contains("Configuration OK"));
+        assert!(text.contains("app.example.com: 3/12/9"));
+    }
+
+    #[test]
+    fn broken_config_reports_every_problem() {
+        let path = write_temp("broken", CFG_BROKEN);
+        let report = validate_config_file(&path, None);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        assert!(!report.valid);
+        assert!(report.summary.is_none());
+        let joined = report.problems.join("\n");
+        assert!(joined.contains("unknown model 'ghost'"), "{}", joined);
+        assert!(joined.contains("unknown model 'missing'"), "{}", joined);
+        assert!(joined.contains("model 'm1' is defined more than once"));
+        assert!(joined.contains("workflow.timeouts.analyzer_timeout_secs must be greater than 0"));
+        assert!(joined.contains(
+            "workflow.domains.\"app.example.com\".worker_timeout_secs must be greater than 0"
+        ));
+
+        let json = serde_json::to_value(&report).unwrap();
+        assert_eq!(json["valid"], false);
+        assert_eq!(json["problems"].as_array().unwrap().len(), 5);
+    }
+
+    #[test]
+    fn load_errors_in_several_sections_are_all_reported() {
+        let content = r#"
+[server]
+host = "127.0.0.1"
+port = "not a port"
+listen_backlog = 5
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[[model]]
+api_key = "k"
+name = "m2"
+
+[workflow-integration]
+json = """{
+  "analyzer": {"ref": "m1", "rubric": [{"name": "x", "weight": 1}]},
+  "workers": [{"name": "m1"}],
+  "selector": {"ref": "m1", "rubric": []}
+}"""
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+"#;
+        let path = write_temp("sections", content);
+        let report = validate_config_file(&path, None);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        assert!(!report.valid);
+        let joined = report.problems.join("\n");
+        assert!(
+            joined.contains("unknown key `server.listen_backlog`"),
+            "{}",
+            joined
+        );
+        assert!(joined.contains("[server]: invalid type"), "{}", joined);
+        assert!(
+            joined.contains("[[model]] #2 'm2': missing field `api_base`"),
+            "{}",
+            joined
+        );
+        // 工作流的结构问题不在第一处停下
+        assert!(joined.contains("analyzer defines a `rubric`"), "{}", joined);
+        assert!(
+            joined.contains("selector has an empty `rubric`"),
+            "{}",
+            joined
+        );
+        // 与整体加载的错误重复的问题只出现一次
+        assert_eq!(
+            joined.matches("server.listen_backlog").count(),
+            1,
+            "{}",
+            joined
+        );
+    }
+
+    #[test]
+    fn unparsable_toml_is_reported() {
+        let path = write_temp("toml", "[server\nhost = ");
+        let report = validate_config_file(&path, None);
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        assert!(!report.valid);
+        assert_eq!(report.problems.len(), 1);
+        assert!(report.problems[0].contains("Failed to parse TOML"));
+    }
+
+    #[test]
+    fn legacy_config_is_migrated_in_memory_only() {
+        let legacy = r#"
+[server]
+host = "127.0.0.1"
+port = 11435
+
+[[model]]
+api_base = "https://api.example.com/v1"
+api_key = "k"
+name = "m1"
+
+[workflow-integration]
+analyzer_model = "m1"
+worker_models = ["m1"]
+synthesizer_model = "m1"
+
+[workflow.timeouts]
+analyzer_timeout_secs = 3
+worker_timeout_secs = 6
+synthesizer_timeout_secs = 9
+"#;
+        let path = write_temp("legacy", legacy);
+        let report = validate_config_file(&path, None);
+        let on_disk = fs::read_to_string(&path).unwrap();
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+
+        assert!(report.valid, "{:?}", report.problems);
+        assert_eq!(on_disk, legacy);
+    }
+
+    fn report_text(path: &Path, content: &str) -> String {
+        fs::write(path, content).unwrap();
+        let text = validate_config_file(path, None).render_text();
+        let _ = fs::remove_dir_all(path.parent().unwrap());
+        text
+    }
+}
@@ -1,27 +1,90 @@
-use crate::config::{Config, ModelConfig, WorkflowModelTarget, WorkflowPlan, WorkflowWorker};
-use crate::llm::{parse_temperature_from_response, ChatMessage, LLMClient};
+use crate::audit::AuditLog;
+use crate::config::{
+    Config, ModelConfig, RubricCriterion, TimeoutConfig, WorkflowModelTarget, WorkflowPlan,
+    WorkflowWorker,
+};
+use crate::inflight::InflightRegistry;
+use crate::latency::{CallSize, LatencyRecorder, Outcome, Phase};
+use crate::llm::{
+    ceil_secs, parse_temperature_from_response, provider_request_ids_of, ChatMessage,
+    CompletionResult, Endpoint, GenerationParams, HeaderInjection, LLMClient, LlmHttpError,
+    ProviderRequestIds, ProxySetting, RequestHook, RetryPolicy, TlsSettings, UpstreamRateLimited,
+    Usage,
+};
+use crate::ratelimit::{ConcurrencyLimitExceeded, Deadline, RateLimitExceeded, RateLimiter};
+use crate::redaction::Redactor;
+use crate::tls::load_ca_certificates;
+use crate::tokens::TokenEstimator;
+use crate::transport::{HttpTransport, TransportError};
+use crate::usage::UsageTracker;
 use anyhow::{anyhow, Result};
 use async_recursion::async_recursion;
 use serde::{Deserialize, Serialize};
-use std::collections::HashMap;
-use tokio::sync::{mpsc::UnboundedSender, RwLock};
-use url::Url;
+use std::collections::{BTreeMap, HashMap};
+use std::future::Future;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::sync::{mpsc::UnboundedSender, OwnedSemaphorePermit, RwLock};
+use tracing::Instrument;
 
 const DEFAULT_TEMPERATURE: f32 = 1.4;
+const MAX_ATTEMPT_ERROR_CHARS: usize = 500;
+
+#[derive(Debug, Clone, Default)]
+pub struct RequestOptions {
+    pub generation: GenerationParams,
+    pub preset: Option<String>,
+    // 入站请求的 ID；发往上游时按阶段追加后缀，如 "<id>/worker-2/synthesizer"
+    pub request_id: Option<String>,
+    // 由 server 分配，写入审计日志
+    pub workflow_id: Option<u64>,
+    // 入站请求命中的路由，只用于工作流汇总日志
+    pub route: Option<String>,
+}
+
+impl RequestOptions {
+    fn upstream_request_id(&self, phase: &str) -> Option<String> {
+        self.request_id
+            .as_ref()
+            .map(|id| format!("{}/{}", id, phase))
+    }
+
+    // 嵌套工作流与单个 worker 的调用都挂在所属 worker 的 ID 下
+    fn scoped(&self, phase: &str) -> RequestOptions {
+        RequestOptions {
+            request_id: self.upstream_request_id(phase),
+            ..self.clone()
+        }
+    }
+}
 
-#[derive(Hash, Eq, PartialEq, Clone, Debug)]
+#[derive(Hash, Eq, PartialEq, Clone)]
 struct LlmClientCacheKey {
-    api_base: String,
+    endpoint: Endpoint,
     api_key: String,
-    timeout_secs: u64,
+    connect_timeout_secs: u64,
+    proxy: ProxySetting,
+    insecure_skip_tls_verify: bool,
+    resolve: Vec<(String, SocketAddr)>,
 }
 
 impl LlmClientCacheKey {
-    fn new(api_base: &str, api_key: &str, timeout_secs: u64) -> Self {
+    fn new(
+        endpoint: &Endpoint,
+        api_key: &str,
+        connect_timeout_secs: u64,
+        proxy: &ProxySetting,
+        insecure_skip_tls_verify: bool,
+        resolve: &[(String, SocketAddr)],
+    ) -> Self {
         Self {
-            api_base: api_base.to_string(),
+            endpoint: endpoint.clone(),
             api_key: api_key.to_string(),
-            timeout_secs,
+            connect_timeout_secs,
+            proxy: proxy.clone(),
+            insecure_skip_tls_verify,
+            resolve: resolve.to_vec(),
         }
     }
 }
@@ -33,12 +96,12 @@ struct SelectedChoice {
     worker_name: String,
     response: String,
     reasoning: Option<String>,
-    raw_output: String,
 }
 
 #[derive(Debug, Clone)]
 struct ParsedSelection {
     index: usize,
+    #[allow(dead_code)]
     label: Option<String>,
     reasoning: Option<String>,
     selected_response: Option<String>,
@@ -50,8 +113,22 @@ pub struct WorkflowResult {
     pub execution_details: WorkflowExecutionDetails,
 }
 
+// 执行详情 JSON 的结构版本：字段改名、删除或改变含义时加一，新增可选字段不变
+pub const DETAILS_SCHEMA_VERSION: u32 = 2;
+
+fn first_schema_version() -> u32 {
+    1
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct WorkflowExecutionDetails {
+    // 引入该字段之前写入历史的记录没有它，按第 1 版读取
+    #[serde(default = "first_schema_version")]
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub preset: Option<String>,
     pub analyzer: AnalyzerDetails,
     pub workers: Vec<WorkerDetails>,
     #[serde(skip_serializing_if = "Option::is_none")]
@@ -65,6 +142,9 @@ pub struct AnalyzerDetails {
     pub model: String,
     pub temperature: f32,
     pub auto_temperature: bool,
+    // 只有调用了上游（自动 temperature）时才有
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provider_request_ids: BTreeMap<String, String>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -75,6 +155,182 @@ pub struct WorkerDetails {
     pub success: bool,
     pub error: Option<String>,
     pub nested: Option<Box<WorkflowExecutionDetails>>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub attempts: Vec<AttemptInfo>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub skipped: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AttemptInfo {
+    pub model: String,
+    pub duration_ms: u64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub status: Option<u16>,
+    #[serde(default)]
+    pub timed_out: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rate_limit_wait_ms: Option<u64>,
+    // 仅在模型配置了 max_concurrent_requests 时出现：排队等空位与实际调用上游各花的时间
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub queued_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub upstream_ms: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub retry_after_secs: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub usage: Option<Usage>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finish_reason: Option<String>,
+    // 输出被截断后自动续写的次数，没有续写时不出现
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuations: Option<u32>,
+    // 上游响应里报告的模型名，与 model（配置里的名字）不同时便于排查
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub provider_model: Option<String>,
+    // 发给上游的 X-Request-Id；provider_request_ids 则是上游自己返回的
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>,
+    // 按 [telemetry] request_id_headers 采集到的全部请求编号，失败的调用也会记录
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provider_request_ids: BTreeMap<String, String>,
+}
+
+const SKIPPED_MODEL_DISABLED: &str = "model_disabled";
+
+#[derive(Debug, thiserror::Error)]
+#[error("No enabled worker models left in plan {plan}; re-enable a model with `enabled = true` or update the workflow")]
+pub struct NoEnabledWorkers {
+    pub plan: String,
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("{message}")]
+pub struct AllWorkersFailed {
+    message: String,
+    // 各 worker 上游给出的 Retry-After 中最长的一个
+    pub retry_after: Option<Duration>,
+}
+
+// 上游给出的 Retry-After，server 据此设置返回给客户端的 Retry-After
+pub fn retry_after_hint(err: &anyhow::Error) -> Option<Duration> {
+    if let Some(limited) = err.downcast_ref::<UpstreamRateLimited>() {
+        return Some(limited.retry_after);
+    }
+    if let Some(failed) = err.downcast_ref::<AllWorkersFailed>() {
+        return failed.retry_after;
+    }
+    err.downcast_ref::<LlmHttpError>()
+        .and_then(|http_err| http_err.retry_after)
+}
+
+#[derive(Debug)]
+struct RateLimitWaited {
+    waited_ms: u64,
+    timeout_secs: u64,
+}
+
+impl std::fmt::Display for RateLimitWaited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "spent {} ms of the {}s timeout waiting for rate limit budget",
+            self.waited_ms, self.timeout_secs
+        )
+    }
+}
+
+#[derive(Debug)]
+struct SlotWaited {
+    waited_ms: u64,
+    timeout_secs: u64,
+}
+
+impl std::fmt::Display for SlotWaited {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "spent {} ms of the {}s timeout waiting for a free request slot",
+            self.waited_ms, self.timeout_secs
+        )
+    }
+}
+
+impl AttemptInfo {
+    fn from_result<T>(model: &str, elapsed: Duration, result: &Result<T>) -> Self {
+        let mut attempt = AttemptInfo {
+            model: model.to_string(),
+            duration_ms: elapsed.as_millis() as u64,
+            status: None,
+            timed_out: false,
+            error: None,
+            rate_limit_wait_ms: None,
+            queued_ms: None,
+            upstream_ms: None,
+            retry_after_secs: None,
+            usage: None,
+            finish_reason: None,
+            continuations: None,
+            provider_model: None,
+            request_id: None,
+            provider_request_ids: BTreeMap::new(),
+        };
+
+        if let Err(err) = result {
+            if let Some(ids) = provider_request_ids_of(err) {
+                attempt = attempt.with_provider_request_ids(ids);
+            }
+            if let Some(waited) = err.downcast_ref::<RateLimitWaited>() {
+                attempt.rate_limit_wait_ms = Some(waited.waited_ms);
+            } else if let Some(exceeded) = err.downcast_ref::<RateLimitExceeded>() {
+                attempt.rate_limit_wait_ms = Some(exceeded.waited_ms);
+            }
+            if let Some(waited) = err.downcast_ref::<SlotWaited>() {
+                attempt = attempt.with_queued(Duration::from_millis(waited.waited_ms));
+            } else if let Some(exceeded) = err.downcast_ref::<ConcurrencyLimitExceeded>() {
+                attempt.queued_ms = Some(exceeded.waited_ms);
+            }
+            attempt.retry_after_secs = retry_after_hint(err).map(ceil_secs);
+            if let Some(http_err) = err.downcast_ref::<LlmHttpError>() {
+                attempt.status = Some(http_err.status.as_u16());
+            } else if let Some(transport_err) = err.downcast_ref::<TransportError>() {
+                attempt.timed_out = transport_err.is_timeout();
+            }
+            attempt.error = Some(truncate_chars(
+                &format!("{:#}", err),
+                MAX_ATTEMPT_ERROR_CHARS,
+            ));
+        }
+
+        attempt
+    }
+
+    // 排队时间之外（扣掉限流等待）都算作上游执行时间
+    fn with_queued(mut self, queued: Duration) -> Self {
+        let queued_ms = queued.as_millis() as u64;
+        self.queued_ms = Some(queued_ms);
+        self.upstream_ms = Some(
+            self.duration_ms
+                .saturating_sub(queued_ms)
+                .saturating_sub(self.rate_limit_wait_ms.unwrap_or(0)),
+        );
+        self
+    }
+
+    fn with_completion(mut self, completion: &CompletionResult) -> Self {
+        self.usage = completion.usage.clone();
+        self.finish_reason = completion.finish_reason.clone();
+        self.continuations = (completion.continuations > 0).then_some(completion.continuations);
+        self.provider_model = completion.model.clone();
+        self.with_provider_request_ids(&completion.provider_request_ids)
+    }
+
+    fn with_provider_request_ids(mut self, ids: &ProviderRequestIds) -> Self {
+        self.provider_request_ids = ids.to_map();
+        self
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -94,80 +350,514 @@ pub struct SelectorDetails {
     pub error: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub raw_output: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub scores: Option<Vec<CandidateScore>>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provider_request_ids: BTreeMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct CandidateScore {
+    pub index: usize,
+    pub worker: String,
+    pub criteria: BTreeMap<String, f32>,
+    pub weighted_total: f32,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SynthesizerDetails {
     pub model: String,
     pub temperature: f32,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub continuations: Option<u32>,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub provider_request_ids: BTreeMap<String, String>,
 }
 
 pub struct WorkflowEngine {
     config: Config,
     model_configs: HashMap<String, ModelConfig>,
     llm_clients: RwLock<HashMap<LlmClientCacheKey, LLMClient>>,
+    rate_limiter: Arc<RateLimiter>,
+    latency: Arc<LatencyRecorder>,
+    audit: Option<Arc<AuditLog>>,
+    inflight: Arc<InflightRegistry>,
+    usage: Arc<UsageTracker>,
+    tokens: TokenEstimator,
+    redactor: Arc<Redactor>,
+    // [network] ca_certificate 在创建引擎时读取，文件有问题时启动或热加载直接失败
+    ca_certificates: Arc<Vec<reqwest::Certificate>>,
+    request_id_headers: Arc<Vec<reqwest::header::HeaderName>>,
+    // 设置后所有上游调用都经由它发出，代理与 TLS 配置不再生效
+    transport: Option<Arc<dyn HttpTransport>>,
 }
 
 impl WorkflowEngine {
-    pub fn new(config: Config) -> Self {
+    pub fn new(config: Config) -> Result<Self> {
+        Self::with_rate_limiter(config, Arc::new(RateLimiter::default()))
+    }
+
+    // 热加载时沿用旧引擎的限流器，避免重置各模型已消耗的额度
+    pub fn with_rate_limiter(config: Config, rate_limiter: Arc<RateLimiter>) -> Result<Self> {
+        config.validate_workflow()?;
+
         let model_configs = config.build_model_map();
-        Self {
+        for model in &config.models {
+            rate_limiter.configure(&model.name, model.rate_limits());
+        }
+        let tokens = TokenEstimator::new(&config.models);
+        let latency = Arc::new(LatencyRecorder::new(
+            &config.stats.latency_buckets(),
+            config.stats.window(),
+        ));
+        let usage = Arc::new(UsageTracker::new(config.stats.usage_retention()));
+        let redactor = Arc::new(Redactor::from_config(&config));
+        let ca_certificates = match &config.network.ca_certificate {
+            Some(path) => load_ca_certificates(path)?,
+            None => Vec::new(),
+        };
+        let request_id_headers = Arc::new(config.request_id_headers());
+        Ok(Self {
             config,
             model_configs,
             llm_clients: RwLock::new(HashMap::new()),
+            rate_limiter,
+            latency,
+            audit: None,
+            inflight: Arc::new(InflightRegistry::default()),
+            usage,
+            tokens,
+            redactor,
+            ca_certificates: Arc::new(ca_certificates),
+            request_id_headers,
+            transport: None,
+        })
+    }
+
+    pub fn rate_limiter(&self) -> Arc<RateLimiter> {
+        self.rate_limiter.clone()
+    }
+
+    // 热加载时沿用旧引擎的延迟统计；桶或窗口设置变了才清空
+    pub fn with_latency(mut self, latency: Arc<LatencyRecorder>) -> Self {
+        latency.configure(
+            &self.config.stats.latency_buckets(),
+            self.config.stats.window(),
+        );
+        self.latency = latency;
+        self
+    }
+
+    pub fn latency(&self) -> Arc<LatencyRecorder> {
+        self.latency.clone()
+    }
+
+    pub fn with_audit(mut self, audit: Option<Arc<AuditLog>>) -> Self {
+        self.audit = audit;
+        self
+    }
+
+    pub fn audit(&self) -> Option<Arc<AuditLog>> {
+        self.audit.clone()
+    }
+
+    // 热加载前开始的工作流仍在旧引擎上执行，共用同一份登记表才能在 /api/status 中看到
+    pub fn with_inflight(mut self, inflight: Arc<InflightRegistry>) -> Self {
+        self.inflight = inflight;
+        self
+    }
+
+    pub fn inflight(&self) -> Arc<InflightRegistry> {
+        self.inflight.clone()
+    }
+
+    // 用量汇总跨热加载保留，保留天数按新配置
+    pub fn with_usage(mut self, usage: Arc<UsageTracker>) -> Self {
+        usage.configure(self.config.stats.usage_retention());
+        self.usage = usage;
+        self
+    }
+
+    pub fn usage(&self) -> Arc<UsageTracker> {
+        self.usage.clone()
+    }
+
+    pub fn with_transport(mut self, transport: Option<Arc<dyn HttpTransport>>) -> Self {
+        self.transport = transport;
+        self
+    }
+
+    pub fn transport(&self) -> Option<Arc<dyn HttpTransport>> {
+        self.transport.clone()
+    }
+
+    // 只跟踪顶层工作流的阶段；嵌套工作流的上游调用仍记在所属工作流下
+    fn enter_phase(&self, depth: usize, options: &RequestOptions, phase: Phase) {
+        if let (0, Some(workflow_id)) = (depth, options.workflow_id) {
+            self.inflight.enter_phase(workflow_id, phase);
         }
     }
 
+    // 只计上游调用本身，不含限流与排队等待；每次调用一个 span，开启 [telemetry] 时即导出的链路节点。
+    // 成功的调用另记 token 与字数分布，上游没报告用量时用 prompt_tokens 与本地估算代替
+    async fn timed(
+        &self,
+        model: &str,
+        phase: Phase,
+        temperature: Option<f32>,
+        workflow_id: Option<u64>,
+        prompt_tokens: u32,
+        call: impl Future<Output = Result<CompletionResult>>,
+    ) -> Result<CompletionResult> {
+        let span = tracing::info_span!(
+            "upstream",
+            phase = phase.as_str(),
+            model,
+            temperature,
+            status = tracing::field::Empty,
+            provider_request_id = tracing::field::Empty,
+            otel.status_code = tracing::field::Empty,
+        );
+        let pending = workflow_id.and_then(|id| self.inflight.call_started(id, model, phase));
+        let started = Instant::now();
+        let result = call.instrument(span.clone()).await;
+        drop(pending);
+        let outcome = Outcome::of(&result);
+        span.record("status", outcome.as_str());
+        let provider_request_ids = match &result {
+            Ok(completion) => Some(&completion.provider_request_ids),
+            Err(err) => provider_request_ids_of(err),
+        };
+        if let Some(id) = provider_request_ids.and_then(ProviderRequestIds::primary) {
+            span.record("provider_request_id", id);
+        }
+        if outcome != Outcome::Ok {
+            span.record("otel.status_code", "error");
+        }
+        let elapsed = started.elapsed();
+        self.latency.record(model, phase, elapsed, outcome);
+        if let Ok(completion) = &result {
+            self.latency.record_size(
+                model,
+                phase,
+                self.call_size(model, prompt_tokens, completion),
+            );
+        }
+        if let Some(workflow_id) = workflow_id {
+            let tokens = result
+                .as_ref()
+                .ok()
+                .and_then(|completion| completion.usage.as_ref())
+                .map_or(0, Usage::total);
+            self.inflight
+                .call_finished(workflow_id, model, phase, elapsed, tokens);
+        }
+        // 超时已经作为错误报告过
+        if outcome != Outcome::Timeout {
+            self.warn_if_slow(model, phase, workflow_id, elapsed);
+        }
+        result
+    }
+
+    fn call_size(
+        &self,
+        model: &str,
+        prompt_tokens: u32,
+        completion: &CompletionResult,
+    ) -> CallSize {
+        let usage = completion.usage.as_ref();
+        let prompt_tokens = usage
+            .and_then(|usage| usage.prompt_tokens)
+            .unwrap_or(prompt_tokens);
+        let completion_tokens = match usage.and_then(|usage| usage.completion_tokens) {
+            Some(tokens) => u64::from(tokens),
+            None => self.tokens.estimate_tokens(model, &completion.content) as u64,
+        };
+        CallSize {
+            prompt_tokens: u64::from(prompt_tokens),
+            completion_tokens,
+            response_chars: completion.content.chars().count() as u64,
+        }
+    }
+
+    fn warn_if_slow(&self, model: &str, phase: Phase, workflow_id: Option<u64>, elapsed: Duration) {
+        let Ok(model_config) = self.lookup_model(model) else {
+            return;
+        };
+        let (threshold, limit) = self.slow_threshold(model_config, phase);
+        if elapsed < threshold {
+            return;
+        }
+        self.latency.record_slow(model, phase);
+        let domain = url::Url::parse(&model_config.api_base)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_string));
+        tracing::warn!(
+            workflow_id,
+            phase = phase.as_str(),
+            model,
+            domain = domain.as_deref().unwrap_or_default(),
+            elapsed_ms = elapsed.as_millis() as u64,
+            threshold_ms = threshold.as_millis() as u64,
+            limit_ms = limit.as_millis() as u64,
+            "Slow {} call to '{}' took {} ms of its {}s timeout",
+            phase.as_str(),
+            model,
+            elapsed.as_millis(),
+            limit.as_secs()
+        );
+    }
+
+    // 返回 (慢调用阈值, 阶段超时)；模型与域名级别的超时覆盖同样生效
+    fn slow_threshold(&self, model_config: &ModelConfig, phase: Phase) -> (Duration, Duration) {
+        let timeouts = self.timeouts_for(model_config);
+        let slow = &self.config.workflow.slow;
+        let (limit_secs, threshold_secs) = match phase {
+            Phase::Analyzer => (timeouts.analyzer_timeout_secs, slow.analyzer_secs),
+            Phase::Worker => (timeouts.worker_timeout_secs, slow.worker_secs),
+            Phase::Selector => (timeouts.synthesizer_timeout_secs, slow.selector_secs),
+            Phase::Synthesizer => (timeouts.synthesizer_timeout_secs, slow.synthesizer_secs),
+        };
+        let limit = Duration::from_secs(limit_secs);
+        let threshold = threshold_secs
+            .map(Duration::from_secs)
+            .unwrap_or_else(|| limit.mul_f64(slow.ratio));
+        (threshold, limit)
+    }
+
+    // 未知模型不算作禁用，交给后续查找报出原有的错误
+    fn worker_enabled(&self, worker: &WorkflowWorker) -> bool {
+        match worker {
+            WorkflowWorker::Model(target) => self
+                .lookup_model(&target.model)
+                .map(ModelConfig::is_enabled)
+                .unwrap_or(true),
+            WorkflowWorker::Workflow(plan) => self.has_enabled_worker(plan),
+        }
+    }
+
+    fn has_enabled_worker(&self, plan: &WorkflowPlan) -> bool {
+        plan.workers
+            .iter()
+            .any(|worker| self.worker_enabled(worker))
+    }
+
+    fn timeouts_for(&self, model_config: &ModelConfig) -> TimeoutConfig {
+        self.config.effective_timeouts_for(model_config)
+    }
+
+    // prompt_tokens 为本地估算的输入 token 数，同时交给 timed 作为上游未报告用量时的替代
+    async fn wait_for_rate_limit(
+        &self,
+        model_config: &ModelConfig,
+        prompt_tokens: u32,
+        deadline: Deadline,
+    ) -> Result<Duration> {
+        let waited = self
+            .rate_limiter
+            .acquire(
+                &model_config.name,
+                model_config.rate_limits(),
+                prompt_tokens,
+                deadline,
+            )
+            .await?;
+        if !waited.is_zero() {
+            tracing::debug!(
+                model = %model_config.name,
+                waited_ms = waited.as_millis() as u64,
+                "Waited for rate limit budget"
+            );
+        }
+        Ok(waited)
+    }
+
+    // 未配置 max_concurrent_requests 时直接返回；permit 要持有到上游调用结束
+    async fn acquire_request_slot(
+        &self,
+        model_config: &ModelConfig,
+        deadline: Deadline,
+    ) -> Result<(Option<OwnedSemaphorePermit>, Duration)> {
+        let (permit, queued) = self
+            .rate_limiter
+            .acquire_slot(
+                &model_config.name,
+                model_config.max_concurrent_requests,
+                deadline,
+            )
+            .await?;
+        if !queued.is_zero() {
+            tracing::debug!(
+                model = %model_config.name,
+                queued_ms = queued.as_millis() as u64,
+                "Waited for a free request slot"
+            );
+        }
+        Ok((permit, queued))
+    }
+
+    fn estimate_tokens(&self, model_config: &ModelConfig, text: &str) -> u32 {
+        let tokens = self.tokens.estimate_tokens(&model_config.name, text);
+        u32::try_from(tokens).unwrap_or(u32::MAX)
+    }
+
+    fn record_completion_tokens(&self, model_config: &ModelConfig, response: &str) {
+        self.rate_limiter.record_tokens(
+            &model_config.name,
+            self.estimate_tokens(model_config, response),
+        );
+    }
+
+    #[allow(dead_code)]
     pub async fn process(&self, prompt: String) -> Result<String> {
-        self.run_plan(&self.config.workflow_integration, &prompt, 0, None)
-            .await
+        let options = RequestOptions::default();
+        self.run_plan(
+            &self.config.workflow_integration,
+            &prompt,
+            0,
+            None,
+            &options,
+        )
+        .await
     }
 
+    #[allow(dead_code)]
     pub async fn process_with_details(&self, prompt: String) -> Result<WorkflowResult> {
-        self.run_plan_with_details(&self.config.workflow_integration, &prompt, 0, None)
-            .await
+        let options = RequestOptions::default();
+        self.run_plan_with_details(
+            &self.config.workflow_integration,
+            &prompt,
+            0,
+            None,
+            &options,
+        )
+        .await
     }
 
     pub async fn process_with_stream(
         &self,
         prompt: String,
+        options: RequestOptions,
         stream: Option<StreamCallback>,
     ) -> Result<String> {
-        self.run_plan(&self.config.workflow_integration, &prompt, 0, stream)
+        self.run_plan(self.plan_for(&options), &prompt, 0, stream, &options)
             .await
     }
 
     pub async fn process_with_details_stream(
         &self,
         prompt: String,
+        options: RequestOptions,
         stream: Option<StreamCallback>,
     ) -> Result<WorkflowResult> {
-        self.run_plan_with_details(&self.config.workflow_integration, &prompt, 0, stream)
+        self.run_plan_with_details(self.plan_for(&options), &prompt, 0, stream, &options)
             .await
     }
 
+    // 顶层计划没有任何启用的 worker 时报错；流式接口在打开 SSE 之前调用，SSE 一旦开始就只能返回 200。
+    // 嵌套计划在执行到时再检查
+    pub fn ensure_enabled_workers(&self, options: &RequestOptions) -> Result<()> {
+        let plan = self.plan_for(options);
+        if !plan.workers.is_empty() && !self.has_enabled_worker(plan) {
+            return Err(NoEnabledWorkers { plan: plan.label() }.into());
+        }
+        Ok(())
+    }
+
+    fn plan_for(&self, options: &RequestOptions) -> &WorkflowPlan {
+        self.config
+            .workflow_integration
+            .plan_for_preset(options.preset.as_deref())
+    }
+
+    fn retry_policy(&self) -> RetryPolicy {
+        let retry = &self.config.workflow.retry;
+        RetryPolicy {
+            max_attempts: retry.max_attempts,
+            base_backoff: Duration::from_millis(retry.base_backoff_ms),
+        }
+    }
+
+    // 同一目的地的各阶段共用一个 client，阶段超时在每次请求时单独指定
     async fn get_llm_client(
         &self,
-        api_base: &str,
-        api_key: &str,
-        timeout_secs: u64,
+        model_config: &ModelConfig,
+        timeouts: &TimeoutConfig,
     ) -> Result<LLMClient> {
-        let key = LlmClientCacheKey::new(api_base, api_key, timeout_secs);
+        let endpoint = model_config.endpoint();
+        let api_key = model_config.api_key();
+        let proxy = self.config.proxy_for(model_config);
+        let connect_timeout_secs = timeouts.connect_timeout_secs;
+        let resolve = self.config.resolve_for(model_config);
+        let key = LlmClientCacheKey::new(
+            &endpoint,
+            api_key,
+            connect_timeout_secs,
+            &proxy,
+            model_config.skips_tls_verify(),
+            &resolve,
+        );
 
         {
             let clients = self.llm_clients.read().await;
             if let Some(client) = clients.get(&key) {
-                return Ok(client.clone());
+                return Ok(self.bind_to_model(client.clone(), model_config));
             }
         }
 
-        let new_client = LLMClient::new(api_base.to_string(), api_key.to_string(), timeout_secs)?;
+        let new_client = match &self.transport {
+            Some(transport) => LLMClient::with_transport(
+                endpoint,
+                api_key.to_string(),
+                Duration::from_secs(connect_timeout_secs),
+                self.retry_policy(),
+                transport.clone(),
+            ),
+            None => LLMClient::new(
+                endpoint,
+                api_key.to_string(),
+                Duration::from_secs(connect_timeout_secs),
+                self.retry_policy(),
+                &proxy,
+                &self.config.network.pool_settings(),
+                &TlsSettings {
+                    extra_roots: self.ca_certificates.clone(),
+                    insecure_skip_verify: model_config.skips_tls_verify(),
+                },
+                &resolve,
+            )?,
+        }
+        .with_redactor(self.redactor.clone());
 
         let mut clients = self.llm_clients.write().await;
-        Ok(clients
+        let client = clients
             .entry(key)
             .or_insert_with(|| new_client.clone())
-            .clone())
+            .clone();
+        Ok(self.bind_to_model(client, model_config))
+    }
+
+    // client 按端点缓存，按模型不同的设置在取出后再套上
+    fn bind_to_model(&self, client: LLMClient, model_config: &ModelConfig) -> LLMClient {
+        let mut hooks: Vec<Arc<dyn RequestHook>> = Vec::new();
+        if !model_config.extra_headers.is_empty() {
+            // 配置加载时已校验过请求头
+            match HeaderInjection::new(&model_config.extra_headers) {
+                Ok(hook) => hooks.push(Arc::new(hook)),
+                Err(err) => tracing::warn!(
+                    model = %model_config.name,
+                    "Ignoring extra_headers: {:#}",
+                    err
+                ),
+            }
+        }
+        client
+            .with_max_response_bytes(self.config.max_response_bytes_for(model_config))
+            .with_max_continuations(model_config.max_continuations.unwrap_or(0))
+            .with_hooks(hooks)
+            .with_audit(self.audit.clone())
+            .with_usage(Some(self.usage.clone()))
+            .with_request_id_headers(self.request_id_headers.clone())
     }
 
     #[async_recursion]
@@ -177,6 +867,7 @@ impl WorkflowEngine {
         prompt: &str,
         depth: usize,
         stream: Option<StreamCallback>,
+        options: &RequestOptions,
     ) -> Result<WorkflowResult> {
         if depth == 0 {
             tracing::info!("Starting workflow processing with details");
@@ -188,6 +879,10 @@ impl WorkflowEngine {
             );
         }
 
+        if !plan.workers.is_empty() && !self.has_enabled_worker(plan) {
+            return Err(NoEnabledWorkers { plan: plan.label() }.into());
+        }
+
         let target = &plan.analyzer;
         let model_config = self.lookup_model(&target.model)?;
 
@@ -196,14 +891,16 @@ impl WorkflowEngine {
             .or(model_config.auto_temperature)
             .unwrap_or(false);
 
-        let temperature = self
-            .resolve_analyzer_temperature(plan, prompt, depth)
+        let (temperature, analyzer_request_ids) = self
+            .resolve_analyzer_temperature(plan, prompt, depth, options)
+            .instrument(phase_span(Phase::Analyzer))
             .await?;
 
         let analyzer_details = AnalyzerDetails {
             model: target.model.clone(),
             temperature,
             auto_temperature,
+            provider_request_ids: analyzer_request_ids.to_map(),
         };
 
         if depth == 0 {
@@ -216,8 +913,10 @@ impl WorkflowEngine {
             );
         }
 
+        self.enter_phase(depth, options, Phase::Worker);
         let worker_details = self
-            .run_workers_with_details(plan, prompt, temperature, auto_temperature, depth)
+            .run_workers_with_details(plan, prompt, temperature, auto_temperature, depth, options)
+            .instrument(phase_span(Phase::Worker))
             .await?;
 
         if depth == 0 {
@@ -244,68 +943,82 @@ impl WorkflowEngine {
             })
             .collect();
 
-        let (selector_details, selected_choice) =
-            if let Some(selector_target) = plan.selector.as_ref() {
-                let (details, choice) = self
-                    .execute_selector(selector_target, prompt, &worker_responses, depth)
-                    .await;
-                (Some(details), choice)
-            } else {
-                (None, None)
-            };
+        let (selector_details, selected_choice) = if let Some(selector_target) =
+            plan.selector.as_ref()
+        {
+            self.enter_phase(depth, options, Phase::Selector);
+            let (details, choice) = self
+                .execute_selector(selector_target, prompt, &worker_responses, depth, options)
+                .instrument(phase_span(Phase::Selector))
+                .await;
+            if let (0, Some(workflow_id), false) = (depth, options.workflow_id, details.success) {
+                self.inflight.selector_fell_back(workflow_id);
+            }
+            (Some(details), choice)
+        } else {
+            (None, None)
+        };
 
         let mut top_level_streamed = false;
 
-        let (synthesizer_details, final_response) =
-            if let Some(synthesizer_target) = plan.synthesizer.as_ref() {
-                let synthesizer_model_config = self.lookup_model(&synthesizer_target.model)?;
-                let synthesizer_temperature = self.resolve_synthesizer_temperature(
-                    synthesizer_target,
-                    synthesizer_model_config,
-                    depth,
-                );
-
-                let synthesizer_details = SynthesizerDetails {
-                    model: synthesizer_target.model.clone(),
-                    temperature: synthesizer_temperature,
-                };
-
-                let stream_for_synth = if depth == 0 { stream.clone() } else { None };
-
-                let (final_response, streamed) = self
-                    .call_synthesizer(
-                        synthesizer_target,
-                        prompt,
-                        &worker_responses,
-                        selected_choice.as_ref(),
-                        depth,
-                        stream_for_synth,
-                    )
-                    .await?;
+        let (synthesizer_details, final_response) = if let Some(synthesizer_target) =
+            plan.synthesizer.as_ref()
+        {
+            let synthesizer_model_config = self.lookup_model(&synthesizer_target.model)?;
+            let synthesizer_temperature = self.resolve_synthesizer_temperature(
+                synthesizer_target,
+                synthesizer_model_config,
+                depth,
+            );
 
-                if depth == 0 {
-                    top_level_streamed = streamed;
-                }
+            let stream_for_synth = if depth == 0 { stream.clone() } else { None };
 
-                (Some(synthesizer_details), final_response)
-            } else {
-                let final_response = self.resolve_final_response_without_synthesizer(
-                    plan,
-                    &worker_details,
-                    selector_details.as_ref(),
+            self.enter_phase(depth, options, Phase::Synthesizer);
+            let completion = self
+                .call_synthesizer(
+                    synthesizer_target,
+                    prompt,
+                    &worker_responses,
                     selected_choice.as_ref(),
                     depth,
-                )?;
+                    stream_for_synth,
+                    options,
+                )
+                .instrument(phase_span(Phase::Synthesizer))
+                .await?;
 
-                (None, final_response)
+            if depth == 0 {
+                top_level_streamed = completion.streamed;
+            }
+
+            let synthesizer_details = SynthesizerDetails {
+                model: synthesizer_target.model.clone(),
+                temperature: synthesizer_temperature,
+                continuations: (completion.continuations > 0).then_some(completion.continuations),
+                provider_request_ids: completion.provider_request_ids.to_map(),
             };
 
+            (Some(synthesizer_details), completion.content)
+        } else {
+            let final_response = self.resolve_final_response_without_synthesizer(
+                plan,
+                &worker_details,
+                selector_details.as_ref(),
+                selected_choice.as_ref(),
+                depth,
+            )?;
+
+            (None, final_response)
+        };
+
         if depth == 0 {
             if let Some(sender) = stream.as_ref() {
                 if !top_level_streamed {
                     let _ = sender.send(final_response.clone());
                 }
             }
+            self.latency
+                .record_final_response(final_response.chars().count() as u64);
             tracing::info!("Step 3 completed - Final response generated");
         } else {
             tracing::debug!("Nested workflow depth {} produced final response", depth);
@@ -314,6 +1027,13 @@ impl WorkflowEngine {
         Ok(WorkflowResult {
             final_response,
             execution_details: WorkflowExecutionDetails {
+                schema_version: DETAILS_SCHEMA_VERSION,
+                request_id: options.request_id.clone(),
+                preset: if depth == 0 {
+                    options.preset.clone()
+                } else {
+                    None
+                },
                 analyzer: analyzer_details,
                 workers: worker_details,
                 selector: selector_details,
@@ -328,9 +1048,10 @@ impl WorkflowEngine {
         prompt: &str,
         depth: usize,
         stream: Option<StreamCallback>,
+        options: &RequestOptions,
     ) -> Result<String> {
         let result = self
-            .run_plan_with_details(plan, prompt, depth, stream)
+            .run_plan_with_details(plan, prompt, depth, stream, options)
             .await?;
         Ok(result.final_response)
     }
@@ -429,7 +1150,8 @@ impl WorkflowEngine {
         plan: &WorkflowPlan,
         prompt: &str,
         depth: usize,
-    ) -> Result<f32> {
+        options: &RequestOptions,
+    ) -> Result<(f32, ProviderRequestIds)> {
         let target = &plan.analyzer;
         let model_config = self.lookup_model(&target.model)?;
 
@@ -443,7 +1165,7 @@ impl WorkflowEngine {
                     explicit
                 );
             }
-            return Ok(explicit);
+            return Ok((explicit, ProviderRequestIds::default()));
         }
 
         let auto = target
@@ -464,7 +1186,7 @@ impl WorkflowEngine {
                     DEFAULT_TEMPERATURE
                 );
             }
-            return Ok(DEFAULT_TEMPERATURE);
+            return Ok((DEFAULT_TEMPERATURE, ProviderRequestIds::default()));
         }
 
         if depth == 0 {
@@ -481,15 +1203,12 @@ impl WorkflowEngine {
             );
         }
 
-        let domain = extract_domain_from_url(&model_config.api_base);
-        let timeouts = self.config.effective_timeouts_for_domain(domain.as_deref());
+        let timeouts = self.timeouts_for(model_config);
         let client = self
-            .get_llm_client(
-                &model_config.api_base,
-                &model_config.api_key,
-                timeouts.analyzer_timeout_secs,
-            )
-            .await?;
+            .get_llm_client(model_config, &timeouts)
+            .await?
+            .with_request_id(options.upstream_request_id("analyzer"))
+            .with_workflow_id(options.workflow_id);
 
         let analysis_prompt = format!(
             r#"请分析以下用户提示，并为其推荐一个合适的temperature参数（0.0-2.0之间的浮点数）。
@@ -511,9 +1230,31 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             content: analysis_prompt,
         }];
 
-        let response = client
-            .chat_completion(&target.model, messages, Some(0.3))
+        let prompt_tokens = self.estimate_tokens(model_config, &messages[0].content);
+        let deadline = Deadline::after(Duration::from_secs(timeouts.analyzer_timeout_secs));
+        self.wait_for_rate_limit(model_config, prompt_tokens, deadline)
+            .await?;
+        let (_permit, _) = self.acquire_request_slot(model_config, deadline).await?;
+        let params = resolve_generation_params(target, model_config, None);
+        let completion = self
+            .timed(
+                &target.model,
+                Phase::Analyzer,
+                Some(0.3),
+                options.workflow_id,
+                prompt_tokens,
+                client.chat_completion_with_stream(
+                    &target.model,
+                    messages,
+                    Some(0.3),
+                    &params,
+                    deadline.remaining(),
+                    None,
+                ),
+            )
             .await?;
+        let response = completion.content;
+        self.record_completion_tokens(model_config, &response);
 
         let temperature = parse_temperature_from_response(&response);
         tracing::debug!(
@@ -524,7 +1265,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             response
         );
 
-        Ok(temperature)
+        Ok((temperature, completion.provider_request_ids))
     }
 
     #[allow(dead_code)]
@@ -534,6 +1275,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         prompt: &str,
         base_temperature: f32,
         depth: usize,
+        options: &RequestOptions,
     ) -> Result<Vec<(String, String)>> {
         let analyzer_target = &plan.analyzer;
         let analyzer_model_config = self.lookup_model(&analyzer_target.model)?;
@@ -543,7 +1285,14 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             .unwrap_or(false);
 
         let details = self
-            .run_workers_with_details(plan, prompt, base_temperature, analyzer_auto, depth)
+            .run_workers_with_details(
+                plan,
+                prompt,
+                base_temperature,
+                analyzer_auto,
+                depth,
+                options,
+            )
             .await?;
         let responses = details
             .into_iter()
@@ -566,6 +1315,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         base_temperature: f32,
         analyzer_auto: bool,
         depth: usize,
+        options: &RequestOptions,
     ) -> Result<Vec<WorkerDetails>> {
         let plan_label = plan.label();
 
@@ -584,127 +1334,37 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
 
         let mut worker_details = Vec::new();
 
-        for worker in &plan.workers {
-            match worker {
-                WorkflowWorker::Model(target) => {
-                    if depth == 0 {
-                        tracing::info!("Calling worker model: {}", target.model);
-                    } else {
-                        tracing::debug!("Calling worker model {} at depth {}", target.model, depth);
-                    }
-
-                    let temperature = if let Ok(model_config) = self.lookup_model(&target.model) {
-                        self.resolve_worker_temperature(
-                            target,
-                            model_config,
-                            base_temperature,
-                            analyzer_auto,
-                        )
-                    } else {
-                        let err = self.lookup_model(&target.model);
-                        let err_display = err.expect_err("lookup should have failed").to_string();
-                        tracing::warn!(
-                            worker = %target.model,
-                            depth,
-                            error = %err_display,
-                            "Worker lookup failed"
-                        );
-                        worker_details.push(WorkerDetails {
-                            name: target.model.clone(),
-                            temperature: None,
-                            response: None,
-                            success: false,
-                            error: Some(err_display),
-                            nested: None,
-                        });
-                        continue;
-                    };
-
-                    match self
-                        .call_worker_model(target, prompt, base_temperature, analyzer_auto, depth)
-                        .await
-                    {
-                        Ok(response) => {
-                            tracing::debug!("Worker {} succeeded at depth {}", target.model, depth);
-                            worker_details.push(WorkerDetails {
-                                name: target.model.clone(),
-                                temperature: Some(temperature),
-                                response: Some(response),
-                                success: true,
-                                error: None,
-                                nested: None,
-                            });
-                        }
-                        Err(err) => {
-                            let err_display = err.to_string();
-                            tracing::warn!(
-                                worker = %target.model,
-                                depth,
-                                error = %err_display,
-                                "Worker call failed"
-                            );
-                            worker_details.push(WorkerDetails {
-                                name: target.model.clone(),
-                                temperature: Some(temperature),
-                                response: None,
-                                success: false,
-                                error: Some(err_display),
-                                nested: None,
-                            });
-                        }
-                    }
-                }
-                WorkflowWorker::Workflow(sub_plan) => {
-                    let label = sub_plan.label();
-                    if depth == 0 {
-                        tracing::info!("Executing nested workflow worker: {}", label);
-                    } else {
-                        tracing::debug!(
-                            "Executing nested workflow worker {} at depth {}",
-                            label,
-                            depth
-                        );
-                    }
+        for (index, worker) in plan.workers.iter().enumerate() {
+            let worker_options = options.scoped(&format!("worker-{}", index + 1));
+            // model / workflow 与 temperature 在 run_worker 中按 worker 类型记录
+            let span = tracing::info_span!(
+                "worker",
+                index = index + 1,
+                model = tracing::field::Empty,
+                workflow = tracing::field::Empty,
+                temperature = tracing::field::Empty,
+            );
+            let details = self
+                .run_worker(
+                    worker,
+                    prompt,
+                    base_temperature,
+                    analyzer_auto,
+                    depth,
+                    &worker_options,
+                )
+                .instrument(span)
+                .await;
+            worker_details.push(details);
+        }
 
-                    match self
-                        .run_plan_with_details(sub_plan, prompt, depth + 1, None)
-                        .await
-                    {
-                        Ok(result) => {
-                            tracing::debug!(
-                                "Nested workflow {} succeeded at depth {}",
-                                label,
-                                depth
-                            );
-                            worker_details.push(WorkerDetails {
-                                name: label.clone(),
-                                temperature: None,
-                                response: Some(result.final_response),
-                                success: true,
-                                error: None,
-                                nested: Some(Box::new(result.execution_details)),
-                            });
-                        }
-                        Err(err) => {
-                            let err_display = err.to_string();
-                            tracing::warn!(
-                                workflow = %label,
-                                depth,
-                                error = %err_display,
-                                "Nested workflow failed"
-                            );
-                            worker_details.push(WorkerDetails {
-                                name: label,
-                                temperature: None,
-                                response: None,
-                                success: false,
-                                error: Some(err_display),
-                                nested: None,
-                            });
-                        }
-                    }
-                }
-            }
+        if let (0, Some(workflow_id)) = (depth, options.workflow_id) {
+            let ok = worker_details.iter().filter(|w| w.success).count();
+            let failed = worker_details
+                .iter()
+                .filter(|w| !w.success && w.skipped.is_none())
+                .count();
+            self.inflight.workers_finished(workflow_id, ok, failed);
         }
 
         if worker_details.iter().filter(|w| w.success).count() == 0 {
@@ -727,12 +1387,198 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                     message.push_str(&labels.join(", "));
                 }
             }
-            return Err(anyhow!(message));
+            let retry_after = worker_details
+                .iter()
+                .flat_map(|w| &w.attempts)
+                .filter_map(|attempt| attempt.retry_after_secs)
+                .max()
+                .map(Duration::from_secs);
+            return Err(AllWorkersFailed {
+                message,
+                retry_after,
+            }
+            .into());
         }
 
         Ok(worker_details)
     }
 
+    // 单个 worker 的执行，运行在调用方创建的 worker span 内
+    async fn run_worker(
+        &self,
+        worker: &WorkflowWorker,
+        prompt: &str,
+        base_temperature: f32,
+        analyzer_auto: bool,
+        depth: usize,
+        options: &RequestOptions,
+    ) -> WorkerDetails {
+        if !self.worker_enabled(worker) {
+            let name = worker.label();
+            tracing::info!(
+                "Skipping worker {} at depth {}: model disabled",
+                name,
+                depth
+            );
+            return WorkerDetails {
+                name,
+                temperature: None,
+                response: None,
+                success: false,
+                error: None,
+                nested: None,
+                attempts: Vec::new(),
+                skipped: Some(SKIPPED_MODEL_DISABLED.to_string()),
+            };
+        }
+
+        match worker {
+            WorkflowWorker::Model(target) => {
+                let span = tracing::Span::current();
+                span.record("model", target.model.as_str());
+                if depth == 0 {
+                    tracing::info!("Calling worker model: {}", target.model);
+                } else {
+                    tracing::debug!("Calling worker model {} at depth {}", target.model, depth);
+                }
+
+                let temperature = if let Ok(model_config) = self.lookup_model(&target.model) {
+                    self.resolve_worker_temperature(
+                        target,
+                        model_config,
+                        base_temperature,
+                        analyzer_auto,
+                    )
+                } else {
+                    let err = self.lookup_model(&target.model);
+                    let err_display = err.expect_err("lookup should have failed").to_string();
+                    tracing::warn!(
+                        worker = %target.model,
+                        depth,
+                        error = %err_display,
+                        "Worker lookup failed"
+                    );
+                    return WorkerDetails {
+                        name: target.model.clone(),
+                        temperature: None,
+                        response: None,
+                        success: false,
+                        error: Some(err_display),
+                        nested: None,
+                        attempts: Vec::new(),
+                        skipped: None,
+                    };
+                };
+                span.record("temperature", temperature);
+
+                let started = Instant::now();
+                let result = self
+                    .call_worker_model(
+                        target,
+                        prompt,
+                        base_temperature,
+                        analyzer_auto,
+                        depth,
+                        options,
+                    )
+                    .await;
+                let mut attempt =
+                    AttemptInfo::from_result(&target.model, started.elapsed(), &result);
+                attempt.request_id = options.request_id.clone();
+
+                match result {
+                    Ok((completion, queued)) => {
+                        tracing::debug!("Worker {} succeeded at depth {}", target.model, depth);
+                        let mut attempt = attempt.with_completion(&completion);
+                        if let Some(queued) = queued {
+                            attempt = attempt.with_queued(queued);
+                        }
+                        WorkerDetails {
+                            name: target.model.clone(),
+                            temperature: Some(temperature),
+                            response: Some(completion.content),
+                            success: true,
+                            error: None,
+                            nested: None,
+                            attempts: vec![attempt],
+                            skipped: None,
+                        }
+                    }
+                    Err(err) => {
+                        let err_display = format!("{:#}", err);
+                        tracing::warn!(
+                            worker = %target.model,
+                            depth,
+                            error = %err_display,
+                            "Worker call failed"
+                        );
+                        WorkerDetails {
+                            name: target.model.clone(),
+                            temperature: Some(temperature),
+                            response: None,
+                            success: false,
+                            error: Some(err_display),
+                            nested: None,
+                            attempts: vec![attempt],
+                            skipped: None,
+                        }
+                    }
+                }
+            }
+            WorkflowWorker::Workflow(sub_plan) => {
+                let label = sub_plan.label();
+                tracing::Span::current().record("workflow", label.as_str());
+                if depth == 0 {
+                    tracing::info!("Executing nested workflow worker: {}", label);
+                } else {
+                    tracing::debug!(
+                        "Executing nested workflow worker {} at depth {}",
+                        label,
+                        depth
+                    );
+                }
+
+                match self
+                    .run_plan_with_details(sub_plan, prompt, depth + 1, None, options)
+                    .await
+                {
+                    Ok(result) => {
+                        tracing::debug!("Nested workflow {} succeeded at depth {}", label, depth);
+                        WorkerDetails {
+                            name: label.clone(),
+                            temperature: None,
+                            response: Some(result.final_response),
+                            success: true,
+                            error: None,
+                            nested: Some(Box::new(result.execution_details)),
+                            attempts: Vec::new(),
+                            skipped: None,
+                        }
+                    }
+                    Err(err) => {
+                        let err_display = err.to_string();
+                        tracing::warn!(
+                            workflow = %label,
+                            depth,
+                            error = %err_display,
+                            "Nested workflow failed"
+                        );
+                        WorkerDetails {
+                            name: label,
+                            temperature: None,
+                            response: None,
+                            success: false,
+                            error: Some(err_display),
+                            nested: None,
+                            attempts: Vec::new(),
+                            skipped: None,
+                        }
+                    }
+                }
+            }
+        }
+    }
+
     async fn call_worker_model(
         &self,
         target: &WorkflowModelTarget,
@@ -740,18 +1586,17 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         base_temperature: f32,
         analyzer_auto: bool,
         depth: usize,
-    ) -> Result<String> {
+        options: &RequestOptions,
+    ) -> Result<(CompletionResult, Option<Duration>)> {
         let model_config = self.lookup_model(&target.model)?;
 
-        let domain = extract_domain_from_url(&model_config.api_base);
-        let timeouts = self.config.effective_timeouts_for_domain(domain.as_deref());
+        let timeouts = self.timeouts_for(model_config);
+        // options 已由调用方限定到这个 worker
         let client = self
-            .get_llm_client(
-                &model_config.api_base,
-                &model_config.api_key,
-                timeouts.worker_timeout_secs,
-            )
-            .await?;
+            .get_llm_client(model_config, &timeouts)
+            .await?
+            .with_request_id(options.request_id.clone())
+            .with_workflow_id(options.workflow_id);
 
         let messages = vec![ChatMessage {
             role: "user".to_string(),
@@ -768,9 +1613,51 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             depth
         );
 
-        let response = client
-            .chat_completion(&target.model, messages, Some(temperature))
+        let prompt_tokens = self.estimate_tokens(model_config, prompt);
+        let deadline = Deadline::after(Duration::from_secs(timeouts.worker_timeout_secs));
+        let waited = self
+            .wait_for_rate_limit(model_config, prompt_tokens, deadline)
             .await?;
+        let (permit, queued) = self.acquire_request_slot(model_config, deadline).await?;
+        let limited = permit.is_some();
+        let params = resolve_generation_params(target, model_config, Some(&options.generation));
+        let completion = self
+            .timed(
+                &target.model,
+                Phase::Worker,
+                Some(temperature),
+                options.workflow_id,
+                prompt_tokens,
+                client.chat_completion_with_stream(
+                    &target.model,
+                    messages,
+                    Some(temperature),
+                    &params,
+                    deadline.remaining(),
+                    None,
+                ),
+            )
+            .await
+            .map_err(|err| {
+                let err = if limited {
+                    err.context(SlotWaited {
+                        waited_ms: queued.as_millis() as u64,
+                        timeout_secs: timeouts.worker_timeout_secs,
+                    })
+                } else {
+                    err
+                };
+                if waited.is_zero() {
+                    err
+                } else {
+                    err.context(RateLimitWaited {
+                        waited_ms: waited.as_millis() as u64,
+                        timeout_secs: timeouts.worker_timeout_secs,
+                    })
+                }
+            })?;
+        drop(permit);
+        self.record_completion_tokens(model_config, &completion.content);
 
         tracing::debug!(
             "Worker {} returned response at depth {}",
@@ -778,7 +1665,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             depth
         );
 
-        Ok(response)
+        Ok((completion, limited.then_some(queued)))
     }
 
     async fn execute_selector(
@@ -787,6 +1674,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         original_prompt: &str,
         worker_responses: &[(String, String)],
         depth: usize,
+        options: &RequestOptions,
     ) -> (SelectorDetails, Option<SelectedChoice>) {
         if worker_responses.is_empty() {
             tracing::warn!(
@@ -805,6 +1693,8 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                     success: false,
                     error: Some("No worker responses available for selector".to_string()),
                     raw_output: None,
+                    scores: None,
+                    provider_request_ids: BTreeMap::new(),
                 },
                 None,
             );
@@ -831,6 +1721,8 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                         success: false,
                         error: Some(message),
                         raw_output: None,
+                        scores: None,
+                        provider_request_ids: BTreeMap::new(),
                     },
                     None,
                 );
@@ -839,17 +1731,11 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
 
         let temperature = self.resolve_selector_temperature(target, model_config, depth);
 
-        let domain = extract_domain_from_url(&model_config.api_base);
-        let timeouts = self.config.effective_timeouts_for_domain(domain.as_deref());
-        let client = match self
-            .get_llm_client(
-                &model_config.api_base,
-                &model_config.api_key,
-                timeouts.synthesizer_timeout_secs,
-            )
-            .await
-        {
-            Ok(client) => client,
+        let timeouts = self.timeouts_for(model_config);
+        let client = match self.get_llm_client(model_config, &timeouts).await {
+            Ok(client) => client
+                .with_request_id(options.upstream_request_id("selector"))
+                .with_workflow_id(options.workflow_id),
             Err(err) => {
                 let message = err.to_string();
                 tracing::warn!(
@@ -869,43 +1755,60 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                         success: false,
                         error: Some(message),
                         raw_output: None,
+                        scores: None,
+                        provider_request_ids: BTreeMap::new(),
                     },
                     None,
                 );
             }
         };
 
-        let mut selector_prompt = format!(
-            "原始用户问题：\n{}\n\n以下是多个模型给出的回答，请选出质量最高的一条。\n\n",
-            original_prompt
-        );
-
-        for (i, (label, response)) in worker_responses.iter().enumerate() {
-            selector_prompt.push_str(&format!("【回答{}：{}】\n{}\n\n", i + 1, label, response));
-        }
-
-        selector_prompt.push_str(
-            "请仅返回一个 JSON 对象，格式如下：\n\
-            {\n  \"selected_index\": 1,\n  \"selected_worker\": \"模型名称\",\n  \"selected_response\": \"可选：直接粘贴所选回答\",\n  \"reasoning\": \"简要说明\"\n}\n\
-            要求：\n\
-            - selected_index 使用上面编号（从 1 开始）\n\
-            - reasoning 简洁说明选择理由，如有不足请指出\n\
-            - 如所有回答都存在问题，请选出相对最佳的一条并说明原因\n\
-            只需输出 JSON 对象。\n",
-        );
-
+        let rubric = target.rubric.as_deref();
+        let selector_prompt = build_selector_prompt(original_prompt, worker_responses, rubric);
+
         let messages = vec![ChatMessage {
             role: "user".to_string(),
             content: selector_prompt,
         }];
 
-        let raw_output = match client
-            .chat_completion(&target.model, messages, Some(temperature))
+        let params = resolve_generation_params(target, model_config, None);
+        let prompt_tokens = self.estimate_tokens(model_config, &messages[0].content);
+        let deadline = Deadline::after(Duration::from_secs(timeouts.synthesizer_timeout_secs));
+        let raw_output = match self
+            .wait_for_rate_limit(model_config, prompt_tokens, deadline)
             .await
         {
-            Ok(content) => content,
+            Ok(_) => match self.acquire_request_slot(model_config, deadline).await {
+                Ok((_permit, _)) => self
+                    .timed(
+                        &target.model,
+                        Phase::Selector,
+                        Some(temperature),
+                        options.workflow_id,
+                        prompt_tokens,
+                        client.chat_completion_with_stream(
+                            &target.model,
+                            messages,
+                            Some(temperature),
+                            &params,
+                            deadline.remaining(),
+                            None,
+                        ),
+                    )
+                    .await
+                    .map(|completion| (completion.content, completion.provider_request_ids)),
+                Err(err) => Err(err),
+            },
+            Err(err) => Err(err),
+        };
+        let (raw_output, provider_request_ids) = match raw_output {
+            Ok((content, ids)) => {
+                self.record_completion_tokens(model_config, &content);
+                (content, ids.to_map())
+            }
             Err(err) => {
-                let message = err.to_string();
+                // 上游请求编号作为上下文挂在错误上，要带上整条错误链
+                let message = format!("{:#}", err);
                 tracing::warn!(
                     selector = %target.model,
                     depth,
@@ -923,6 +1826,10 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                         success: false,
                         error: Some(message),
                         raw_output: None,
+                        scores: None,
+                        provider_request_ids: provider_request_ids_of(&err)
+                            .map(ProviderRequestIds::to_map)
+                            .unwrap_or_default(),
                     },
                     None,
                 );
@@ -957,6 +1864,9 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                     );
                 }
 
+                let scores = rubric
+                    .and_then(|rubric| parse_rubric_scores(&raw_output, rubric, worker_responses));
+
                 let details = SelectorDetails {
                     model: target.model.clone(),
                     temperature,
@@ -966,7 +1876,9 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                     reasoning: reasoning.clone(),
                     success: true,
                     error: None,
-                    raw_output: Some(raw_output.clone()),
+                    raw_output: Some(raw_output),
+                    scores,
+                    provider_request_ids,
                 };
 
                 let choice = SelectedChoice {
@@ -974,7 +1886,6 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                     worker_name,
                     response: worker_response,
                     reasoning,
-                    raw_output,
                 };
 
                 (details, Some(choice))
@@ -998,6 +1909,8 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
                         success: false,
                         error: Some(message),
                         raw_output: Some(raw_output),
+                        scores: None,
+                        provider_request_ids,
                     },
                     None,
                 )
@@ -1005,6 +1918,7 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         }
     }
 
+    #[allow(clippy::too_many_arguments)]
     async fn call_synthesizer(
         &self,
         target: &WorkflowModelTarget,
@@ -1013,18 +1927,16 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
         selected_choice: Option<&SelectedChoice>,
         depth: usize,
         stream: Option<StreamCallback>,
-    ) -> Result<(String, bool)> {
+        options: &RequestOptions,
+    ) -> Result<CompletionResult> {
         let model_config = self.lookup_model(&target.model)?;
 
-        let domain = extract_domain_from_url(&model_config.api_base);
-        let timeouts = self.config.effective_timeouts_for_domain(domain.as_deref());
+        let timeouts = self.timeouts_for(model_config);
         let client = self
-            .get_llm_client(
-                &model_config.api_base,
-                &model_config.api_key,
-                timeouts.synthesizer_timeout_secs,
-            )
-            .await?;
+            .get_llm_client(model_config, &timeouts)
+            .await?
+            .with_request_id(options.upstream_request_id("synthesizer"))
+            .with_workflow_id(options.workflow_id);
 
         let mut synthesis_prompt = format!(
             "原始用户问题：\n{}\n\n以下是多个AI模型对该问题的回答：\n\n",
@@ -1076,11 +1988,32 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
             depth
         );
 
-        let completion = client
-            .chat_completion_with_stream(&target.model, messages, Some(temperature), stream)
+        let prompt_tokens = self.estimate_tokens(model_config, &messages[0].content);
+        let deadline = Deadline::after(Duration::from_secs(timeouts.synthesizer_timeout_secs));
+        self.wait_for_rate_limit(model_config, prompt_tokens, deadline)
+            .await?;
+        let (_permit, _) = self.acquire_request_slot(model_config, deadline).await?;
+        let params = resolve_generation_params(target, model_config, Some(&options.generation));
+        let completion = self
+            .timed(
+                &target.model,
+                Phase::Synthesizer,
+                Some(temperature),
+                options.workflow_id,
+                prompt_tokens,
+                client.chat_completion_with_stream(
+                    &target.model,
+                    messages,
+                    Some(temperature),
+                    &params,
+                    deadline.remaining(),
+                    stream,
+                ),
+            )
             .await?;
+        self.record_completion_tokens(model_config, &completion.content);
 
-        Ok((completion.content, completion.streamed))
+        Ok(completion)
     }
 
     fn resolve_worker_temperature(
@@ -1174,10 +2107,166 @@ Temperature越低（接近0），输出越确定和保守；temperature越高（
     }
 }
 
-fn extract_domain_from_url(url: &str) -> Option<String> {
-    Url::parse(url)
-        .ok()
-        .and_then(|u| u.host_str().map(|s| s.to_string()))
+// 嵌套工作流的阶段 span 挂在外层 worker 阶段之下
+fn phase_span(phase: Phase) -> tracing::Span {
+    tracing::info_span!("phase", phase = phase.as_str())
+}
+
+fn truncate_chars(text: &str, max_chars: usize) -> String {
+    match text.char_indices().nth(max_chars) {
+        Some((idx, _)) => format!("{}...", &text[..idx]),
+        None => text.to_string(),
+    }
+}
+
+// 优先级：请求 > 工作流节点 > 模型默认值；均未设置时不下发该参数
+fn resolve_generation_params(
+    target: &WorkflowModelTarget,
+    model_config: &ModelConfig,
+    request: Option<&GenerationParams>,
+) -> GenerationParams {
+    let configured = target
+        .generation_params()
+        .or(&model_config.generation_defaults());
+    match request {
+        Some(request) => request.or(&configured),
+        None => configured,
+    }
+}
+
+fn build_selector_prompt(
+    original_prompt: &str,
+    worker_responses: &[(String, String)],
+    rubric: Option<&[RubricCriterion]>,
+) -> String {
+    let mut selector_prompt = format!(
+        "原始用户问题：\n{}\n\n以下是多个模型给出的回答，请选出质量最高的一条。\n\n",
+        original_prompt
+    );
+
+    for (i, (label, response)) in worker_responses.iter().enumerate() {
+        selector_prompt.push_str(&format!("【回答{}：{}】\n{}\n\n", i + 1, label, response));
+    }
+
+    let Some(rubric) = rubric else {
+        selector_prompt.push_str(
+            "请仅返回一个 JSON 对象，格式如下：\n\
+            {\n  \"selected_index\": 1,\n  \"selected_worker\": \"模型名称\",\n  \"selected_response\": \"可选：直接粘贴所选回答\",\n  \"reasoning\": \"简要说明\"\n}\n\
+            要求：\n\
+            - selected_index 使用上面编号（从 1 开始）\n\
+            - reasoning 简洁说明选择理由，如有不足请指出\n\
+            - 如所有回答都存在问题，请选出相对最佳的一条并说明原因\n\
+            只需输出 JSON 对象。\n",
+        );
+        return selector_prompt;
+    };
+
+    selector_prompt.push_str("评分标准（权重越高越重要）：\n");
+    for criterion in rubric {
+        selector_prompt.push_str(&format!(
+            "- {}（权重 {}）\n",
+            criterion.name, criterion.weight
+        ));
+    }
+
+    let example_criteria = rubric
+        .iter()
+        .map(|criterion| format!("\"{}\": 8", criterion.name))
+        .collect::<Vec<_>>()
+        .join(", ");
+
+    selector_prompt.push_str(&format!(
+        "\n请先按上述标准逐项为每条回答打分（0-10 分），再根据加权结果选出最佳回答。\n\
+        请仅返回一个 JSON 对象，格式如下：\n\
+        {{\n  \"scores\": [\n    {{\"index\": 1, \"criteria\": {{{}}}}}\n  ],\n  \"selected_index\": 1,\n  \"selected_worker\": \"模型名称\",\n  \"selected_response\": \"可选：直接粘贴所选回答\",\n  \"reasoning\": \"简要说明\"\n}}\n\
+        要求：\n\
+        - scores 需覆盖每一条回答，index 使用上面编号（从 1 开始）\n\
+        - selected_index 使用上面编号（从 1 开始）\n\
+        - reasoning 简洁说明选择理由，指出落选回答在哪些标准上失分\n\
+        只需输出 JSON 对象。\n",
+        example_criteria
+    ));
+
+    selector_prompt
+}
+
+fn parse_rubric_scores(
+    response: &str,
+    rubric: &[RubricCriterion],
+    worker_responses: &[(String, String)],
+) -> Option<Vec<CandidateScore>> {
+    let json_str = extract_first_json_object(response)?;
+    let value = serde_json::from_str::<serde_json::Value>(json_str).ok()?;
+    let entries = value.get("scores")?;
+
+    let mut candidates: Vec<(usize, &serde_json::Value)> = Vec::new();
+    match entries {
+        serde_json::Value::Array(items) => {
+            for (position, item) in items.iter().enumerate() {
+                let index = find_value_in_json(item, &["index", "candidate", "selected_index"])
+                    .and_then(value_to_usize)
+                    .unwrap_or(position + 1);
+                candidates.push((index, item));
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, item) in map {
+                if let Ok(index) = key.trim().parse::<usize>() {
+                    candidates.push((index, item));
+                }
+            }
+        }
+        _ => return None,
+    }
+
+    let mut scores = Vec::new();
+    for (index, item) in candidates {
+        if index == 0 || index > worker_responses.len() {
+            continue;
+        }
+        let criteria_source = item.get("criteria").unwrap_or(item);
+
+        let mut criteria = BTreeMap::new();
+        for criterion in rubric {
+            if let Some(score) = criteria_source.get(&criterion.name).and_then(value_to_f32) {
+                criteria.insert(criterion.name.clone(), score);
+            }
+        }
+        if criteria.is_empty() {
+            continue;
+        }
+
+        let weighted_total = rubric
+            .iter()
+            .filter_map(|criterion| {
+                criteria
+                    .get(&criterion.name)
+                    .map(|score| score * criterion.weight)
+            })
+            .sum();
+
+        scores.push(CandidateScore {
+            index,
+            worker: worker_responses[index - 1].0.clone(),
+            criteria,
+            weighted_total,
+        });
+    }
+
+    if scores.is_empty() {
+        None
+    } else {
+        scores.sort_by_key(|score| score.index);
+        Some(scores)
+    }
+}
+
+fn value_to_f32(value: &serde_json::Value) -> Option<f32> {
+    match value {
+        serde_json::Value::Number(n) => n.as_f64().map(|v| v as f32),
+        serde_json::Value::String(s) => s.trim().parse::<f32>().ok(),
+        _ => None,
+    }
 }
 
 fn parse_selector_choice(response: &str, worker_count: usize) -> Result<ParsedSelection> {
@@ -1286,13 +2375,11 @@ fn extract_first_json_object(input: &str) -> Option<&str> {
                 }
                 depth += 1;
             }
-            '}' => {
-                if depth > 0 {
-                    depth -= 1;
-                    if depth == 0 {
-                        if let Some(begin) = start {
-                            return input.get(begin..=idx);
-                        }
+            '}' if depth > 0 => {
+                depth -= 1;
+                if depth == 0 {
+                    if let Some(begin) = start {
+                        return input.get(begin..=idx);
                     }
                 }
             }
@@ -1427,13 +2514,17 @@ mod tests {
         Config, ModelConfig, ServerConfig, TimeoutConfig, WorkflowConfig, WorkflowModelTarget,
         WorkflowPlan, WorkflowWorker,
     };
+    use crate::transport::{Scripted, ScriptedTransport};
     use std::collections::HashMap;
 
     fn build_test_config_with_workers(workers: Vec<WorkflowWorker>) -> Config {
         Config {
             server: ServerConfig {
-                host: "127.0.0.1".to_string(),
-                port: 11435,
+                host: Some("127.0.0.1".to_string()),
+                port: Some(11435),
+                unix_socket: None,
+                unix_socket_mode: None,
+                tls: None,
             },
             models: vec![ModelConfig {
                 name: "primary".to_string(),
@@ -1441,44 +2532,74 @@ mod tests {
                 api_key: "sk-test".to_string(),
                 temperature: Some(0.2),
                 auto_temperature: None,
+                ..Default::default()
             }],
             workflow_integration: WorkflowPlan {
                 analyzer: WorkflowModelTarget {
                     model: "primary".to_string(),
                     temperature: Some(0.2),
                     auto_temperature: None,
+                    ..Default::default()
                 },
                 workers,
                 synthesizer: Some(WorkflowModelTarget {
                     model: "primary".to_string(),
                     temperature: Some(0.2),
                     auto_temperature: None,
+                    ..Default::default()
                 }),
                 selector: None,
                 nested_worker_depth: None,
+                presets: Default::default(),
+                preset_model_prefix: None,
             },
             workflow: WorkflowConfig {
+                timeout_warning_secs: 3600,
                 timeouts: TimeoutConfig {
                     analyzer_timeout_secs: 30,
                     worker_timeout_secs: 30,
                     synthesizer_timeout_secs: 30,
+                    connect_timeout_secs: 10,
                 },
                 domains: HashMap::new(),
+                retry: Default::default(),
+                slow: Default::default(),
             },
+            network: Default::default(),
+            logging: Default::default(),
+            stats: Default::default(),
+            telemetry: None,
+            history: None,
+            audit: None,
+            access_log: None,
+            model_groups: BTreeMap::new(),
+            workflow_json_file: None,
+            include_files: Vec::new(),
+            profile: None,
         }
     }
 
+    fn primary_worker() -> WorkflowWorker {
+        WorkflowWorker::Model(WorkflowModelTarget {
+            model: "primary".to_string(),
+            temperature: None,
+            auto_temperature: None,
+            ..Default::default()
+        })
+    }
+
     #[test]
     fn worker_auto_temperature_disabled_falls_back_to_default() {
-        let mut config = build_test_config_with_workers(Vec::new());
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
         config.models[0].temperature = None;
         config.models[0].auto_temperature = None;
 
-        let engine = WorkflowEngine::new(config);
+        let engine = WorkflowEngine::new(config).expect("valid config");
         let target = WorkflowModelTarget {
             model: "primary".to_string(),
             temperature: None,
             auto_temperature: None,
+            ..Default::default()
         };
 
         let resolved = {
@@ -1496,15 +2617,16 @@ mod tests {
 
     #[test]
     fn worker_auto_temperature_flag_enables_analyzer_reuse() {
-        let mut config = build_test_config_with_workers(Vec::new());
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
         config.models[0].temperature = None;
         config.models[0].auto_temperature = None;
 
-        let engine = WorkflowEngine::new(config);
+        let engine = WorkflowEngine::new(config).expect("valid config");
         let target = WorkflowModelTarget {
             model: "primary".to_string(),
             temperature: None,
             auto_temperature: Some(true),
+            ..Default::default()
         };
         let base = 0.42;
 
@@ -1523,15 +2645,16 @@ mod tests {
 
     #[test]
     fn worker_inherits_analyzer_auto_when_unspecified() {
-        let mut config = build_test_config_with_workers(Vec::new());
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
         config.models[0].temperature = None;
         config.models[0].auto_temperature = None;
 
-        let engine = WorkflowEngine::new(config);
+        let engine = WorkflowEngine::new(config).expect("valid config");
         let target = WorkflowModelTarget {
             model: "primary".to_string(),
             temperature: None,
             auto_temperature: None,
+            ..Default::default()
         };
         let base = 0.73;
 
@@ -1550,15 +2673,16 @@ mod tests {
 
     #[test]
     fn worker_explicit_auto_false_overrides_analyzer_auto() {
-        let mut config = build_test_config_with_workers(Vec::new());
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
         config.models[0].temperature = None;
         config.models[0].auto_temperature = None;
 
-        let engine = WorkflowEngine::new(config);
+        let engine = WorkflowEngine::new(config).expect("valid config");
         let target = WorkflowModelTarget {
             model: "primary".to_string(),
             temperature: None,
             auto_temperature: Some(false),
+            ..Default::default()
         };
 
         let resolved = {
@@ -1576,15 +2700,16 @@ mod tests {
 
     #[test]
     fn worker_uses_model_config_auto_flag() {
-        let mut config = build_test_config_with_workers(Vec::new());
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
         config.models[0].temperature = None;
         config.models[0].auto_temperature = Some(true);
 
-        let engine = WorkflowEngine::new(config);
+        let engine = WorkflowEngine::new(config).expect("valid config");
         let target = WorkflowModelTarget {
             model: "primary".to_string(),
             temperature: None,
             auto_temperature: None,
+            ..Default::default()
         };
         let base = 0.37;
 
@@ -1628,18 +2753,16 @@ mod tests {
             .contains("回答"));
     }
 
-    #[tokio::test]
-    async fn reports_missing_workers_in_error() {
+    #[test]
+    fn reports_missing_workers_in_error() {
         let config = build_test_config_with_workers(Vec::new());
-        let engine = WorkflowEngine::new(config);
-        let err = engine
-            .process("hello world".to_string())
-            .await
-            .expect_err("expected failure when no workers configured");
+        let err = WorkflowEngine::new(config)
+            .err()
+            .expect("expected failure when no workers configured");
         let message = err.to_string();
         assert!(
-            message.contains("workflow:primary"),
-            "message did not include plan label: {}",
+            message.contains("Workflow validation failed"),
+            "message did not identify a validation failure: {}",
             message
         );
         assert!(
@@ -1649,34 +2772,810 @@ mod tests {
         );
     }
 
-    #[tokio::test]
-    async fn includes_worker_failure_details() {
+    #[test]
+    fn includes_worker_failure_details() {
         let workers = vec![WorkflowWorker::Model(WorkflowModelTarget {
             model: "missing".to_string(),
             temperature: None,
             auto_temperature: None,
+            ..Default::default()
         })];
         let config = build_test_config_with_workers(workers);
-        let engine = WorkflowEngine::new(config);
-        let err = engine
-            .process("hello world".to_string())
-            .await
-            .expect_err("expected failure when worker model missing");
+        let err = WorkflowEngine::new(config)
+            .err()
+            .expect("expected failure when worker model missing");
         let message = err.to_string();
         assert!(
-            message.contains("missing"),
-            "message did not include worker label: {}",
+            message.contains("workflow -> workers[0]"),
+            "message did not include worker path: {}",
             message
         );
         assert!(
-            message.contains("Model 'missing' not found in configuration"),
+            message.contains("unknown model 'missing'"),
             "message did not include underlying error: {}",
             message
         );
+    }
+
+    #[test]
+    fn validation_reports_every_problem_at_once() {
+        let workers = vec![
+            WorkflowWorker::Model(WorkflowModelTarget {
+                model: "ghost-a".to_string(),
+                temperature: None,
+                auto_temperature: None,
+                ..Default::default()
+            }),
+            WorkflowWorker::Model(WorkflowModelTarget {
+                model: "ghost-b".to_string(),
+                temperature: None,
+                auto_temperature: None,
+                ..Default::default()
+            }),
+        ];
+        let mut config = build_test_config_with_workers(workers);
+        config.workflow_integration.synthesizer = Some(WorkflowModelTarget {
+            model: "ghost-synth".to_string(),
+            temperature: None,
+            auto_temperature: None,
+            ..Default::default()
+        });
+
+        let err = config.validate_workflow().expect_err("expected failures");
+        assert_eq!(err.problems.len(), 3, "problems: {:?}", err.problems);
+        let message = err.to_string();
+        for name in ["ghost-a", "ghost-b", "ghost-synth"] {
+            assert!(message.contains(name), "missing {} in {}", name, message);
+        }
+    }
+
+    #[test]
+    fn attempt_records_http_status_and_truncated_error() {
+        let result: Result<String> = Err(LlmHttpError {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+            body: "上游".repeat(MAX_ATTEMPT_ERROR_CHARS),
+            retry_after: None,
+            provider_request_ids: Default::default(),
+        }
+        .into());
+        let attempt = AttemptInfo::from_result("m1", Duration::from_millis(1234), &result);
+
+        assert_eq!(attempt.model, "m1");
+        assert_eq!(attempt.duration_ms, 1234);
+        assert_eq!(attempt.status, Some(502));
+        assert!(!attempt.timed_out);
+        let error = attempt.error.expect("error recorded");
+        assert!(error.ends_with("..."));
+        assert_eq!(error.chars().count(), MAX_ATTEMPT_ERROR_CHARS + 3);
+    }
+
+    #[test]
+    fn truncation_never_splits_a_multibyte_char() {
+        for text in ["中文提示词", "🙂🙃🙂🙃🙂", "e\u{301}e\u{301}e\u{301}"] {
+            for max_chars in 0..=text.chars().count() {
+                let truncated = truncate_chars(text, max_chars);
+                let kept = truncated.strip_suffix("...").unwrap_or(&truncated);
+                assert!(text.starts_with(kept), "{:?} at {}", text, max_chars);
+                assert_eq!(kept.chars().count(), max_chars);
+            }
+        }
+        assert_eq!(truncate_chars("中文", 5), "中文");
+    }
+
+    #[test]
+    fn successful_attempt_carries_provider_metadata() {
+        let completion = CompletionResult {
+            content: "fine".to_string(),
+            usage: Some(Usage {
+                prompt_tokens: Some(10),
+                completion_tokens: Some(4),
+                total_tokens: Some(14),
+            }),
+            finish_reason: Some("stop".to_string()),
+            model: Some("qwen3-max-2025-09-23".to_string()),
+            provider_request_ids: ProviderRequestIds::from_headers(
+                &reqwest::header::HeaderMap::from_iter([
+                    (
+                        reqwest::header::HeaderName::from_static("cf-ray"),
+                        reqwest::header::HeaderValue::from_static("8f1c-SJC"),
+                    ),
+                    (
+                        reqwest::header::HeaderName::from_static("x-request-id"),
+                        reqwest::header::HeaderValue::from_static("req-1"),
+                    ),
+                ]),
+                &[
+                    reqwest::header::HeaderName::from_static("x-request-id"),
+                    reqwest::header::HeaderName::from_static("cf-ray"),
+                ],
+            ),
+            ..Default::default()
+        };
+        let result: Result<CompletionResult> = Ok(completion);
+        let attempt = AttemptInfo::from_result("m1", Duration::from_millis(5), &result)
+            .with_completion(result.as_ref().unwrap());
+
+        let json = serde_json::to_value(&attempt).unwrap();
+        assert_eq!(json["usage"]["total_tokens"], 14);
+        assert_eq!(json["finish_reason"], "stop");
+        assert_eq!(json["provider_model"], "qwen3-max-2025-09-23");
+        assert!(json.get("provider_request_id").is_none());
+        assert_eq!(
+            json["provider_request_ids"],
+            serde_json::json!({"cf-ray": "8f1c-SJC", "x-request-id": "req-1"})
+        );
+    }
+
+    #[test]
+    fn failed_attempt_keeps_provider_request_ids() {
+        let ids = ProviderRequestIds::from_headers(
+            &reqwest::header::HeaderMap::from_iter([(
+                reqwest::header::HeaderName::from_static("x-request-id"),
+                reqwest::header::HeaderValue::from_static("req-9"),
+            )]),
+            &[reqwest::header::HeaderName::from_static("x-request-id")],
+        );
+        let http_err: Result<String> = Err(LlmHttpError {
+            status: reqwest::StatusCode::INTERNAL_SERVER_ERROR,
+            body: "overloaded".to_string(),
+            retry_after: None,
+            provider_request_ids: ids.clone(),
+        }
+        .into());
+        let attempt = AttemptInfo::from_result("m1", Duration::from_millis(5), &http_err);
+        assert_eq!(
+            attempt.provider_request_ids,
+            BTreeMap::from([("x-request-id".to_string(), "req-9".to_string())])
+        );
+        assert_eq!(
+            attempt.error.as_deref(),
+            Some("LLM API request failed with status 500 Internal Server Error: overloaded (x-request-id: req-9)")
+        );
+
+        // 2xx 之后读取失败：编号挂在上下文上，原来的错误仍可识别
+        let read_err: Result<String> =
+            Err(anyhow!("stream ended early").context(crate::llm::UpstreamResponseFailed { ids }));
+        let attempt = AttemptInfo::from_result("m1", Duration::from_millis(5), &read_err);
+        assert_eq!(
+            attempt.provider_request_ids,
+            BTreeMap::from([("x-request-id".to_string(), "req-9".to_string())])
+        );
+        assert_eq!(
+            attempt.error.as_deref(),
+            Some("upstream response (x-request-id: req-9): stream ended early")
+        );
+    }
+
+    // 执行详情是对外的 JSON 结构，改动这里的期望值时要同时考虑 DETAILS_SCHEMA_VERSION
+    #[test]
+    fn execution_details_json_is_pinned() {
+        let attempt = AttemptInfo {
+            model: "m1".to_string(),
+            duration_ms: 1250,
+            status: Some(200),
+            timed_out: false,
+            error: None,
+            rate_limit_wait_ms: Some(10),
+            queued_ms: Some(40),
+            upstream_ms: Some(1200),
+            retry_after_secs: None,
+            usage: Some(Usage {
+                prompt_tokens: Some(12),
+                completion_tokens: Some(30),
+                total_tokens: Some(42),
+            }),
+            finish_reason: Some("stop".to_string()),
+            continuations: Some(1),
+            provider_model: Some("m1-2024".to_string()),
+            request_id: Some("req-1/worker-1".to_string()),
+            provider_request_ids: BTreeMap::from([
+                ("cf-ray".to_string(), "8f1c-SJC".to_string()),
+                ("x-request-id".to_string(), "chatcmpl-9".to_string()),
+            ]),
+        };
+        let failed = AttemptInfo {
+            model: "m2".to_string(),
+            duration_ms: 5000,
+            status: None,
+            timed_out: true,
+            error: Some("request timeout (5s)".to_string()),
+            rate_limit_wait_ms: None,
+            queued_ms: None,
+            upstream_ms: None,
+            retry_after_secs: Some(3),
+            usage: None,
+            finish_reason: None,
+            continuations: None,
+            provider_model: None,
+            request_id: None,
+            provider_request_ids: BTreeMap::new(),
+        };
+        let details = WorkflowExecutionDetails {
+            schema_version: DETAILS_SCHEMA_VERSION,
+            request_id: Some("req-1".to_string()),
+            preset: Some("fast".to_string()),
+            analyzer: AnalyzerDetails {
+                model: "m1".to_string(),
+                temperature: 0.5,
+                auto_temperature: true,
+                provider_request_ids: BTreeMap::new(),
+            },
+            workers: vec![
+                WorkerDetails {
+                    name: "m1".to_string(),
+                    temperature: Some(0.5),
+                    response: Some("answer".to_string()),
+                    success: true,
+                    error: None,
+                    nested: None,
+                    attempts: vec![attempt],
+                    skipped: None,
+                },
+                WorkerDetails {
+                    name: "m2".to_string(),
+                    temperature: Some(0.5),
+                    response: None,
+                    success: false,
+                    error: Some("request timeout (5s)".to_string()),
+                    nested: None,
+                    attempts: vec![failed],
+                    skipped: None,
+                },
+            ],
+            selector: Some(SelectorDetails {
+                model: "m1".to_string(),
+                temperature: 0.0,
+                selected_index: Some(0),
+                selected_worker: Some("m1".to_string()),
+                selected_response: Some("answer".to_string()),
+                reasoning: Some("only answer".to_string()),
+                success: true,
+                error: None,
+                raw_output: None,
+                scores: Some(vec![CandidateScore {
+                    index: 0,
+                    worker: "m1".to_string(),
+                    criteria: BTreeMap::from([("accuracy".to_string(), 4.0)]),
+                    weighted_total: 4.0,
+                }]),
+                provider_request_ids: BTreeMap::new(),
+            }),
+            synthesizer: Some(SynthesizerDetails {
+                model: "m1".to_string(),
+                temperature: 0.25,
+                continuations: None,
+                provider_request_ids: BTreeMap::from([(
+                    "x-request-id".to_string(),
+                    "chatcmpl-10".to_string(),
+                )]),
+            }),
+        };
+        let expected = r#"{
+  "schema_version": 2,
+  "request_id": "req-1",
+  "preset": "fast",
+  "analyzer": {
+    "model": "m1",
+    "temperature": 0.5,
+    "auto_temperature": true
+  },
+  "workers": [
+    {
+      "name": "m1",
+      "temperature": 0.5,
+      "response": "answer",
+      "success": true,
+      "error": null,
+      "nested": null,
+      "attempts": [
+        {
+          "model": "m1",
+          "duration_ms": 1250,
+          "status": 200,
+          "timed_out": false,
+          "rate_limit_wait_ms": 10,
+          "queued_ms": 40,
+          "upstream_ms": 1200,
+          "usage": {
+            "prompt_tokens": 12,
+            "completion_tokens": 30,
+            "total_tokens": 42
+          },
+          "finish_reason": "stop",
+          "continuations": 1,
+          "provider_model": "m1-2024",
+          "request_id": "req-1/worker-1",
+          "provider_request_ids": {
+            "cf-ray": "8f1c-SJC",
+            "x-request-id": "chatcmpl-9"
+          }
+        }
+      ]
+    },
+    {
+      "name": "m2",
+      "temperature": 0.5,
+      "response": null,
+      "success": false,
+      "error": "request timeout (5s)",
+      "nested": null,
+      "attempts": [
+        {
+          "model": "m2",
+          "duration_ms": 5000,
+          "timed_out": true,
+          "error": "request timeout (5s)",
+          "retry_after_secs": 3
+        }
+      ]
+    }
+  ],
+  "selector": {
+    "model": "m1",
+    "temperature": 0.0,
+    "selected_index": 0,
+    "selected_worker": "m1",
+    "selected_response": "answer",
+    "reasoning": "only answer",
+    "success": true,
+    "scores": [
+      {
+        "index": 0,
+        "worker": "m1",
+        "criteria": {
+          "accuracy": 4.0
+        },
+        "weighted_total": 4.0
+      }
+    ]
+  },
+  "synthesizer": {
+    "model": "m1",
+    "temperature": 0.25,
+    "provider_request_ids": {
+      "x-request-id": "chatcmpl-10"
+    }
+  }
+}"#;
+        assert_eq!(serde_json::to_string_pretty(&details).unwrap(), expected);
+
+        // 引入 schema_version 之前保存的详情仍能读取
+        let mut legacy = serde_json::to_value(&details).unwrap();
+        legacy.as_object_mut().unwrap().remove("schema_version");
+        let parsed: WorkflowExecutionDetails = serde_json::from_value(legacy).unwrap();
+        assert_eq!(parsed.schema_version, 1);
+    }
+
+    #[test]
+    fn successful_attempt_has_no_error() {
+        let result: Result<String> = Ok("fine".to_string());
+        let attempt = AttemptInfo::from_result("m1", Duration::from_millis(5), &result);
+        assert!(attempt.status.is_none());
+        assert!(attempt.error.is_none());
+
+        let json = serde_json::to_value(&attempt).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"model": "m1", "duration_ms": 5, "timed_out": false})
+        );
+    }
+
+    #[test]
+    fn selector_prompt_without_rubric_is_unchanged() {
+        let responses = vec![
+            ("m1".to_string(), "A".to_string()),
+            ("m2".to_string(), "B".to_string()),
+        ];
+        let prompt = build_selector_prompt("Q", &responses, None);
+        let expected = "原始用户问题：\nQ\n\n以下是多个模型给出的回答，请选出质量最高的一条。\n\n【回答1：m1】\nA\n\n【回答2：m2】\nB\n\n请仅返回一个 JSON 对象，格式如下：\n{\n  \"selected_index\": 1,\n  \"selected_worker\": \"模型名称\",\n  \"selected_response\": \"可选：直接粘贴所选回答\",\n  \"reasoning\": \"简要说明\"\n}\n要求：\n- selected_index 使用上面编号（从 1 开始）\n- reasoning 简洁说明选择理由，如有不足请指出\n- 如所有回答都存在问题，请选出相对最佳的一条并说明原因\n只需输出 JSON 对象。\n";
+        assert_eq!(prompt, expected);
+    }
+
+    fn code_review_rubric() -> Vec<RubricCriterion> {
+        vec![
+            RubricCriterion {
+                name: "correctness".to_string(),
+                weight: 3.0,
+            },
+            RubricCriterion {
+                name: "brevity".to_string(),
+                weight: 1.0,
+            },
+        ]
+    }
+
+    #[test]
+    fn selector_prompt_renders_rubric_criteria() {
+        let responses = vec![("m1".to_string(), "A".to_string())];
+        let rubric = code_review_rubric();
+        let prompt = build_selector_prompt("Q", &responses, Some(&rubric));
+        assert!(prompt.contains("- correctness（权重 3）"));
+        assert!(prompt.contains("- brevity（权重 1）"));
+        assert!(prompt.contains(r#"{"index": 1, "criteria": {"correctness": 8, "brevity": 8}}"#));
+    }
+
+    #[test]
+    fn rubric_scores_are_parsed_and_weighted() {
+        let responses = vec![
+            ("m1".to_string(), "A".to_string()),
+            ("m2".to_string(), "B".to_string()),
+        ];
+        let payload = r#"```json
+{
+  "scores": [
+    {"index": 1, "criteria": {"correctness": 9, "brevity": "4"}},
+    {"index": 2, "correctness": 6, "brevity": 10}
+  ],
+  "selected_index": 1,
+  "reasoning": "A is correct"
+}
+```"#;
+        let scores = parse_rubric_scores(payload, &code_review_rubric(), &responses)
+            .expect("scores should parse");
+        assert_eq!(scores.len(), 2);
+        assert_eq!(scores[0].worker, "m1");
+        assert_eq!(scores[0].criteria.get("brevity"), Some(&4.0));
+        assert!((scores[0].weighted_total - 31.0).abs() < f32::EPSILON);
+        assert_eq!(scores[1].worker, "m2");
+        assert!((scores[1].weighted_total - 28.0).abs() < f32::EPSILON);
+    }
+
+    #[test]
+    fn rubric_scores_missing_from_output_are_ignored() {
+        let responses = vec![("m1".to_string(), "A".to_string())];
+        let payload = r#"{"selected_index": 1}"#;
+        assert!(parse_rubric_scores(payload, &code_review_rubric(), &responses).is_none());
+    }
+
+    #[test]
+    fn generation_params_follow_request_node_model_precedence() {
+        let model_config = ModelConfig {
+            name: "primary".to_string(),
+            default_max_tokens: Some(2048),
+            default_top_p: Some(0.9),
+            default_frequency_penalty: Some(0.4),
+            ..Default::default()
+        };
+        let target = WorkflowModelTarget {
+            model: "primary".to_string(),
+            max_tokens: Some(1024),
+            top_k: Some(40),
+            ..Default::default()
+        };
+        let request = GenerationParams {
+            max_tokens: Some(256),
+            ..Default::default()
+        };
+
+        let bare = WorkflowModelTarget {
+            model: "primary".to_string(),
+            ..Default::default()
+        };
+        let model_only = resolve_generation_params(&bare, &model_config, None);
+        assert_eq!(model_only, model_config.generation_defaults());
+
+        let node = resolve_generation_params(&target, &model_config, None);
+        assert_eq!(node.max_tokens, Some(1024));
+        assert_eq!(node.top_k, Some(40));
+        assert_eq!(node.top_p, Some(0.9));
+
+        let merged = resolve_generation_params(&target, &model_config, Some(&request));
+        assert_eq!(merged.max_tokens, Some(256));
+        assert_eq!(merged.top_k, Some(40));
+        assert_eq!(merged.top_p, Some(0.9));
+        assert_eq!(merged.frequency_penalty, Some(0.4));
+        assert_eq!(merged.presence_penalty, None);
+
+        let nothing = resolve_generation_params(&bare, &ModelConfig::default(), None);
+        assert_eq!(nothing, GenerationParams::default());
+    }
+
+    #[test]
+    fn extra_body_merges_per_key_with_node_over_model() {
+        let model_config = ModelConfig {
+            name: "primary".to_string(),
+            extra_body: serde_json::json!({"reasoning_effort": "high", "enable_thinking": true})
+                .as_object()
+                .cloned()
+                .unwrap(),
+            ..Default::default()
+        };
+        let target = WorkflowModelTarget {
+            model: "primary".to_string(),
+            extra_body: serde_json::json!({"reasoning_effort": "low"})
+                .as_object()
+                .cloned()
+                .unwrap(),
+            ..Default::default()
+        };
+        let params =
+            resolve_generation_params(&target, &model_config, Some(&GenerationParams::default()));
+        assert_eq!(
+            serde_json::Value::Object(params.extra_body),
+            serde_json::json!({"reasoning_effort": "low", "enable_thinking": true})
+        );
+    }
+
+    #[test]
+    fn rate_limiter_is_shared_across_engines() {
+        let mut config = build_test_config_with_workers(vec![primary_worker()]);
+        config.models[0].rate_limit_rpm = Some(30);
+
+        let engine = WorkflowEngine::new(config.clone()).expect("valid config");
+        let stats = engine.rate_limiter().stats();
+        assert_eq!(stats.len(), 1);
+        assert_eq!(stats[0].model, "primary");
+        assert_eq!(stats[0].rate_limit_rpm, Some(30));
+
+        config.models[0].rate_limit_rpm = None;
+        let reloaded =
+            WorkflowEngine::with_rate_limiter(config, engine.rate_limiter()).expect("valid config");
+        assert!(Arc::ptr_eq(
+            &engine.rate_limiter(),
+            &reloaded.rate_limiter()
+        ));
+        assert!(reloaded.rate_limiter().stats().is_empty());
+    }
+
+    #[test]
+    fn attempt_reports_rate_limit_wait_on_failure() {
+        let err: anyhow::Error = LlmHttpError {
+            status: reqwest::StatusCode::GATEWAY_TIMEOUT,
+            body: "upstream timed out".to_string(),
+            retry_after: None,
+            provider_request_ids: Default::default(),
+        }
+        .into();
+        let result: Result<()> = Err(err.context(RateLimitWaited {
+            waited_ms: 45_000,
+            timeout_secs: 60,
+        }));
+        let attempt = AttemptInfo::from_result("primary", Duration::from_secs(59), &result);
+        assert_eq!(attempt.rate_limit_wait_ms, Some(45_000));
+        assert_eq!(attempt.status, Some(504));
+        let error = attempt.error.unwrap();
         assert!(
-            message.contains("Worker errors"),
-            "message did not include worker errors section: {}",
-            message
+            error.starts_with("spent 45000 ms of the 60s timeout waiting for rate limit budget: ")
+        );
+        assert!(error.contains("upstream timed out"));
+    }
+
+    #[test]
+    fn attempt_splits_queued_and_upstream_time() {
+        let err: anyhow::Error = LlmHttpError {
+            status: reqwest::StatusCode::BAD_GATEWAY,
+            body: "bad gateway".to_string(),
+            retry_after: None,
+            provider_request_ids: Default::default(),
+        }
+        .into();
+        let result: Result<()> = Err(err
+            .context(SlotWaited {
+                waited_ms: 3_000,
+                timeout_secs: 60,
+            })
+            .context(RateLimitWaited {
+                waited_ms: 1_000,
+                timeout_secs: 60,
+            }));
+        let attempt = AttemptInfo::from_result("primary", Duration::from_secs(10), &result);
+        assert_eq!(attempt.rate_limit_wait_ms, Some(1_000));
+        assert_eq!(attempt.queued_ms, Some(3_000));
+        assert_eq!(attempt.upstream_ms, Some(6_000));
+        assert_eq!(attempt.status, Some(502));
+
+        let exceeded: Result<()> = Err(ConcurrencyLimitExceeded {
+            model: "primary".to_string(),
+            max: 2,
+            waited_ms: 60_000,
+            timeout_secs: 60,
+        }
+        .into());
+        let attempt = AttemptInfo::from_result("primary", Duration::from_secs(60), &exceeded);
+        assert_eq!(attempt.queued_ms, Some(60_000));
+        assert_eq!(attempt.upstream_ms, None);
+
+        // 未配置上限的模型不输出这两个字段
+        let ok: Result<()> = Ok(());
+        let attempt = AttemptInfo::from_result("primary", Duration::from_secs(1), &ok);
+        let json = serde_json::to_value(&attempt).unwrap();
+        assert!(json.get("queued_ms").is_none());
+        assert!(json.get("upstream_ms").is_none());
+    }
+
+    fn config_with_disabled_backup(workers: Vec<WorkflowWorker>) -> Config {
+        let mut config = build_test_config_with_workers(workers);
+        config.models.push(ModelConfig {
+            name: "backup".to_string(),
+            api_base: "http://localhost".to_string(),
+            api_key: "sk-test".to_string(),
+            enabled: Some(false),
+            ..Default::default()
+        });
+        config
+    }
+
+    fn backup_worker() -> WorkflowWorker {
+        WorkflowWorker::Model(WorkflowModelTarget {
+            model: "backup".to_string(),
+            ..Default::default()
+        })
+    }
+
+    #[test]
+    fn disabled_models_do_not_count_as_enabled_workers() {
+        let nested = WorkflowWorker::Workflow(Box::new(WorkflowPlan {
+            workers: vec![backup_worker(), backup_worker()],
+            ..build_test_config_with_workers(Vec::new()).workflow_integration
+        }));
+        let config = config_with_disabled_backup(vec![backup_worker(), nested.clone()]);
+        let engine = WorkflowEngine::new(config).expect("disabled workers are not a load error");
+
+        assert!(!engine.worker_enabled(&backup_worker()));
+        assert!(!engine.worker_enabled(&nested));
+        assert!(engine.worker_enabled(&primary_worker()));
+        assert!(!engine.has_enabled_worker(&engine.config.workflow_integration));
+    }
+
+    #[tokio::test]
+    async fn all_workers_disabled_fails_before_calling_models() {
+        let config = config_with_disabled_backup(vec![backup_worker()]);
+        let engine = WorkflowEngine::new(config).unwrap();
+
+        let err = engine
+            .process_with_details("hello".to_string())
+            .await
+            .expect_err("no enabled workers");
+        assert!(
+            err.downcast_ref::<NoEnabledWorkers>().is_some(),
+            "{:#}",
+            err
+        );
+    }
+
+    #[test]
+    fn disabled_model_cannot_be_analyzer() {
+        let mut config = config_with_disabled_backup(vec![primary_worker()]);
+        config.workflow_integration.analyzer.model = "backup".to_string();
+        let err = config.validate_workflow().expect_err("disabled analyzer");
+        assert_eq!(
+            err.problems,
+            vec!["workflow analyzer references disabled model 'backup'; enable it or pick another ref"]
+        );
+    }
+
+    // 每个模型一个独立的 api_base，按 host 给各模型预设结果，用来走到各阶段的失败分支
+    fn scripted_config(models: &[&str], workflow: &str) -> Config {
+        let mut toml_str = String::from("[server]\nhost = \"127.0.0.1\"\nport = 11435\n");
+        for name in models {
+            toml_str.push_str(&format!(
+                "\n[[model]]\nname = \"{}\"\napi_base = \"http://{}.test/v1\"\napi_key = \"k\"\nproxy = \"direct\"\ntemperature = 0.2\n",
+                name, name
+            ));
+        }
+        toml_str.push_str(&format!(
+            "\n[workflow-integration]\njson = \"\"\"{}\"\"\"\n\n[workflow.timeouts]\nanalyzer_timeout_secs = 5\nworker_timeout_secs = 5\nsynthesizer_timeout_secs = 1\n\n[workflow.retry]\nmax_attempts = 1\n",
+            workflow
+        ));
+        toml::from_str(&toml_str).unwrap()
+    }
+
+    fn scripted_engine(config: Config, transport: ScriptedTransport) -> WorkflowEngine {
+        WorkflowEngine::new(config)
+            .unwrap()
+            .with_transport(Some(Arc::new(transport)))
+    }
+
+    #[tokio::test]
+    async fn calls_over_the_slow_threshold_are_counted() {
+        let transport = ScriptedTransport::default()
+            .on("good.test", Scripted::completion("good answer"))
+            .on(
+                "synth.test",
+                Scripted::completion("too late").after(Duration::from_millis(300)),
+            );
+        let mut config = scripted_config(
+            &["good", "synth"],
+            r#"{"analyzer": {"ref": "good"}, "workers": [{"name": "good"}], "synthesizer": {"ref": "synth"}}"#,
+        );
+        // synthesizer 超时 1s，阈值 100ms；worker 超时 5s，阈值 500ms
+        config.workflow.slow.ratio = 0.1;
+        let engine = scripted_engine(config, transport);
+
+        engine.process("hello".to_string()).await.unwrap();
+        let slow: Vec<(String, Phase, u64)> = engine
+            .latency()
+            .stats()
+            .into_iter()
+            .map(|stats| (stats.model, stats.phase, stats.slow))
+            .collect();
+        assert_eq!(
+            slow,
+            vec![
+                ("good".to_string(), Phase::Worker, 0),
+                ("synth".to_string(), Phase::Synthesizer, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_worker_is_reported_and_synthesis_uses_the_rest() {
+        let transport = ScriptedTransport::default()
+            .on("good.test", Scripted::completion("good answer"))
+            .on("bad.test", Scripted::status(500, "scripted failure"))
+            .on("synth.test", Scripted::completion("combined answer"));
+        let config = scripted_config(
+            &["good", "bad", "synth"],
+            r#"{"analyzer": {"ref": "good"}, "workers": [{"name": "good"}, {"name": "bad"}], "synthesizer": {"ref": "synth"}}"#,
+        );
+        let engine = scripted_engine(config, transport);
+
+        let result = engine
+            .process_with_details("hello".to_string())
+            .await
+            .unwrap();
+        assert_eq!(result.final_response, "combined answer");
+        let workers = &result.execution_details.workers;
+        assert!(workers[0].success);
+        assert!(!workers[1].success);
+        assert_eq!(workers[1].attempts[0].status, Some(500));
+        assert!(
+            workers[1]
+                .error
+                .as_deref()
+                .unwrap()
+                .contains("scripted failure"),
+            "{:?}",
+            workers[1].error
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_selector_falls_back_to_first_successful_worker() {
+        let transport = ScriptedTransport::default()
+            .on("bad.test", Scripted::status(502, "scripted failure"))
+            .on("good.test", Scripted::completion("good answer"))
+            .on("judge.test", Scripted::status(500, "scripted failure"));
+        let config = scripted_config(
+            &["bad", "good", "judge"],
+            r#"{"analyzer": {"ref": "good"}, "workers": [{"name": "bad"}, {"name": "good"}], "selector": {"ref": "judge"}}"#,
+        );
+        let engine = scripted_engine(config, transport);
+
+        let result = engine
+            .process_with_details("hello".to_string())
+            .await
+            .unwrap();
+        assert_eq!(result.final_response, "good answer");
+        let selector = result.execution_details.selector.unwrap();
+        assert!(!selector.success);
+        assert!(selector.error.is_some());
+    }
+
+    #[tokio::test]
+    async fn synthesizer_timeout_fails_the_workflow() {
+        let transport = ScriptedTransport::default()
+            .on("good.test", Scripted::completion("good answer"))
+            .on(
+                "synth.test",
+                Scripted::completion("too late").after(Duration::from_secs(3)),
+            );
+        let config = scripted_config(
+            &["good", "synth"],
+            r#"{"analyzer": {"ref": "good"}, "workers": [{"name": "good"}], "synthesizer": {"ref": "synth"}}"#,
+        );
+        let engine = scripted_engine(config, transport);
+
+        let started = Instant::now();
+        let err = engine
+            .process_with_details("hello".to_string())
+            .await
+            .unwrap_err();
+        assert!(started.elapsed() < Duration::from_secs(3));
+        assert!(
+            format!("{:#}", err).contains("request timeout (1s)"),
+            "{:#}",
+            err
         );
     }
 }
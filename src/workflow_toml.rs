@@ -0,0 +1,195 @@
+use anyhow::{anyhow, Context, Result};
+use serde_json::Value as JsonValue;
+use toml_edit::{Array, ArrayOfTables, DocumentMut, InlineTable, Item, Table, Value};
+
+// 生成的内联表按这个顺序排列键，其余键排在后面
+const PLAN_KEY_ORDER: &[&str] = &[
+    "analyzer",
+    "workers",
+    "synthesizer",
+    "selector",
+    "nested_worker_depth",
+    "preset_model_prefix",
+    "presets",
+];
+const TARGET_ROLES: &[&str] = &["analyzer", "synthesizer", "selector"];
+
+// 把 [workflow-integration]（包括各个 preset）里的 `json` 字符串改写成内联 TOML 表，
+// 文件的其余部分与注释保持不变；没有需要改写的内容时返回 None
+pub fn inline_workflow_json(content: &str) -> Result<Option<String>> {
+    let mut doc: DocumentMut = content
+        .parse()
+        .with_context(|| "Failed to parse config for migration")?;
+    let Some(integration) = doc
+        .get_mut("workflow-integration")
+        .and_then(Item::as_table_mut)
+    else {
+        return Ok(None);
+    };
+    if !inline_table_json(integration)? {
+        return Ok(None);
+    }
+    Ok(Some(doc.to_string()))
+}
+
+fn inline_table_json(table: &mut Table) -> Result<bool> {
+    let mut changed = false;
+    if let Some(item) = table.remove("json") {
+        let json = item
+            .as_str()
+            .ok_or_else(|| anyhow!("`json` in [workflow-integration] must be a string"))?;
+        let plan: JsonValue = serde_json::from_str(json)
+            .with_context(|| "Failed to parse workflow integration JSON")?;
+        // 与 `json` 同级的键（如 nested_worker_depth）在加载时优先，这里同样保留它们
+        for (key, item) in plan_table(&plan, "workflow")? {
+            match (table.get_mut(&key), item) {
+                (Some(Item::Table(existing)), Item::Table(presets))
+                    if key.as_str() == "presets" =>
+                {
+                    for (name, preset) in presets {
+                        if !existing.contains_key(&name) {
+                            existing.insert(&name, preset);
+                        }
+                    }
+                }
+                (Some(_), _) => {}
+                (None, item) => {
+                    table.insert(&key, item);
+                }
+            }
+        }
+        changed = true;
+    }
+
+    if let Some(presets) = table.get_mut("presets").and_then(Item::as_table_mut) {
+        for (_, preset) in presets.iter_mut() {
+            if let Some(preset) = preset.as_table_mut() {
+                changed |= inline_table_json(preset)?;
+            }
+        }
+    }
+    Ok(changed)
+}
+
+// 模型节点写成单行内联表；嵌套工作流无法放进单行，改用 [[...workers]] 表数组
+fn plan_table(plan: &JsonValue, path: &str) -> Result<Table> {
+    let map = plan
+        .as_object()
+        .ok_or_else(|| anyhow!("Workflow node at {} must be a JSON object", path))?;
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by_key(|key| {
+        PLAN_KEY_ORDER
+            .iter()
+            .position(|known| known == key)
+            .unwrap_or(PLAN_KEY_ORDER.len())
+    });
+
+    let mut table = Table::new();
+    for key in keys {
+        let value = &map[key];
+        let item = match key.as_str() {
+            role if TARGET_ROLES.contains(&role) => {
+                Item::Value(Value::InlineTable(target_inline(value, path)?))
+            }
+            "workers" => workers_item(value, path)?,
+            "presets" => {
+                let presets = value
+                    .as_object()
+                    .ok_or_else(|| anyhow!("`presets` at {} must be an object", path))?;
+                let mut table = Table::new();
+                table.set_implicit(true);
+                for (name, preset) in presets {
+                    let preset_path = format!("{} preset '{}'", path, name);
+                    table.insert(name, Item::Table(plan_table(preset, &preset_path)?));
+                }
+                Item::Table(table)
+            }
+            _ => Item::Value(to_value(value, path)?),
+        };
+        table.insert(key, item);
+    }
+    Ok(table)
+}
+
+fn workers_item(workers: &JsonValue, path: &str) -> Result<Item> {
+    let workers = workers
+        .as_array()
+        .ok_or_else(|| anyhow!("`workers` at {} must be an array", path))?;
+    let is_nested = |worker: &JsonValue| {
+        worker
+            .as_object()
+            .is_some_and(|map| map.contains_key("workers"))
+    };
+
+    if !workers.iter().any(is_nested) {
+        let mut array = Array::new();
+        for (index, worker) in workers.iter().enumerate() {
+            let worker_path = format!("{} -> workers[{}]", path, index);
+            let target = target_inline(worker, &worker_path)?;
+            array.push_formatted(Value::InlineTable(target).decorated("\n  ", ""));
+        }
+        array.set_trailing_comma(true);
+        array.set_trailing("\n");
+        return Ok(Item::Value(Value::Array(array)));
+    }
+
+    let mut tables = ArrayOfTables::new();
+    for (index, worker) in workers.iter().enumerate() {
+        let worker_path = format!("{} -> workers[{}]", path, index);
+        let table = if is_nested(worker) {
+            plan_table(worker, &worker_path)?
+        } else {
+            target_inline(worker, &worker_path)?.into_table()
+        };
+        tables.push(table);
+    }
+    Ok(Item::ArrayOfTables(tables))
+}
+
+// `ref` / `name` 放在最前面，其余字段保持原顺序
+fn target_inline(target: &JsonValue, path: &str) -> Result<InlineTable> {
+    let map = target
+        .as_object()
+        .ok_or_else(|| anyhow!("Workflow node at {} must be a JSON object", path))?;
+    let mut keys: Vec<&String> = map.keys().collect();
+    keys.sort_by_key(|key| !matches!(key.as_str(), "ref" | "name"));
+    let mut inline = InlineTable::new();
+    for key in keys {
+        inline.insert(key, to_value(&map[key], path)?);
+    }
+    inline.fmt();
+    Ok(inline)
+}
+
+fn to_value(value: &JsonValue, path: &str) -> Result<Value> {
+    Ok(match value {
+        JsonValue::Null => {
+            return Err(anyhow!(
+                "Workflow node at {} contains null, which TOML cannot represent",
+                path
+            ))
+        }
+        JsonValue::Bool(b) => Value::from(*b),
+        JsonValue::Number(n) => match (n.as_i64(), n.as_f64()) {
+            (Some(i), _) => Value::from(i),
+            (None, Some(f)) => Value::from(f),
+            (None, None) => return Err(anyhow!("Number {} at {} is out of range", n, path)),
+        },
+        JsonValue::String(s) => Value::from(s.as_str()),
+        JsonValue::Array(items) => {
+            let mut array = Array::new();
+            for item in items {
+                array.push(to_value(item, path)?);
+            }
+            Value::Array(array)
+        }
+        JsonValue::Object(map) => {
+            let mut inline = InlineTable::new();
+            for (key, item) in map {
+                inline.insert(key, to_value(item, path)?);
+            }
+            inline.fmt();
+            Value::InlineTable(inline)
+        }
+    })
+}